@@ -56,6 +56,15 @@ impl MPC20Contract {
     /// Shortname of the [`MPC20Contract::approve_relative`] invocation
     const SHORTNAME_APPROVE_RELATIVE: Shortname = Shortname::from_u32(0x07);
 
+    /// Shortname of the [`MPC20Contract::mint`] invocation
+    const SHORTNAME_MINT: Shortname = Shortname::from_u32(0x06);
+
+    /// Shortname of the [`MPC20Contract::burn`] invocation
+    const SHORTNAME_BURN: Shortname = Shortname::from_u32(0x08);
+
+    /// Shortname of the [`MPC20Contract::burn_from`] invocation
+    const SHORTNAME_BURN_FROM: Shortname = Shortname::from_u32(0x0A);
+
     /// Gas amount sufficient for [`MPC20Contract::transfer`] invocation.
     ///
     /// Guarantees that the invocation does not fail due to insufficient gas.
@@ -76,6 +85,21 @@ impl MPC20Contract {
     /// Guarantees that the invocation does not fail due to insufficient gas.
     pub const GAS_COST_APPROVE_RELATIVE: GasCost = 1400;
 
+    /// Gas amount sufficient for [`MPC20Contract::mint`] invocation.
+    ///
+    /// Guarantees that the invocation does not fail due to insufficient gas.
+    pub const GAS_COST_MINT: GasCost = 15500;
+
+    /// Gas amount sufficient for [`MPC20Contract::burn`] invocation.
+    ///
+    /// Guarantees that the invocation does not fail due to insufficient gas.
+    pub const GAS_COST_BURN: GasCost = 15500;
+
+    /// Gas amount sufficient for [`MPC20Contract::burn_from`] invocation.
+    ///
+    /// Guarantees that the invocation does not fail due to insufficient gas.
+    pub const GAS_COST_BURN_FROM: GasCost = 15500;
+
     /// Create new token contract representation for the given `contract_address`.
     ///
     /// It is expected that the given address indicates a [MPC20
@@ -153,4 +177,57 @@ impl MPC20Contract {
             .with_cost(Self::GAS_COST_APPROVE_RELATIVE)
             .done();
     }
+
+    /// Create an interaction with the `self` token contract, for burning an `amount` of tokens
+    /// owned by the calling contract, decreasing the total supply.
+    ///
+    /// Not part of the MPC20 standard, but a useful extension supported by the `token-v2`
+    /// contract.
+    pub fn burn(&self, event_group_builder: &mut EventGroupBuilder, amount: TokenTransferAmount) {
+        event_group_builder
+            .call(self.contract_address, Self::SHORTNAME_BURN)
+            .argument(amount)
+            .with_cost(Self::GAS_COST_BURN)
+            .done();
+    }
+
+    /// Create an interaction with the `self` token contract, for minting an `amount` of new tokens
+    /// to `receiver`, increasing the total supply. Requires that the calling contract owns the
+    /// token contract.
+    ///
+    /// Not part of the MPC20 standard, but a useful extension supported by the `token-v2`
+    /// contract.
+    pub fn mint(
+        &self,
+        event_group_builder: &mut EventGroupBuilder,
+        receiver: &Address,
+        amount: TokenTransferAmount,
+    ) {
+        event_group_builder
+            .call(self.contract_address, Self::SHORTNAME_MINT)
+            .argument(*receiver)
+            .argument(amount)
+            .with_cost(Self::GAS_COST_MINT)
+            .done();
+    }
+
+    /// Create an interaction with the `self` token contract, for burning an `amount` of tokens
+    /// owned by `owner`, decreasing the total supply. Requires that calling contract have been
+    /// given an allowance by `owner`, by using [`Self::approve`].
+    ///
+    /// Not part of the MPC20 standard, but a useful extension supported by the `token-v2`
+    /// contract.
+    pub fn burn_from(
+        &self,
+        event_group_builder: &mut EventGroupBuilder,
+        owner: &Address,
+        amount: TokenTransferAmount,
+    ) {
+        event_group_builder
+            .call(self.contract_address, Self::SHORTNAME_BURN_FROM)
+            .argument(*owner)
+            .argument(amount)
+            .with_cost(Self::GAS_COST_BURN_FROM)
+            .done();
+    }
 }
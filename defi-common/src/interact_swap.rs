@@ -8,6 +8,7 @@
 //! ```ignore
 //! #[action(shortname=0x01)] deposit(token_address: Address, amount: TokenAmount);
 //! #[action(shortname=0x03)] withdraw(token_address: Address, amount: TokenAmount, wait_for_callback: bool);
+//! #[action(shortname=0x1B)] deposit_on_behalf(beneficiary: Address, token_address: Address, amount: TokenAmount);
 //! ```
 
 use pbc_contract_common::{
@@ -30,6 +31,9 @@ impl SwapContract {
     /// Shortname of the [`SwapContract::withdraw`] invocation
     const SHORTNAME_WITHDRAW_SWAP_LOCK: Shortname = Shortname::from_u32(0x03);
 
+    /// Shortname of the [`SwapContract::deposit_on_behalf`] invocation
+    const SHORTNAME_DEPOSIT_ON_BEHALF: Shortname = Shortname::from_u32(0x1B);
+
     const DEPOSIT_BASE_COST: GasCost = 1770;
 
     /// Gas amount sufficient for [`SwapContract::deposit`] invocation.
@@ -68,6 +72,25 @@ impl SwapContract {
             .done();
     }
 
+    /// Create an interaction with the `self` swap contract, for depositing an `amount` of
+    /// `token`s from calling contract into the swap contract, credited to `beneficiary`. <br>
+    /// Requires that the calling contract has [`approve`](crate::interact_mpc20::MPC20Contract::approve)d the swap contract.
+    pub fn deposit_on_behalf(
+        &self,
+        event_group_builder: &mut EventGroupBuilder,
+        beneficiary: &Address,
+        token: &Address,
+        amount: TokenAmount,
+    ) {
+        event_group_builder
+            .call(self.contract_address, Self::SHORTNAME_DEPOSIT_ON_BEHALF)
+            .argument(*beneficiary)
+            .argument(*token)
+            .argument(amount)
+            .with_cost(Self::GAS_COST_DEPOSIT)
+            .done();
+    }
+
     /// Create an interaction with the `self` swap contract, for withdrawing an `amount` of
     /// `token`s from the swap contract.
     ///
@@ -91,3 +114,79 @@ impl SwapContract {
             .done();
     }
 }
+
+/// Validates a path of tokens for a multi-hop swap, where each consecutive pair of tokens
+/// corresponds to a swap.
+///
+/// ### Parameters:
+///
+/// * `path`: [`&[Address]`] - The token addresses, starting with the input token and ending with the output token.
+///
+/// ### Returns:
+///
+/// An error if the path contains less than two tokens, or if any token is visited more than once.
+pub fn validate_swap_path(path: &[Address]) -> Result<(), &'static str> {
+    if path.len() < 2 {
+        return Err("Swap path must contain at least two tokens");
+    }
+    if path.windows(2).any(|pair| pair[0] == pair[1]) {
+        return Err("Swap path must not swap a token to itself");
+    }
+    for (index, token) in path.iter().enumerate() {
+        if path[index + 1..].contains(token) {
+            return Err("Swap path must not visit a token more than once");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pbc_contract_common::address::AddressType;
+
+    use super::*;
+
+    fn token(id: u8) -> Address {
+        Address {
+            address_type: AddressType::PublicContract,
+            identifier: [id; 20],
+        }
+    }
+
+    #[test]
+    pub fn valid_swap_paths() {
+        assert_eq!(validate_swap_path(&[token(1), token(2)]), Ok(()));
+        assert_eq!(
+            validate_swap_path(&[token(1), token(2), token(3), token(4)]),
+            Ok(())
+        );
+    }
+
+    #[test]
+    pub fn too_short_swap_paths() {
+        assert_eq!(
+            validate_swap_path(&[]),
+            Err("Swap path must contain at least two tokens")
+        );
+        assert_eq!(
+            validate_swap_path(&[token(1)]),
+            Err("Swap path must contain at least two tokens")
+        );
+    }
+
+    #[test]
+    pub fn repeating_swap_paths() {
+        assert_eq!(
+            validate_swap_path(&[token(1), token(2), token(2), token(3)]),
+            Err("Swap path must not swap a token to itself")
+        );
+        assert_eq!(
+            validate_swap_path(&[token(1), token(2), token(1), token(2)]),
+            Err("Swap path must not visit a token more than once")
+        );
+        assert_eq!(
+            validate_swap_path(&[token(1), token(2), token(1)]),
+            Err("Swap path must not visit a token more than once")
+        );
+    }
+}
@@ -66,13 +66,33 @@ impl SwapLockContract {
         token_in: &Address,
         amount_in: TokenAmount,
         amount_out_minimum: TokenAmount,
+    ) {
+        self.acquire_swap_lock_with_gas(
+            event_group_builder,
+            token_in,
+            amount_in,
+            amount_out_minimum,
+            Self::GAS_COST_ACQUIRE_SWAP_LOCK,
+        );
+    }
+
+    /// Like [`SwapLockContract::acquire_swap_lock`], but with an explicit `gas_cost` instead of
+    /// [`SwapLockContract::GAS_COST_ACQUIRE_SWAP_LOCK`]. <br>
+    /// Useful for scaling the gas with the number of locks in the target contract.
+    pub fn acquire_swap_lock_with_gas(
+        &self,
+        event_group_builder: &mut EventGroupBuilder,
+        token_in: &Address,
+        amount_in: TokenAmount,
+        amount_out_minimum: TokenAmount,
+        gas_cost: GasCost,
     ) {
         event_group_builder
             .call(self.swap_address, Self::SHORTNAME_ACQUIRE_SWAP_LOCK)
             .argument(*token_in)
             .argument(amount_in)
             .argument(amount_out_minimum)
-            .with_cost(Self::GAS_COST_ACQUIRE_SWAP_LOCK)
+            .with_cost(gas_cost)
             .done();
     }
 
@@ -82,11 +102,22 @@ impl SwapLockContract {
         &self,
         event_group_builder: &mut EventGroupBuilder,
         lock_id: LiquidityLockId,
+    ) {
+        self.execute_lock_swap_with_gas(event_group_builder, lock_id, Self::GAS_COST_EXECUTE_LOCK);
+    }
+
+    /// Like [`SwapLockContract::execute_lock_swap`], but with an explicit `gas_cost` instead of
+    /// [`SwapLockContract::GAS_COST_EXECUTE_LOCK`].
+    pub fn execute_lock_swap_with_gas(
+        &self,
+        event_group_builder: &mut EventGroupBuilder,
+        lock_id: LiquidityLockId,
+        gas_cost: GasCost,
     ) {
         event_group_builder
             .call(self.swap_address, Self::SHORTNAME_EXECUTE_SWAP_LOCK)
             .argument(lock_id)
-            .with_cost(Self::GAS_COST_EXECUTE_LOCK)
+            .with_cost(gas_cost)
             .done();
     }
 
@@ -96,11 +127,88 @@ impl SwapLockContract {
         &self,
         event_group_builder: &mut EventGroupBuilder,
         lock_id: LiquidityLockId,
+    ) {
+        self.cancel_lock_with_gas(event_group_builder, lock_id, Self::GAS_COST_CANCEL_LOCK);
+    }
+
+    /// Like [`SwapLockContract::cancel_lock`], but with an explicit `gas_cost` instead of
+    /// [`SwapLockContract::GAS_COST_CANCEL_LOCK`].
+    pub fn cancel_lock_with_gas(
+        &self,
+        event_group_builder: &mut EventGroupBuilder,
+        lock_id: LiquidityLockId,
+        gas_cost: GasCost,
     ) {
         event_group_builder
             .call(self.swap_address, Self::SHORTNAME_CANCEL_LOCK)
             .argument(lock_id)
-            .with_cost(Self::GAS_COST_CANCEL_LOCK)
+            .with_cost(gas_cost)
             .done();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use pbc_contract_common::{address::AddressType, events::EventGroup};
+
+    use super::*;
+
+    const SWAP_ADDRESS: Address = Address {
+        address_type: AddressType::PublicContract,
+        identifier: [1; 20],
+    };
+
+    const TOKEN_ADDRESS: Address = Address {
+        address_type: AddressType::PublicContract,
+        identifier: [2; 20],
+    };
+
+    /// Builds an event group using `add_interaction`, and returns the cost of its only interaction.
+    fn interaction_cost(add_interaction: impl FnOnce(&mut EventGroupBuilder)) -> Option<GasCost> {
+        let mut event_group_builder = EventGroup::builder();
+        add_interaction(&mut event_group_builder);
+        let event_group = event_group_builder.build();
+        assert_eq!(event_group.events.len(), 1);
+        event_group.events[0].cost
+    }
+
+    #[test]
+    pub fn test_default_gas_costs() {
+        let contract = SwapLockContract::at_address(SWAP_ADDRESS);
+        let lock_id = LiquidityLockId::initial_id();
+        assert_eq!(
+            interaction_cost(|builder| contract.acquire_swap_lock(builder, &TOKEN_ADDRESS, 10, 5)),
+            Some(SwapLockContract::GAS_COST_ACQUIRE_SWAP_LOCK)
+        );
+        assert_eq!(
+            interaction_cost(|builder| contract.execute_lock_swap(builder, lock_id)),
+            Some(SwapLockContract::GAS_COST_EXECUTE_LOCK)
+        );
+        assert_eq!(
+            interaction_cost(|builder| contract.cancel_lock(builder, lock_id)),
+            Some(SwapLockContract::GAS_COST_CANCEL_LOCK)
+        );
+    }
+
+    #[test]
+    pub fn test_custom_gas_costs() {
+        let contract = SwapLockContract::at_address(SWAP_ADDRESS);
+        let lock_id = LiquidityLockId::initial_id();
+        assert_eq!(
+            interaction_cost(|builder| {
+                contract.acquire_swap_lock_with_gas(builder, &TOKEN_ADDRESS, 10, 5, 10_000)
+            }),
+            Some(10_000)
+        );
+        assert_eq!(
+            interaction_cost(
+                |builder| contract.execute_lock_swap_with_gas(builder, lock_id, 20_000)
+            ),
+            Some(20_000)
+        );
+        assert_eq!(
+            interaction_cost(|builder| contract.cancel_lock_with_gas(builder, lock_id, 30_000)),
+            Some(30_000)
+        );
+    }
+}
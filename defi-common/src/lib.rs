@@ -6,5 +6,8 @@ pub mod interact_swap;
 pub mod interact_swap_lock_partial;
 pub mod liquidity_util;
 pub mod math;
+pub mod pagination;
 pub mod permission;
+pub mod reward_schedule;
+pub mod time_guard;
 pub mod token_balances;
@@ -1,10 +1,36 @@
 //! Shared functionality for contracts involving (locked) liquidity swaps.
 
+use std::ops::RangeInclusive;
+
 use create_type_spec_derive::CreateTypeSpec;
 use read_write_rpc_derive::ReadWriteRPC;
 use read_write_state_derive::ReadWriteState;
 
-use crate::token_balances::TokenAmount;
+use crate::{
+    math::{
+        u128_division_ceil, u128_gcd, u128_mul_div, u128_mul_div_ceil, u128_sqrt, u128_widening_mul,
+    },
+    token_balances::TokenAmount,
+};
+
+/// The range of allowed swap fees in per mille.
+pub const ALLOWED_FEE_PER_MILLE: RangeInclusive<u16> = 0..=1000;
+
+/// Validates that `fee_per_mille` is in [`ALLOWED_FEE_PER_MILLE`].
+///
+/// ### Parameters:
+///
+/// * `fee_per_mille`: [`u16`] - The fee to validate.
+///
+/// # Returns
+/// The fee if it is allowed, otherwise an error message. [`Result<u16, &'static str>`]
+pub fn validate_fee_per_mille(fee_per_mille: u16) -> Result<u16, &'static str> {
+    if ALLOWED_FEE_PER_MILLE.contains(&fee_per_mille) {
+        Ok(fee_per_mille)
+    } else {
+        Err("Fee must be in range [0,1000]")
+    }
+}
 
 /// Id of a liquidity-lock.
 #[derive(
@@ -63,3 +89,1244 @@ pub fn calculate_swap_to_amount(
     (remainder_ratio * swap_amount_in * pool_token_out)
         / (1000 * pool_token_in + remainder_ratio * swap_amount_in)
 }
+
+/// Calculates the portion of `swap_amount_in` taken as fee by [`calculate_swap_to_amount`]. <br>
+/// Allows displaying the fee separately from the amount received.
+///
+/// ### Parameters:
+///
+/// * `swap_amount_in`: [`TokenAmount`] - The amount being swapped.
+///
+/// * `swap_fee_per_mille`: [`u16`] - The fee to take out of swapped to amount. Must be in [`ALLOWED_FEE_PER_MILLE`].
+///
+/// # Returns
+/// The fee in the input token, rounded down. [`TokenAmount`]
+pub fn swap_fee_amount(swap_amount_in: TokenAmount, swap_fee_per_mille: u16) -> TokenAmount {
+    swap_amount_in * swap_fee_per_mille as TokenAmount / 1000
+}
+
+/// Calculates the largest amount that can be swapped into a pool, while keeping the price impact
+/// at or below `max_impact_per_mille`. <br>
+/// The price impact is the relative decrease of the exchange rate received, compared to the spot
+/// exchange rate of the pools, disregarding the fee. For the constant product formula used by
+/// [`calculate_swap_to_amount`], the impact of swapping `a` tokens, where `a'` is `a` minus the
+/// fee, is `a' / (pool_token_in + a')`. An input receiving no output tokens at all has an impact
+/// of 100%, which is where the output pool bounds the result.
+///
+/// ### Parameters:
+///
+/// * `pool_token_in`: [`TokenAmount`] - The token pool matching the token being swapped.
+///
+/// * `pool_token_out`: [`TokenAmount`] - The token pool matching the token being received.
+///
+/// * `max_impact_per_mille`: [`u16`] - The maximum allowed price impact in per mille.
+///
+/// * `swap_fee_per_mille`: [`u16`] - The fee taken out of swapped amounts. Must be at most 1000.
+///
+/// # Returns
+/// The maximum input amount, rounded down. Returns [`TokenAmount::MAX`] if any input is allowed. [`TokenAmount`]
+pub fn max_input_for_impact(
+    pool_token_in: TokenAmount,
+    pool_token_out: TokenAmount,
+    max_impact_per_mille: u16,
+    swap_fee_per_mille: u16,
+) -> TokenAmount {
+    if max_impact_per_mille >= 1000 || swap_fee_per_mille >= 1000 {
+        return TokenAmount::MAX;
+    }
+    let max_impact = max_impact_per_mille as TokenAmount;
+    let remainder_ratio = (1000 - swap_fee_per_mille) as TokenAmount;
+
+    // Solves remainder_ratio * input * (1000 - max_impact) <= 1000 * max_impact * pool_token_in.
+    let denominator = remainder_ratio * (1000 - max_impact);
+    let max_input =
+        u128_mul_div(pool_token_in, 1000 * max_impact, denominator).unwrap_or(TokenAmount::MAX);
+
+    // The output is increasing in the input, so if the largest input receives nothing, every
+    // smaller input does too. It receives nothing when
+    // remainder_ratio * input * pool_token_out < 1000 * pool_token_in + remainder_ratio * input.
+    let receives_nothing = pool_token_out == 0
+        || u128_widening_mul(max_input, pool_token_out - 1)
+            .checked_mul_u128(remainder_ratio)
+            .is_some_and(|product| product < u128_widening_mul(pool_token_in, 1000));
+    if receives_nothing {
+        0
+    } else {
+        max_input
+    }
+}
+
+/// Calculates the smallest input pool for which swapping `typical_trade` keeps the price impact
+/// at or below `max_impact_per_mille`, as defined by [`max_input_for_impact`] for a deep output
+/// pool. <br>
+/// Allows operators to determine how deep a pool must be seeded.
+///
+/// ### Parameters:
+///
+/// * `typical_trade`: [`TokenAmount`] - The amount of the input token swapped by a typical trade.
+///
+/// * `max_impact_per_mille`: [`u16`] - The maximum allowed price impact in per mille.
+///
+/// * `swap_fee_per_mille`: [`u16`] - The fee taken out of swapped amounts. Must be at most 1000.
+///
+/// # Returns
+/// The minimum input pool, rounded up. Returns `0` if any pool suffices, and [`TokenAmount::MAX`]
+/// if no pool can be deep enough. [`TokenAmount`]
+pub fn min_reserves_for_impact(
+    typical_trade: TokenAmount,
+    max_impact_per_mille: u16,
+    swap_fee_per_mille: u16,
+) -> TokenAmount {
+    if typical_trade == 0 || max_impact_per_mille >= 1000 || swap_fee_per_mille >= 1000 {
+        return 0;
+    }
+    if max_impact_per_mille == 0 {
+        return TokenAmount::MAX;
+    }
+    let max_impact = max_impact_per_mille as TokenAmount;
+    let remainder_ratio = (1000 - swap_fee_per_mille) as TokenAmount;
+
+    // Solves remainder_ratio * typical_trade * (1000 - max_impact) <= 1000 * max_impact * pool.
+    let factor = remainder_ratio * (1000 - max_impact);
+    let denominator = 1000 * max_impact;
+    match typical_trade.checked_mul(factor) {
+        Some(numerator) => u128_division_ceil(numerator, denominator).unwrap(),
+        // Dividing first rounds up further, still keeping the impact below the cap.
+        None => u128_division_ceil(typical_trade, denominator)
+            .unwrap()
+            .saturating_mul(factor),
+    }
+}
+
+/// Calculates the amount of the output token required when providing `amount_in` of the input
+/// token as liquidity. <br>
+/// Due to integer rounding, one additional output token is required, protecting the pools. Allows
+/// clients to fund the output token before providing liquidity.
+///
+/// ### Parameters:
+///
+/// * `amount_in`: [`TokenAmount`] - The amount of the input token being provided.
+///
+/// * `reserve_in`: [`TokenAmount`] - The pool of the input token. Must be non-zero.
+///
+/// * `reserve_out`: [`TokenAmount`] - The pool of the output token.
+///
+/// # Returns
+/// The required amount of the output token, or `0` if `amount_in` is `0`. [`TokenAmount`]
+pub fn required_token_out_for(
+    amount_in: TokenAmount,
+    reserve_in: TokenAmount,
+    reserve_out: TokenAmount,
+) -> TokenAmount {
+    // Handle zero-case
+    if amount_in > 0 {
+        (amount_in * reserve_out / reserve_in) + 1
+    } else {
+        0
+    }
+}
+
+/// Calculates how much of [`required_token_out_for`] exceeds the exact equivalent of `amount_in`,
+/// rounded up. <br>
+/// The additional output token only protects the pools when the equivalent is rounded down, so
+/// it is an over-deposit whenever the equivalent is exact. Allows refunding liquidity providers.
+///
+/// ### Parameters:
+///
+/// * `amount_in`: [`TokenAmount`] - The amount of the input token being provided.
+///
+/// * `reserve_in`: [`TokenAmount`] - The pool of the input token. Must be non-zero.
+///
+/// * `reserve_out`: [`TokenAmount`] - The pool of the output token.
+///
+/// # Returns
+/// `1` if the equivalent is exact, otherwise `0`. [`TokenAmount`]
+pub fn over_deposit_for(
+    amount_in: TokenAmount,
+    reserve_in: TokenAmount,
+    reserve_out: TokenAmount,
+) -> TokenAmount {
+    if amount_in > 0 && (amount_in * reserve_out) % reserve_in == 0 {
+        1
+    } else {
+        0
+    }
+}
+
+/// Calculates the minimum amount of liquidity tokens a liquidity provider should accept when
+/// providing `amount_in` of a token, tolerating `slippage_per_mille` of the expected amount. <br>
+/// The expected amount `amount_in * total_minted / reserve_in` is minted when the pools do not
+/// change before the liquidity is provided, along with the equivalent amount of the output token.
+///
+/// ### Parameters:
+///
+/// * `amount_in`: [`TokenAmount`] - The amount of the input token being provided.
+///
+/// * `reserve_in`: [`TokenAmount`] - The pool of the input token.
+///
+/// * `reserve_out`: [`TokenAmount`] - The pool of the output token.
+///
+/// * `total_minted`: [`TokenAmount`] - The total current minted liquidity.
+///
+/// * `slippage_per_mille`: [`u16`] - The tolerated decrease from the expected amount. Saturates at 1000.
+///
+/// # Returns
+/// The expected amount of minted liquidity tokens, less the tolerated slippage, rounded down.
+/// `0` if either pool is empty, as the initial liquidity is not a share of the pools. [`TokenAmount`]
+pub fn min_minted_with_slippage(
+    amount_in: TokenAmount,
+    reserve_in: TokenAmount,
+    reserve_out: TokenAmount,
+    total_minted: TokenAmount,
+    slippage_per_mille: u16,
+) -> TokenAmount {
+    if reserve_in == 0 || reserve_out == 0 {
+        return 0;
+    }
+    // Saturates, as no more than TokenAmount::MAX liquidity tokens can be minted.
+    let expected_minted =
+        u128_mul_div(amount_in, total_minted, reserve_in).unwrap_or(TokenAmount::MAX);
+    let remainder_ratio = 1000 - slippage_per_mille.min(1000) as TokenAmount;
+    // Cannot fail, as the result is at most `expected_minted`.
+    u128_mul_div(expected_minted, remainder_ratio, 1000).unwrap()
+}
+
+/// Calculates the amount of liquidity tokens to reclaim, such that at least `target_output` of a
+/// pool token is received. <br>
+/// This is the inverse of reclaiming liquidity, which outputs `pool * liquidity_token_amount / minted_liquidity`
+/// of each pool token, rounded down. The result is rounded up, such that the target is reached.
+///
+/// ### Parameters:
+///
+/// * `target_output`: [`TokenAmount`] - The desired amount of the pool token.
+///
+/// * `pool`: [`TokenAmount`] - The pool of the desired token.
+///
+/// * `minted_liquidity`: [`TokenAmount`] - The total current minted liquidity.
+///
+/// # Returns
+/// The smallest amount of liquidity tokens yielding at least `target_output`, or [`None`] if the
+/// pool holds less than `target_output`. [`Option<TokenAmount>`]
+pub fn liquidity_for_reclaim_output(
+    target_output: TokenAmount,
+    pool: TokenAmount,
+    minted_liquidity: TokenAmount,
+) -> Option<TokenAmount> {
+    if target_output > pool {
+        return None;
+    }
+    if target_output == 0 {
+        return Some(0);
+    }
+    // Cannot overflow, as the result is at most `minted_liquidity`.
+    Some(u128_mul_div_ceil(target_output, minted_liquidity, pool).unwrap())
+}
+
+/// Calculates the input amount maximizing the profit of an arbitrage between the pools and an
+/// external market with price `external_price_num / external_price_den` output tokens per input
+/// token. <br>
+/// Swapping the amount moves the marginal price of the pools, including the fee, to the external
+/// price. The profit `output - amount * external_price` is maximized where
+/// `swap_fee_ratio * reserve_in * reserve_out / (reserve_in + swap_fee_ratio * amount)^2 = external_price`.
+///
+/// ### Parameters:
+///
+/// * `reserve_in`: [`TokenAmount`] - The pool of the input token.
+///
+/// * `reserve_out`: [`TokenAmount`] - The pool of the output token.
+///
+/// * `external_price_num`: [`TokenAmount`] - The numerator of the external price. Must be non-zero.
+///
+/// * `external_price_den`: [`TokenAmount`] - The denominator of the external price.
+///
+/// * `swap_fee_per_mille`: [`u16`] - The fee taken out of swapped amounts. Must be in [`ALLOWED_FEE_PER_MILLE`].
+///
+/// # Returns
+/// The optimal input amount, rounded down, or `0` if no arbitrage is profitable. [`TokenAmount`]
+pub fn optimal_arb_amount(
+    reserve_in: TokenAmount,
+    reserve_out: TokenAmount,
+    external_price_num: TokenAmount,
+    external_price_den: TokenAmount,
+    swap_fee_per_mille: u16,
+) -> TokenAmount {
+    let remainder_ratio = (1000 - swap_fee_per_mille) as TokenAmount;
+    if remainder_ratio == 0 {
+        return 0;
+    }
+
+    // The square of the input pool, including the input amount after fees, at which the prices
+    // are equal. Exceeds the range of U256, and thereby any swappable amount, only for extreme
+    // external prices.
+    let Some(balanced_square) = u128_widening_mul(reserve_in, reserve_out)
+        .checked_mul_div(remainder_ratio, 1000)
+        .and_then(|square| square.checked_mul_div(external_price_den, external_price_num))
+    else {
+        return TokenAmount::MAX;
+    };
+    let balanced_reserve_in = balanced_square.sqrt();
+    if balanced_reserve_in <= reserve_in {
+        return 0;
+    }
+    u128_mul_div(balanced_reserve_in - reserve_in, 1000, remainder_ratio)
+        .unwrap_or(TokenAmount::MAX)
+}
+
+/// Calculates the marginal price of the pools after swapping `amount_in` of the input token. <br>
+/// The entire input amount, including the fee, is added to the input pool, and the output of
+/// [`calculate_swap_to_amount`] is removed from the output pool. Allows predicting the effect of
+/// chained swaps without simulating the contract state.
+///
+/// ### Parameters:
+///
+/// * `reserve_in`: [`TokenAmount`] - The pool of the input token.
+///
+/// * `reserve_out`: [`TokenAmount`] - The pool of the output token.
+///
+/// * `amount_in`: [`TokenAmount`] - The amount being swapped.
+///
+/// * `swap_fee_per_mille`: [`u16`] - The fee taken out of swapped amounts. Must be in [`ALLOWED_FEE_PER_MILLE`].
+///
+/// # Returns
+/// The price in output tokens per input token, as the numerator and denominator of the post-swap
+/// pools `(reserve_out, reserve_in)`. [`(u128, u128)`]
+pub fn price_after_swap(
+    reserve_in: TokenAmount,
+    reserve_out: TokenAmount,
+    amount_in: TokenAmount,
+    swap_fee_per_mille: u16,
+) -> (u128, u128) {
+    let amount_out =
+        calculate_swap_to_amount(reserve_in, reserve_out, amount_in, swap_fee_per_mille);
+    (reserve_out - amount_out, reserve_in + amount_in)
+}
+
+/// Calculates the depth of the pools down to a target price, being the largest amount of the input
+/// token that can be swapped before the price, as defined by [`price_after_swap`], falls below
+/// `target_price_num / target_price_den`. <br>
+/// Allows traders to see how much liquidity is available before the price moves past a threshold.
+///
+/// ### Parameters:
+///
+/// * `reserve_in`: [`TokenAmount`] - The pool of the input token. Must be non-zero.
+///
+/// * `reserve_out`: [`TokenAmount`] - The pool of the output token.
+///
+/// * `target_price_num`: [`TokenAmount`] - The numerator of the target price, in output tokens per input token.
+///
+/// * `target_price_den`: [`TokenAmount`] - The denominator of the target price. Must be non-zero.
+///
+/// * `swap_fee_per_mille`: [`u16`] - The fee taken out of swapped amounts. Must be at most 1000.
+///
+/// # Returns
+/// The input amount, rounded down. Returns `0` if the price is already at or below the target,
+/// and [`TokenAmount::MAX`] if the target price can never be reached, or only beyond the range of
+/// [`TokenAmount`]. [`TokenAmount`]
+pub fn depth_to_price(
+    reserve_in: TokenAmount,
+    reserve_out: TokenAmount,
+    target_price_num: TokenAmount,
+    target_price_den: TokenAmount,
+    swap_fee_per_mille: u16,
+) -> TokenAmount {
+    if target_price_num == 0 || swap_fee_per_mille >= 1000 {
+        return TokenAmount::MAX;
+    }
+    if u128_widening_mul(target_price_num, reserve_in)
+        >= u128_widening_mul(reserve_out, target_price_den)
+    {
+        return 0;
+    }
+    let fee = swap_fee_per_mille as TokenAmount;
+    let remainder_ratio = 1000 - fee;
+
+    // Solves reserve_in * reserve_out / target_price = (reserve_in + amount_in * remainder_ratio / 1000)
+    // * (reserve_in + amount_in), which is quadratic in amount_in.
+    let Some(discriminant) = u128_widening_mul(reserve_in, reserve_in)
+        .checked_mul_u128(fee * fee)
+        .zip(
+            u128_widening_mul(reserve_in, reserve_out)
+                .checked_mul_u128(4 * remainder_ratio * 1000)
+                .and_then(|product| product.checked_mul_div(target_price_den, target_price_num)),
+        )
+        .and_then(|(fee_term, price_term)| fee_term.checked_add(price_term))
+    else {
+        return TokenAmount::MAX;
+    };
+    let root = discriminant.sqrt();
+    // The root is below the subtracted term, whenever that term exceeds the range of TokenAmount.
+    (1000 + remainder_ratio)
+        .checked_mul(reserve_in)
+        .map_or(0, |subtracted| root.saturating_sub(subtracted))
+        / (2 * remainder_ratio)
+}
+
+/// Calculates the effective exchange rate of swapping `amount_in` of the input token, including
+/// the fee and price impact, as a fraction. <br>
+/// Allows displaying the rate received without rounding it to an integer.
+///
+/// ### Parameters:
+///
+/// * `reserve_in`: [`TokenAmount`] - The pool of the input token.
+///
+/// * `reserve_out`: [`TokenAmount`] - The pool of the output token.
+///
+/// * `amount_in`: [`TokenAmount`] - The amount being swapped.
+///
+/// * `swap_fee_per_mille`: [`u16`] - The fee taken out of swapped amounts. Must be in [`ALLOWED_FEE_PER_MILLE`].
+///
+/// # Returns
+/// The output amount per input token, as the reduced numerator and denominator
+/// `(amount_out, amount_in)`. Returns `(0, 1)` if `amount_in` is `0`. [`(u128, u128)`]
+pub fn effective_rate(
+    reserve_in: TokenAmount,
+    reserve_out: TokenAmount,
+    amount_in: TokenAmount,
+    swap_fee_per_mille: u16,
+) -> (u128, u128) {
+    if amount_in == 0 {
+        return (0, 1);
+    }
+    let amount_out =
+        calculate_swap_to_amount(reserve_in, reserve_out, amount_in, swap_fee_per_mille);
+    let divisor = u128_gcd(amount_out, amount_in);
+    (amount_out / divisor, amount_in / divisor)
+}
+
+/// Calculates the impermanent loss of a liquidity position, i.e. how much less the position is
+/// worth compared to holding the provided tokens, after the price moved from
+/// `initial_price_ratio` to `current_price_ratio`. <br>
+/// For the price change `r = current_price_ratio / initial_price_ratio`, the impermanent loss is
+/// `1 - 2 * sqrt(r) / (1 + r)`, which is independent of the amount provided and excludes fees.
+///
+/// ### Parameters:
+///
+/// * `initial_price_ratio`: [`u128`] - The price when the liquidity was provided, e.g. `reserve_b / reserve_a`.
+///
+/// * `current_price_ratio`: [`u128`] - The current price, in the same fixed-point scale as `initial_price_ratio`.
+///
+/// # Returns
+/// The impermanent loss in per mille, rounded down. Price changes beyond a factor of `2^63` are
+/// approximated, approaching a loss of 1000. Returns `0` if both prices are `0`. [`u16`]
+pub fn impermanent_loss_per_mille(initial_price_ratio: u128, current_price_ratio: u128) -> u16 {
+    let (mut current, mut initial) = (current_price_ratio, initial_price_ratio);
+    let bits = 128 - current.max(initial).leading_zeros();
+    if bits == 0 {
+        return 0;
+    }
+
+    // Only the ratio matters, so both prices are scaled to 63 bits, such that their product
+    // cannot overflow, while keeping the precision of the square root.
+    if bits > 63 {
+        (current, initial) = (current >> (bits - 63), initial >> (bits - 63));
+    } else {
+        (current, initial) = (current << (63 - bits), initial << (63 - bits));
+    }
+    let sum = current + initial;
+    let twice_geometric_mean = 2 * u128_sqrt(current * initial) as u128;
+    (1000 * (sum - twice_geometric_mean) / sum) as u16
+}
+
+/// Calculates the smallest swap fee that yields at least `target_revenue` in fees, when swapping
+/// `expected_volume` input tokens in total.
+///
+/// ### Parameters:
+///
+/// * `target_revenue`: [`TokenAmount`] - The desired amount of fees.
+///
+/// * `expected_volume`: [`TokenAmount`] - The expected total amount of swapped input tokens.
+///
+/// # Returns
+/// The fee in per mille, rounded up and clamped to [`ALLOWED_FEE_PER_MILLE`]. [`u16`]
+pub fn break_even_fee_per_mille(target_revenue: TokenAmount, expected_volume: TokenAmount) -> u16 {
+    let max_fee = *ALLOWED_FEE_PER_MILLE.end();
+    if target_revenue == 0 {
+        return *ALLOWED_FEE_PER_MILLE.start();
+    }
+    if target_revenue >= expected_volume {
+        return max_fee;
+    }
+
+    let fee_per_mille = match target_revenue.checked_mul(1000) {
+        Some(numerator) => u128_division_ceil(numerator, expected_volume).unwrap(),
+        // The volume is at least 1000, as it exceeds the revenue.
+        None => u128_division_ceil(target_revenue, expected_volume / 1000).unwrap(),
+    };
+    fee_per_mille.min(max_fee as u128) as u16
+}
+
+/// Calculates the smallest amount to swap into a pool, for which the output received by
+/// [`calculate_swap_to_amount`] exceeds the gas cost of the swap. <br>
+/// Allows arbitrage bots to filter out swaps too small to be profitable.
+///
+/// ### Parameters:
+///
+/// * `reserve_in`: [`TokenAmount`] - The token pool matching the token being swapped. Must be non-zero.
+///
+/// * `reserve_out`: [`TokenAmount`] - The output token pool.
+///
+/// * `swap_fee_per_mille`: [`u16`] - The fee taken out of swapped amounts. Must be at most 1000.
+///
+/// * `gas_cost_in_token_terms`: [`TokenAmount`] - The gas cost of the swap, expressed in the output token.
+///
+/// # Returns
+/// The minimum input amount, rounded up. Returns [`TokenAmount::MAX`] if no swap can produce
+/// more than the gas cost. [`TokenAmount`]
+pub fn min_profitable_swap(
+    reserve_in: TokenAmount,
+    reserve_out: TokenAmount,
+    swap_fee_per_mille: u16,
+    gas_cost_in_token_terms: TokenAmount,
+) -> TokenAmount {
+    // The output is always less than the output pool.
+    if swap_fee_per_mille >= 1000 || reserve_out <= gas_cost_in_token_terms.saturating_add(1) {
+        return TokenAmount::MAX;
+    }
+    let min_output = gas_cost_in_token_terms + 1;
+    let remainder_ratio = (1000 - swap_fee_per_mille) as TokenAmount;
+
+    // Solves remainder_ratio * input * (reserve_out - min_output) >= 1000 * min_output * reserve_in.
+    let surplus_out = reserve_out - min_output;
+    let numerator = min_output
+        .checked_mul(1000)
+        .and_then(|scaled_output| scaled_output.checked_mul(reserve_in));
+    match (numerator, surplus_out.checked_mul(remainder_ratio)) {
+        (Some(numerator), Some(denominator)) => u128_division_ceil(numerator, denominator).unwrap(),
+        // Dividing first rounds up further, still producing more than the gas cost.
+        _ => {
+            let scaled_input = u128_division_ceil(reserve_in, surplus_out)
+                .unwrap()
+                .saturating_mul(min_output.saturating_mul(1000));
+            u128_division_ceil(scaled_input, remainder_ratio).unwrap()
+        }
+    }
+}
+
+/// Calculates the output of swapping `amount_in`, if the price of the output token moves against
+/// the trader by `adverse_move_per_mille` before the swap is executed. <br>
+/// The move is modelled by shrinking the output pool, which lowers the spot price by exactly the
+/// move, while keeping the depth of the input pool. This is more pessimistic than a move along the
+/// constant product curve, making the result a principled `amount_out_minimum`.
+///
+/// ### Parameters:
+///
+/// * `reserve_in`: [`TokenAmount`] - The pool of the input token.
+///
+/// * `reserve_out`: [`TokenAmount`] - The pool of the output token.
+///
+/// * `amount_in`: [`TokenAmount`] - The amount being swapped.
+///
+/// * `adverse_move_per_mille`: [`u16`] - The largest tolerated decrease of the price in per mille.
+///
+/// * `swap_fee_per_mille`: [`u16`] - The fee taken out of swapped amounts. Must be in [`ALLOWED_FEE_PER_MILLE`].
+///
+/// # Returns
+/// The worst-case output, rounded down. Returns `0` if the move is at least 1000 per mille. [`TokenAmount`]
+pub fn worst_case_output(
+    reserve_in: TokenAmount,
+    reserve_out: TokenAmount,
+    amount_in: TokenAmount,
+    adverse_move_per_mille: u16,
+    swap_fee_per_mille: u16,
+) -> TokenAmount {
+    if adverse_move_per_mille >= 1000 {
+        return 0;
+    }
+    let remaining_per_mille = (1000 - adverse_move_per_mille) as TokenAmount;
+    let shifted_reserve_out = match reserve_out.checked_mul(remaining_per_mille) {
+        Some(scaled_reserve_out) => scaled_reserve_out / 1000,
+        // Dividing first rounds down further, still being a worst case.
+        None => reserve_out / 1000 * remaining_per_mille,
+    };
+    calculate_swap_to_amount(
+        reserve_in,
+        shifted_reserve_out,
+        amount_in,
+        swap_fee_per_mille,
+    )
+}
+
+/// Upper bound on the number of swaps simulated by [`swaps_to_drain`].
+pub const MAX_DRAIN_SIMULATION_SWAPS: u32 = 10_000;
+
+/// Simulates repeated swaps of `max_swap_in` into the pool, and counts how many it takes to
+/// reduce `reserve_out` below `target_reserve_out`. <br>
+/// Each swap uses the full per-swap cap, i.e. the largest impact allowed, and the reserves are
+/// updated between swaps as by [`calculate_swap_to_amount`]. Fees are kept in the pool.
+///
+/// ### Parameters:
+///
+/// * `reserve_in`: [`TokenAmount`] - The pool of the input token.
+///
+/// * `reserve_out`: [`TokenAmount`] - The pool of the output token being drained.
+///
+/// * `max_swap_in`: [`TokenAmount`] - The input amount of every swap.
+///
+/// * `target_reserve_out`: [`TokenAmount`] - The output reserve to drain below.
+///
+/// * `swap_fee_per_mille`: [`u16`] - The fee taken out of swapped amounts. Must be in [`ALLOWED_FEE_PER_MILLE`].
+///
+/// # Returns
+/// The number of swaps, `0` if `reserve_out` is already below the target. Returns [`None`] if
+/// the target is not reached within [`MAX_DRAIN_SIMULATION_SWAPS`] swaps, or if a swap would
+/// yield nothing or overflow the input reserve. [`Option<u32>`]
+pub fn swaps_to_drain(
+    reserve_in: TokenAmount,
+    reserve_out: TokenAmount,
+    max_swap_in: TokenAmount,
+    target_reserve_out: TokenAmount,
+    swap_fee_per_mille: u16,
+) -> Option<u32> {
+    let (mut reserve_in, mut reserve_out) = (reserve_in, reserve_out);
+    for swaps in 0..MAX_DRAIN_SIMULATION_SWAPS {
+        if reserve_out < target_reserve_out {
+            return Some(swaps);
+        }
+        let amount_out =
+            calculate_swap_to_amount(reserve_in, reserve_out, max_swap_in, swap_fee_per_mille);
+        if amount_out == 0 {
+            return None;
+        }
+        reserve_in = reserve_in.checked_add(max_swap_in)?;
+        reserve_out -= amount_out;
+    }
+    (reserve_out < target_reserve_out).then_some(MAX_DRAIN_SIMULATION_SWAPS)
+}
+
+/// Number of milliseconds in a (non-leap) year.
+const MILLIS_PER_YEAR: u128 = 365 * 24 * 60 * 60 * 1000;
+
+/// Estimates the annualized yield, in per mille, of `fees_collected` over `elapsed_millis`,
+/// relative to the total value locked `tvl`. <br>
+/// Assumes that fees are collected at a constant rate, and are not compounded.
+///
+/// ### Parameters:
+///
+/// * `fees_collected`: [`TokenAmount`] - The fees collected during the period.
+///
+/// * `tvl`: [`TokenAmount`] - The total value locked, in the same token as `fees_collected`.
+///
+/// * `elapsed_millis`: [`i64`] - The length of the period in milliseconds.
+///
+/// # Returns
+/// The estimated APR in per mille, saturating at [`u16::MAX`]. Returns `0` if either `tvl` or
+/// `elapsed_millis` is not positive. [`u16`]
+pub fn estimate_apr_per_mille(
+    fees_collected: TokenAmount,
+    tvl: TokenAmount,
+    elapsed_millis: i64,
+) -> u16 {
+    if tvl == 0 || elapsed_millis <= 0 {
+        return 0;
+    }
+    let elapsed_millis = elapsed_millis as u128;
+
+    // The annualized fees may exceed 128 bits for large fee amounts, so divide in 256 bits.
+    let apr_per_mille = u128_widening_mul(fees_collected, 1000 * MILLIS_PER_YEAR)
+        .checked_div_rem_u128(tvl)
+        .and_then(|(per_tvl, _)| per_tvl.checked_div_rem_u128(elapsed_millis))
+        .and_then(|(apr_per_mille, _)| apr_per_mille.to_u128())
+        .unwrap_or(u128::MAX);
+    u16::try_from(apr_per_mille).unwrap_or(u16::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    const MILLIS_PER_YEAR_I64: i64 = MILLIS_PER_YEAR as i64;
+
+    /// Determines whether swapping `amount_in` into `pool_token_in` has at most the given impact.
+    fn impact_within(
+        pool_token_in: TokenAmount,
+        amount_in: TokenAmount,
+        max_impact_per_mille: u16,
+        swap_fee_per_mille: u16,
+    ) -> bool {
+        let max_impact = max_impact_per_mille as TokenAmount;
+        let remainder_ratio = (1000 - swap_fee_per_mille) as TokenAmount;
+        remainder_ratio * amount_in * (1000 - max_impact) <= 1000 * max_impact * pool_token_in
+    }
+
+    #[test]
+    pub fn test_max_input_for_impact() {
+        // Without fee, 10% impact is reached when the input is 1/9 of the pool.
+        assert_eq!(max_input_for_impact(9000, 9000, 100, 0), 1000);
+        // The fee is not part of the impact, allowing a slightly larger input.
+        assert_eq!(max_input_for_impact(9000, 9000, 100, 3), 1003);
+        assert_eq!(max_input_for_impact(9000, 9000, 0, 3), 0);
+        assert_eq!(max_input_for_impact(0, 9000, 100, 3), 0);
+        assert_eq!(max_input_for_impact(9000, 9000, 1000, 3), TokenAmount::MAX);
+        assert_eq!(
+            max_input_for_impact(9000, 9000, 100, 1000),
+            TokenAmount::MAX
+        );
+        assert_eq!(
+            max_input_for_impact(TokenAmount::MAX, TokenAmount::MAX, 999, 0),
+            TokenAmount::MAX
+        );
+        assert_eq!(
+            max_input_for_impact(TokenAmount::MAX / 1000, TokenAmount::MAX, 500, 0),
+            TokenAmount::MAX / 1000
+        );
+    }
+
+    #[test]
+    pub fn test_max_input_for_impact_shallow_output_pool() {
+        // Swapping 1000 into 9000 receives 1 of 10, but nothing of 9.
+        assert_eq!(max_input_for_impact(9000, 10, 100, 0), 1000);
+        assert_eq!(max_input_for_impact(9000, 9, 100, 0), 0);
+        assert_eq!(max_input_for_impact(9000, 0, 100, 0), 0);
+    }
+
+    #[test]
+    pub fn test_validate_fee_per_mille() {
+        assert_eq!(validate_fee_per_mille(0), Ok(0));
+        assert_eq!(validate_fee_per_mille(3), Ok(3));
+        assert_eq!(validate_fee_per_mille(1000), Ok(1000));
+        assert_eq!(
+            validate_fee_per_mille(1001),
+            Err("Fee must be in range [0,1000]")
+        );
+        assert!(validate_fee_per_mille(u16::MAX).is_err());
+    }
+
+    #[test]
+    pub fn test_swap_fee_amount() {
+        assert_eq!(swap_fee_amount(1000, 0), 0);
+        assert_eq!(swap_fee_amount(1000, 1000), 1000);
+        assert_eq!(swap_fee_amount(1000, 3), 3);
+        assert_eq!(swap_fee_amount(10_000, 3), 30);
+        // Rounds down.
+        assert_eq!(swap_fee_amount(999, 3), 2);
+        assert_eq!(swap_fee_amount(333, 3), 0);
+    }
+
+    #[test]
+    pub fn test_swap_fee_amount_is_what_pool_keeps() {
+        // Swapping the fee-less remainder without fee yields the same output, up to rounding.
+        let amount_in = 10_000;
+        let fee = swap_fee_amount(amount_in, 3);
+        assert_eq!(
+            calculate_swap_to_amount(100_000, 100_000, amount_in, 3),
+            calculate_swap_to_amount(100_000, 100_000, amount_in - fee, 0)
+        );
+    }
+
+    #[test]
+    pub fn test_break_even_fee_per_mille() {
+        assert_eq!(break_even_fee_per_mille(3, 1000), 3);
+        assert_eq!(break_even_fee_per_mille(30_000, 10_000_000), 3);
+        // Rounds up, such that the target is reached.
+        assert_eq!(break_even_fee_per_mille(30_001, 10_000_000), 4);
+        assert_eq!(break_even_fee_per_mille(1, 1_000_000), 1);
+        assert_eq!(break_even_fee_per_mille(500, 1000), 500);
+        assert_eq!(break_even_fee_per_mille(u128::MAX / 2, u128::MAX), 501);
+    }
+
+    #[test]
+    pub fn test_break_even_fee_per_mille_clamped() {
+        assert_eq!(break_even_fee_per_mille(0, 0), 0);
+        assert_eq!(break_even_fee_per_mille(0, 1000), 0);
+        assert_eq!(break_even_fee_per_mille(1000, 1000), 1000);
+        assert_eq!(break_even_fee_per_mille(2000, 1000), 1000);
+        assert_eq!(break_even_fee_per_mille(1, 0), 1000);
+    }
+
+    #[test]
+    pub fn test_min_profitable_swap() {
+        // Swapping 101 yields exactly the gas cost of 100, so one more token is required.
+        assert_eq!(min_profitable_swap(1_000_000, 1_000_000, 3, 100), 102);
+        // A pricier output token lowers the threshold.
+        assert_eq!(min_profitable_swap(1_000_000, 2_000_000, 3, 100), 51);
+        // Without gas, any swap producing output is profitable.
+        assert_eq!(min_profitable_swap(1_000_000, 1_000_000, 3, 0), 2);
+        assert_eq!(min_profitable_swap(1_000_000, 1_000_000, 0, 9), 11);
+        // Near the depth of the output pool, the threshold grows quickly.
+        assert_eq!(min_profitable_swap(1000, 1000, 3, 998), 1_002_007);
+        assert_eq!(min_profitable_swap(1000, 1000, 3, 999), TokenAmount::MAX);
+        assert_eq!(min_profitable_swap(1000, 1000, 1000, 0), TokenAmount::MAX);
+        assert!(min_profitable_swap(TokenAmount::MAX, TokenAmount::MAX, 3, 100) > 0);
+    }
+
+    proptest! {
+        #[test]
+        fn min_profitable_swap_is_just_profitable(
+            reserve_in in 1..u32::MAX,
+            reserve_out in any::<u32>(),
+            swap_fee_per_mille in 0u16..1000,
+            gas_cost in any::<u16>(),
+        ) {
+            let (reserve_in, reserve_out) = (reserve_in as TokenAmount, reserve_out as TokenAmount);
+            let gas_cost = gas_cost as TokenAmount;
+            let amount_in = min_profitable_swap(reserve_in, reserve_out, swap_fee_per_mille, gas_cost);
+            if amount_in != TokenAmount::MAX {
+                assert!(calculate_swap_to_amount(reserve_in, reserve_out, amount_in, swap_fee_per_mille) > gas_cost);
+                assert!(calculate_swap_to_amount(reserve_in, reserve_out, amount_in - 1, swap_fee_per_mille) <= gas_cost);
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn max_input_for_impact_is_at_or_just_under_cap(
+            pool_token_in in any::<u64>(),
+            max_impact_per_mille in 0u16..1000,
+            swap_fee_per_mille in 0u16..1000,
+        ) {
+            let pool_token_in = pool_token_in as TokenAmount;
+            let amount_in = max_input_for_impact(pool_token_in, TokenAmount::MAX, max_impact_per_mille, swap_fee_per_mille);
+            assert!(impact_within(pool_token_in, amount_in, max_impact_per_mille, swap_fee_per_mille));
+            assert!(!impact_within(pool_token_in, amount_in + 1, max_impact_per_mille, swap_fee_per_mille));
+        }
+    }
+
+    #[test]
+    pub fn test_min_reserves_for_impact() {
+        // Without fee, a trade of 1/9 of the pool has an impact of 10%.
+        assert_eq!(min_reserves_for_impact(1000, 100, 0), 9000);
+        // The fee is not part of the impact, allowing a slightly shallower pool.
+        assert_eq!(min_reserves_for_impact(1000, 100, 3), 8973);
+        // Keeping a trade at 1% impact requires a pool roughly 100 times larger.
+        assert_eq!(min_reserves_for_impact(1000, 10, 3), 98_703);
+        assert_eq!(min_reserves_for_impact(0, 10, 3), 0);
+        assert_eq!(min_reserves_for_impact(1000, 1000, 3), 0);
+        assert_eq!(min_reserves_for_impact(1000, 10, 1000), 0);
+        assert_eq!(min_reserves_for_impact(1000, 0, 3), TokenAmount::MAX);
+        assert!(min_reserves_for_impact(TokenAmount::MAX, 1, 0) > 0);
+    }
+
+    proptest! {
+        #[test]
+        fn min_reserves_for_impact_is_at_or_just_over_cap(
+            typical_trade in any::<u64>(),
+            max_impact_per_mille in 1u16..1000,
+            swap_fee_per_mille in 0u16..1000,
+        ) {
+            let typical_trade = typical_trade as TokenAmount;
+            let pool = min_reserves_for_impact(typical_trade, max_impact_per_mille, swap_fee_per_mille);
+            assert!(impact_within(pool, typical_trade, max_impact_per_mille, swap_fee_per_mille));
+            if pool > 0 {
+                assert!(!impact_within(pool - 1, typical_trade, max_impact_per_mille, swap_fee_per_mille));
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_required_token_out_for() {
+        assert_eq!(required_token_out_for(100, 1000, 2000), 201);
+        assert_eq!(required_token_out_for(1, 3, 1), 1);
+        assert_eq!(required_token_out_for(10, 3, 10), 34);
+        assert_eq!(required_token_out_for(0, 1000, 2000), 0);
+    }
+
+    #[test]
+    pub fn test_over_deposit_for() {
+        // 100 A is worth exactly 200 B, so the additional token is not needed.
+        assert_eq!(over_deposit_for(100, 1000, 2000), 1);
+        assert_eq!(over_deposit_for(30, 3, 10), 1);
+        // The equivalent is rounded down, and the additional token rounds it up.
+        assert_eq!(over_deposit_for(1, 3, 1), 0);
+        assert_eq!(over_deposit_for(10, 3, 10), 0);
+        assert_eq!(over_deposit_for(0, 1000, 2000), 0);
+    }
+
+    proptest! {
+        #[test]
+        fn over_deposit_leaves_ceiled_equivalent(
+            amount_in in any::<u32>(),
+            reserve_in in 1..u32::MAX,
+            reserve_out in any::<u32>(),
+        ) {
+            let (amount_in, reserve_in, reserve_out) =
+                (amount_in as TokenAmount, reserve_in as TokenAmount, reserve_out as TokenAmount);
+            let charged = required_token_out_for(amount_in, reserve_in, reserve_out)
+                - over_deposit_for(amount_in, reserve_in, reserve_out);
+            assert_eq!(charged, u128_division_ceil(amount_in * reserve_out, reserve_in).unwrap());
+        }
+    }
+
+    #[test]
+    pub fn test_min_minted_with_slippage() {
+        // Expected to mint 500 liquidity tokens.
+        assert_eq!(min_minted_with_slippage(100, 1000, 2000, 5000, 0), 500);
+        assert_eq!(min_minted_with_slippage(100, 1000, 2000, 5000, 10), 495);
+        assert_eq!(min_minted_with_slippage(100, 1000, 2000, 5000, 100), 450);
+        assert_eq!(min_minted_with_slippage(100, 1000, 2000, 5000, 500), 250);
+        assert_eq!(min_minted_with_slippage(100, 1000, 2000, 5000, 1000), 0);
+        assert_eq!(min_minted_with_slippage(100, 1000, 2000, 5000, u16::MAX), 0);
+        // Rounds down.
+        assert_eq!(min_minted_with_slippage(100, 1000, 2000, 5010, 3), 499);
+        // Empty pools mint the initial liquidity.
+        assert_eq!(min_minted_with_slippage(100, 0, 0, 0, 0), 0);
+    }
+
+    #[test]
+    pub fn test_min_minted_with_slippage_large_amounts() {
+        let expected_minted = TokenAmount::MAX / 2;
+        assert_eq!(
+            min_minted_with_slippage(1, 2, 2, TokenAmount::MAX, 0),
+            expected_minted
+        );
+        assert_eq!(
+            min_minted_with_slippage(1, 2, 2, TokenAmount::MAX, 500),
+            expected_minted / 2
+        );
+        // Products exceeding u128.
+        assert_eq!(
+            min_minted_with_slippage(
+                TokenAmount::MAX / 4,
+                TokenAmount::MAX / 2,
+                TokenAmount::MAX,
+                TokenAmount::MAX / 2,
+                100
+            ),
+            76_563_532_557_211_154_279_259_286_672_147_847_576
+        );
+    }
+
+    /// The output of reclaiming `liquidity_token_amount` from `pool`.
+    fn reclaim_output(
+        liquidity_token_amount: TokenAmount,
+        pool: TokenAmount,
+        minted_liquidity: TokenAmount,
+    ) -> TokenAmount {
+        u128_mul_div(pool, liquidity_token_amount, minted_liquidity).unwrap()
+    }
+
+    #[test]
+    pub fn test_liquidity_for_reclaim_output() {
+        assert_eq!(liquidity_for_reclaim_output(500, 1000, 100), Some(50));
+        // Rounds up, such that the target is reached.
+        assert_eq!(liquidity_for_reclaim_output(501, 1000, 100), Some(51));
+        assert_eq!(liquidity_for_reclaim_output(1000, 1000, 100), Some(100));
+        assert_eq!(liquidity_for_reclaim_output(0, 1000, 100), Some(0));
+        assert_eq!(liquidity_for_reclaim_output(1001, 1000, 100), None);
+        // Products exceeding u128.
+        assert_eq!(
+            liquidity_for_reclaim_output(u128::MAX / 2, u128::MAX, u128::MAX),
+            Some(u128::MAX / 2)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn liquidity_for_reclaim_output_reaches_target(
+            pool in 1..u128::MAX,
+            minted_liquidity in 1..u128::MAX,
+            target_output in any::<u128>(),
+        ) {
+            let target_output = target_output % (pool + 1);
+            let liquidity_token_amount =
+                liquidity_for_reclaim_output(target_output, pool, minted_liquidity).unwrap();
+            assert!(liquidity_token_amount <= minted_liquidity);
+            assert!(reclaim_output(liquidity_token_amount, pool, minted_liquidity) >= target_output);
+            if liquidity_token_amount > 0 {
+                assert!(reclaim_output(liquidity_token_amount - 1, pool, minted_liquidity) < target_output);
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_optimal_arb_amount() {
+        // The pools price the input token at 4, the external market at 1.
+        assert_eq!(optimal_arb_amount(1000, 4000, 1, 1, 0), 1000);
+        // Already balanced, or priced lower than the external market.
+        assert_eq!(optimal_arb_amount(1000, 4000, 4, 1, 0), 0);
+        assert_eq!(optimal_arb_amount(1000, 4000, 8, 1, 0), 0);
+        // The fee outweighs the price difference.
+        assert_eq!(optimal_arb_amount(1000, 1001, 1, 1, 3), 0);
+        assert_eq!(optimal_arb_amount(1000, 4000, 1, 1, 1000), 0);
+    }
+
+    #[test]
+    pub fn test_optimal_arb_amount_large_reserves() {
+        // Pools of millions of 18 decimal tokens, whose product exceeds u128.
+        let reserve_in = 1_000_000 * 10u128.pow(18);
+        let reserve_out = 4 * reserve_in;
+        assert_eq!(
+            optimal_arb_amount(reserve_in, reserve_out, 1, 1, 0),
+            reserve_in
+        );
+        assert_eq!(
+            optimal_arb_amount(reserve_in, reserve_out, 1, 1, 3),
+            999_997_739_838_173_075_192_033
+        );
+        assert_eq!(optimal_arb_amount(reserve_in, reserve_out, 4, 1, 3), 0);
+    }
+
+    proptest! {
+        #[test]
+        fn optimal_arb_amount_reaches_external_price(
+            reserve_in in 1_000_000u64..1_000_000_000_000,
+            reserve_out in 1_000_000u64..1_000_000_000_000,
+            external_price_num in 1u32..1_000_000,
+            external_price_den in 1u32..1_000_000,
+            swap_fee_per_mille in 0u16..100,
+        ) {
+            let amount_in = optimal_arb_amount(
+                reserve_in.into(),
+                reserve_out.into(),
+                external_price_num.into(),
+                external_price_den.into(),
+                swap_fee_per_mille,
+            );
+
+            let remainder_ratio = (1000 - swap_fee_per_mille) as f64 / 1000.0;
+            let (reserve_in, reserve_out) = (reserve_in as f64, reserve_out as f64);
+            let external_price = external_price_num as f64 / external_price_den as f64;
+            let marginal_price = |amount_in: f64| {
+                remainder_ratio * reserve_in * reserve_out
+                    / (reserve_in + remainder_ratio * amount_in).powi(2)
+            };
+
+            if amount_in == 0 {
+                assert!(marginal_price(0.0) <= external_price * 1.00001);
+            } else {
+                let relative_error = marginal_price(amount_in as f64) / external_price - 1.0;
+                assert!(relative_error.abs() < 0.00001);
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_price_after_swap() {
+        assert_eq!(price_after_swap(1000, 4000, 0, 3), (4000, 1000));
+        // Swapping 1000 without fee halves the output pool.
+        assert_eq!(price_after_swap(1000, 4000, 1000, 0), (2000, 2000));
+        // The fee stays in the input pool, leaving slightly more in the output pool.
+        assert_eq!(price_after_swap(1000, 4000, 1000, 3), (2004, 2000));
+    }
+
+    proptest! {
+        #[test]
+        fn price_after_swap_matches_swapped_pools(
+            reserve_in in 1..u32::MAX,
+            reserve_out in 1..u32::MAX,
+            amount_in in any::<u32>(),
+            swap_fee_per_mille in 0u16..=1000,
+        ) {
+            let (reserve_in, reserve_out, amount_in) =
+                (reserve_in as TokenAmount, reserve_out as TokenAmount, amount_in as TokenAmount);
+            let amount_out =
+                calculate_swap_to_amount(reserve_in, reserve_out, amount_in, swap_fee_per_mille);
+            let (price_num, price_den) =
+                price_after_swap(reserve_in, reserve_out, amount_in, swap_fee_per_mille);
+            assert_eq!(price_num, reserve_out - amount_out);
+            assert_eq!(price_den, reserve_in + amount_in);
+            // Swapping never increases the price of the input token.
+            assert!(price_num * reserve_in <= reserve_out * price_den);
+        }
+    }
+
+    #[test]
+    pub fn test_depth_to_price() {
+        // The depth grows as the target price moves away from the spot price of 1.
+        assert_eq!(depth_to_price(10_000, 10_000, 99, 100, 3), 50);
+        assert_eq!(depth_to_price(10_000, 10_000, 9, 10, 3), 541);
+        assert_eq!(depth_to_price(10_000, 10_000, 1, 2, 3), 4148);
+        assert_eq!(depth_to_price(10_000, 10_000, 1, 4, 3), 10_015);
+        // Without fee, the price is quartered by doubling the input pool.
+        assert_eq!(depth_to_price(10_000, 10_000, 1, 4, 0), 10_000);
+        assert_eq!(depth_to_price(10_000, 10_000, 1, 1, 3), 0);
+        assert_eq!(depth_to_price(10_000, 10_000, 2, 1, 3), 0);
+        assert_eq!(depth_to_price(10_000, 10_000, 0, 1, 3), TokenAmount::MAX);
+        assert_eq!(depth_to_price(10_000, 10_000, 1, 2, 1000), TokenAmount::MAX);
+    }
+
+    #[test]
+    pub fn test_depth_to_price_large_reserves() {
+        // Pools of a million 18 decimal tokens, whose product exceeds u128.
+        let reserve = 1_000_000 * 10u128.pow(18);
+        assert_eq!(depth_to_price(reserve, reserve, 1, 4, 0), reserve);
+        assert_eq!(
+            depth_to_price(reserve, reserve, 1, 2, 3),
+            414_835_953_198_742_810_714_915
+        );
+        assert_eq!(depth_to_price(reserve, reserve, 2, 1, 3), 0);
+    }
+
+    proptest! {
+        #[test]
+        fn depth_to_price_stays_above_target(
+            reserve_in in 1..u32::MAX,
+            reserve_out in any::<u32>(),
+            target_price_num in 1..u16::MAX,
+            closer_price_num in 1..u16::MAX,
+            target_price_den in 1..u16::MAX,
+            swap_fee_per_mille in 0u16..1000,
+        ) {
+            let (reserve_in, reserve_out) = (reserve_in as TokenAmount, reserve_out as TokenAmount);
+            let (target_price_num, target_price_den) =
+                (target_price_num as TokenAmount, target_price_den as TokenAmount);
+            let closer_price_num = target_price_num.max(closer_price_num as TokenAmount);
+
+            let depth = depth_to_price(reserve_in, reserve_out, target_price_num, target_price_den, swap_fee_per_mille);
+            let closer_depth = depth_to_price(reserve_in, reserve_out, closer_price_num, target_price_den, swap_fee_per_mille);
+            assert!(closer_depth <= depth);
+            if target_price_num * reserve_in <= reserve_out * target_price_den {
+                let (price_num, price_den) = price_after_swap(reserve_in, reserve_out, depth, swap_fee_per_mille);
+                assert!(price_num * target_price_den >= target_price_num * price_den);
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_effective_rate() {
+        // Swapping 1000 into equal pools of 4000 without fee yields 800.
+        assert_eq!(effective_rate(4000, 4000, 1000, 0), (4, 5));
+        // 997 * 1000 * 4000 / (4000 * 1000 + 997 * 1000) = 798.
+        assert_eq!(effective_rate(4000, 4000, 1000, 3), (399, 500));
+        // The rate worsens as the amount grows relative to the pools.
+        assert_eq!(effective_rate(4000, 4000, 2000, 0), (1333, 2000));
+        assert_eq!(effective_rate(4000, 4000, 4000, 0), (1, 2));
+        assert_eq!(effective_rate(4000, 4000, 0, 3), (0, 1));
+        assert_eq!(effective_rate(4000, 4000, 1000, 1000), (0, 1));
+    }
+
+    proptest! {
+        #[test]
+        fn effective_rate_worsens_with_amount_in(
+            reserve_in in 1..u32::MAX,
+            reserve_out in 1..u32::MAX,
+            amount_in in 1..u32::MAX,
+            additional_amount_in in 1..u32::MAX,
+            swap_fee_per_mille in 0u16..=1000,
+        ) {
+            let (reserve_in, reserve_out) = (reserve_in as TokenAmount, reserve_out as TokenAmount);
+            let (amount_in, larger_amount_in) =
+                (amount_in as TokenAmount, amount_in as TokenAmount + additional_amount_in as TokenAmount);
+            let (rate_num, rate_den) =
+                effective_rate(reserve_in, reserve_out, amount_in, swap_fee_per_mille);
+            let (larger_rate_num, larger_rate_den) =
+                effective_rate(reserve_in, reserve_out, larger_amount_in, swap_fee_per_mille);
+            assert_eq!(u128_gcd(rate_num, rate_den), 1);
+            assert_eq!(
+                rate_num * amount_in / rate_den,
+                calculate_swap_to_amount(reserve_in, reserve_out, amount_in, swap_fee_per_mille)
+            );
+            // The output is rounded down, so the rate of the smaller amount is only bounded by
+            // its output plus one.
+            let (unrounded_num, unrounded_den) = (rate_num * amount_in / rate_den + 1, amount_in);
+            assert!(larger_rate_num * unrounded_den < unrounded_num * larger_rate_den);
+        }
+    }
+
+    #[test]
+    pub fn test_impermanent_loss_per_mille() {
+        assert_eq!(impermanent_loss_per_mille(1, 1), 0);
+        assert_eq!(impermanent_loss_per_mille(12_345, 12_345), 0);
+        // 1 - 2 * sqrt(4) / (1 + 4) = 0.2, regardless of direction.
+        assert_eq!(impermanent_loss_per_mille(1, 4), 200);
+        assert_eq!(impermanent_loss_per_mille(4, 1), 200);
+        assert_eq!(impermanent_loss_per_mille(1_000_000, 4_000_000), 200);
+        // 1 - 2 * sqrt(2) / 3 = 0.0572.
+        assert_eq!(impermanent_loss_per_mille(1, 2), 57);
+        assert_eq!(impermanent_loss_per_mille(1, 0), 1000);
+        assert_eq!(impermanent_loss_per_mille(0, 0), 0);
+        assert_eq!(impermanent_loss_per_mille(1, u128::MAX), 1000);
+        assert_eq!(impermanent_loss_per_mille(u128::MAX, u128::MAX), 0);
+    }
+
+    #[test]
+    pub fn test_worst_case_output() {
+        assert_eq!(worst_case_output(1_000_000, 1_000_000, 1000, 0, 3), 996);
+        assert_eq!(worst_case_output(1_000_000, 1_000_000, 1000, 10, 3), 986);
+        assert_eq!(worst_case_output(1_000_000, 1_000_000, 1000, 50, 3), 946);
+        assert_eq!(worst_case_output(1_000_000, 1_000_000, 1000, 100, 3), 896);
+        assert_eq!(worst_case_output(1_000_000, 1_000_000, 1000, 999, 3), 0);
+        assert_eq!(worst_case_output(1_000_000, 1_000_000, 1000, 1000, 3), 0);
+        // Without a move, the worst case is the regular output.
+        assert_eq!(
+            worst_case_output(1_000_000, 1_000_000, 1000, 0, 3),
+            calculate_swap_to_amount(1_000_000, 1_000_000, 1000, 3)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn worst_case_output_decreases_with_move(
+            reserve_in in 1..u64::MAX,
+            reserve_out in any::<u64>(),
+            amount_in in any::<u32>(),
+            adverse_move_per_mille in 0u16..1000,
+            swap_fee_per_mille in 0u16..=1000,
+        ) {
+            let (reserve_in, reserve_out) = (reserve_in as TokenAmount, reserve_out as TokenAmount);
+            let amount_in = amount_in as TokenAmount;
+            let output = worst_case_output(reserve_in, reserve_out, amount_in, adverse_move_per_mille, swap_fee_per_mille);
+            let worse_output = worst_case_output(reserve_in, reserve_out, amount_in, adverse_move_per_mille + 1, swap_fee_per_mille);
+            assert!(worse_output <= output);
+            assert!(output <= calculate_swap_to_amount(reserve_in, reserve_out, amount_in, swap_fee_per_mille));
+        }
+    }
+
+    #[test]
+    pub fn test_swaps_to_drain() {
+        assert_eq!(swaps_to_drain(1000, 1000, 100, 500, 0), Some(11));
+        assert_eq!(swaps_to_drain(1000, 1000, 100, 500, 3), Some(11));
+        assert_eq!(swaps_to_drain(1000, 1000, 1000, 500, 0), Some(2));
+        assert_eq!(swaps_to_drain(1000, 1000, 1000, 501, 0), Some(1));
+        // Already below the target.
+        assert_eq!(swaps_to_drain(1000, 1000, 100, 1001, 3), Some(0));
+        // Swaps eventually yield nothing before the pool is emptied.
+        assert_eq!(swaps_to_drain(1000, 1000, 100, 1, 3), None);
+        assert_eq!(swaps_to_drain(1000, 1000, 100, 0, 3), None);
+    }
+
+    #[test]
+    pub fn test_swaps_to_drain_matches_manual_simulation() {
+        let (mut reserve_in, mut reserve_out) = (1000, 1000);
+        let mut swaps = 0;
+        while reserve_out >= 800 {
+            let amount_out = calculate_swap_to_amount(reserve_in, reserve_out, 50, 3);
+            reserve_in += 50;
+            reserve_out -= amount_out;
+            swaps += 1;
+        }
+        assert_eq!(swaps_to_drain(1000, 1000, 50, 800, 3), Some(swaps));
+    }
+
+    #[test]
+    pub fn test_estimate_apr_per_mille() {
+        // 1% of TVL collected over a year.
+        assert_eq!(estimate_apr_per_mille(10, 1000, MILLIS_PER_YEAR_I64), 10);
+        // 1% of TVL collected over half a year.
+        assert_eq!(
+            estimate_apr_per_mille(10, 1000, MILLIS_PER_YEAR_I64 / 2),
+            20
+        );
+        // 0.3% of TVL collected over a day.
+        assert_eq!(estimate_apr_per_mille(3, 1000, 24 * 60 * 60 * 1000), 1095);
+        assert_eq!(estimate_apr_per_mille(0, 1000, MILLIS_PER_YEAR_I64), 0);
+    }
+
+    #[test]
+    pub fn test_estimate_apr_per_mille_degenerate_inputs() {
+        assert_eq!(estimate_apr_per_mille(10, 0, MILLIS_PER_YEAR_I64), 0);
+        assert_eq!(estimate_apr_per_mille(10, 1000, 0), 0);
+        assert_eq!(estimate_apr_per_mille(10, 1000, -1), 0);
+        assert_eq!(estimate_apr_per_mille(1000, 1000, 1), u16::MAX);
+        assert_eq!(estimate_apr_per_mille(u128::MAX, 1, 1), u16::MAX);
+        assert_eq!(
+            estimate_apr_per_mille(u128::MAX, u128::MAX, MILLIS_PER_YEAR_I64),
+            1000
+        );
+    }
+
+    #[test]
+    pub fn test_estimate_apr_per_mille_large_tvl() {
+        // 1e30 fees on 1e32 TVL is 1 %, although the annualized fees exceed 128 bits.
+        let tvl = 10u128.pow(32);
+        assert_eq!(
+            estimate_apr_per_mille(10u128.pow(30), tvl, MILLIS_PER_YEAR_I64),
+            10
+        );
+        assert_eq!(
+            estimate_apr_per_mille(10u128.pow(30), tvl, MILLIS_PER_YEAR_I64 / 2),
+            20
+        );
+    }
+}
@@ -52,6 +52,221 @@ pub fn u128_division_ceil(numerator: u128, denominator: u128) -> Result<u128, &'
     Ok(div_floor + u128::from(rem != 0))
 }
 
+/// Computes the greatest common divisor of two [`u128`] types, using the Euclidean algorithm.
+///
+/// ### Parameters:
+///
+/// * `a`: The first number.
+///
+/// * `b`: The second number.
+///
+/// ### Returns:
+///
+/// The greatest common divisor of `a` and `b`, or `0` if both are `0`, of type [`u128`].
+pub fn u128_gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Unsigned 256-bit integer, represented by its high and low 128-bit words. <br>
+/// Supports comparison and the few checked operations needed to keep intermediate products of
+/// [`u128`]s exact.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct U256 {
+    /// The most significant 128 bits.
+    pub high: u128,
+    /// The least significant 128 bits.
+    pub low: u128,
+}
+
+/// Multiplies two [`u128`] types without overflowing.
+///
+/// ### Parameters:
+///
+/// * `a`: The first factor.
+///
+/// * `b`: The second factor.
+///
+/// ### Returns:
+///
+/// The full product of `a` and `b`, of type [`U256`].
+pub fn u128_widening_mul(a: u128, b: u128) -> U256 {
+    const MASK: u128 = u64::MAX as u128;
+    let (a_low, a_high) = (a & MASK, a >> 64);
+    let (b_low, b_high) = (b & MASK, b >> 64);
+
+    // Each partial product of 64-bit halves fits in 128 bits.
+    let low_low = a_low * b_low;
+    let low_high = a_low * b_high;
+    let high_low = a_high * b_low;
+    let high_high = a_high * b_high;
+
+    let middle = (low_low >> 64) + (low_high & MASK) + (high_low & MASK);
+    U256 {
+        high: high_high + (low_high >> 64) + (high_low >> 64) + (middle >> 64),
+        low: (low_low & MASK) | (middle << 64),
+    }
+}
+
+impl U256 {
+    /// The value zero.
+    pub const ZERO: U256 = U256 { high: 0, low: 0 };
+
+    /// Widens a [`u128`] to a [`U256`].
+    pub fn from_u128(value: u128) -> U256 {
+        U256 {
+            high: 0,
+            low: value,
+        }
+    }
+
+    /// Narrows to a [`u128`], or [`None`] if the value does not fit.
+    pub fn to_u128(self) -> Option<u128> {
+        if self.high == 0 {
+            Some(self.low)
+        } else {
+            None
+        }
+    }
+
+    /// Adds `other`, or [`None`] if the sum overflows.
+    pub fn checked_add(self, other: U256) -> Option<U256> {
+        let (low, carry) = self.low.overflowing_add(other.low);
+        let high = self
+            .high
+            .checked_add(other.high)?
+            .checked_add(u128::from(carry))?;
+        Some(U256 { high, low })
+    }
+
+    /// Subtracts `other`, or [`None`] if the difference is negative.
+    pub fn checked_sub(self, other: U256) -> Option<U256> {
+        let (low, borrow) = self.low.overflowing_sub(other.low);
+        let high = self
+            .high
+            .checked_sub(other.high)?
+            .checked_sub(u128::from(borrow))?;
+        Some(U256 { high, low })
+    }
+
+    /// Multiplies by `factor`, or [`None`] if the product overflows.
+    pub fn checked_mul_u128(self, factor: u128) -> Option<U256> {
+        let low_product = u128_widening_mul(self.low, factor);
+        let high = self
+            .high
+            .checked_mul(factor)?
+            .checked_add(low_product.high)?;
+        Some(U256 {
+            high,
+            low: low_product.low,
+        })
+    }
+
+    /// Divides by `divisor`, rounding down, and returns the quotient and the remainder, or
+    /// [`None`] when dividing by zero.
+    pub fn checked_div_rem_u128(self, divisor: u128) -> Option<(U256, u128)> {
+        let quotient_high = self.high.checked_div(divisor)?;
+        let mut remainder = self.high % divisor;
+        let mut quotient_low = 0;
+
+        // Long division of the low word, one bit at a time. The remainder stays below the
+        // divisor, so a bit shifted out of it means the shifted remainder exceeds the divisor.
+        for bit in (0..128).rev() {
+            let carry = remainder >> 127;
+            remainder = (remainder << 1) | ((self.low >> bit) & 1);
+            quotient_low <<= 1;
+            if carry == 1 || remainder >= divisor {
+                remainder = remainder.wrapping_sub(divisor);
+                quotient_low |= 1;
+            }
+        }
+
+        Some((
+            U256 {
+                high: quotient_high,
+                low: quotient_low,
+            },
+            remainder,
+        ))
+    }
+
+    /// Computes `self * numerator / denominator`, rounding down, or [`None`] when dividing by zero
+    /// or if the result overflows. <br>
+    /// When `self * numerator` does not fit, `self` is divided first, which loses at most
+    /// `numerator` of precision on an already huge quotient.
+    pub fn checked_mul_div(self, numerator: u128, denominator: u128) -> Option<U256> {
+        match self.checked_mul_u128(numerator) {
+            Some(product) => Some(product.checked_div_rem_u128(denominator)?.0),
+            None => self
+                .checked_div_rem_u128(denominator)?
+                .0
+                .checked_mul_u128(numerator),
+        }
+    }
+
+    /// Finds the square root (using binary search) rounding down.
+    ///
+    /// ### Returns:
+    /// The largest x, such that x*x is <= self, of type [`u128`]
+    pub fn sqrt(self) -> u128 {
+        let mut low: u128 = 0;
+        let mut high: u128 = u128::MAX;
+
+        while low != high {
+            // Rounds the middle up, so that the search progresses when high = low + 1.
+            let middle = high - (high - low) / 2;
+            if u128_widening_mul(middle, middle) <= self {
+                low = middle;
+            } else {
+                high = middle - 1;
+            }
+        }
+        low
+    }
+}
+
+/// Computes `a * b / denominator`, rounding down, without overflowing the intermediate product.
+///
+/// ### Parameters:
+///
+/// * `a`: The first factor.
+///
+/// * `b`: The second factor.
+///
+/// * `denominator`: The denominator for the division.
+///
+/// ### Returns:
+///
+/// The quotient of type [`u128`], or [`None`] when dividing by zero or if the quotient does not
+/// fit.
+pub fn u128_mul_div(a: u128, b: u128, denominator: u128) -> Option<u128> {
+    u128_widening_mul(a, b)
+        .checked_div_rem_u128(denominator)?
+        .0
+        .to_u128()
+}
+
+/// Computes `a * b / denominator`, rounding up, without overflowing the intermediate product.
+///
+/// ### Parameters:
+///
+/// * `a`: The first factor.
+///
+/// * `b`: The second factor.
+///
+/// * `denominator`: The denominator for the division.
+///
+/// ### Returns:
+///
+/// The quotient of type [`u128`], or [`None`] when dividing by zero or if the quotient does not
+/// fit.
+pub fn u128_mul_div_ceil(a: u128, b: u128, denominator: u128) -> Option<u128> {
+    let (quotient, remainder) = u128_widening_mul(a, b).checked_div_rem_u128(denominator)?;
+    quotient.to_u128()?.checked_add(u128::from(remainder != 0))
+}
+
 #[cfg(test)]
 mod tests {
     use proptest::prelude::*;
@@ -119,6 +334,32 @@ mod tests {
         }
     }
 
+    #[test]
+    pub fn test_u128_widening_mul() {
+        assert_eq!(u128_widening_mul(0, u128::MAX), U256 { high: 0, low: 0 });
+        assert_eq!(u128_widening_mul(6, 7), U256 { high: 0, low: 42 });
+        assert_eq!(
+            u128_widening_mul(1 << 64, 1 << 64),
+            U256 { high: 1, low: 0 }
+        );
+        assert_eq!(
+            u128_widening_mul(u128::MAX, u128::MAX),
+            U256 {
+                high: u128::MAX - 1,
+                low: 1
+            }
+        );
+        assert!(u128_widening_mul(u128::MAX, 2) > u128_widening_mul(u128::MAX - 1, 2));
+    }
+
+    proptest! {
+        #[test]
+        fn u128_widening_mul_matches_small_products(a in any::<u64>(), b in any::<u128>()) {
+            let (a, b) = (a as u128, b >> 64);
+            assert_eq!(u128_widening_mul(a, b), U256 { high: 0, low: a * b });
+        }
+    }
+
     proptest! {
         #[test]
         fn u128_sqrt_must_not_crash(i in any::<u128>()) {
@@ -126,6 +367,65 @@ mod tests {
         }
     }
 
+    #[test]
+    pub fn test_u256_arithmetic() {
+        let max_squared = u128_widening_mul(u128::MAX, u128::MAX);
+        assert_eq!(
+            U256::from_u128(u128::MAX).checked_add(U256::from_u128(1)),
+            Some(U256 { high: 1, low: 0 })
+        );
+        assert_eq!(max_squared.checked_add(max_squared), None);
+        assert_eq!(
+            U256 { high: 1, low: 0 }.checked_sub(U256::from_u128(1)),
+            Some(U256::from_u128(u128::MAX))
+        );
+        assert_eq!(U256::ZERO.checked_sub(U256::from_u128(1)), None);
+        assert_eq!(
+            U256::from_u128(u128::MAX).checked_mul_u128(u128::MAX),
+            Some(max_squared)
+        );
+        assert_eq!(max_squared.checked_mul_u128(2), None);
+        assert_eq!(
+            max_squared.checked_div_rem_u128(u128::MAX),
+            Some((U256::from_u128(u128::MAX), 0))
+        );
+        assert_eq!(U256::from_u128(7).checked_div_rem_u128(0), None);
+        assert_eq!(U256 { high: 1, low: 0 }.to_u128(), None);
+        assert_eq!(max_squared.sqrt(), u128::MAX);
+        assert_eq!(U256::from_u128(24).sqrt(), 4);
+        assert_eq!(U256::ZERO.sqrt(), 0);
+    }
+
+    #[test]
+    pub fn test_u128_mul_div() {
+        assert_eq!(
+            u128_mul_div(u128::MAX, u128::MAX, u128::MAX),
+            Some(u128::MAX)
+        );
+        assert_eq!(u128_mul_div(u128::MAX, 3, 4), Some(u128::MAX / 4 * 3 + 2));
+        assert_eq!(u128_mul_div(u128::MAX, 2, 1), None);
+        assert_eq!(u128_mul_div(1, 1, 0), None);
+        assert_eq!(u128_mul_div_ceil(10, 3, 4), Some(8));
+        assert_eq!(u128_mul_div_ceil(10, 2, 4), Some(5));
+        assert_eq!(
+            u128_mul_div_ceil(u128::MAX, u128::MAX - 1, u128::MAX),
+            Some(u128::MAX - 1)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn u256_div_rem_inverts_mul(a in any::<u128>(), b in 1..u128::MAX) {
+            let product = u128_widening_mul(a, b);
+            assert_eq!(product.checked_div_rem_u128(b), Some((U256::from_u128(a), 0)));
+            let root = product.sqrt();
+            assert!(u128_widening_mul(root, root) <= product);
+            if root < u128::MAX {
+                assert!(u128_widening_mul(root + 1, root + 1) > product);
+            }
+        }
+    }
+
     #[test]
     pub fn test_u128_division_ceil() {
         // Division by 0 cases is guarded against by u128 type and the source code
@@ -140,6 +440,17 @@ mod tests {
         assert_eq!(u128_division_ceil(15, 0), Err("Division by zero"));
     }
 
+    #[test]
+    pub fn test_u128_gcd() {
+        assert_eq!(u128_gcd(12, 18), 6);
+        assert_eq!(u128_gcd(18, 12), 6);
+        assert_eq!(u128_gcd(17, 5), 1);
+        assert_eq!(u128_gcd(0, 5), 5);
+        assert_eq!(u128_gcd(5, 0), 5);
+        assert_eq!(u128_gcd(0, 0), 0);
+        assert_eq!(u128_gcd(u128::MAX, u128::MAX - 1), 1);
+    }
+
     #[test]
     pub fn test_u128_division_ceil_2() {
         let a: u128 = 0xDEADBEEF;
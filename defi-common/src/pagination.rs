@@ -0,0 +1,126 @@
+//! Small utility library for bounded iteration over [`AvlTreeMap`]s.
+//!
+//! Iterating an entire map in a single invocation may run out of gas, once the map grows large.
+//! Instead, the map can be processed in pages, where each invocation handles a bounded number of
+//! entries, and continues from where the previous invocation stopped.
+
+use pbc_contract_common::avl_tree_map::AvlTreeMap;
+use pbc_traits::ReadWriteState;
+
+/// A bounded number of consecutive entries of a map.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Page<K, V> {
+    /// The entries of the page, ordered by key.
+    pub entries: Vec<(K, V)>,
+    /// The key to start the next page from, or [`None`] if this is the last page.
+    pub next_start: Option<K>,
+}
+
+/// Retrieves at most `limit` entries from `map`, starting from the first key at or after `start`.
+/// The cost of a page depends on `limit`, but not on how far into the map `start` is.
+///
+/// ### Parameters:
+///
+/// * `map`: [`AvlTreeMap`] - The map to retrieve entries from.
+///
+/// * `start`: [`Option<K>`] - The key to start from, usually the `next_start` of the previous page.
+///   Starts from the first entry if [`None`].
+///
+/// * `limit`: [`usize`] - The maximum number of entries to retrieve.
+///
+/// ### Returns:
+///
+/// The retrieved entries, and the key to start the next page from. [`Page`]
+pub fn paginate<K: ReadWriteState + Ord, V: ReadWriteState>(
+    map: &AvlTreeMap<K, V>,
+    start: Option<&K>,
+    limit: usize,
+) -> Page<K, V> {
+    // Seeks directly to `start`, so the cost of a page does not grow with its position.
+    let mut entries: Vec<(K, V)> = map
+        .iter_range(start, None)
+        .take(limit.saturating_add(1))
+        .collect();
+
+    let next_start = if entries.len() > limit {
+        entries.pop().map(|(key, _)| key)
+    } else {
+        None
+    };
+    Page {
+        entries,
+        next_start,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn populated_map(size: u32) -> AvlTreeMap<u32, u64> {
+        let mut map = AvlTreeMap::new();
+        for key in 0..size {
+            map.insert(key * 10, u64::from(key));
+        }
+        map
+    }
+
+    #[test]
+    pub fn paginate_in_fixed_size_chunks() {
+        let map = populated_map(10);
+
+        let mut start = None;
+        let mut pages = vec![];
+        loop {
+            let page = paginate(&map, start.as_ref(), 3);
+            start = page.next_start;
+            pages.push(page.entries);
+            if start.is_none() {
+                break;
+            }
+        }
+
+        assert_eq!(
+            pages.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![3, 3, 3, 1]
+        );
+        let keys: Vec<u32> = pages.concat().into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, (0..10).map(|key| key * 10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    pub fn paginate_exact_page() {
+        let map = populated_map(3);
+        let page = paginate(&map, None, 3);
+        assert_eq!(page.entries.len(), 3);
+        assert_eq!(page.next_start, None);
+
+        let page = paginate(&map, None, 2);
+        assert_eq!(page.entries, vec![(0, 0), (10, 1)]);
+        assert_eq!(page.next_start, Some(20));
+    }
+
+    #[test]
+    pub fn paginate_from_missing_key() {
+        let map = populated_map(5);
+        let page = paginate(&map, Some(&15), 2);
+        assert_eq!(page.entries, vec![(20, 2), (30, 3)]);
+        assert_eq!(page.next_start, Some(40));
+
+        let page = paginate(&map, Some(&100), 2);
+        assert!(page.entries.is_empty());
+        assert_eq!(page.next_start, None);
+
+        let page = paginate(&map, None, 0);
+        assert!(page.entries.is_empty());
+        assert_eq!(page.next_start, Some(0));
+    }
+
+    #[test]
+    pub fn paginate_unbounded_limit() {
+        let map = populated_map(5);
+        let page = paginate(&map, Some(&20), usize::MAX);
+        assert_eq!(page.entries, vec![(20, 2), (30, 3), (40, 4)]);
+        assert_eq!(page.next_start, None);
+    }
+}
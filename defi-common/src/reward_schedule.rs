@@ -0,0 +1,200 @@
+//! Small utility library for accruing rewards under a time-based multiplier schedule.
+//!
+//! A schedule consists of tiers, each boosting the reward rate by a multiplier from its start
+//! time, until the start of the next tier. Allows e.g. a staking contract to pay 2x rewards for
+//! the first month. Times are given in UTC millis.
+
+use create_type_spec_derive::CreateTypeSpec;
+use read_write_rpc_derive::ReadWriteRPC;
+use read_write_state_derive::ReadWriteState;
+
+use crate::token_balances::TokenAmount;
+
+/// The multiplier in per mille applied before the first tier of a schedule, i.e. no boost.
+pub const NO_BOOST_PER_MILLE: u32 = 1000;
+
+/// A tier of a reward multiplier schedule.
+#[derive(ReadWriteRPC, ReadWriteState, CreateTypeSpec, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MultiplierTier {
+    /// The time in UTC millis from which the tier is active.
+    pub start_millis: i64,
+    /// The reward multiplier in per mille, e.g. `2000` for 2x rewards.
+    pub multiplier_per_mille: u32,
+}
+
+/// Determines the multiplier active at `time`.
+///
+/// ### Parameters:
+///
+/// * `schedule`: [`&[MultiplierTier]`] - The tiers, ordered by strictly increasing start time.
+///
+/// * `time`: [`i64`] - The time in UTC millis.
+///
+/// # Returns
+/// The multiplier in per mille of the latest tier started at or before `time`, or
+/// [`NO_BOOST_PER_MILLE`] if no tier has started. [`u32`]
+pub fn active_multiplier_per_mille(schedule: &[MultiplierTier], time: i64) -> u32 {
+    schedule
+        .iter()
+        .take_while(|tier| tier.start_millis <= time)
+        .last()
+        .map_or(NO_BOOST_PER_MILLE, |tier| tier.multiplier_per_mille)
+}
+
+/// Calculates the rewards accrued from `from_millis` to `to_millis`, at a base rate of
+/// `reward_per_milli`, boosted by the multipliers of `schedule`. <br>
+/// The period is split into segments at the start of each tier, such that every segment accrues
+/// at the multiplier active during it. Rounding happens only once, over the entire period.
+///
+/// ### Parameters:
+///
+/// * `schedule`: [`&[MultiplierTier]`] - The tiers, ordered by strictly increasing start time.
+///
+/// * `reward_per_milli`: [`TokenAmount`] - The unboosted reward per millisecond.
+///
+/// * `from_millis`: [`i64`] - The start of the accrual period in UTC millis.
+///
+/// * `to_millis`: [`i64`] - The end of the accrual period in UTC millis. Must not be before `from_millis`.
+///
+/// # Returns
+/// The accrued rewards, rounded down. Returns `0` if the period is empty. Panics if the period
+/// ends before it starts, or if the rewards overflow. [`TokenAmount`]
+pub fn accrue_rewards(
+    schedule: &[MultiplierTier],
+    reward_per_milli: TokenAmount,
+    from_millis: i64,
+    to_millis: i64,
+) -> TokenAmount {
+    assert!(
+        from_millis <= to_millis,
+        "Accrual period must not end before it starts, but was [{}, {}]",
+        from_millis,
+        to_millis
+    );
+    // Segments never end before they start, so the exact distance is their duration.
+    let segment_rewards_per_mille = |start: i64, end: i64, multiplier_per_mille: u32| {
+        TokenAmount::from(end.abs_diff(start))
+            .checked_mul(reward_per_milli)
+            .and_then(|rewards| rewards.checked_mul(multiplier_per_mille as TokenAmount))
+            .expect("Accrued rewards overflow")
+    };
+
+    let mut rewards_per_mille: TokenAmount = 0;
+    let mut segment_start = from_millis;
+    let mut multiplier_per_mille = active_multiplier_per_mille(schedule, from_millis);
+    for tier in schedule
+        .iter()
+        .filter(|tier| from_millis < tier.start_millis && tier.start_millis < to_millis)
+    {
+        rewards_per_mille = rewards_per_mille
+            .checked_add(segment_rewards_per_mille(
+                segment_start,
+                tier.start_millis,
+                multiplier_per_mille,
+            ))
+            .expect("Accrued rewards overflow");
+        segment_start = tier.start_millis;
+        multiplier_per_mille = tier.multiplier_per_mille;
+    }
+    rewards_per_mille = rewards_per_mille
+        .checked_add(segment_rewards_per_mille(
+            segment_start,
+            to_millis,
+            multiplier_per_mille,
+        ))
+        .expect("Accrued rewards overflow");
+
+    rewards_per_mille / 1000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Doubles rewards during the first 1000 millis, and boosts by half until 2000 millis.
+    const SCHEDULE: [MultiplierTier; 3] = [
+        MultiplierTier {
+            start_millis: 0,
+            multiplier_per_mille: 2000,
+        },
+        MultiplierTier {
+            start_millis: 1000,
+            multiplier_per_mille: 1500,
+        },
+        MultiplierTier {
+            start_millis: 2000,
+            multiplier_per_mille: 1000,
+        },
+    ];
+
+    #[test]
+    pub fn active_multiplier_follows_tiers() {
+        assert_eq!(
+            active_multiplier_per_mille(&SCHEDULE, -1),
+            NO_BOOST_PER_MILLE
+        );
+        assert_eq!(active_multiplier_per_mille(&SCHEDULE, 0), 2000);
+        assert_eq!(active_multiplier_per_mille(&SCHEDULE, 999), 2000);
+        assert_eq!(active_multiplier_per_mille(&SCHEDULE, 1000), 1500);
+        assert_eq!(active_multiplier_per_mille(&SCHEDULE, 5000), 1000);
+        assert_eq!(active_multiplier_per_mille(&[], 0), NO_BOOST_PER_MILLE);
+    }
+
+    #[test]
+    pub fn accrue_within_single_tier() {
+        assert_eq!(accrue_rewards(&SCHEDULE, 3, 100, 600), 3000);
+        assert_eq!(accrue_rewards(&SCHEDULE, 3, 2000, 2500), 1500);
+        assert_eq!(accrue_rewards(&[], 3, 100, 600), 1500);
+    }
+
+    #[test]
+    pub fn accrue_across_multiplier_change() {
+        // 500 millis at 2x, then 500 millis at 1.5x.
+        assert_eq!(accrue_rewards(&SCHEDULE, 4, 500, 1500), 4000 + 3000);
+        // Spanning every tier, starting before the schedule.
+        assert_eq!(
+            accrue_rewards(&SCHEDULE, 4, -500, 2500),
+            2000 + 8000 + 6000 + 2000
+        );
+    }
+
+    #[test]
+    pub fn accrual_splits_without_loss() {
+        let whole = accrue_rewards(&SCHEDULE, 7, 300, 2300);
+        let split =
+            accrue_rewards(&SCHEDULE, 7, 300, 1000) + accrue_rewards(&SCHEDULE, 7, 1000, 2300);
+        assert_eq!(whole, split);
+    }
+
+    #[test]
+    pub fn accrue_empty_period() {
+        assert_eq!(accrue_rewards(&SCHEDULE, 3, 500, 500), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Accrual period must not end before it starts, but was [600, 500]")]
+    pub fn accrue_reversed_period() {
+        accrue_rewards(&SCHEDULE, 3, 600, 500);
+    }
+
+    #[test]
+    pub fn accrue_entire_time_range() {
+        assert_eq!(
+            accrue_rewards(&[], 1, i64::MIN, i64::MAX),
+            u64::MAX as TokenAmount
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Accrued rewards overflow")]
+    pub fn accrue_overflow_across_segments() {
+        // Each segment fits, but their sum does not.
+        accrue_rewards(&SCHEDULE, TokenAmount::MAX / 3_000_000, 0, 2000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Accrued rewards overflow")]
+    pub fn accrue_overflow() {
+        accrue_rewards(&SCHEDULE, TokenAmount::MAX, 0, 10);
+    }
+}
@@ -0,0 +1,74 @@
+//! Small utility library to guard invocations by the time of the block producing them.
+//!
+//! Times are given in UTC millis (milliseconds after 1970-01-01 00:00:00 UTC), and compared
+//! against [`ContractContext::block_production_time`]. A time equal to the deadline counts as
+//! after the deadline, such that exactly one of [`assert_before`] and [`assert_after`] holds.
+
+use pbc_contract_common::context::ContractContext;
+
+/// Determines whether `time` is strictly before `deadline`.
+///
+/// ## Parameters
+///
+/// - `time`: The time to check, in UTC millis.
+/// - `deadline`: The deadline, in UTC millis.
+///
+/// ## Return
+///
+/// True if `time < deadline`, false otherwise.
+pub fn is_before(time: i64, deadline: i64) -> bool {
+    time < deadline
+}
+
+/// Determines whether `time` is at or after `deadline`.
+///
+/// ## Parameters
+///
+/// - `time`: The time to check, in UTC millis.
+/// - `deadline`: The deadline, in UTC millis.
+///
+/// ## Return
+///
+/// True if `time >= deadline`, false otherwise.
+pub fn is_after(time: i64, deadline: i64) -> bool {
+    !is_before(time, deadline)
+}
+
+/// Asserts that the block producing the invocation was produced strictly before `deadline`.
+///
+/// Panics with `msg` when:
+///
+/// - The block production time is at or after `deadline`.
+pub fn assert_before(ctx: &ContractContext, deadline: i64, msg: &str) {
+    assert!(is_before(ctx.block_production_time, deadline), "{}", msg);
+}
+
+/// Asserts that the block producing the invocation was produced at or after `deadline`.
+///
+/// Panics with `msg` when:
+///
+/// - The block production time is strictly before `deadline`.
+pub fn assert_after(ctx: &ContractContext, deadline: i64, msg: &str) {
+    assert!(is_after(ctx.block_production_time, deadline), "{}", msg);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn deadline_is_after() {
+        assert!(!is_before(1000, 1000));
+        assert!(is_after(1000, 1000));
+    }
+
+    #[test]
+    pub fn around_deadline() {
+        assert!(is_before(999, 1000));
+        assert!(!is_after(999, 1000));
+        assert!(!is_before(1001, 1000));
+        assert!(is_after(1001, 1000));
+        assert!(is_before(i64::MIN, i64::MAX));
+        assert!(is_after(i64::MAX, i64::MIN));
+    }
+}
@@ -9,6 +9,8 @@ use pbc_traits::ReadWriteState;
 use read_write_rpc_derive::ReadWriteRPC;
 use read_write_state_derive::ReadWriteState;
 
+use crate::permission::Permission;
+
 /// Type used to represent token amounts.
 pub type TokenAmount = u128;
 
@@ -122,6 +124,9 @@ pub struct TokenBalances {
     /// The map containing all token balances of all users and the contract itself. <br>
     /// The contract should always have a balance equal to the sum of all token balances.
     balances: Map<Address, TokenBalance>,
+    /// The sum of all token balances in `balances`, kept up to date as tokens are added and
+    /// deducted, such that it can be read without iterating every balance.
+    total_held: TokenBalance,
 }
 
 impl TokenBalances {
@@ -147,9 +152,29 @@ impl TokenBalances {
             token_a_address,
             token_b_address,
             balances: Map::new(),
+            total_held: EMPTY_BALANCE,
         })
     }
 
+    /// Creates new token balances structure like [`TokenBalances::new`], additionally requiring
+    /// that both token addresses are allowed by `allowed_tokens`.
+    ///
+    /// Checks whether the state is valid, if not it will return an error reason.
+    pub fn new_with_allowed_tokens(
+        token_lp_address: Address,
+        token_a_address: Address,
+        token_b_address: Address,
+        allowed_tokens: &Permission,
+    ) -> Result<Self, &'static str> {
+        if !allowed_tokens.does_address_have_permission(&token_a_address) {
+            return Result::Err("Token address A is not an allowed token");
+        }
+        if !allowed_tokens.does_address_have_permission(&token_b_address) {
+            return Result::Err("Token address B is not an allowed token");
+        }
+        Self::new(token_lp_address, token_a_address, token_b_address)
+    }
+
     /// Adds tokens to the `balances` map of the contract. <br>
     /// If the user isn't already present, creates an entry with an empty TokenBalance.
     ///
@@ -164,6 +189,7 @@ impl TokenBalances {
         let mut token_balance = self.get_balance_for(&user);
         *token_balance.get_mut_amount_of(token) += amount;
         self.balances.insert(user, token_balance);
+        *self.total_held.get_mut_amount_of(token) += amount;
     }
 
     /// Deducts tokens from the `balances` map of the contract. <br>
@@ -194,6 +220,8 @@ impl TokenBalances {
         } else {
             self.balances.insert(user, user_balances);
         }
+        // The deducted amount is part of the total, so this cannot underflow.
+        *self.total_held.get_mut_amount_of(token) -= amount;
     }
 
     /// Moves internal tokens from the `from`-address to the `to`-address.
@@ -218,6 +246,45 @@ impl TokenBalances {
         self.add_to_token_balance(to, moved_token, amount);
     }
 
+    /// Removes all liquidity tokens from every balance, including the contract's own. <br>
+    /// Users left without any tokens are removed from the `balances` map.
+    pub fn clear_liquidity_tokens(&mut self) {
+        let holders: Vec<(Address, TokenBalance)> = self
+            .balances
+            .iter()
+            .filter(|(_, balance)| balance.liquidity_tokens != 0)
+            .collect();
+
+        for (user, mut user_balances) in holders {
+            user_balances.liquidity_tokens = 0;
+            if user_balances.user_has_no_tokens() {
+                self.balances.remove(&user);
+            } else {
+                self.balances.insert(user, user_balances);
+            }
+        }
+        self.total_held.liquidity_tokens = 0;
+    }
+
+    /// The total amount of `token` held by every user, including the contract itself. <br>
+    /// Kept as a running total, so the cost does not grow with the number of users.
+    ///
+    /// ### Parameters:
+    ///
+    /// * `token`: [`Token`] - The token to sum.
+    ///
+    /// # Returns
+    /// The total amount of `token` held. [`TokenAmount`]
+    pub fn total_held(&self, token: Token) -> TokenAmount {
+        self.total_held.get_amount_of(token)
+    }
+
+    /// The number of users holding any tokens, including the contract itself. <br>
+    /// Useful for estimating the gas cost of operations iterating the balances.
+    pub fn balances_len(&self) -> usize {
+        self.balances.len()
+    }
+
     /// Retrieves a copy of the token balance that matches `user`.
     ///
     /// ### Parameters:
@@ -263,7 +330,7 @@ impl TokenBalances {
 
 /// Tracks the from-to pairs for transfers, etc.
 #[non_exhaustive]
-#[derive(ReadWriteState, CreateTypeSpec, Debug)]
+#[derive(ReadWriteState, CreateTypeSpec, Debug, Clone, Copy)]
 pub struct TokensInOut {
     /// The input token.
     pub token_in: Token,
@@ -284,3 +351,103 @@ impl TokensInOut {
         token_out: Token::A,
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contract(id: u8) -> Address {
+        Address {
+            address_type: AddressType::PublicContract,
+            identifier: [id; 20],
+        }
+    }
+
+    #[test]
+    pub fn allowed_tokens() {
+        let allowed_tokens = Permission::Specific {
+            addresses: vec![contract(2), contract(3)],
+        };
+        let token_balances = TokenBalances::new_with_allowed_tokens(
+            contract(1),
+            contract(2),
+            contract(3),
+            &allowed_tokens,
+        )
+        .unwrap();
+        assert_eq!(token_balances.token_a_address, contract(2));
+        assert_eq!(token_balances.token_b_address, contract(3));
+
+        assert!(TokenBalances::new_with_allowed_tokens(
+            contract(1),
+            contract(4),
+            contract(5),
+            &Permission::Anybody {}
+        )
+        .is_ok());
+    }
+
+    #[test]
+    pub fn balances_len_tracks_holders() {
+        let mut token_balances = TokenBalances::new(contract(1), contract(2), contract(3)).unwrap();
+        assert_eq!(token_balances.balances_len(), 0);
+
+        token_balances.add_to_token_balance(contract(4), Token::A, 10);
+        token_balances.add_to_token_balance(contract(4), Token::B, 10);
+        token_balances.add_to_token_balance(contract(5), Token::A, 10);
+        assert_eq!(token_balances.balances_len(), 2);
+
+        token_balances.move_tokens(contract(5), contract(6), Token::A, 10);
+        assert_eq!(token_balances.balances_len(), 2);
+
+        token_balances.deduct_from_token_balance(contract(4), Token::A, 10);
+        assert_eq!(token_balances.balances_len(), 2);
+        token_balances.deduct_from_token_balance(contract(4), Token::B, 10);
+        assert_eq!(token_balances.balances_len(), 1);
+    }
+
+    #[test]
+    pub fn total_held_tracks_every_balance() {
+        let mut token_balances = TokenBalances::new(contract(1), contract(2), contract(3)).unwrap();
+        token_balances.add_to_token_balance(contract(1), Token::A, 100);
+        token_balances.add_to_token_balance(contract(4), Token::A, 30);
+        token_balances.add_to_token_balance(contract(4), Token::LIQUIDITY, 70);
+        token_balances.move_tokens(contract(4), contract(5), Token::A, 10);
+        token_balances.deduct_from_token_balance(contract(1), Token::A, 40);
+
+        assert_eq!(token_balances.total_held(Token::A), 90);
+        assert_eq!(token_balances.total_held(Token::B), 0);
+        assert_eq!(token_balances.total_held(Token::LIQUIDITY), 70);
+
+        token_balances.clear_liquidity_tokens();
+        assert_eq!(token_balances.total_held(Token::LIQUIDITY), 0);
+        assert_eq!(token_balances.total_held(Token::A), 90);
+    }
+
+    #[test]
+    pub fn disallowed_tokens() {
+        let allowed_tokens = Permission::Specific {
+            addresses: vec![contract(2), contract(3)],
+        };
+        assert_eq!(
+            TokenBalances::new_with_allowed_tokens(
+                contract(1),
+                contract(4),
+                contract(3),
+                &allowed_tokens
+            )
+            .unwrap_err(),
+            "Token address A is not an allowed token"
+        );
+        assert_eq!(
+            TokenBalances::new_with_allowed_tokens(
+                contract(1),
+                contract(2),
+                contract(4),
+                &allowed_tokens
+            )
+            .unwrap_err(),
+            "Token address B is not an allowed token"
+        );
+    }
+}
@@ -3,42 +3,151 @@
 #[cfg(test)]
 mod tests;
 
-use pbc_contract_codegen::*;
+use std::collections::BTreeMap;
 
-use std::ops::RangeInclusive;
+use pbc_contract_codegen::*;
 
 use create_type_spec_derive::CreateTypeSpec;
-pub use defi_common::token_balances::Token;
 use defi_common::{
     interact_mpc20,
-    liquidity_util::{calculate_swap_to_amount, AcquiredLiquidityLockInformation, LiquidityLockId},
-    math::u128_sqrt,
+    interact_swap::SwapContract,
+    liquidity_util::{
+        calculate_swap_to_amount, over_deposit_for, required_token_out_for, swap_fee_amount,
+        validate_fee_per_mille, AcquiredLiquidityLockInformation, LiquidityLockId,
+    },
+    math::{u128_mul_div, u128_sqrt, u128_widening_mul, U256},
+    pagination::paginate,
     permission::Permission,
-    token_balances::{TokenAmount, TokenBalance, TokenBalances, TokensInOut},
+    time_guard::{is_after, is_before},
+    token_balances::{TokenAmount, TokenBalance, TokenBalances, TokensInOut, EMPTY_BALANCE},
 };
+pub use defi_common::{liquidity_util::ALLOWED_FEE_PER_MILLE, token_balances::Token};
 use pbc_contract_common::{
-    address::Address,
+    address::{Address, Shortname},
     avl_tree_map::AvlTreeMap,
     context::{CallbackContext, ContractContext},
-    events::EventGroup,
+    events::{EventGroup, GasCost},
 };
+use read_write_rpc_derive::ReadWriteRPC;
 use read_write_state_derive::ReadWriteState;
 
-/// The range of allowed [`LiquiditySwapContractState::swap_fee_per_mille`].
-pub const ALLOWED_FEE_PER_MILLE: RangeInclusive<u16> = 0..=1000;
+/// The default [`LiquiditySwapContractState::minimum_initial_liquidity`].
+pub const DEFAULT_MINIMUM_INITIAL_LIQUIDITY: TokenAmount = 1000;
+
+/// The default [`LiquiditySwapContractState::max_total_locks`].
+pub const DEFAULT_MAX_TOTAL_LOCKS: u32 = 10_000;
+
+/// The maximum number of locks retrieved by a single [`VirtualState::list_locks`].
+pub const MAX_LOCKS_PER_PAGE: u32 = 100;
+
+/// Gas amount sufficient for an [`instant_swap`] invocation, when no locks are held.
+pub const GAS_COST_SWAP_BASE: GasCost = 2500;
+
+/// Additional gas amount for an [`instant_swap`] invocation, per level of the tree holding the locks.
+pub const GAS_COST_SWAP_PER_LOCK_LEVEL: GasCost = 100;
+
+/// Estimates the gas sufficient for an [`instant_swap`] invocation, given the locks currently held. <br>
+/// An instant swap adds and removes a lock, each traversing the tree holding the locks, so the
+/// cost grows with the height of the tree, i.e. logarithmically in [`VirtualState::locks_len`].
+///
+/// ### Parameters:
+///
+///  * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
+///
+/// ### Returns:
+/// The estimated gas cost, of type [`GasCost`].
+pub fn estimate_swap_gas(state: &LiquiditySwapContractState) -> GasCost {
+    let locks_len = state.virtual_state.locks_len() as u64;
+    // The height of a balanced tree holding the locks, and the lock added by the swap.
+    let lock_levels = (locks_len + 1).ilog2() as GasCost + 1;
+    GAS_COST_SWAP_BASE + 2 * GAS_COST_SWAP_PER_LOCK_LEVEL * lock_levels
+}
 
 /// Stores data about a lock, which is later used when the lock is executed or cancelled.
-#[derive(ReadWriteState, CreateTypeSpec, Debug)]
+#[derive(ReadWriteState, CreateTypeSpec, Debug, Clone)]
 pub struct LiquidityLock {
     amount_in: TokenAmount,
     amount_out: TokenAmount,
     tokens_in_out: TokensInOut,
     owner: Address,
+    /// The swap fee in per mille charged to `owner`, as determined by [`LiquiditySwapContractState::swap_fee_for`].
+    swap_fee_per_mille: u16,
+    /// If present, the time in UTC millis from which the lock is automatically executed.
+    scheduled_execution_utc_millis: Option<i64>,
+    /// If present, the time in UTC millis from which the lock can be swept by [`sweep_expired_locks`].
+    expires_utc_millis: Option<i64>,
+    /// If true, an execution that `owner` cannot pay for cancels the lock instead of failing,
+    /// see [`acquire_swap_lock_auto_cancel`].
+    cancel_on_failed_execution: bool,
+}
+
+impl LiquidityLock {
+    /// True if the lock has an expiry, which is at or before `now`, otherwise false.
+    fn is_expired(&self, now: i64) -> bool {
+        self.expires_utc_millis
+            .is_some_and(|expires| is_after(now, expires))
+    }
+}
+
+/// A lock together with its id.
+#[derive(ReadWriteState, CreateTypeSpec, Debug)]
+pub struct IdentifiedLock {
+    /// Id of the lock.
+    pub lock_id: LiquidityLockId,
+    /// The lock associated with `lock_id`.
+    pub lock: LiquidityLock,
+}
+
+/// Everything the contract knows about a single user.
+#[derive(ReadWriteState, CreateTypeSpec, Debug)]
+pub struct UserPosition {
+    /// The tokens deposited by the user, including liquidity tokens.
+    pub balance: TokenBalance,
+    /// The user's share of the total liquidity in per mille, rounded down.
+    pub lp_share_per_mille: u16,
+    /// The locks currently owned by the user.
+    pub locks: Vec<IdentifiedLock>,
+}
+
+/// Outcome of an attempt to execute a scheduled lock.
+#[derive(Debug, PartialEq, Eq)]
+enum ScheduledExecution {
+    /// The lock was executed, yielding the given amount of output tokens.
+    Executed(TokenAmount),
+    /// The scheduled execution time has not been reached yet.
+    Pending,
+    /// The lock no longer exists, as it has been cancelled or executed manually.
+    Gone,
 }
 
 /// Type representing difference in [`TokenAmount`]
 type TokenDelta = i128;
 
+/// Converts a lock `amount` to a [`TokenDelta`] of the lock liquidity.
+///
+/// Fails if `amount` exceeds [`TokenDelta::MAX`].
+fn lock_amount_delta(amount: TokenAmount) -> TokenDelta {
+    TokenDelta::try_from(amount).expect("Lock amount exceeds the range of the lock liquidity")
+}
+
+/// Applies `delta` to `base`, e.g. to compute a virtual reserve from an actual reserve.
+///
+/// ### Parameters:
+///
+///  * `base`: [`TokenAmount`] - The amount to apply `delta` to.
+///
+///  * `delta`: [`TokenDelta`] - The signed difference to apply.
+///
+/// ### Returns:
+/// The resulting amount, or an error if it would be negative or exceed [`TokenAmount::MAX`]. [`Result<TokenAmount, &'static str>`]
+pub fn delta_to_amount(base: TokenAmount, delta: TokenDelta) -> Result<TokenAmount, &'static str> {
+    base.checked_add_signed(delta).ok_or(if delta < 0 {
+        "Virtual reserve underflow"
+    } else {
+        "Virtual reserve overflow"
+    })
+}
+
 /// Keeps track of the 'virtual' liquidity that is held in locks.
 #[derive(CreateTypeSpec, ReadWriteState)]
 struct LockLiquidity {
@@ -49,6 +158,15 @@ struct LockLiquidity {
 }
 
 impl LockLiquidity {
+    /// Retrieves the amount of `token` held in locks.
+    pub fn get_amount_of(&self, token: Token) -> TokenDelta {
+        if token == Token::A {
+            self.a_tokens
+        } else {
+            self.b_tokens
+        }
+    }
+
     /// Retrieves a mutable reference to the amount of `token` held in locks.
     pub fn get_mut_amount_of(&mut self, token: Token) -> &mut TokenDelta {
         if token == Token::A {
@@ -80,35 +198,57 @@ impl Default for VirtualState {
 impl VirtualState {
     /// A new virtual state contains no locks and starts `lock_id` at the initial id.
     pub fn new() -> Self {
+        Self::with_initial_lock_id(LiquidityLockId::initial_id())
+    }
+
+    /// A new virtual state contains no locks and starts `lock_id` at `initial_lock_id`. <br>
+    /// Allows a migrated contract to continue the lock id sequence of a previous contract.
+    pub fn with_initial_lock_id(initial_lock_id: LiquidityLockId) -> Self {
         let lock_liquidity = LockLiquidity {
             a_tokens: 0,
             b_tokens: 0,
         };
         Self {
-            next_lock_id: LiquidityLockId::initial_id(),
+            next_lock_id: initial_lock_id,
             locks: AvlTreeMap::new(),
             lock_liquidity,
         }
     }
 
+    /// The id of the next acquired lock. Does not advance the lock id sequence.
+    pub fn next_lock_id(&self) -> LiquidityLockId {
+        self.next_lock_id
+    }
+
     /// Adds `lock` to the virtual state, updating the virtual state as if the swap has happened.
     ///
     /// The next unique lock id will be associated with `lock` and returned.
     /// Updates the lock liquidity based on `lock` input and output amounts,
     /// to maintain the invariant: virtual_liquidity = actual_liquidity + `lock_liquidity`.
-    fn add_lock(&mut self, lock: LiquidityLock) -> LiquidityLockId {
-        let lock_id = self.next_lock_id();
+    ///
+    /// Fails if `max_total_locks` locks are already held.
+    fn add_lock(&mut self, lock: LiquidityLock, max_total_locks: u32) -> LiquidityLockId {
+        assert!(
+            self.locks_len() < max_total_locks as usize,
+            "Cannot hold more than {} locks in total",
+            max_total_locks
+        );
+        let lock_id = self.take_next_lock_id();
+        self.insert_lock(lock_id, lock);
+        lock_id
+    }
 
+    /// Associates `lock` with `lock_id` in the virtual state, updating the lock liquidity like
+    /// [`VirtualState::add_lock`].
+    fn insert_lock(&mut self, lock_id: LiquidityLockId, lock: LiquidityLock) {
         *self
             .lock_liquidity
-            .get_mut_amount_of(lock.tokens_in_out.token_in) += lock.amount_in as TokenDelta;
+            .get_mut_amount_of(lock.tokens_in_out.token_in) += lock_amount_delta(lock.amount_in);
         *self
             .lock_liquidity
-            .get_mut_amount_of(lock.tokens_in_out.token_out) -= lock.amount_out as TokenDelta;
+            .get_mut_amount_of(lock.tokens_in_out.token_out) -= lock_amount_delta(lock.amount_out);
 
         self.locks.insert(lock_id, lock);
-
-        lock_id
     }
 
     /// Removes a lock from the virtual state, if `lock_id` is a valid id, and associated with `sender`.
@@ -126,48 +266,226 @@ impl VirtualState {
             lock_id
         );
 
+        self.take_lock(lock_id, lock)
+    }
+
+    /// Marks the lock associated with `lock_id` to be cancelled on a failed execution.
+    /// Does not change the lock liquidity.
+    fn mark_cancel_on_failed_execution(&mut self, lock_id: LiquidityLockId) {
+        let mut lock = self.get_lock(&lock_id).unwrap();
+        lock.cancel_on_failed_execution = true;
+        self.locks.insert(lock_id, lock);
+    }
+
+    /// Removes `lock` associated with `lock_id` from the virtual state, regardless of its owner.
+    ///
+    /// Updates the virtual liquidity state like [`VirtualState::remove_lock`].
+    fn take_lock(&mut self, lock_id: LiquidityLockId, lock: LiquidityLock) -> LiquidityLock {
         self.locks.remove(&lock_id);
 
         *self
             .lock_liquidity
-            .get_mut_amount_of(lock.tokens_in_out.token_in) -= lock.amount_in as TokenDelta;
+            .get_mut_amount_of(lock.tokens_in_out.token_in) -= lock_amount_delta(lock.amount_in);
         *self
             .lock_liquidity
-            .get_mut_amount_of(lock.tokens_in_out.token_out) += lock.amount_out as TokenDelta;
+            .get_mut_amount_of(lock.tokens_in_out.token_out) += lock_amount_delta(lock.amount_out);
 
         lock
     }
 
+    /// Splits a portion of `amount_in_portion` input tokens off a lock, if `lock_id` is a valid id,
+    /// and associated with `sender`. <br>
+    /// The output of the portion is computed at the rate of the lock, rounded down. The remainder
+    /// stays associated with `lock_id`, unless nothing remains.
+    ///
+    /// Returns the portion, which has been removed from the virtual state like [`VirtualState::remove_lock`].
+    fn split_lock(
+        &mut self,
+        lock_id: LiquidityLockId,
+        sender: Address,
+        amount_in_portion: TokenAmount,
+    ) -> LiquidityLock {
+        let lock = self.remove_lock(lock_id, sender);
+        assert!(
+            0 < amount_in_portion && amount_in_portion <= lock.amount_in,
+            "Portion must be in range [1,{}], but was {}",
+            lock.amount_in,
+            amount_in_portion
+        );
+
+        // Cannot fail, as the portion of the output is at most the output of the lock.
+        let amount_out_portion =
+            u128_mul_div(lock.amount_out, amount_in_portion, lock.amount_in).unwrap();
+        if amount_in_portion < lock.amount_in {
+            let remainder = LiquidityLock {
+                amount_in: lock.amount_in - amount_in_portion,
+                amount_out: lock.amount_out - amount_out_portion,
+                ..lock
+            };
+            self.insert_lock(lock_id, remainder);
+        }
+
+        LiquidityLock {
+            amount_in: amount_in_portion,
+            amount_out: amount_out_portion,
+            ..lock
+        }
+    }
+
+    /// Retrieves a copy of the lock associated with `lock_id`, if any.
+    fn get_lock(&self, lock_id: &LiquidityLockId) -> Option<LiquidityLock> {
+        self.locks.get(lock_id)
+    }
+
+    /// Retrieves at most `max` locks, ordered by id, starting from the first lock at or after
+    /// `start`, or from the first lock if [`None`]. <br>
+    /// Allows listing every outstanding lock over several invocations, without running out of gas.
+    ///
+    /// ### Parameters:
+    ///
+    ///  * `start`: [`Option<LiquidityLockId>`] - The id to start from, usually the cursor of the previous page.
+    ///
+    ///  * `max`: [`u32`] - The maximum number of locks to retrieve. Clamped to [`MAX_LOCKS_PER_PAGE`].
+    ///
+    /// ### Returns:
+    /// The locks with their ids, and the id to start the next page from, or [`None`] if there are
+    /// no more locks.
+    pub fn list_locks(
+        &self,
+        start: Option<LiquidityLockId>,
+        max: u32,
+    ) -> (
+        Vec<(LiquidityLockId, LiquidityLock)>,
+        Option<LiquidityLockId>,
+    ) {
+        let limit = max.min(MAX_LOCKS_PER_PAGE) as usize;
+        let page = paginate(&self.locks, start.as_ref(), limit);
+        (page.entries, page.next_start)
+    }
+
+    /// Retrieves the ids of the locks that are expired at `now` among at most `max_locks` locks,
+    /// ordered by id, starting from the first lock at or after `start`.
+    ///
+    /// ### Returns:
+    /// The ids of the expired locks, and the id to continue from, or [`None`] if there are no
+    /// more locks.
+    fn expired_lock_ids(
+        &self,
+        now: i64,
+        start: Option<LiquidityLockId>,
+        max_locks: usize,
+    ) -> (Vec<LiquidityLockId>, Option<LiquidityLockId>) {
+        let page = paginate(&self.locks, start.as_ref(), max_locks);
+        let expired_lock_ids = page
+            .entries
+            .into_iter()
+            .filter(|(_, lock)| lock.is_expired(now))
+            .map(|(lock_id, _)| lock_id)
+            .collect();
+        (expired_lock_ids, page.next_start)
+    }
+
+    /// Retrieves all locks owned by `owner`, ordered by id.
+    fn locks_owned_by(&self, owner: &Address) -> Vec<IdentifiedLock> {
+        self.locks
+            .iter()
+            .filter(|(_, lock)| lock.owner == *owner)
+            .map(|(lock_id, lock)| IdentifiedLock { lock_id, lock })
+            .collect()
+    }
+
+    /// Counts the locks held by each owner, ordered by owner address. <br>
+    /// Iterates every lock, so the cost is linear in [`VirtualState::locks_len`]. Intended for
+    /// off-chain risk monitoring rather than use within actions.
+    pub fn lock_owner_summary(&self) -> Vec<(Address, u64)> {
+        let mut lock_counts: BTreeMap<Address, u64> = BTreeMap::new();
+        for (_, lock) in self.locks.iter() {
+            *lock_counts.entry(lock.owner).or_insert(0) += 1;
+        }
+        lock_counts.into_iter().collect()
+    }
+
     /// Returns the virtual pool state, guaranteed to be `actual_a` + sum(lock_a), `actual_b` + sum(lock_b).
     fn virtual_liquidity_pools(
-        &mut self,
+        &self,
         actual_a: TokenAmount,
         actual_b: TokenAmount,
     ) -> TokenBalance {
         TokenBalance {
-            a_tokens: actual_a
-                .checked_add_signed(self.lock_liquidity.a_tokens)
-                .unwrap(),
-            b_tokens: actual_b
-                .checked_add_signed(self.lock_liquidity.b_tokens)
-                .unwrap(),
+            a_tokens: delta_to_amount(actual_a, self.lock_liquidity.a_tokens)
+                .unwrap_or_else(|msg| panic!("{}", msg)),
+            b_tokens: delta_to_amount(actual_b, self.lock_liquidity.b_tokens)
+                .unwrap_or_else(|msg| panic!("{}", msg)),
             liquidity_tokens: 0,
         }
     }
 
     /// Returns an id for a requested lock, and updates state for a future lock id.
-    fn next_lock_id(&mut self) -> LiquidityLockId {
+    fn take_next_lock_id(&mut self) -> LiquidityLockId {
         let res = self.next_lock_id;
         self.next_lock_id = self.next_lock_id.next();
         res
     }
 
+    /// The number of locks currently held.
+    pub fn locks_len(&self) -> usize {
+        self.locks.len()
+    }
+
     /// True if locks currently exists, otherwise false.
     pub fn any_locked_liquidity(&self) -> bool {
         self.locks.is_empty()
     }
 }
 
+/// Current layout version of [`PoolSummary`].
+pub const POOL_SUMMARY_VERSION: u8 = 1;
+
+/// Compact summary of the actual liquidity pools, e.g. for attesting the pool state on another chain.
+///
+/// The field order is part of the serialized layout; any change to the layout must increment
+/// [`POOL_SUMMARY_VERSION`].
+#[derive(ReadWriteState, ReadWriteRPC, CreateTypeSpec, Debug, PartialEq, Eq)]
+pub struct PoolSummary {
+    /// Layout version of the summary. Always [`POOL_SUMMARY_VERSION`].
+    pub version: u8,
+    /// The address of token A.
+    pub token_a: Address,
+    /// The address of token B.
+    pub token_b: Address,
+    /// The amount of token A in the pool.
+    pub reserve_a: TokenAmount,
+    /// The amount of token B in the pool.
+    pub reserve_b: TokenAmount,
+    /// The total amount of minted liquidity tokens.
+    pub total_liquidity: TokenAmount,
+    /// The fee for making swaps per mille.
+    pub fee_per_mille: u16,
+}
+
+/// The permissions of a contract, given at [`initialize`]. See the identically named fields of
+/// [`LiquiditySwapContractState`].
+#[derive(ReadWriteRPC, CreateTypeSpec)]
+pub struct InitialPermissions {
+    /// Determines which callers are allowed to acquired locks.
+    pub permission_lock_swap: Permission,
+    /// Determines which callers are allowed to perform administrative actions.
+    pub permission_admin: Permission,
+    /// Determines which callers are allowed to swap on behalf of other users.
+    pub permission_swap_on_behalf: Permission,
+    /// Determines which users swap without paying the swap fee.
+    pub permission_fee_exempt: Permission,
+}
+
+/// A router authorized by `owner` to swap on their behalf using [`swap_from_allowance`].
+#[derive(ReadWriteState, CreateTypeSpec, Eq, Ord, PartialEq, PartialOrd)]
+pub struct RouterAuthorization {
+    /// The owner of the swapped tokens.
+    pub owner: Address,
+    /// The router allowed to swap them.
+    pub router: Address,
+}
+
 /// This is the state of the contract which is persisted on the chain.
 ///
 /// The #\[state\] macro generates serialization logic for the struct.
@@ -175,6 +493,13 @@ impl VirtualState {
 pub struct LiquiditySwapContractState {
     /// Determines which callers are allowed to acquired locks.
     pub permission_lock_swap: Permission,
+    /// Determines which callers are allowed to perform administrative actions.
+    pub permission_admin: Permission,
+    /// Determines which callers are allowed to swap on behalf of other users, using their allowance.
+    /// Each user must additionally authorize the router, see [`set_router_authorization`].
+    pub permission_swap_on_behalf: Permission,
+    /// Determines which users swap without paying the swap fee.
+    pub permission_fee_exempt: Permission,
     /// The address of this contract
     pub liquidity_pool_address: Address,
     /// The fee for making swaps per mille. Must be in range [`ALLOWED_FEE_PER_MILLE`].
@@ -184,6 +509,74 @@ pub struct LiquiditySwapContractState {
     pub token_balances: TokenBalances,
     /// Contains the virtual liquidity pool state, and its locks.
     pub virtual_state: VirtualState,
+    /// The maximum amount of token A the pool may hold, if any.
+    pub cap_a: Option<TokenAmount>,
+    /// The maximum amount of token B the pool may hold, if any.
+    pub cap_b: Option<TokenAmount>,
+    /// The address allowed to withdraw the accrued protocol fees, if any.
+    pub fee_recipient: Option<Address>,
+    /// The share of each swap fee, in per mille, which is taken out of the pools as protocol fees.
+    pub protocol_fee_share_per_mille: u16,
+    /// The protocol fees accrued since the last withdrawal. Held by the contract, but not part of the pools.
+    pub accrued_protocol_fees: TokenBalance,
+    /// The share of each swap fee, in per mille, which is burned rather than retained by the
+    /// pools. Only taken for burnable tokens.
+    pub fee_burn_share_per_mille: u16,
+    /// Whether token A supports being burned by the contract.
+    pub token_a_burnable: bool,
+    /// Whether token B supports being burned by the contract.
+    pub token_b_burnable: bool,
+    /// The swap fees accrued for burning since the last burn. Held by the contract, but not part of the pools.
+    pub accrued_burn_fees: TokenBalance,
+    /// How long acquired locks last before they can be swept, in milliseconds. Locks never expire if [`None`].
+    pub lock_lifetime_millis: Option<i64>,
+    /// The amount of input tokens of a swept lock, paid from the keeper bounty fund to the sweeper.
+    pub keeper_bounty_per_lock: TokenAmount,
+    /// The tokens funded by [`fund_keeper_bounty`] for paying keeper bounties. Held by the
+    /// contract, but not part of the pools.
+    pub keeper_bounty_fund: TokenBalance,
+    /// Halts swaps on extreme price movements, if present.
+    pub circuit_breaker: Option<CircuitBreaker>,
+    /// Whether initial liquidity has ever been provided. Stays true once set, even if the pools
+    /// are drained later.
+    pub has_been_initialized: bool,
+    /// The most recent samples of the pools, recorded by [`record_price`].
+    pub price_history: PriceHistory,
+    /// The accumulated swap fees of each token retained by the pools, per liquidity token, scaled
+    /// by [`FEE_GROWTH_SCALE`].
+    pub fee_growth_per_liquidity: TokenBalance,
+    /// The snapshot of each liquidity provider, taken when their liquidity tokens last changed.
+    pub fee_snapshots: AvlTreeMap<Address, LiquidityFeeSnapshot>,
+    /// The amount of liquidity tokens permanently locked in the pools by
+    /// [`provide_initial_liquidity`], which must mint more than this. <br>
+    /// Prevents the supply of liquidity tokens from ever dropping to a tiny amount, whose value
+    /// could be inflated such that later liquidity providers mint zero or heavily rounded down shares.
+    pub minimum_initial_liquidity: TokenAmount,
+    /// The amount of liquidity tokens locked when the pools were initialized, i.e. the
+    /// [`LiquiditySwapContractState::minimum_initial_liquidity`] at the time. Counted in the total
+    /// liquidity, but held by no one.
+    pub locked_liquidity: TokenAmount,
+    /// If present, only the given liquidity provider can provide liquidity until the grace period ends.
+    pub exclusive_lp: Option<ExclusiveLiquidityProvider>,
+    /// Whether the contract is in emergency mode, set by [`set_emergency_mode`], allowing
+    /// [`reclaim_liquidity`] while locks are present.
+    pub emergency_mode: bool,
+    /// The minimum time between two [`instant_swap`]s by the same user, in milliseconds. `0`
+    /// disables the limit. Only direct [`instant_swap`]s are limited; swaps by permissioned
+    /// routers ([`swap_from_allowance`]) and the execution of locks ([`execute_lock_swap`],
+    /// [`execute_scheduled_lock`]) are not.
+    pub min_swap_interval_millis: i64,
+    /// The block production time of the latest [`instant_swap`] by each user, recorded while the
+    /// swap interval is limited. Cleared when the limit is disabled.
+    pub last_swap_millis: AvlTreeMap<Address, i64>,
+    /// The maximum number of outstanding locks across all owners. Bounds the state growth, and
+    /// the gas cost of actions iterating every lock.
+    pub max_total_locks: u32,
+    /// The routers each user has authorized to swap their allowance using [`swap_from_allowance`].
+    pub router_authorizations: AvlTreeMap<RouterAuthorization, bool>,
+    /// The contracts users may migrate their balances to using [`migrate_to`], set by
+    /// [`set_migration_target`].
+    pub migration_targets: AvlTreeMap<Address, bool>,
 }
 
 impl LiquiditySwapContractState {
@@ -201,139 +594,534 @@ impl LiquiditySwapContractState {
             .get_balance_for(&self.liquidity_pool_address);
         contract_token_balance.a_tokens != 0 && contract_token_balance.b_tokens != 0
     }
-}
 
-/// Initialize the contract.
-///
-/// # Parameters
-///
-///   * `context`: [`ContractContext`] - The contract context containing sender and chain information.
-///
-///   * `permission_lock_swap`: [`Permission`] - Determines which callers are allowed to acquired locks.
-///
-///   * `token_a_address`: [`Address`] - The address of token A.
-///
-///   * `token_b_address`: [`Address`] - The address of token B.
-///
-///   * `swap_fee_per_mille`: [`TokenAmount`] - The fee for swapping, in per mille, i.e. a fee set to 3 corresponds to a fee of 0.3%.
-///
-/// The new state object of type [`LiquiditySwapContractState`] with all address fields initialized to their final state and remaining fields initialized to a default value.
-#[init]
-pub fn initialize(
-    context: ContractContext,
-    token_a_address: Address,
-    token_b_address: Address,
-    swap_fee_per_mille: u16,
-    permission_lock_swap: Permission,
-) -> (LiquiditySwapContractState, Vec<EventGroup>) {
-    if !ALLOWED_FEE_PER_MILLE.contains(&swap_fee_per_mille) {
-        panic!("Swap fee must be in range [0,1000]");
+    /// Gets the addresses of the two token contracts traded by the pools.
+    ///
+    /// ### Returns:
+    /// The addresses of token A and token B, in that order. [`(Address, Address)`]
+    pub fn token_addresses(&self) -> (Address, Address) {
+        (
+            self.token_balances.token_a_address,
+            self.token_balances.token_b_address,
+        )
     }
 
-    let token_balances =
-        match TokenBalances::new(context.contract_address, token_a_address, token_b_address) {
-            Ok(tb) => tb,
-            Err(msg) => panic!("{}", msg),
-        };
-
-    let new_state = LiquiditySwapContractState {
-        permission_lock_swap,
-        liquidity_pool_address: context.contract_address,
-        swap_fee_per_mille,
-        token_balances,
-        virtual_state: VirtualState::default(),
-    };
+    /// Checks whether exactly one of the pools is empty. <br>
+    /// Such pools cannot be swapped against, unlike pools where both are empty, which can simply
+    /// be initialized again. Allows diagnosing stuck pools.
+    ///
+    /// ### Returns:
+    /// True if exactly one pool is empty, false otherwise [`bool`]
+    pub fn is_one_sided(&self) -> bool {
+        let contract_token_balance = self
+            .token_balances
+            .get_balance_for(&self.liquidity_pool_address);
+        (contract_token_balance.a_tokens == 0) != (contract_token_balance.b_tokens == 0)
+    }
 
-    (new_state, vec![])
-}
+    /// Checks whether the pools have been initialized, but no longer have liquidity. <br>
+    /// Allows distinguishing drained pools from pools that never had liquidity.
+    ///
+    /// ### Returns:
+    /// True if the pools are drained, false otherwise [`bool`]
+    pub fn is_drained(&self) -> bool {
+        self.has_been_initialized && !self.contract_pools_have_liquidity()
+    }
 
-/// Deposit token {A, B} into the calling user's balance on the contract.
-///
-/// Requires that the swap contract has been approved at `token_address`
-/// by the sender. This is checked in a callback, implicitly guaranteeing
-/// that this only returns after the deposit transfer is complete.
-///
-/// ### Parameters:
-///
-///  * `context`: [`ContractContext`] - The contract context containing sender and chain information.
-///
-///  * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
-///
-///  * `token_address`: [`Address`] - The address of the deposited token contract.
-///
-///  * `amount`: [`TokenAmount`] - The amount to deposit.
-///
-/// # Returns
-/// The unchanged state object of type [`LiquiditySwapContractState`].
-#[action(shortname = 0x01)]
-pub fn deposit(
-    context: ContractContext,
-    state: LiquiditySwapContractState,
-    token_address: Address,
-    amount: TokenAmount,
-) -> (LiquiditySwapContractState, Vec<EventGroup>) {
-    let tokens = state.token_balances.deduce_tokens_in_out(token_address);
+    /// Determines how long ago the reference price of the circuit breaker, see
+    /// [`CircuitBreaker::reference_price`], was last updated, by a sample recorded with
+    /// [`record_price`] or by a reset of the breaker. <br>
+    /// Allows consumers of the price to reject stale prices.
+    ///
+    /// ### Parameters:
+    ///
+    ///  * `now`: [`i64`] - The current time in UTC millis.
+    ///
+    /// ### Returns:
+    /// The milliseconds since the last update, or [`i64::MAX`] if no sample has been recorded and
+    /// the breaker is disabled. [`i64`]
+    pub fn twap_staleness_millis(&self, now: i64) -> i64 {
+        let latest_sample_time = self
+            .price_history
+            .latest()
+            .map(|sample| sample.block_production_time);
+        let reset_time = self
+            .circuit_breaker
+            .as_ref()
+            .map(|breaker| breaker.reset_sample.block_production_time);
+        match latest_sample_time.max(reset_time) {
+            Some(last_update) => now.saturating_sub(last_update),
+            None => i64::MAX,
+        }
+    }
 
-    let mut event_group_builder = EventGroup::builder();
-    interact_mpc20::MPC20Contract::at_address(token_address).transfer_from(
-        &mut event_group_builder,
-        &context.sender,
-        &state.liquidity_pool_address,
-        amount,
-    );
+    /// Determines the swap fee charged to `trader`, which is `0` for fee-exempt users.
+    ///
+    /// ### Parameters:
+    ///
+    ///  * `trader`: [`Address`] - The user whose tokens are swapped.
+    ///
+    /// ### Returns:
+    /// The swap fee in per mille [`u16`]
+    pub fn swap_fee_for(&self, trader: &Address) -> u16 {
+        if self
+            .permission_fee_exempt
+            .does_address_have_permission(trader)
+        {
+            0
+        } else {
+            self.swap_fee_per_mille
+        }
+    }
 
-    event_group_builder
-        .with_callback(SHORTNAME_DEPOSIT_CALLBACK)
-        .argument(tokens.token_in)
-        .argument(amount)
-        .done();
+    /// Asserts that `user` may provide liquidity at `now`, i.e. that no grace period of an
+    /// exclusive liquidity provider other than `user` is ongoing.
+    ///
+    /// ### Parameters:
+    ///
+    ///  * `user`: [`Address`] - The user providing liquidity.
+    ///
+    ///  * `now`: [`i64`] - The current time in UTC millis.
+    fn assert_may_provide_liquidity(&self, user: &Address, now: i64) {
+        if let Some(exclusive_lp) = &self.exclusive_lp {
+            assert!(
+                exclusive_lp.provider == *user || is_after(now, exclusive_lp.end_utc_millis),
+                "Only the exclusive liquidity provider can provide liquidity until {}",
+                exclusive_lp.end_utc_millis
+            );
+        }
+    }
 
-    (state, vec![event_group_builder.build()])
-}
+    /// Records a swap by `user` at `now`, asserting that at least
+    /// [`LiquiditySwapContractState::min_swap_interval_millis`] have passed since their previous
+    /// swap. Nothing is recorded while the interval is unlimited.
+    ///
+    /// ### Parameters:
+    ///
+    ///  * `user`: [`Address`] - The user swapping.
+    ///
+    ///  * `now`: [`i64`] - The current block production time.
+    fn record_rate_limited_swap(&mut self, user: Address, now: i64) {
+        if self.min_swap_interval_millis == 0 {
+            return;
+        }
+        if let Some(last_swap_millis) = self.last_swap_millis.get(&user) {
+            assert!(
+                now.saturating_sub(last_swap_millis) >= self.min_swap_interval_millis,
+                "Swaps are limited to one per {} milliseconds",
+                self.min_swap_interval_millis
+            );
+        }
+        self.last_swap_millis.insert(user, now);
+    }
 
-/// Handles callback from [`deposit`]. <br>
-/// If the transfer event is successful,
-/// the caller of [`deposit`] is registered as a user of the contract with (additional) `amount` added to their balance.
-///
-/// ### Parameters:
-///
-/// * `context`: [`ContractContext`] - The contractContext for the callback.
-///
-/// * `callback_context`: [`CallbackContext`] - The callbackContext.
-///
-/// * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
-///
-/// * `token`: [`Token`] - Indicating the token of which to add `amount` to.
-///
-/// * `amount`: [`TokenAmount`] - The desired amount to add to the user's total amount of `token`.
-/// ### Returns
-///
-/// The updated state object of type [`LiquiditySwapContractState`] with an updated entry for the caller of `deposit`.
-#[callback(shortname = 0x10)]
-pub fn deposit_callback(
-    context: ContractContext,
-    callback_context: CallbackContext,
-    mut state: LiquiditySwapContractState,
-    token: Token,
-    amount: TokenAmount,
-) -> (LiquiditySwapContractState, Vec<EventGroup>) {
-    assert!(callback_context.success, "Transfer did not succeed");
+    /// Determines whether `token` supports being burned by the contract.
+    fn is_burnable(&self, token: Token) -> bool {
+        if token == Token::A {
+            self.token_a_burnable
+        } else {
+            self.token_b_burnable
+        }
+    }
 
-    state
-        .token_balances
-        .add_to_token_balance(context.sender, token, amount);
+    /// Asserts that adding `amount` of `token` to the pool keeps it within its cap, if any.
+    ///
+    /// ### Parameters:
+    ///
+    ///  * `token`: [`Token`] - The token being added to the pool.
+    ///
+    ///  * `amount`: [`TokenAmount`] - The amount being added.
+    fn assert_within_cap(&self, token: Token, amount: TokenAmount) {
+        if let Some(cap) = self.cap_for(token) {
+            let reserve = self
+                .token_balances
+                .get_balance_for(&self.liquidity_pool_address)
+                .get_amount_of(token);
+            assert!(
+                reserve.saturating_add(amount) <= cap,
+                "{:?} pool would exceed its cap: {}/{}",
+                token,
+                reserve.saturating_add(amount),
+                cap
+            );
+        }
+    }
 
-    (state, vec![])
-}
+    /// Determines whether crediting a deposit of `amount` of `token` keeps the amount held by the
+    /// contract, in the pool and in the balances of users, within the cap of the pool, if any. <br>
+    /// Deposited tokens can be provided as liquidity at any time, so every credited deposit
+    /// counts towards the cap.
+    fn deposit_within_cap(&self, token: Token, amount: TokenAmount) -> bool {
+        self.cap_for(token).map_or(true, |cap| {
+            self.token_balances.total_held(token).saturating_add(amount) <= cap
+        })
+    }
 
-/// Swap <em>amount</em> of token A or B to the output token at the exchange rate dictated by <em>the constant product formula</em>.
-/// The swap is executed on the token balances for the calling user.
+    /// The cap of the pool of `token`, if any.
+    fn cap_for(&self, token: Token) -> Option<TokenAmount> {
+        if token == Token::A {
+            self.cap_a
+        } else {
+            self.cap_b
+        }
+    }
+
+    /// Asserts that swaps are not halted by the circuit breaker.
+    fn assert_circuit_breaker_not_tripped(&self) {
+        assert!(
+            !self
+                .circuit_breaker
+                .as_ref()
+                .is_some_and(|breaker| breaker.tripped),
+            "Swaps are halted by the circuit breaker"
+        );
+    }
+
+    /// Trips the circuit breaker, if the current pools deviate too far from its reference price.
+    fn update_circuit_breaker(&mut self) {
+        let contract_token_balance = self
+            .token_balances
+            .get_balance_for(&self.liquidity_pool_address);
+        let price_history = &self.price_history;
+        if let Some(breaker) = self.circuit_breaker.as_mut() {
+            if breaker.is_exceeded_by(
+                price_history,
+                contract_token_balance.a_tokens,
+                contract_token_balance.b_tokens,
+            ) {
+                breaker.tripped = true;
+            }
+        }
+    }
+
+    /// Determines whether `owner` has authorized `router` to swap on their behalf.
+    fn is_router_authorized(&self, owner: Address, router: Address) -> bool {
+        self.router_authorizations
+            .get(&RouterAuthorization { owner, router })
+            .unwrap_or(false)
+    }
+
+    /// Determines the expiry of a lock, which becomes active at `start`.
+    fn lock_expiry(&self, start: i64) -> Option<i64> {
+        self.lock_lifetime_millis
+            .map(|lifetime| start.saturating_add(lifetime))
+    }
+
+    /// Collects the balances, share of liquidity and locks of `user`.
+    ///
+    /// ### Parameters:
+    ///
+    ///  * `user`: [`Address`] - The user to retrieve the position of.
+    ///
+    /// ### Returns:
+    /// The position of the user, of type [`UserPosition`].
+    pub fn user_position(&self, user: &Address) -> UserPosition {
+        let balance = self.token_balances.get_balance_for(user);
+        let total_liquidity = self
+            .token_balances
+            .get_balance_for(&self.liquidity_pool_address)
+            .liquidity_tokens;
+        let lp_share_per_mille = if total_liquidity == 0 {
+            0
+        } else {
+            // Cannot overflow, as no holder has more than the total liquidity.
+            u128_mul_div(balance.liquidity_tokens, 1000, total_liquidity).unwrap() as u16
+        };
+
+        UserPosition {
+            balance,
+            lp_share_per_mille,
+            locks: self.virtual_state.locks_owned_by(user),
+        }
+    }
+
+    /// Previews providing liquidity with at most `amount_a` of token A and `amount_b` of token B,
+    /// without changing the state. <br>
+    /// Like Uniswap's `quote`, all of one token is provided, together with its equivalent in the
+    /// other token, as computed by [`provide_liquidity`] when providing the fully consumed token,
+    /// after refunding any over-deposit.
+    ///
+    /// ### Parameters:
+    ///
+    ///  * `amount_a`: [`TokenAmount`] - The maximum amount of token A to provide.
+    ///
+    ///  * `amount_b`: [`TokenAmount`] - The maximum amount of token B to provide.
+    ///
+    /// ### Returns:
+    /// The consumed amounts of token A and B, and the minted liquidity tokens. All are `0` if the
+    /// pools have no liquidity, or if neither amount covers the equivalent of the other.
+    /// ([`TokenAmount`], [`TokenAmount`], [`TokenAmount`])
+    pub fn preview_provide(
+        &self,
+        amount_a: TokenAmount,
+        amount_b: TokenAmount,
+    ) -> (TokenAmount, TokenAmount, TokenAmount) {
+        if !self.contract_pools_have_liquidity() {
+            return (0, 0, 0);
+        }
+        let contract_token_balance = self
+            .token_balances
+            .get_balance_for(&self.liquidity_pool_address);
+        let (reserve_a, reserve_b) = (
+            contract_token_balance.a_tokens,
+            contract_token_balance.b_tokens,
+        );
+        let total_liquidity = contract_token_balance.liquidity_tokens;
+
+        let (equivalent_b, minted_from_a) =
+            calculate_equivalent_and_minted_tokens(amount_a, reserve_a, reserve_b, total_liquidity);
+        let equivalent_b = equivalent_b - over_deposit_for(amount_a, reserve_a, reserve_b);
+        if equivalent_b <= amount_b {
+            return (amount_a, equivalent_b, minted_from_a);
+        }
+        let (equivalent_a, minted_from_b) =
+            calculate_equivalent_and_minted_tokens(amount_b, reserve_b, reserve_a, total_liquidity);
+        let equivalent_a = equivalent_a - over_deposit_for(amount_b, reserve_b, reserve_a);
+        if equivalent_a <= amount_a {
+            return (equivalent_a, amount_b, minted_from_b);
+        }
+        (0, 0, 0)
+    }
+
+    /// Computes the amount of each token `user` controls, being their deposited balance plus the
+    /// input amounts of their open locks. <br>
+    /// Acquiring a lock does not reserve its input from the balance, so inputs already deposited
+    /// are counted twice, making the exposure an upper bound suitable for enforcing limits.
+    ///
+    /// ### Parameters:
+    ///
+    ///  * `user`: [`Address`] - The user to compute the exposure of.
+    ///
+    /// ### Returns:
+    /// The exposure of the user, including their liquidity tokens, of type [`TokenBalance`].
+    pub fn user_exposure(&self, user: &Address) -> TokenBalance {
+        let mut exposure = self.token_balances.get_balance_for(user);
+        for IdentifiedLock { lock, .. } in self.virtual_state.locks_owned_by(user) {
+            *exposure.get_mut_amount_of(lock.tokens_in_out.token_in) += lock.amount_in;
+        }
+        exposure
+    }
+
+    /// Checks whether the lock with `lock_id` could be executed now, with the output amount
+    /// recorded when it was acquired. <br>
+    /// Both the actual pool and the virtual pool of the output token must cover the output of
+    /// the lock. This always holds by construction, so `false` for an existing lock indicates an
+    /// accounting bug.
+    ///
+    /// ### Parameters:
+    ///
+    ///  * `lock_id`: [`LiquidityLockId`] - The id of the lock to check.
+    ///
+    /// ### Returns:
+    /// True if the lock exists and can be honored, false otherwise [`bool`]
+    pub fn lock_still_valid(&self, lock_id: &LiquidityLockId) -> bool {
+        let Some(lock) = self.virtual_state.get_lock(lock_id) else {
+            return false;
+        };
+        let token_out = lock.tokens_in_out.token_out;
+        let actual_pool_out = self
+            .token_balances
+            .get_balance_for(&self.liquidity_pool_address)
+            .get_amount_of(token_out);
+
+        // The virtual pool has already deducted the output of the lock.
+        let virtual_pool_out = actual_pool_out
+            .checked_add_signed(self.virtual_state.lock_liquidity.get_amount_of(token_out));
+        actual_pool_out >= lock.amount_out && virtual_pool_out.is_some()
+    }
+
+    /// Computes the constant product invariant `reserve_a * reserve_b` of the actual pools. <br>
+    /// Swap fees remain in the pools, so the invariant never decreases due to swaps. Computed
+    /// with a 256-bit intermediate, such that large pools cannot overflow.
+    ///
+    /// ### Returns:
+    /// The product of the pools, of type [`U256`].
+    pub fn invariant_with_fees(&self) -> U256 {
+        let contract_token_balance = self
+            .token_balances
+            .get_balance_for(&self.liquidity_pool_address);
+        u128_widening_mul(
+            contract_token_balance.a_tokens,
+            contract_token_balance.b_tokens,
+        )
+    }
+
+    /// Computes the swap fees attributed to `user` as a liquidity provider, for analytics. <br>
+    /// Fees are retained by the pools, and are thus reclaimed as part of the liquidity, rather
+    /// than claimed separately. Each swap fee is attributed to the liquidity providers by their
+    /// share of the liquidity tokens at the time of the swap.
+    ///
+    /// ### Parameters:
+    ///
+    ///  * `user`: [`Address`] - The liquidity provider.
+    ///
+    /// ### Returns:
+    /// The attributed fees of token A and B, of type [`TokenBalance`].
+    pub fn claimable_fees(&self, user: &Address) -> TokenBalance {
+        let Some(snapshot) = self.fee_snapshots.get(user) else {
+            return EMPTY_BALANCE;
+        };
+        let liquidity_tokens = self.token_balances.get_balance_for(user).liquidity_tokens;
+
+        let mut fees = snapshot.settled_fees;
+        for token in [Token::A, Token::B] {
+            let growth = self.fee_growth_per_liquidity.get_amount_of(token)
+                - snapshot.fee_growth_per_liquidity.get_amount_of(token);
+            let fee = fees.get_mut_amount_of(token);
+            *fee = fee.saturating_add(fees_for_growth(growth, liquidity_tokens));
+        }
+        fees
+    }
+
+    /// Settles the fees attributed to `user`, and snapshots the current fee growth. <br>
+    /// Must be called before the liquidity tokens of `user` change.
+    fn settle_fees(&mut self, user: &Address) {
+        let snapshot = LiquidityFeeSnapshot {
+            fee_growth_per_liquidity: self.fee_growth_per_liquidity.clone(),
+            settled_fees: self.claimable_fees(user),
+        };
+        self.fee_snapshots.insert(*user, snapshot);
+    }
+
+    /// Values both pools in terms of `token`, using the current spot price of the pools.
+    ///
+    /// As the spot price can be moved by swaps, the result can be manipulated, and should only be
+    /// used for informational purposes, not for critical accounting.
+    ///
+    /// ### Parameters:
+    ///
+    ///  * `token`: [`Token`] - The token to express the value in. Must be token A or B.
+    ///
+    /// ### Returns:
+    /// The total value locked in the pools, saturating at [`TokenAmount::MAX`], of type [`TokenAmount`].
+    pub fn tvl_in(&self, token: Token) -> TokenAmount {
+        assert_ne!(
+            token,
+            Token::LIQUIDITY,
+            "TVL must be in terms of token A or B"
+        );
+        let other_token = if token == Token::A {
+            Token::B
+        } else {
+            Token::A
+        };
+        let contract_token_balance = self
+            .token_balances
+            .get_balance_for(&self.liquidity_pool_address);
+        let reserve = contract_token_balance.get_amount_of(token);
+        let other_reserve = contract_token_balance.get_amount_of(other_token);
+
+        // At the spot price reserve / other_reserve, the other pool is worth exactly `reserve`.
+        let other_value = if other_reserve == 0 { 0 } else { reserve };
+        reserve.saturating_add(other_value)
+    }
+
+    /// Summarizes the actual liquidity pools of the contract.
+    ///
+    /// ### Returns:
+    /// The summary of the pools, of type [`PoolSummary`].
+    pub fn summary(&self) -> PoolSummary {
+        let contract_token_balance = self
+            .token_balances
+            .get_balance_for(&self.liquidity_pool_address);
+        PoolSummary {
+            version: POOL_SUMMARY_VERSION,
+            token_a: self.token_balances.token_a_address,
+            token_b: self.token_balances.token_b_address,
+            reserve_a: contract_token_balance.a_tokens,
+            reserve_b: contract_token_balance.b_tokens,
+            total_liquidity: contract_token_balance.liquidity_tokens,
+            fee_per_mille: self.swap_fee_per_mille,
+        }
+    }
+}
+
+/// Initialize the contract.
 ///
-/// The action will fail when:
+/// # Parameters
 ///
-/// - The contract does not have any liquidity.
-/// - The caller does not have sufficient input token balance.
-/// - The amount of output tokens is less than minimum specified (`amount_out_minimum`).
+///   * `context`: [`ContractContext`] - The contract context containing sender and chain information.
+///
+///   * `permissions`: [`InitialPermissions`] - Determines which callers are allowed to acquire
+///     locks, administrate the contract, swap on behalf of other users, and swap without fees.
+///
+///   * `token_a_address`: [`Address`] - The address of token A.
+///
+///   * `token_b_address`: [`Address`] - The address of token B.
+///
+///   * `swap_fee_per_mille`: [`TokenAmount`] - The fee for swapping, in per mille, i.e. a fee set to 3 corresponds to a fee of 0.3%.
+///
+///   * `initial_lock_id`: [`Option<LiquidityLockId>`] - The id of the first acquired lock, e.g. the
+///     [`VirtualState::next_lock_id`] of a previous contract when migrating. Starts at the initial id if [`None`].
+///
+/// The new state object of type [`LiquiditySwapContractState`] with all address fields initialized to their final state and remaining fields initialized to a default value.
+#[init]
+pub fn initialize(
+    context: ContractContext,
+    token_a_address: Address,
+    token_b_address: Address,
+    swap_fee_per_mille: u16,
+    permissions: InitialPermissions,
+    initial_lock_id: Option<LiquidityLockId>,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    let swap_fee_per_mille = validate_fee_per_mille(swap_fee_per_mille)
+        .unwrap_or_else(|msg| panic!("Invalid swap fee: {}", msg));
+
+    let token_balances =
+        match TokenBalances::new(context.contract_address, token_a_address, token_b_address) {
+            Ok(tb) => tb,
+            Err(msg) => panic!("{}", msg),
+        };
+
+    let new_state = LiquiditySwapContractState {
+        permission_lock_swap: permissions.permission_lock_swap,
+        permission_admin: permissions.permission_admin,
+        permission_swap_on_behalf: permissions.permission_swap_on_behalf,
+        permission_fee_exempt: permissions.permission_fee_exempt,
+        liquidity_pool_address: context.contract_address,
+        swap_fee_per_mille,
+        token_balances,
+        virtual_state: initial_lock_id
+            .map_or_else(VirtualState::new, VirtualState::with_initial_lock_id),
+        cap_a: None,
+        cap_b: None,
+        fee_recipient: None,
+        protocol_fee_share_per_mille: 0,
+        accrued_protocol_fees: EMPTY_BALANCE,
+        fee_burn_share_per_mille: 0,
+        token_a_burnable: false,
+        token_b_burnable: false,
+        accrued_burn_fees: EMPTY_BALANCE,
+        lock_lifetime_millis: None,
+        keeper_bounty_per_lock: 0,
+        keeper_bounty_fund: EMPTY_BALANCE,
+        circuit_breaker: None,
+        has_been_initialized: false,
+        price_history: PriceHistory::default(),
+        fee_growth_per_liquidity: EMPTY_BALANCE,
+        fee_snapshots: AvlTreeMap::new(),
+        minimum_initial_liquidity: DEFAULT_MINIMUM_INITIAL_LIQUIDITY,
+        locked_liquidity: 0,
+        exclusive_lp: None,
+        emergency_mode: false,
+        min_swap_interval_millis: 0,
+        last_swap_millis: AvlTreeMap::new(),
+        max_total_locks: DEFAULT_MAX_TOTAL_LOCKS,
+        router_authorizations: AvlTreeMap::new(),
+        migration_targets: AvlTreeMap::new(),
+    };
+
+    (new_state, vec![])
+}
+
+/// Deposit token {A, B} into the calling user's balance on the contract.
+///
+/// Requires that the swap contract has been approved at `token_address`
+/// by the sender. This is checked in a callback, implicitly guaranteeing
+/// that this only returns after the deposit transfer is complete.
+///
+/// If crediting `amount` would exceed the cap of the pool of the deposited token, the tokens are
+/// transferred back to the sender instead.
 ///
 /// ### Parameters:
 ///
@@ -341,413 +1129,2608 @@ pub fn deposit_callback(
 ///
 ///  * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
 ///
-///  * `token_address`: [`Address`] - The address of the token contract being swapped from.
+///  * `token_address`: [`Address`] - The address of the deposited token contract.
 ///
-///  * `amount_in`: [`TokenAmount`] - The amount to swap of the token matching `input_token`.
+///  * `amount`: [`TokenAmount`] - The amount to deposit.
 ///
-///  * `amount_out_minimum`: [`TokenAmount`] - The minimum allowed amount of output tokens from the
-///    swap. Should basically never be `0`, and should preferably be computed client-side with
-///    a set amount of allowed slippage.
+/// # Returns
+/// The unchanged state object of type [`LiquiditySwapContractState`].
+#[action(shortname = 0x01)]
+pub fn deposit(
+    context: ContractContext,
+    state: LiquiditySwapContractState,
+    token_address: Address,
+    amount: TokenAmount,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    let tokens = state.token_balances.deduce_tokens_in_out(token_address);
+
+    let mut event_group_builder = EventGroup::builder();
+    interact_mpc20::MPC20Contract::at_address(token_address).transfer_from(
+        &mut event_group_builder,
+        &context.sender,
+        &state.liquidity_pool_address,
+        amount,
+    );
+
+    event_group_builder
+        .with_callback(SHORTNAME_DEPOSIT_CALLBACK)
+        .argument(tokens.token_in)
+        .argument(amount)
+        .done();
+
+    (state, vec![event_group_builder.build()])
+}
+
+/// Handles callback from [`deposit`]. <br>
+/// If the transfer event is successful,
+/// the caller of [`deposit`] is registered as a user of the contract with (additional) `amount` added to their balance.
+/// If the deposit would exceed the cap of the pool, it is transferred back rather than credited.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`] - The contractContext for the callback.
+///
+/// * `callback_context`: [`CallbackContext`] - The callbackContext.
+///
+/// * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
+///
+/// * `token`: [`Token`] - Indicating the token of which to add `amount` to.
+///
+/// * `amount`: [`TokenAmount`] - The desired amount to add to the user's total amount of `token`.
+/// ### Returns
+///
+/// The updated state object of type [`LiquiditySwapContractState`] with an updated entry for the caller of `deposit`.
+#[callback(shortname = 0x10)]
+pub fn deposit_callback(
+    context: ContractContext,
+    callback_context: CallbackContext,
+    mut state: LiquiditySwapContractState,
+    token: Token,
+    amount: TokenAmount,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    assert!(callback_context.success, "Transfer did not succeed");
+
+    let event_groups = credit_deposit(&mut state, context.sender, token, amount, None);
+
+    (state, event_groups)
+}
+
+/// Deposit token {A, B} into the calling user's balance on the contract, like [`deposit`], and
+/// afterwards invoke the `next_callback` action of the sender, allowing integrators to continue
+/// with their own logic once the deposit has been credited.
+///
+/// ### Parameters:
+///
+///  * `context`: [`ContractContext`] - The contract context containing sender and chain information.
+///
+///  * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
+///
+///  * `token_address`: [`Address`] - The address of the deposited token contract.
+///
+///  * `amount`: [`TokenAmount`] - The amount to deposit.
+///
+///  * `next_callback`: [`Option<Shortname>`] - Shortname of an action on the sender, which is
+///    invoked with `token_address` and `amount` once the deposit has been credited. Nothing is
+///    invoked if [`None`].
+///
+/// # Returns
+/// The unchanged state object of type [`LiquiditySwapContractState`].
+#[action(shortname = 0x2F)]
+pub fn deposit_and_call(
+    context: ContractContext,
+    state: LiquiditySwapContractState,
+    token_address: Address,
+    amount: TokenAmount,
+    next_callback: Option<Shortname>,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    let tokens = state.token_balances.deduce_tokens_in_out(token_address);
+
+    let mut event_group_builder = EventGroup::builder();
+    interact_mpc20::MPC20Contract::at_address(token_address).transfer_from(
+        &mut event_group_builder,
+        &context.sender,
+        &state.liquidity_pool_address,
+        amount,
+    );
+
+    event_group_builder
+        .with_callback(SHORTNAME_DEPOSIT_AND_CALL_CALLBACK)
+        .argument(tokens.token_in)
+        .argument(amount)
+        .argument(next_callback)
+        .done();
+
+    (state, vec![event_group_builder.build()])
+}
+
+/// Handles callback from [`deposit_and_call`]. <br>
+/// Credits the deposit like [`deposit_callback`], and afterwards invokes the `next_callback`
+/// action of the caller, if present. If the deposit would exceed the cap of the pool, it is
+/// transferred back, and `next_callback` is not invoked.
+#[callback(shortname = 0x30)]
+pub fn deposit_and_call_callback(
+    context: ContractContext,
+    callback_context: CallbackContext,
+    mut state: LiquiditySwapContractState,
+    token: Token,
+    amount: TokenAmount,
+    next_callback: Option<Shortname>,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    assert!(callback_context.success, "Transfer did not succeed");
+
+    let event_groups = credit_deposit(&mut state, context.sender, token, amount, next_callback);
+
+    (state, event_groups)
+}
+
+/// A deposit of `amount` of the token at `token_address`, used by [`bulk_deposit`].
+#[derive(ReadWriteRPC, CreateTypeSpec, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deposit {
+    /// The address of the deposited token contract.
+    pub token_address: Address,
+    /// The amount to deposit.
+    pub amount: TokenAmount,
+}
+
+/// Deposit both token A and B into the calling user's balance on the contract in a single
+/// transaction, like [`deposit`]. <br>
+/// All transfers are performed in one event group, and credited in [`bulk_deposit_callback`].
+///
+/// Requires that the swap contract has been approved at each deposited token by the sender.
+///
+/// Fails if `deposits` is empty, or if a token is deposited more than once.
+///
+/// ### Parameters:
+///
+///  * `context`: [`ContractContext`] - The contract context containing sender and chain information.
+///
+///  * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
+///
+///  * `deposits`: [`Vec<Deposit>`] - The tokens and amounts to deposit.
+///
+/// # Returns
+/// The unchanged state object of type [`LiquiditySwapContractState`].
+#[action(shortname = 0x22)]
+pub fn bulk_deposit(
+    context: ContractContext,
+    state: LiquiditySwapContractState,
+    deposits: Vec<Deposit>,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    assert!(!deposits.is_empty(), "Must deposit at least one token");
+
+    let mut event_group_builder = EventGroup::builder();
+    let mut deposited_tokens = Vec::with_capacity(deposits.len());
+    for deposit in &deposits {
+        let token = state
+            .token_balances
+            .deduce_tokens_in_out(deposit.token_address)
+            .token_in;
+        assert!(
+            !deposited_tokens.contains(&token),
+            "Each token can be deposited at most once"
+        );
+        deposited_tokens.push(token);
+
+        interact_mpc20::MPC20Contract::at_address(deposit.token_address).transfer_from(
+            &mut event_group_builder,
+            &context.sender,
+            &state.liquidity_pool_address,
+            deposit.amount,
+        );
+    }
+
+    event_group_builder
+        .with_callback(SHORTNAME_BULK_DEPOSIT_CALLBACK)
+        .argument(deposits)
+        .done();
+
+    (state, vec![event_group_builder.build()])
+}
+
+/// Handles callback from [`bulk_deposit`]. <br>
+/// Each deposit whose transfer succeeded is added to the balance of the caller of
+/// [`bulk_deposit`]. Deposits whose transfer failed are not credited, while the remaining
+/// deposits are still credited, as their tokens have been transferred to the contract.
+/// Deposits which would exceed the cap of their pool are transferred back rather than credited.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`] - The contractContext for the callback.
+///
+/// * `callback_context`: [`CallbackContext`] - The callbackContext, holding a result per deposit.
+///
+/// * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
+///
+/// * `deposits`: [`Vec<Deposit>`] - The deposits, in the order of their transfers.
+///
+/// ### Returns
+///
+/// The updated state object of type [`LiquiditySwapContractState`] with an updated entry for the caller of `bulk_deposit`.
+#[callback(shortname = 0x23)]
+pub fn bulk_deposit_callback(
+    context: ContractContext,
+    callback_context: CallbackContext,
+    mut state: LiquiditySwapContractState,
+    deposits: Vec<Deposit>,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    let mut refund_builder = EventGroup::builder();
+    let mut any_refunded = false;
+    for (deposit, result) in deposits.iter().zip(callback_context.results.iter()) {
+        if !result.succeeded {
+            continue;
+        }
+        let token = state
+            .token_balances
+            .deduce_tokens_in_out(deposit.token_address)
+            .token_in;
+        if state.deposit_within_cap(token, deposit.amount) {
+            state
+                .token_balances
+                .add_to_token_balance(context.sender, token, deposit.amount);
+        } else {
+            interact_mpc20::MPC20Contract::at_address(deposit.token_address).transfer(
+                &mut refund_builder,
+                &context.sender,
+                deposit.amount,
+            );
+            any_refunded = true;
+        }
+    }
+
+    let event_groups = if any_refunded {
+        vec![refund_builder.build()]
+    } else {
+        vec![]
+    };
+    (state, event_groups)
+}
+
+/// Deposit token {A, B} into the calling user's balance on the contract, like [`deposit`], but
+/// credit the deposit to `beneficiary` instead of the caller. <br>
+/// Allows other contracts, such as previous versions of this contract, to move funds into the
+/// contract for their users.
+///
+/// ### Parameters:
+///
+///  * `context`: [`ContractContext`] - The contract context containing sender and chain information.
+///
+///  * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
+///
+///  * `beneficiary`: [`Address`] - The user credited with the deposit.
+///
+///  * `token_address`: [`Address`] - The address of the deposited token contract.
+///
+///  * `amount`: [`TokenAmount`] - The amount to deposit.
+///
+/// # Returns
+/// The unchanged state object of type [`LiquiditySwapContractState`].
+#[action(shortname = 0x1B)]
+pub fn deposit_on_behalf(
+    context: ContractContext,
+    state: LiquiditySwapContractState,
+    beneficiary: Address,
+    token_address: Address,
+    amount: TokenAmount,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    let tokens = state.token_balances.deduce_tokens_in_out(token_address);
+
+    let mut event_group_builder = EventGroup::builder();
+    interact_mpc20::MPC20Contract::at_address(token_address).transfer_from(
+        &mut event_group_builder,
+        &context.sender,
+        &state.liquidity_pool_address,
+        amount,
+    );
+
+    event_group_builder
+        .with_callback(SHORTNAME_DEPOSIT_ON_BEHALF_CALLBACK)
+        .argument(beneficiary)
+        .argument(tokens.token_in)
+        .argument(amount)
+        .done();
+
+    (state, vec![event_group_builder.build()])
+}
+
+/// Handles callback from [`deposit_on_behalf`]. <br>
+/// If the transfer event is successful, `amount` is added to the balance of `beneficiary`.
+/// If the deposit would exceed the cap of the pool, it is transferred to `beneficiary` rather than
+/// credited.
+#[callback(shortname = 0x1C)]
+pub fn deposit_on_behalf_callback(
+    _context: ContractContext,
+    callback_context: CallbackContext,
+    mut state: LiquiditySwapContractState,
+    beneficiary: Address,
+    token: Token,
+    amount: TokenAmount,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    assert!(callback_context.success, "Transfer did not succeed");
+
+    let event_groups = credit_deposit(&mut state, beneficiary, token, amount, None);
+
+    (state, event_groups)
+}
+
+/// Adds `amount` of `token` to the balance of `user`, and creates the invocation of the
+/// `next_callback` action on `user`, if present. <br>
+/// If the deposit would exceed the cap of the pool, the tokens are transferred to `user` instead,
+/// and `next_callback` is not invoked.
+fn credit_deposit(
+    state: &mut LiquiditySwapContractState,
+    user: Address,
+    token: Token,
+    amount: TokenAmount,
+    next_callback: Option<Shortname>,
+) -> Vec<EventGroup> {
+    let token_address = if token == Token::A {
+        state.token_balances.token_a_address
+    } else {
+        state.token_balances.token_b_address
+    };
+
+    if !state.deposit_within_cap(token, amount) {
+        let mut event_group_builder = EventGroup::builder();
+        interact_mpc20::MPC20Contract::at_address(token_address).transfer(
+            &mut event_group_builder,
+            &user,
+            amount,
+        );
+        return vec![event_group_builder.build()];
+    }
+
+    state
+        .token_balances
+        .add_to_token_balance(user, token, amount);
+
+    let Some(next_callback) = next_callback else {
+        return vec![];
+    };
+
+    let mut event_group_builder = EventGroup::builder();
+    event_group_builder
+        .call(user, next_callback)
+        .argument(token_address)
+        .argument(amount)
+        .done();
+    vec![event_group_builder.build()]
+}
+
+/// Swap <em>amount</em> of token A or B to the output token at the exchange rate dictated by <em>the constant product formula</em>.
+/// The swap is executed on the token balances for the calling user.
+///
+/// The action will fail when:
+///
+/// - The contract does not have any liquidity.
+/// - The caller does not have sufficient input token balance.
+/// - The amount of output tokens is less than minimum specified (`amount_out_minimum`).
+/// - The caller swapped less than [`LiquiditySwapContractState::min_swap_interval_millis`] ago.
+///
+/// ### Parameters:
+///
+///  * `context`: [`ContractContext`] - The contract context containing sender and chain information.
+///
+///  * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
+///
+///  * `token_address`: [`Address`] - The address of the token contract being swapped from.
+///
+///  * `amount_in`: [`TokenAmount`] - The amount to swap of the token matching `input_token`.
+///
+///  * `amount_out_minimum`: [`TokenAmount`] - The minimum allowed amount of output tokens from the
+///    swap. Should basically never be `0`, and should preferably be computed client-side with
+///    a set amount of allowed slippage.
+///
+/// # Returns
+/// The updated state object of type [`LiquiditySwapContractState`] yielding the result of the swap.
+#[action(shortname = 0x02)]
+pub fn instant_swap(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    token_in: Address,
+    amount_in: TokenAmount,
+    amount_out_minimum: TokenAmount,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    instant_swap_for_recipient(
+        &mut state,
+        &context,
+        token_in,
+        amount_in,
+        amount_out_minimum,
+        context.sender,
+    );
+    (state, vec![])
+}
+
+/// Like [`instant_swap`], but credits the output tokens to the balance of `recipient` rather than
+/// the caller, e.g. for routers. The input tokens are still taken from the caller's balance.
+///
+/// ### Parameters:
+///
+///  * `context`: [`ContractContext`] - The contract context containing sender and chain information.
+///
+///  * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
+///
+///  * `token_address`: [`Address`] - The address of the token contract being swapped from.
+///
+///  * `amount_in`: [`TokenAmount`] - The amount to swap of the token matching `input_token`.
+///
+///  * `amount_out_minimum`: [`TokenAmount`] - The minimum allowed amount of output tokens from the
+///    swap.
+///
+///  * `recipient`: [`Address`] - The address whose balance is credited with the output tokens.
+///
+/// # Returns
+/// The updated state object of type [`LiquiditySwapContractState`] yielding the result of the swap.
+#[action(shortname = 0x35)]
+pub fn instant_swap_to(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    token_in: Address,
+    amount_in: TokenAmount,
+    amount_out_minimum: TokenAmount,
+    recipient: Address,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    instant_swap_for_recipient(
+        &mut state,
+        &context,
+        token_in,
+        amount_in,
+        amount_out_minimum,
+        recipient,
+    );
+    (state, vec![])
+}
+
+/// Swaps `amount_in` of `token_in` from the balance of the caller, subject to the rate limit of
+/// the caller, and credits the output tokens to `recipient`.
+fn instant_swap_for_recipient(
+    state: &mut LiquiditySwapContractState,
+    context: &ContractContext,
+    token_in: Address,
+    amount_in: TokenAmount,
+    amount_out_minimum: TokenAmount,
+    recipient: Address,
+) {
+    assert!(
+        state.contract_pools_have_liquidity(),
+        "Pools must have existing liquidity to perform a swap"
+    );
+    state.record_rate_limited_swap(context.sender, context.block_production_time);
+
+    let amount_out = instant_swap_internal(
+        state,
+        token_in,
+        amount_in,
+        amount_out_minimum,
+        context.sender,
+    );
+
+    if recipient != context.sender {
+        let token_out = state
+            .token_balances
+            .deduce_tokens_in_out(token_in)
+            .token_out;
+        state
+            .token_balances
+            .move_tokens(context.sender, recipient, token_out, amount_out);
+    }
+}
+
+/// Swaps `amount_in` of `token_in` from the balance of `user`, at the current exchange rate.
+///
+/// Returns the output amount of the swap.
+///
+/// Fails if the calculated receiving amount is less than `amount_out_minimum`.
+fn instant_swap_internal(
+    state: &mut LiquiditySwapContractState,
+    token_in: Address,
+    amount_in: TokenAmount,
+    amount_out_minimum: TokenAmount,
+    user: Address,
+) -> TokenAmount {
+    // Instant swaps can be represented by acquiring a lock, and executing it straight away.
+    let (lock_id, _) = lock_internal(
+        state,
+        amount_in,
+        token_in,
+        amount_out_minimum,
+        user,
+        None,
+        None,
+    );
+    execute_lock_swap_internal(state, lock_id, user)
+}
+
+/// Swap `amount_in` of `token_in` on behalf of `owner`, taking the input tokens directly from
+/// `owner` instead of from their balance on the contract.
+///
+/// Requires that the swap contract has been approved at `token_in` by `owner`, and that `owner`
+/// has authorized the sender using [`set_router_authorization`].
+/// The input tokens are transferred to the contract, after which the swap is performed in
+/// [`swap_from_allowance_callback`]. The output tokens are added to the balance of `owner`, who
+/// can then [`withdraw`] them.
+///
+/// # Fails
+///
+/// Fails if the sender (caller) does not have permission to swap on behalf of other users.
+/// Fails if `owner` has not authorized the sender.
+/// Fails if `amount_out_minimum` is `0`, as the router must protect the owner against slippage.
+/// Fails if `token_in` is neither token A nor B.
+#[action(shortname = 0x0D)]
+pub fn swap_from_allowance(
+    context: ContractContext,
+    state: LiquiditySwapContractState,
+    owner: Address,
+    token_in: Address,
+    amount_in: TokenAmount,
+    amount_out_minimum: TokenAmount,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    state
+        .permission_swap_on_behalf
+        .assert_permission_for(&context.sender, "swap on behalf");
+    assert!(
+        state.is_router_authorized(owner, context.sender),
+        "{:?} has not authorized {:?} to swap on their behalf",
+        owner,
+        context.sender
+    );
+    assert!(
+        amount_out_minimum > 0,
+        "Minimum output amount must be positive"
+    );
+    state.token_balances.deduce_tokens_in_out(token_in);
+
+    let mut event_group_builder = EventGroup::builder();
+    interact_mpc20::MPC20Contract::at_address(token_in).transfer_from(
+        &mut event_group_builder,
+        &owner,
+        &state.liquidity_pool_address,
+        amount_in,
+    );
+
+    event_group_builder
+        .with_callback(SHORTNAME_SWAP_FROM_ALLOWANCE_CALLBACK)
+        .argument(owner)
+        .argument(token_in)
+        .argument(amount_in)
+        .argument(amount_out_minimum)
+        .done();
+
+    (state, vec![event_group_builder.build()])
+}
+
+/// Handles callback from [`swap_from_allowance`]. <br>
+/// If the transfer event is successful, `amount_in` is added to the balance of `owner`, and swapped
+/// if the swap yields at least `amount_out_minimum`. Otherwise the input tokens remain in the
+/// balance of `owner`, to be withdrawn.
+///
+/// The output amount of the swap is returned to any callbacks, which is `0` if no swap happened.
+#[callback(shortname = 0x11)]
+pub fn swap_from_allowance_callback(
+    _context: ContractContext,
+    callback_context: CallbackContext,
+    mut state: LiquiditySwapContractState,
+    owner: Address,
+    token_in: Address,
+    amount_in: TokenAmount,
+    amount_out_minimum: TokenAmount,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    assert!(callback_context.success, "Transfer did not succeed");
+
+    let amount_out =
+        swap_from_allowance_internal(&mut state, owner, token_in, amount_in, amount_out_minimum)
+            .unwrap_or(0);
+
+    let mut return_event = EventGroup::builder();
+    return_event.return_data(amount_out);
+
+    (state, vec![return_event.build()])
+}
+
+/// Adds the transferred `amount_in` of `token_in` to the balance of `owner`, and swaps them if
+/// possible.
+///
+/// Returns the output amount of the swap, or [`None`] if the pools have no liquidity, or the swap
+/// would yield less than `amount_out_minimum`.
+fn swap_from_allowance_internal(
+    state: &mut LiquiditySwapContractState,
+    owner: Address,
+    token_in: Address,
+    amount_in: TokenAmount,
+    amount_out_minimum: TokenAmount,
+) -> Option<TokenAmount> {
+    let tokens = state.token_balances.deduce_tokens_in_out(token_in);
+    state
+        .token_balances
+        .add_to_token_balance(owner, tokens.token_in, amount_in);
+
+    // The tokens have already been transferred, so the swap must not fail at this point.
+    if !state.contract_pools_have_liquidity()
+        || calculate_minimum_swap_to_amount(state, amount_in, &tokens, state.swap_fee_for(&owner))
+            < amount_out_minimum
+    {
+        return None;
+    }
+    Some(instant_swap_internal(
+        state,
+        token_in,
+        amount_in,
+        amount_out_minimum,
+        owner,
+    ))
+}
+
+/// Withdraw <em>amount</em> of token {A, B} from the contract for the calling user.
+/// This fails if `amount` is larger than the token balance of the corresponding token.
+///
+/// It preemptively updates the state of the user's balance before making the transfer.
+/// This means that if the transfer fails, the contract could end up with more money than it has registered, which is acceptable.
+/// This is to incentivize the user to spend enough gas to complete the transfer.
+/// If `wait_for_callback` is true, any callbacks will happen only after the withdrawal has completed.
+///
+/// ### Parameters:
+///
+///  * `context`: [`ContractContext`] - The contract context containing sender and chain information.
+///
+///  * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
+///
+///  * `token_address`: [`Address`] - The address of the token contract to withdraw to.
+///
+///  * `amount`: [`TokenAmount`] - The amount to withdraw.
+///
+/// # Returns
+/// The unchanged state object of type [`LiquiditySwapContractState`].
+#[action(shortname = 0x03)]
+pub fn withdraw(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    token_address: Address,
+    amount: TokenAmount,
+    wait_for_callback: bool,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    let tokens = state.token_balances.deduce_tokens_in_out(token_address);
+
+    state
+        .token_balances
+        .deduct_from_token_balance(context.sender, tokens.token_in, amount);
+
+    let mut event_group_builder = EventGroup::builder();
+    interact_mpc20::MPC20Contract::at_address(token_address).transfer(
+        &mut event_group_builder,
+        &context.sender,
+        amount,
+    );
+
+    if wait_for_callback {
+        event_group_builder
+            .with_callback(SHORTNAME_WAIT_WITHDRAW_CALLBACK)
+            .done();
+    }
+
+    (state, vec![event_group_builder.build()])
+}
+
+#[callback(shortname = 0x15)]
+fn wait_withdraw_callback(
+    _context: ContractContext,
+    _callback_context: CallbackContext,
+    state: LiquiditySwapContractState,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    (state, vec![])
+}
+
+/// Withdraw <em>amount</em> of token {A, B} from the contract for the calling user, like
+/// [`withdraw`], but restores the balance of the user if the transfer fails.
+/// This fails if `amount` is larger than the token balance of the corresponding token.
+///
+/// The balance is deducted before making the transfer, such that it cannot be spent while the
+/// transfer is in progress, and is credited back in [`withdraw_strict_callback`] if the transfer
+/// fails. Any callbacks happen only after the withdrawal has completed.
+///
+/// ### Parameters:
+///
+///  * `context`: [`ContractContext`] - The contract context containing sender and chain information.
+///
+///  * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
+///
+///  * `token_address`: [`Address`] - The address of the token contract to withdraw to.
+///
+///  * `amount`: [`TokenAmount`] - The amount to withdraw.
+///
+/// # Returns
+/// The updated state object of type [`LiquiditySwapContractState`].
+#[action(shortname = 0x13)]
+pub fn withdraw_strict(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    token_address: Address,
+    amount: TokenAmount,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    let tokens = state.token_balances.deduce_tokens_in_out(token_address);
+
+    state
+        .token_balances
+        .deduct_from_token_balance(context.sender, tokens.token_in, amount);
+
+    let mut event_group_builder = EventGroup::builder();
+    interact_mpc20::MPC20Contract::at_address(token_address).transfer(
+        &mut event_group_builder,
+        &context.sender,
+        amount,
+    );
+
+    event_group_builder
+        .with_callback(SHORTNAME_WITHDRAW_STRICT_CALLBACK)
+        .argument(context.sender)
+        .argument(tokens.token_in)
+        .argument(amount)
+        .done();
+
+    (state, vec![event_group_builder.build()])
+}
+
+/// Handles callback from [`withdraw_strict`]. <br>
+/// If the transfer failed, `amount` of `token` is credited back to the balance of `user`.
+#[callback(shortname = 0x16)]
+fn withdraw_strict_callback(
+    _context: ContractContext,
+    callback_context: CallbackContext,
+    mut state: LiquiditySwapContractState,
+    user: Address,
+    token: Token,
+    amount: TokenAmount,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    finalize_withdraw_strict(&mut state, callback_context.success, user, token, amount);
+    (state, vec![])
+}
+
+/// Finalizes a strict withdrawal, restoring the balance of `user` if the transfer did not succeed.
+fn finalize_withdraw_strict(
+    state: &mut LiquiditySwapContractState,
+    transfer_succeeded: bool,
+    user: Address,
+    token: Token,
+    amount: TokenAmount,
+) {
+    if !transfer_succeeded {
+        state
+            .token_balances
+            .add_to_token_balance(user, token, amount);
+    }
+}
+
+/// Migrates the caller's entire balance of token A and B to `new_contract`, typically a newer
+/// version of this contract, using [`deposit_on_behalf`] on `new_contract`. <br>
+/// Each token is migrated in a separate event group, approving `new_contract` to transfer the
+/// tokens and then depositing them for the caller. The balances are deducted up front, and a
+/// token whose deposit fails is credited back to the caller by [`migrate_callback`], such that
+/// a partially failed migration does not lose funds.
+///
+/// Liquidity tokens are not migrated, and must be reclaimed first.
+///
+/// # Fails
+///
+/// Fails if `new_contract` has not been allowed by [`set_migration_target`], or if the caller has
+/// neither token A nor token B.
+#[action(shortname = 0x1D)]
+pub fn migrate_to(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    new_contract: Address,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    assert!(
+        state.migration_targets.get(&new_contract).unwrap_or(false),
+        "{:?} is not an allowed migration target",
+        new_contract
+    );
+    let user = context.sender;
+    let user_balance = state.token_balances.get_balance_for(&user);
+    assert!(
+        user_balance.a_tokens != 0 || user_balance.b_tokens != 0,
+        "Nothing to migrate"
+    );
+
+    let mut event_groups = vec![];
+    for (token, token_address) in [
+        (Token::A, state.token_balances.token_a_address),
+        (Token::B, state.token_balances.token_b_address),
+    ] {
+        let amount = user_balance.get_amount_of(token);
+        if amount == 0 {
+            continue;
+        }
+        state
+            .token_balances
+            .deduct_from_token_balance(user, token, amount);
+
+        let mut event_group_builder = EventGroup::builder();
+        interact_mpc20::MPC20Contract::at_address(token_address).approve_relative(
+            &mut event_group_builder,
+            &new_contract,
+            i128::try_from(amount).expect("Migrated amount exceeds the approvable range"),
+        );
+        SwapContract::at_address(new_contract).deposit_on_behalf(
+            &mut event_group_builder,
+            &user,
+            &token_address,
+            amount,
+        );
+        event_group_builder
+            .with_callback(SHORTNAME_MIGRATE_CALLBACK)
+            .argument(user)
+            .argument(token)
+            .argument(amount)
+            .argument(new_contract)
+            .done();
+        event_groups.push(event_group_builder.build());
+    }
+
+    (state, event_groups)
+}
+
+/// Handles callback from [`migrate_to`]. <br>
+/// If the migration of `token` failed, `amount` of `token` is credited back to the balance of
+/// `user`, and the approval of `new_contract` is revoked, such that it cannot transfer the
+/// tokens after all.
+#[callback(shortname = 0x1E)]
+fn migrate_callback(
+    _context: ContractContext,
+    callback_context: CallbackContext,
+    mut state: LiquiditySwapContractState,
+    user: Address,
+    token: Token,
+    amount: TokenAmount,
+    new_contract: Address,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    // Restores the balance like a failed strict withdrawal.
+    finalize_withdraw_strict(&mut state, callback_context.success, user, token, amount);
+    if callback_context.success {
+        return (state, vec![]);
+    }
+
+    let token_address = if token == Token::A {
+        state.token_balances.token_a_address
+    } else {
+        state.token_balances.token_b_address
+    };
+    let mut event_group_builder = EventGroup::builder();
+    interact_mpc20::MPC20Contract::at_address(token_address).approve_relative(
+        &mut event_group_builder,
+        &new_contract,
+        // Cannot fail, as the amount was approved by migrate_to.
+        -i128::try_from(amount).unwrap(),
+    );
+    (state, vec![event_group_builder.build()])
+}
+
+/// Allows or disallows users to migrate their balances to `new_contract` using [`migrate_to`]. <br>
+/// Migrating approves `new_contract` to transfer the migrated tokens, so only trusted contracts,
+/// typically newer versions of this contract, should be allowed.
+///
+/// # Fails
+///
+/// Fails if the sender (caller) does not have permission to administrate the contract.
+#[action(shortname = 0x31)]
+pub fn set_migration_target(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    new_contract: Address,
+    allowed: bool,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    state
+        .permission_admin
+        .assert_permission_for(&context.sender, "admin");
+
+    if allowed {
+        state.migration_targets.insert(new_contract, true);
+    } else {
+        state.migration_targets.remove(&new_contract);
+    }
+
+    (state, vec![])
+}
+
+/// Become a liquidity provider to the contract by providing `amount` of tokens from the caller's balance. <br>
+/// An equivalent amount of the output token is required to succeed and will be token_in implicitly. <br>
+/// This is the inverse of [`reclaim_liquidity`].
+///
+/// ### Parameters:
+///
+///  * `context`: [`ContractContext`] - The contract context containing sender and chain information.
+///
+///  * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
+///
+///  * `token_address`: [`Address`] - The address of the input token.
+///
+///  * `token_amount`: [`TokenAmount`] - The amount to provide.
+///
+/// # Returns
+/// The unchanged state object of type [`LiquiditySwapContractState`].
+#[action(shortname = 0x04)]
+pub fn provide_liquidity(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    token_address: Address,
+    amount: TokenAmount,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    state.assert_may_provide_liquidity(&context.sender, context.block_production_time);
+    provide_liquidity_up_to(
+        &mut state,
+        &context.sender,
+        token_address,
+        amount,
+        TokenAmount::MAX,
+    );
+    (state, vec![])
+}
+
+/// Like [`provide_liquidity`], but fails if the implicitly provided amount of the output token
+/// exceeds `token_out_maximum`. <br>
+/// Allows liquidity providers to bound how much of the output token they are willing to supply.
+///
+/// ### Parameters:
+///
+///  * `context`: [`ContractContext`] - The contract context containing sender and chain information.
+///
+///  * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
+///
+///  * `token_address`: [`Address`] - The address of the input token.
+///
+///  * `token_amount`: [`TokenAmount`] - The amount to provide.
+///
+///  * `token_out_maximum`: [`TokenAmount`] - The maximum amount of the output token to provide.
+///
+/// # Returns
+/// The updated state object of type [`LiquiditySwapContractState`].
+#[action(shortname = 0x18)]
+pub fn provide_liquidity_with_maximum(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    token_address: Address,
+    amount: TokenAmount,
+    token_out_maximum: TokenAmount,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    state.assert_may_provide_liquidity(&context.sender, context.block_production_time);
+    provide_liquidity_up_to(
+        &mut state,
+        &context.sender,
+        token_address,
+        amount,
+        token_out_maximum,
+    );
+    (state, vec![])
+}
+
+/// Become a liquidity provider to the contract by providing both `amount_a` of token A and
+/// `amount_b` of token B from the caller's balance. <br>
+/// The minted liquidity tokens are the smaller of the shares implied by each amount, see
+/// [`calculate_minted_for_pair`]. Unlike [`provide_liquidity`], no additional output token is
+/// required, but any amount exceeding the current ratio of the pools is provided without minting
+/// additional liquidity tokens, so the amounts should match the ratio of the pools.
+///
+/// ### Parameters:
+///
+///  * `context`: [`ContractContext`] - The contract context containing sender and chain information.
+///
+///  * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
+///
+///  * `amount_a`: [`TokenAmount`] - The amount of token A to provide.
+///
+///  * `amount_b`: [`TokenAmount`] - The amount of token B to provide.
+///
+///  * `min_liquidity_out`: [`TokenAmount`] - The minimum amount of liquidity tokens to mint,
+///    protecting the provider against price movements.
+///
+/// # Returns
+/// The updated state object of type [`LiquiditySwapContractState`].
+///
+/// # Fails
+///
+/// Fails if the pools have no liquidity, if the amounts yield no liquidity tokens, or fewer than
+/// `min_liquidity_out`.
+#[action(shortname = 0x34)]
+pub fn provide_liquidity_both(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    amount_a: TokenAmount,
+    amount_b: TokenAmount,
+    min_liquidity_out: TokenAmount,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    state.assert_may_provide_liquidity(&context.sender, context.block_production_time);
+
+    let contract_token_balance = state
+        .token_balances
+        .get_balance_for(&state.liquidity_pool_address);
+    let minted_liquidity_tokens = calculate_minted_for_pair(
+        amount_a,
+        amount_b,
+        contract_token_balance.a_tokens,
+        contract_token_balance.b_tokens,
+        contract_token_balance.liquidity_tokens,
+    );
+    assert!(
+        minted_liquidity_tokens > 0,
+        "The given amounts yielded 0 minted liquidity"
+    );
+    assert!(
+        minted_liquidity_tokens >= min_liquidity_out,
+        "Providing would mint {} liquidity tokens, but minimum was set to {}.",
+        minted_liquidity_tokens,
+        min_liquidity_out
+    );
+    state.assert_within_cap(Token::A, amount_a);
+    state.assert_within_cap(Token::B, amount_b);
+
+    provide_liquidity_internal(
+        &mut state,
+        &context.sender,
+        TokensInOut::A_IN_B_OUT,
+        amount_a,
+        amount_b,
+        minted_liquidity_tokens,
+    );
+    (state, vec![])
+}
+
+/// Provides `amount` of the input token, and the equivalent amount of the output token, from the
+/// balance of `user`, failing if the provided output token amount exceeds `token_out_maximum`. <br>
+/// Any over-deposit of the output token, as computed by [`over_deposit_for`], is refunded, such
+/// that the exact equivalent, rounded up, is provided.
+fn provide_liquidity_up_to(
+    state: &mut LiquiditySwapContractState,
+    user: &Address,
+    token_address: Address,
+    amount: TokenAmount,
+    token_out_maximum: TokenAmount,
+) {
+    let tokens = state.token_balances.deduce_tokens_in_out(token_address);
+    let contract_token_balance = state
+        .token_balances
+        .get_balance_for(&state.liquidity_pool_address);
+
+    let (token_out_equivalent, minted_liquidity_tokens) = calculate_equivalent_and_minted_tokens(
+        amount,
+        contract_token_balance.get_amount_of(tokens.token_in),
+        contract_token_balance.get_amount_of(tokens.token_out),
+        contract_token_balance.liquidity_tokens,
+    );
+    assert!(
+        minted_liquidity_tokens > 0,
+        "The given input amount yielded 0 minted liquidity"
+    );
+    // Refund the additional output token, when it does not protect the pools against rounding.
+    let token_out_amount = token_out_equivalent
+        - over_deposit_for(
+            amount,
+            contract_token_balance.get_amount_of(tokens.token_in),
+            contract_token_balance.get_amount_of(tokens.token_out),
+        );
+    assert!(
+        token_out_amount <= token_out_maximum,
+        "Output token equivalent exceeds the maximum: {}/{}",
+        token_out_amount,
+        token_out_maximum
+    );
+    state.assert_within_cap(tokens.token_in, amount);
+    state.assert_within_cap(tokens.token_out, token_out_amount);
+
+    provide_liquidity_internal(
+        state,
+        user,
+        tokens,
+        amount,
+        token_out_amount,
+        minted_liquidity_tokens,
+    );
+}
+
+/// The largest excess of one token, in per mille of the provided amount of that token, that
+/// [`provide_dust_as_liquidity`] leaves as dust instead of swapping.
+const DUST_IMBALANCE_TOLERANCE_PER_MILLE: TokenAmount = 50;
+
+/// Provides the caller's entire token A and B balances as liquidity, to consolidate dust too small
+/// to withdraw profitably. <br>
+/// If the balances are balanced within [`DUST_IMBALANCE_TOLERANCE_PER_MILLE`], they are provided
+/// like [`LiquiditySwapContractState::preview_provide`]. Otherwise, half of the excess token is
+/// first swapped to the other token, like [`instant_swap`], subject to the same rate limit. As the
+/// swap moves the price and takes a fee, a small remainder may stay in the balance.
+///
+/// ### Parameters:
+///
+///  * `context`: [`ContractContext`] - The contract context containing sender and chain information.
+///
+///  * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
+///
+///  * `min_liquidity_out`: [`TokenAmount`] - The minimum amount of liquidity tokens to mint,
+///    protecting the swap of the excess token against price movements.
+///
+/// # Returns
+/// The updated state object of type [`LiquiditySwapContractState`].
+///
+/// # Fails
+///
+/// Fails if the pools have no liquidity, if the balances yield no liquidity tokens, or fewer than
+/// `min_liquidity_out`. Fails if the excess token must be swapped, but the caller swapped less
+/// than [`LiquiditySwapContractState::min_swap_interval_millis`] ago.
+#[action(shortname = 0x28)]
+pub fn provide_dust_as_liquidity(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    min_liquidity_out: TokenAmount,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    state.assert_may_provide_liquidity(&context.sender, context.block_production_time);
+    assert!(
+        state.contract_pools_have_liquidity(),
+        "Pools must have existing liquidity to provide dust"
+    );
+
+    let balance = state.token_balances.get_balance_for(&context.sender);
+    let (consumed_a, consumed_b, _) = state.preview_provide(balance.a_tokens, balance.b_tokens);
+    let (excess_token, excess, consumed) = if consumed_a < balance.a_tokens {
+        (
+            state.token_balances.token_a_address,
+            balance.a_tokens - consumed_a,
+            consumed_a,
+        )
+    } else {
+        (
+            state.token_balances.token_b_address,
+            balance.b_tokens - consumed_b,
+            consumed_b,
+        )
+    };
+    // Compared in 256 bits, as large balances would overflow the products.
+    let is_imbalanced = u128_widening_mul(excess, 1000)
+        > u128_widening_mul(consumed, DUST_IMBALANCE_TOLERANCE_PER_MILLE);
+    if is_imbalanced && excess / 2 > 0 {
+        state.record_rate_limited_swap(context.sender, context.block_production_time);
+        instant_swap_internal(&mut state, excess_token, excess / 2, 0, context.sender);
+    }
+
+    let balance = state.token_balances.get_balance_for(&context.sender);
+    let (amount_a, amount_b, minted_liquidity_tokens) =
+        state.preview_provide(balance.a_tokens, balance.b_tokens);
+    assert!(
+        minted_liquidity_tokens > 0,
+        "The dust balances yielded 0 minted liquidity"
+    );
+    assert!(
+        minted_liquidity_tokens >= min_liquidity_out,
+        "Dust would mint {} liquidity tokens, but minimum was set to {}.",
+        minted_liquidity_tokens,
+        min_liquidity_out
+    );
+    state.assert_within_cap(Token::A, amount_a);
+    state.assert_within_cap(Token::B, amount_b);
+
+    provide_liquidity_internal(
+        &mut state,
+        &context.sender,
+        TokensInOut::A_IN_B_OUT,
+        amount_a,
+        amount_b,
+        minted_liquidity_tokens,
+    );
+    (state, vec![])
+}
+
+/// Reclaim a calling user's share of the contract's total liquidity based on `liquidity_token_amount`. <br>
+/// This is the inverse of [`provide_liquidity`].
+///
+/// Liquidity tokens are synonymous to weighted shares of the contract's total liquidity. <br>
+/// As such, we calculate how much to output of token A and B,
+/// based on the ratio between the input liquidity token amount and the total amount of liquidity minted by the contract.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`] - The context for the action call.
+///
+/// * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
+///
+/// * `liquidity_token_amount`: [`TokenAmount`] - The amount of liquidity tokens to burn.
+///
+/// ### Returns
+///
+/// The updated state object of type [`LiquiditySwapContractState`].
+#[action(shortname = 0x05)]
+pub fn reclaim_liquidity(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    liquidity_token_amount: TokenAmount,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    assert!(
+        state.emergency_mode || state.virtual_state.any_locked_liquidity(),
+        "Cannot reclaim liquidity while locks are present."
+    );
+
+    let user = &context.sender;
+
+    state.settle_fees(user);
+    state
+        .token_balances
+        .deduct_from_token_balance(*user, Token::LIQUIDITY, liquidity_token_amount);
+
+    let contract_token_balance = state
+        .token_balances
+        .get_balance_for(&state.liquidity_pool_address);
+
+    let (a_output, b_output) = calculate_reclaim_output(
+        liquidity_token_amount,
+        contract_token_balance.a_tokens,
+        contract_token_balance.b_tokens,
+        contract_token_balance.liquidity_tokens,
+    );
+
+    state
+        .token_balances
+        .move_tokens(state.liquidity_pool_address, *user, Token::A, a_output);
+    state
+        .token_balances
+        .move_tokens(state.liquidity_pool_address, *user, Token::B, b_output);
+    state.token_balances.deduct_from_token_balance(
+        state.liquidity_pool_address,
+        Token::LIQUIDITY,
+        liquidity_token_amount,
+    );
+
+    if !state.emergency_mode {
+        return (state, vec![]);
+    }
+    // Make emergency reclaims stand out to any callbacks and observers.
+    let mut event_group_builder = EventGroup::builder();
+    event_group_builder.return_data(EmergencyReclaim {
+        a_output,
+        b_output,
+        locks_len: state.virtual_state.locks_len() as u32,
+    });
+    (state, vec![event_group_builder.build()])
+}
+
+/// The outcome of a [`reclaim_liquidity`] in emergency mode, returned to any callbacks.
+#[derive(ReadWriteRPC, CreateTypeSpec, Debug, PartialEq, Eq)]
+pub struct EmergencyReclaim {
+    /// The amount of token A reclaimed.
+    pub a_output: TokenAmount,
+    /// The amount of token B reclaimed.
+    pub b_output: TokenAmount,
+    /// The number of locks present, whose owners may be shortchanged by the reclaim.
+    pub locks_len: u32,
+}
+
+/// Initialize token liquidity pools, and mint initial liquidity tokens.
+///
+/// Calling this action makes the calling user the first liquidity provider, receiving liquidity
+/// tokens amounting to 100% of the contract's total liquidity, until another user becomes an
+/// liquidity provider. Except [`LiquiditySwapContractState::minimum_initial_liquidity`] of the
+/// minted liquidity tokens, which are held by no one, and can never be reclaimed.
+///
+/// Returns whether the initial liquidity is imbalanced to any callbacks, as determined by
+/// [`is_initial_liquidity_imbalanced`]. This is advisory only, and does not prevent the initialization.
+///
+/// ### Parameters:
+///
+///  * `context`: [`ContractContext`] - The contract context containing sender and chain information.
+///
+///  * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
+///
+///  * `token_a_amount`: [`TokenAmount`] - The amount to initialize pool A with.
+///
+///  * `token_b_amount`: [`TokenAmount`] - The amount to initialize pool B with.
+///
+/// # Returns
+///
+/// The updated state object of type [`LiquiditySwapContractState`].
+#[action(shortname = 0x06)]
+pub fn provide_initial_liquidity(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    token_a_amount: TokenAmount,
+    token_b_amount: TokenAmount,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    let is_imbalanced = provide_initial_liquidity_internal(
+        &mut state,
+        &context.sender,
+        token_a_amount,
+        token_b_amount,
+    );
+
+    let mut return_event = EventGroup::builder();
+    return_event.return_data(is_imbalanced);
+
+    (state, vec![return_event.build()])
+}
+
+/// Initializes the pools with liquidity provided by `user`, as described by [`provide_initial_liquidity`].
+///
+/// Returns whether the initial liquidity is imbalanced.
+fn provide_initial_liquidity_internal(
+    state: &mut LiquiditySwapContractState,
+    user: &Address,
+    token_a_amount: TokenAmount,
+    token_b_amount: TokenAmount,
+) -> bool {
+    assert!(
+        !state.contract_pools_have_liquidity(),
+        "Can only initialize when both pools are empty"
+    );
+    assert_eq!(
+        state
+            .token_balances
+            .get_balance_for(&state.liquidity_pool_address)
+            .liquidity_tokens,
+        0,
+        "Outstanding liquidity tokens must be reset before initializing"
+    );
+    assert!(
+        token_a_amount > 0 && token_b_amount > 0,
+        "Both initial amounts must be positive"
+    );
+
+    let minted_liquidity_tokens = initial_liquidity_tokens(token_a_amount, token_b_amount);
+    assert!(
+        minted_liquidity_tokens > 0,
+        "The given input amount yielded 0 minted liquidity"
+    );
+    assert!(
+        minted_liquidity_tokens > state.minimum_initial_liquidity,
+        "Initial liquidity must mint more than {} liquidity tokens, but minted {}",
+        state.minimum_initial_liquidity,
+        minted_liquidity_tokens
+    );
+    state.assert_within_cap(Token::A, token_a_amount);
+    state.assert_within_cap(Token::B, token_b_amount);
+
+    provide_liquidity_internal(
+        state,
+        user,
+        TokensInOut::A_IN_B_OUT,
+        token_a_amount,
+        token_b_amount,
+        minted_liquidity_tokens,
+    );
+    // Locks the minimum in the pools, by counting it in the total liquidity without crediting it.
+    state.token_balances.deduct_from_token_balance(
+        *user,
+        Token::LIQUIDITY,
+        state.minimum_initial_liquidity,
+    );
+    state.locked_liquidity = state.minimum_initial_liquidity;
+    state.has_been_initialized = true;
+
+    is_initial_liquidity_imbalanced(token_a_amount, token_b_amount)
+}
+
+/// The ratio between the initial pools, from which the initial liquidity is considered imbalanced.
+const IMBALANCED_INITIAL_LIQUIDITY_RATIO: TokenAmount = 1000;
+
+/// Determines whether one of the initial pools is at least [`IMBALANCED_INITIAL_LIQUIDITY_RATIO`]
+/// times larger than the other. <br>
+/// The contract cannot know the market price of the tokens, but such an extreme ratio is likely
+/// to be far from it, causing the pools to be arbitraged.
+fn is_initial_liquidity_imbalanced(
+    token_a_amount: TokenAmount,
+    token_b_amount: TokenAmount,
+) -> bool {
+    let smallest = token_a_amount.min(token_b_amount);
+    let largest = token_a_amount.max(token_b_amount);
+    largest / IMBALANCED_INITIAL_LIQUIDITY_RATIO >= smallest
+}
+
+/// Saves a lock on the current state of the liquidity pools for Token A and B,
+/// implicitly updating the virtual pools.
+///
+/// A lock acts as a privilege for swapping `amount_in` of `token_in`, and receiving at least
+/// `amount_out_minimum` of the token being swapped to, at a later point in time,
+/// at the minimum exchange rate given by the actual and virtual liquidity pool states,
+/// at the acquisition time of the lock.
+/// The id, and output amount of the lock is returned to any callbacks.
+/// Other users can still interact with the swap contract while the lock exists.
+///
+/// # Fails
+///
+/// Fails if `amount_out_minimum` is greater than what the current contract state will provide.
+/// Fails if the sender (caller) does not have permission to acquire locks.
+#[action(shortname = 0x07)]
+pub fn acquire_swap_lock(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    token_in: Address,
+    amount_in: TokenAmount,
+    amount_out_minimum: TokenAmount,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    let lock_info = acquire_swap_lock_internal(
+        &mut state,
+        context.sender,
+        context.block_production_time,
+        token_in,
+        amount_in,
+        amount_out_minimum,
+        false,
+    );
+
+    // Pass the lock id to any callbacks.
+    let mut event_group_builder = EventGroup::builder();
+    event_group_builder.return_data(lock_info);
+
+    (state, vec![event_group_builder.build()])
+}
+
+/// Acquires a lock like [`acquire_swap_lock`], which is cancelled instead of failing if it is
+/// executed while the sender (caller) does not hold its input amount. <br>
+/// Intended for composed flows, where the input tokens are deposited right before the execution,
+/// such that a failed deposit releases the locked liquidity rather than leaving the lock dangling.
+/// An execution which cancels the lock returns an output amount of 0.
+/// The id, and output amount of the lock is returned to any callbacks.
+///
+/// # Fails
+///
+/// Fails if `amount_out_minimum` is greater than what the current contract state will provide.
+/// Fails if the sender (caller) does not have permission to acquire locks.
+#[action(shortname = 0x27)]
+pub fn acquire_swap_lock_auto_cancel(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    token_in: Address,
+    amount_in: TokenAmount,
+    amount_out_minimum: TokenAmount,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    let lock_info = acquire_swap_lock_internal(
+        &mut state,
+        context.sender,
+        context.block_production_time,
+        token_in,
+        amount_in,
+        amount_out_minimum,
+        true,
+    );
+
+    let mut event_group_builder = EventGroup::builder();
+    event_group_builder.return_data(lock_info);
+
+    (state, vec![event_group_builder.build()])
+}
+
+/// Acquires a lock for `sender` at `now`, as described by [`acquire_swap_lock`].
+/// If `cancel_on_failed_execution` is true, the lock is cancelled on a failed execution, as
+/// described by [`acquire_swap_lock_auto_cancel`].
+///
+/// Returns the id and output amount of the lock.
+fn acquire_swap_lock_internal(
+    state: &mut LiquiditySwapContractState,
+    sender: Address,
+    now: i64,
+    token_in: Address,
+    amount_in: TokenAmount,
+    amount_out_minimum: TokenAmount,
+    cancel_on_failed_execution: bool,
+) -> AcquiredLiquidityLockInformation {
+    state
+        .permission_lock_swap
+        .assert_permission_for(&sender, "lock swap");
+    assert!(
+        state.contract_pools_have_liquidity(),
+        "Pools must have existing liquidity to acquire a lock"
+    );
+
+    // Acquire a lock internally.
+    let expires_utc_millis = state.lock_expiry(now);
+    let (lock_id, amount_out) = lock_internal(
+        state,
+        amount_in,
+        token_in,
+        amount_out_minimum,
+        sender,
+        None,
+        expires_utc_millis,
+    );
+    if cancel_on_failed_execution {
+        state.virtual_state.mark_cancel_on_failed_execution(lock_id);
+    }
+
+    AcquiredLiquidityLockInformation {
+        lock_id,
+        amount_out,
+    }
+}
+
+/// Initializes the pools like [`provide_initial_liquidity`], and then acquires a lock on the new
+/// pools like [`acquire_swap_lock`], in a single transaction. <br>
+/// Allows market makers bootstrapping a pool to start quoting immediately.
+/// The id, and output amount of the lock is returned to any callbacks.
+///
+/// # Fails
+///
+/// Fails, without initializing the pools, if either step fails. In particular if the sender
+/// (caller) does not have permission to acquire locks.
+#[action(shortname = 0x26)]
+pub fn provide_initial_liquidity_and_lock(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    token_a_amount: TokenAmount,
+    token_b_amount: TokenAmount,
+    token_in: Address,
+    amount_in: TokenAmount,
+    amount_out_minimum: TokenAmount,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    provide_initial_liquidity_internal(&mut state, &context.sender, token_a_amount, token_b_amount);
+    let lock_info = acquire_swap_lock_internal(
+        &mut state,
+        context.sender,
+        context.block_production_time,
+        token_in,
+        amount_in,
+        amount_out_minimum,
+        false,
+    );
+
+    let mut event_group_builder = EventGroup::builder();
+    event_group_builder.return_data(lock_info);
+
+    (state, vec![event_group_builder.build()])
+}
+
+/// Calculates the received amount of the outgoing swap token, if swapping `amount_in` of `token_in`,
+/// and updates the virtual state with a lock.
+///
+/// Fails if the calculated receiving amount is less than `amount_out_minimum`.
+/// The `owner` becomes the address associated with the lock, who has sole permission to execute it.
+/// If `scheduled_execution_utc_millis` is present, the lock can additionally be executed by
+/// [`execute_scheduled_lock`] from that time.
+/// If `expires_utc_millis` is present, the lock can be swept by [`sweep_expired_locks`] from that time.
+fn lock_internal(
+    state: &mut LiquiditySwapContractState,
+    amount_in: TokenAmount,
+    token_in: Address,
+    amount_out_minimum: TokenAmount,
+    owner: Address,
+    scheduled_execution_utc_millis: Option<i64>,
+    expires_utc_millis: Option<i64>,
+) -> (LiquidityLockId, TokenAmount) {
+    state.assert_circuit_breaker_not_tripped();
+    let tokens = state.token_balances.deduce_tokens_in_out(token_in);
+    let swap_fee_per_mille = state.swap_fee_for(&owner);
+
+    let amount_out =
+        calculate_minimum_swap_to_amount(state, amount_in, &tokens, swap_fee_per_mille);
+
+    if amount_out < amount_out_minimum {
+        panic!(
+            "Swap would produce {} output tokens, but minimum was set to {}.",
+            amount_out, amount_out_minimum
+        );
+    }
+
+    let tokens_in_out = state.token_balances.deduce_tokens_in_out(token_in);
+
+    let lock = LiquidityLock {
+        amount_in,
+        amount_out,
+        tokens_in_out,
+        owner,
+        swap_fee_per_mille,
+        scheduled_execution_utc_millis,
+        expires_utc_millis,
+        cancel_on_failed_execution: false,
+    };
+    (
+        state.virtual_state.add_lock(lock, state.max_total_locks),
+        amount_out,
+    )
+}
+
+/// Acquires a lock like [`acquire_swap_lock`], which is automatically executed once `delay_millis`
+/// has passed, unless cancelled or executed before then.
+///
+/// Once the delay has passed, anybody, typically a keeper, can execute the lock by invoking
+/// [`execute_scheduled_lock`]. The owner can still execute or cancel the lock at any time using
+/// [`execute_lock_swap`] and [`cancel_lock`], after which the scheduled execution does nothing.
+/// The id, and output amount of the lock is returned to any callbacks.
+///
+/// # Fails
+///
+/// Fails if `delay_millis` is negative, or too large to represent the scheduled execution time.
+/// Fails if `amount_out_minimum` is greater than what the current contract state will provide.
+/// Fails if the sender (caller) does not have permission to acquire locks.
+#[action(shortname = 0x0B)]
+pub fn acquire_and_schedule_lock(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    token_in: Address,
+    amount_in: TokenAmount,
+    amount_out_minimum: TokenAmount,
+    delay_millis: i64,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    state
+        .permission_lock_swap
+        .assert_permission_for(&context.sender, "lock swap");
+    assert!(
+        state.contract_pools_have_liquidity(),
+        "Pools must have existing liquidity to acquire a lock"
+    );
+    assert!(delay_millis >= 0, "Delay must not be negative");
+
+    let scheduled_execution_utc_millis = context
+        .block_production_time
+        .checked_add(delay_millis)
+        .expect("Scheduled execution time is out of range");
+    let expires_utc_millis = state.lock_expiry(scheduled_execution_utc_millis);
+    let (lock_id, amount_out) = lock_internal(
+        &mut state,
+        amount_in,
+        token_in,
+        amount_out_minimum,
+        context.sender,
+        Some(scheduled_execution_utc_millis),
+        expires_utc_millis,
+    );
+
+    let mut event_group_builder = EventGroup::builder();
+    event_group_builder.return_data(AcquiredLiquidityLockInformation {
+        lock_id,
+        amount_out,
+    });
+
+    (state, vec![event_group_builder.build()])
+}
+
+/// Executes a lock acquired by [`acquire_and_schedule_lock`] on behalf of its owner, once the
+/// scheduled execution time has been reached. <br>
+/// Anybody can invoke this, allowing keepers to execute scheduled locks on time.
+///
+/// If the lock has already been cancelled or executed, this does nothing.
+///
+/// # Fails
+///
+/// Fails if the lock was not acquired with a scheduled execution.
+/// Fails if the scheduled execution time has not been reached.
+#[action(shortname = 0x0C)]
+pub fn execute_scheduled_lock(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    lock_id: LiquidityLockId,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    let execution =
+        execute_scheduled_lock_internal(&mut state, lock_id, context.block_production_time);
+    assert_ne!(
+        execution,
+        ScheduledExecution::Pending,
+        "{:?} is not due for execution yet.",
+        lock_id
+    );
+
+    (state, vec![])
+}
+
+/// Executes the scheduled lock associated with `lock_id`, if it still exists and `now` is at or
+/// after its scheduled execution time.
+fn execute_scheduled_lock_internal(
+    state: &mut LiquiditySwapContractState,
+    lock_id: LiquidityLockId,
+    now: i64,
+) -> ScheduledExecution {
+    let Some(lock) = state.virtual_state.get_lock(&lock_id) else {
+        return ScheduledExecution::Gone;
+    };
+    let scheduled_time = lock
+        .scheduled_execution_utc_millis
+        .unwrap_or_else(|| panic!("{:?} is not scheduled for execution.", lock_id));
+
+    if is_before(now, scheduled_time) {
+        ScheduledExecution::Pending
+    } else {
+        ScheduledExecution::Executed(execute_lock_swap_internal(state, lock_id, lock.owner))
+    }
+}
+
+/// Executes a previously acquired lock, performing the intended swap and
+/// updating the actual balances of the contract.
+///
+/// Returns the amount received from the swap to any registered callbacks.
+///
+/// # Fails
+///
+/// If an unknown `lock_id` is provided this fails.
+/// Also fails if a user who didn't acquire the lock associated with `lock_id` tries to execute it.
+#[action(shortname = 0x08)]
+pub fn execute_lock_swap(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    lock_id: LiquidityLockId,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    let output_amount = execute_lock_swap_internal(&mut state, lock_id, context.sender);
+
+    let mut return_event = EventGroup::builder();
+    return_event.return_data(output_amount);
+
+    (state, vec![return_event.build()])
+}
+
+/// Removes the lock associated with `lock_id` from the internal state and executes the corresponding swap,
+/// exchanging tokens on the actual liquidity pools.
+///
+/// Returns the output amount of the lock.
+///
+/// Does nothing if the lock was not acquired by `sender`.
+fn execute_lock_swap_internal(
+    state: &mut LiquiditySwapContractState,
+    lock_id: LiquidityLockId,
+    sender: Address,
+) -> TokenAmount {
+    let lock = state.virtual_state.remove_lock(lock_id, sender);
+    swap_removed_lock(state, lock)
+}
+
+/// Executes part of a previously acquired lock, swapping `amount_in_portion` of its input tokens
+/// at the rate of the lock. The remainder of the lock stays locked, and can be executed or
+/// cancelled later.
+///
+/// Returns the amount received from the swap to any registered callbacks.
+///
+/// # Fails
+///
+/// If an unknown `lock_id` is provided this fails.
+/// Also fails if a user who didn't acquire the lock associated with `lock_id` tries to execute it,
+/// or if `amount_in_portion` is zero or exceeds the input amount of the lock.
+#[action(shortname = 0x24)]
+pub fn execute_lock_swap_partial(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    lock_id: LiquidityLockId,
+    amount_in_portion: TokenAmount,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    let portion = state
+        .virtual_state
+        .split_lock(lock_id, context.sender, amount_in_portion);
+    let output_amount = swap_removed_lock(&mut state, portion);
+
+    let mut return_event = EventGroup::builder();
+    return_event.return_data(output_amount);
+
+    (state, vec![return_event.build()])
+}
+
+/// Executes the swap of `lock`, which has already been removed from the virtual state,
+/// exchanging tokens on the actual liquidity pools.
+///
+/// Returns the output amount of the lock. <br>
+/// If the lock is cancelled on a failed execution, and the owner does not hold the input amount,
+/// no swap happens and 0 is returned, leaving the lock cancelled.
+fn swap_removed_lock(state: &mut LiquiditySwapContractState, lock: LiquidityLock) -> TokenAmount {
+    let owner_input = state
+        .token_balances
+        .get_balance_for(&lock.owner)
+        .get_amount_of(lock.tokens_in_out.token_in);
+    if lock.cancel_on_failed_execution && owner_input < lock.amount_in {
+        return 0;
+    }
+
+    state.token_balances.move_tokens(
+        lock.owner,
+        state.liquidity_pool_address,
+        lock.tokens_in_out.token_in,
+        lock.amount_in,
+    );
+    state.token_balances.move_tokens(
+        state.liquidity_pool_address,
+        lock.owner,
+        lock.tokens_in_out.token_out,
+        lock.amount_out,
+    );
+    accrue_fees(
+        state,
+        lock.tokens_in_out.token_in,
+        lock.amount_in,
+        lock.swap_fee_per_mille,
+    );
+    state.update_circuit_breaker();
+
+    lock.amount_out
+}
+
+/// Moves the protocol's share of the swap fee for swapping `amount_in` of `token_in` with a fee of
+/// `swap_fee_per_mille` out of the pools, and into the accrued protocol fees. Likewise moves the
+/// burned share into the accrued burn fees, if `token_in` is burnable. The remaining swap fee is
+/// retained by the pools, and recorded in the fee growth per liquidity token.
+///
+/// As the protocol and burn fees are shares of the swap fee, the constant product of the pools
+/// never decreases.
+fn accrue_fees(
+    state: &mut LiquiditySwapContractState,
+    token_in: Token,
+    amount_in: TokenAmount,
+    swap_fee_per_mille: u16,
+) {
+    let swap_fee = swap_fee_amount(amount_in, swap_fee_per_mille);
+    let protocol_fee = swap_fee * state.protocol_fee_share_per_mille as TokenAmount / 1000;
+    let burn_fee = if state.is_burnable(token_in) {
+        swap_fee * state.fee_burn_share_per_mille as TokenAmount / 1000
+    } else {
+        0
+    };
+
+    let total_liquidity = state
+        .token_balances
+        .get_balance_for(&state.liquidity_pool_address)
+        .liquidity_tokens;
+    if total_liquidity != 0 {
+        // The growth is only used for analytics, so it saturates rather than failing the swap.
+        let growth = state.fee_growth_per_liquidity.get_mut_amount_of(token_in);
+        *growth = growth.saturating_add(fee_growth_for(
+            swap_fee - protocol_fee - burn_fee,
+            total_liquidity,
+        ));
+    }
+
+    if protocol_fee + burn_fee == 0 {
+        return;
+    }
+
+    state.token_balances.deduct_from_token_balance(
+        state.liquidity_pool_address,
+        token_in,
+        protocol_fee + burn_fee,
+    );
+    *state.accrued_protocol_fees.get_mut_amount_of(token_in) += protocol_fee;
+    *state.accrued_burn_fees.get_mut_amount_of(token_in) += burn_fee;
+}
+
+/// Asserts that the protocol fee and burn shares together take at most the entire swap fee.
+fn assert_fee_shares_within_swap_fee(
+    protocol_fee_share_per_mille: u16,
+    fee_burn_share_per_mille: u16,
+) {
+    assert!(
+        protocol_fee_share_per_mille + fee_burn_share_per_mille <= 1000,
+        "Protocol fee and burn shares must not exceed 1000 per mille combined, but was {} + {}",
+        protocol_fee_share_per_mille,
+        fee_burn_share_per_mille
+    );
+}
+
+/// Calculates the growth in fees per liquidity token, scaled by [`FEE_GROWTH_SCALE`], when
+/// `fee` is shared by `total_liquidity` liquidity tokens. Rounded down, and saturating at
+/// [`TokenAmount::MAX`].
+fn fee_growth_for(fee: TokenAmount, total_liquidity: TokenAmount) -> TokenAmount {
+    u128_mul_div(fee, FEE_GROWTH_SCALE, total_liquidity).unwrap_or(TokenAmount::MAX)
+}
+
+/// Calculates the fees attributed to `liquidity_tokens` liquidity tokens, when the fee growth per
+/// liquidity token, scaled by [`FEE_GROWTH_SCALE`], is `growth`. Rounded down, and saturating at
+/// [`TokenAmount::MAX`].
+fn fees_for_growth(growth: TokenAmount, liquidity_tokens: TokenAmount) -> TokenAmount {
+    u128_mul_div(growth, liquidity_tokens, FEE_GROWTH_SCALE).unwrap_or(TokenAmount::MAX)
+}
+
+/// Sets the recipient of protocol fees, and the share of each swap fee taken as protocol fees. <br>
+/// Fees accrued before the change can be withdrawn by the new recipient.
+///
+/// # Fails
+///
+/// Fails if the sender (caller) does not have permission to administrate the contract.
+/// Fails if `protocol_fee_share_per_mille` is not in [`ALLOWED_FEE_PER_MILLE`].
+/// Fails if the protocol fee and burn shares exceed 1000 per mille combined.
+#[action(shortname = 0x0F)]
+pub fn set_protocol_fee(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    fee_recipient: Option<Address>,
+    protocol_fee_share_per_mille: u16,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    state
+        .permission_admin
+        .assert_permission_for(&context.sender, "admin");
+    let protocol_fee_share_per_mille = validate_fee_per_mille(protocol_fee_share_per_mille)
+        .unwrap_or_else(|msg| panic!("Invalid protocol fee share: {}", msg));
+    assert_fee_shares_within_swap_fee(protocol_fee_share_per_mille, state.fee_burn_share_per_mille);
+
+    state.fee_recipient = fee_recipient;
+    state.protocol_fee_share_per_mille = protocol_fee_share_per_mille;
+
+    (state, vec![])
+}
+
+/// Transfers all accrued protocol fees to the fee recipient, which must be the sender. <br>
+/// Nothing is transferred if no fees have accrued.
+///
+/// # Fails
+///
+/// Fails if the sender (caller) is not the fee recipient.
+#[action(shortname = 0x12)]
+pub fn withdraw_protocol_fees(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    assert_eq!(
+        state.fee_recipient,
+        Some(context.sender),
+        "Only the fee recipient can withdraw protocol fees"
+    );
+
+    let fees = take_protocol_fees(&mut state);
+    if fees.user_has_no_tokens() {
+        return (state, vec![]);
+    }
+
+    let mut event_group_builder = EventGroup::builder();
+    for (token_address, amount) in [
+        (state.token_balances.token_a_address, fees.a_tokens),
+        (state.token_balances.token_b_address, fees.b_tokens),
+    ] {
+        if amount != 0 {
+            interact_mpc20::MPC20Contract::at_address(token_address).transfer(
+                &mut event_group_builder,
+                &context.sender,
+                amount,
+            );
+        }
+    }
+
+    (state, vec![event_group_builder.build()])
+}
+
+/// Resets the accrued protocol fees, returning the fees accrued until now.
+fn take_protocol_fees(state: &mut LiquiditySwapContractState) -> TokenBalance {
+    std::mem::replace(&mut state.accrued_protocol_fees, EMPTY_BALANCE)
+}
+
+/// Sets the share of each swap fee which is burned rather than retained by the pools, and which
+/// tokens support being burned. <br>
+/// Swap fees of tokens which don't support burning are retained by the pools in full. Fees
+/// accrued for burning are burned by [`burn_accrued_fees`]. Fees accrued for a token which no
+/// longer supports burning are returned to the pools.
+///
+/// # Fails
+///
+/// Fails if the sender (caller) does not have permission to administrate the contract.
+/// Fails if `fee_burn_share_per_mille` is not in [`ALLOWED_FEE_PER_MILLE`].
+/// Fails if the protocol fee and burn shares exceed 1000 per mille combined.
+#[action(shortname = 0x2B)]
+pub fn set_fee_burn(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    fee_burn_share_per_mille: u16,
+    token_a_burnable: bool,
+    token_b_burnable: bool,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    state
+        .permission_admin
+        .assert_permission_for(&context.sender, "admin");
+    let fee_burn_share_per_mille = validate_fee_per_mille(fee_burn_share_per_mille)
+        .unwrap_or_else(|msg| panic!("Invalid fee burn share: {}", msg));
+    assert_fee_shares_within_swap_fee(state.protocol_fee_share_per_mille, fee_burn_share_per_mille);
+
+    state.fee_burn_share_per_mille = fee_burn_share_per_mille;
+    state.token_a_burnable = token_a_burnable;
+    state.token_b_burnable = token_b_burnable;
+    for token in [Token::A, Token::B] {
+        if !state.is_burnable(token) {
+            let amount = std::mem::take(state.accrued_burn_fees.get_mut_amount_of(token));
+            return_burn_fees(&mut state, token, amount);
+        }
+    }
+
+    (state, vec![])
+}
+
+/// Returns `amount` of `token`, accrued for burning, to the pools.
+fn return_burn_fees(state: &mut LiquiditySwapContractState, token: Token, amount: TokenAmount) {
+    if amount != 0 {
+        state
+            .token_balances
+            .add_to_token_balance(state.liquidity_pool_address, token, amount);
+    }
+}
+
+/// Burns the swap fees accrued for burning, by invoking burn on the token contracts. Can be
+/// invoked by anyone. <br>
+/// Each token is burned in a separate event group, and its accrued fees are restored by
+/// [`burn_accrued_fees_callback`] if the burn fails. Tokens which don't support burning are
+/// skipped. Nothing happens if no fees have accrued.
+#[action(shortname = 0x2C)]
+pub fn burn_accrued_fees(
+    _context: ContractContext,
+    mut state: LiquiditySwapContractState,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    let mut event_groups = vec![];
+    for (token, token_address) in [
+        (Token::A, state.token_balances.token_a_address),
+        (Token::B, state.token_balances.token_b_address),
+    ] {
+        if !state.is_burnable(token) {
+            continue;
+        }
+        let amount = std::mem::take(state.accrued_burn_fees.get_mut_amount_of(token));
+        if amount == 0 {
+            continue;
+        }
+
+        let mut event_group_builder = EventGroup::builder();
+        interact_mpc20::MPC20Contract::at_address(token_address)
+            .burn(&mut event_group_builder, amount);
+        event_group_builder
+            .with_callback(SHORTNAME_BURN_ACCRUED_FEES_CALLBACK)
+            .argument(token)
+            .argument(amount)
+            .done();
+        event_groups.push(event_group_builder.build());
+    }
+
+    (state, event_groups)
+}
+
+/// Handles callback from [`burn_accrued_fees`]. <br>
+/// If the burn failed, `amount` of `token` is accrued for burning again, or returned to the pools
+/// if `token` no longer supports burning.
+#[callback(shortname = 0x32)]
+fn burn_accrued_fees_callback(
+    _context: ContractContext,
+    callback_context: CallbackContext,
+    mut state: LiquiditySwapContractState,
+    token: Token,
+    amount: TokenAmount,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    finalize_burn_accrued_fees(&mut state, callback_context.success, token, amount);
+    (state, vec![])
+}
+
+/// Finalizes a burn of accrued fees, restoring the fees if the burn did not succeed.
+fn finalize_burn_accrued_fees(
+    state: &mut LiquiditySwapContractState,
+    burn_succeeded: bool,
+    token: Token,
+    amount: TokenAmount,
+) {
+    if burn_succeeded {
+        return;
+    }
+    if state.is_burnable(token) {
+        *state.accrued_burn_fees.get_mut_amount_of(token) += amount;
+    } else {
+        return_burn_fees(state, token, amount);
+    }
+}
+
+/// Sets how long acquired locks last before they can be swept by [`sweep_expired_locks`], and the
+/// bounty paid for each swept lock. <br>
+/// Only affects locks acquired after the change. Scheduled locks expire `lock_lifetime_millis`
+/// after their scheduled execution time.
 ///
-/// # Returns
-/// The updated state object of type [`LiquiditySwapContractState`] yielding the result of the swap.
-#[action(shortname = 0x02)]
-pub fn instant_swap(
+/// # Fails
+///
+/// Fails if the sender (caller) does not have permission to administrate the contract.
+/// Fails if `lock_lifetime_millis` is negative.
+#[action(shortname = 0x14)]
+pub fn set_lock_expiry(
     context: ContractContext,
     mut state: LiquiditySwapContractState,
-    token_in: Address,
-    amount_in: TokenAmount,
-    amount_out_minimum: TokenAmount,
+    lock_lifetime_millis: Option<i64>,
+    keeper_bounty_per_lock: TokenAmount,
 ) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    state
+        .permission_admin
+        .assert_permission_for(&context.sender, "admin");
     assert!(
-        state.contract_pools_have_liquidity(),
-        "Pools must have existing liquidity to perform a swap"
+        lock_lifetime_millis.map_or(true, |lifetime| lifetime >= 0),
+        "Lock lifetime must not be negative"
     );
 
-    // Instant swaps can be represented by acquiring a lock, and executing it straight away.
-    let (lock_id, _) = lock_internal(
-        &mut state,
-        amount_in,
-        token_in,
-        amount_out_minimum,
-        context.sender,
-    );
-    execute_lock_swap_internal(&mut state, lock_id, context.sender);
+    state.lock_lifetime_millis = lock_lifetime_millis;
+    state.keeper_bounty_per_lock = keeper_bounty_per_lock;
 
     (state, vec![])
 }
 
-/// Withdraw <em>amount</em> of token {A, B} from the contract for the calling user.
-/// This fails if `amount` is larger than the token balance of the corresponding token.
-///
-/// It preemptively updates the state of the user's balance before making the transfer.
-/// This means that if the transfer fails, the contract could end up with more money than it has registered, which is acceptable.
-/// This is to incentivize the user to spend enough gas to complete the transfer.
-/// If `wait_for_callback` is true, any callbacks will happen only after the withdrawal has completed.
-///
-/// ### Parameters:
-///
-///  * `context`: [`ContractContext`] - The contract context containing sender and chain information.
+/// Cancels the expired locks among at most `max_locks` locks, as if the swaps didn't happen. <br>
+/// For each swept lock, the sender is paid [`LiquiditySwapContractState::keeper_bounty_per_lock`]
+/// of the input token of the lock from the keeper bounty fund of the pool, see
+/// [`fund_keeper_bounty`], as an incentive to keep the pools free of stale locks. Acquiring a lock
+/// does not reserve any balance, so the bounty is never paid from the reserves of the pools, and
+/// is capped at what remains in the fund.
 ///
-///  * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
+/// The locks are scanned in order of id, starting from `start`, so the gas cost is bounded by
+/// `max_locks`. Every lock is scanned over several invocations, by passing the returned cursor
+/// as the `start` of the next invocation.
 ///
-///  * `token_address`: [`Address`] - The address of the token contract to withdraw to.
+/// The number of swept locks and the cursor are returned to any callbacks as a [`LockSweep`].
+#[action(shortname = 0x17)]
+pub fn sweep_expired_locks(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    start: Option<LiquidityLockId>,
+    max_locks: u32,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    let sweep = sweep_expired_locks_internal(
+        &mut state,
+        context.sender,
+        context.block_production_time,
+        start,
+        max_locks as usize,
+    );
+
+    let mut return_event = EventGroup::builder();
+    return_event.return_data(sweep);
+
+    (state, vec![return_event.build()])
+}
+
+/// The result of a single [`sweep_expired_locks`] invocation.
+#[derive(ReadWriteRPC, CreateTypeSpec, Debug, PartialEq, Eq)]
+pub struct LockSweep {
+    /// The number of swept locks.
+    pub swept: u32,
+    /// The id to continue sweeping from, or [`None`] if there are no more locks.
+    pub next_start: Option<LiquidityLockId>,
+}
+
+/// Removes the locks expired at `now` among at most `max_locks` locks starting from `start`,
+/// paying `keeper` the bounty for each from the keeper bounty fund.
+fn sweep_expired_locks_internal(
+    state: &mut LiquiditySwapContractState,
+    keeper: Address,
+    now: i64,
+    start: Option<LiquidityLockId>,
+    max_locks: usize,
+) -> LockSweep {
+    let (expired_lock_ids, next_start) =
+        state.virtual_state.expired_lock_ids(now, start, max_locks);
+    for lock_id in &expired_lock_ids {
+        let lock = state.virtual_state.get_lock(lock_id).unwrap();
+        let lock = state.virtual_state.take_lock(*lock_id, lock);
+
+        let token_in = lock.tokens_in_out.token_in;
+        let fund = state.keeper_bounty_fund.get_mut_amount_of(token_in);
+        let bounty = state.keeper_bounty_per_lock.min(*fund);
+        if bounty != 0 {
+            *fund -= bounty;
+            state
+                .token_balances
+                .add_to_token_balance(keeper, token_in, bounty);
+        }
+    }
+    LockSweep {
+        swept: expired_lock_ids.len() as u32,
+        next_start,
+    }
+}
+
+/// Moves `amount` of the token at `token_address` from the balance of the sender into the keeper
+/// bounty fund, from which [`sweep_expired_locks`] pays keepers. <br>
+/// Funded tokens cannot be withdrawn again, other than by sweeping locks.
 ///
-///  * `amount`: [`TokenAmount`] - The amount to withdraw.
+/// # Fails
 ///
-/// # Returns
-/// The unchanged state object of type [`LiquiditySwapContractState`].
-#[action(shortname = 0x03)]
-pub fn withdraw(
+/// Fails if `token_address` is neither token A nor B.
+/// Fails if the sender does not hold `amount` of the token.
+#[action(shortname = 0x33)]
+pub fn fund_keeper_bounty(
     context: ContractContext,
     mut state: LiquiditySwapContractState,
     token_address: Address,
     amount: TokenAmount,
-    wait_for_callback: bool,
 ) -> (LiquiditySwapContractState, Vec<EventGroup>) {
-    let tokens = state.token_balances.deduce_tokens_in_out(token_address);
-
+    let token = state
+        .token_balances
+        .deduce_tokens_in_out(token_address)
+        .token_in;
     state
         .token_balances
-        .deduct_from_token_balance(context.sender, tokens.token_in, amount);
-
-    let mut event_group_builder = EventGroup::builder();
-    interact_mpc20::MPC20Contract::at_address(token_address).transfer(
-        &mut event_group_builder,
-        &context.sender,
-        amount,
-    );
-
-    if wait_for_callback {
-        event_group_builder
-            .with_callback(SHORTNAME_WAIT_WITHDRAW_CALLBACK)
-            .done();
-    }
+        .deduct_from_token_balance(context.sender, token, amount);
+    *state.keeper_bounty_fund.get_mut_amount_of(token) += amount;
 
-    (state, vec![event_group_builder.build()])
+    (state, vec![])
 }
 
-#[callback(shortname = 0x15)]
-fn wait_withdraw_callback(
-    _context: ContractContext,
-    _callback_context: CallbackContext,
-    state: LiquiditySwapContractState,
+/// Cancels a previously acquired lock, updating the virtual balances of the contract,
+/// as if the swap didn't happen.
+///
+/// If an unknown `lockID` is provided this fails.
+/// Also fails if a user who didn't acquire the lock associated with `lockID` tries to cancel the lock.
+#[action(shortname = 0x09)]
+pub fn cancel_lock(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    lock_id: LiquidityLockId,
 ) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    state.virtual_state.remove_lock(lock_id, context.sender);
+
     (state, vec![])
 }
 
-/// Become a liquidity provider to the contract by providing `amount` of tokens from the caller's balance. <br>
-/// An equivalent amount of the output token is required to succeed and will be token_in implicitly. <br>
-/// This is the inverse of [`reclaim_liquidity`].
-///
-/// ### Parameters:
-///
-///  * `context`: [`ContractContext`] - The contract context containing sender and chain information.
-///
-///  * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
-///
-///  * `token_address`: [`Address`] - The address of the input token.
+/// Records the current pools and total liquidity in the price history, overwriting the oldest
+/// sample once [`PRICE_HISTORY_CAPACITY`] samples have been recorded. <br>
+/// Intended to be called periodically by a keeper, to allow charting the price and the share
+/// price, see [`PriceHistory::share_price_change`], over time.
 ///
-///  * `token_amount`: [`TokenAmount`] - The amount to provide.
+/// # Fails
 ///
-/// # Returns
-/// The unchanged state object of type [`LiquiditySwapContractState`].
-#[action(shortname = 0x04)]
-pub fn provide_liquidity(
+/// Fails if the pools have no liquidity, or if a sample was already recorded at the current
+/// block production time.
+#[action(shortname = 0x1F)]
+pub fn record_price(
     context: ContractContext,
     mut state: LiquiditySwapContractState,
-    token_address: Address,
-    amount: TokenAmount,
 ) -> (LiquiditySwapContractState, Vec<EventGroup>) {
-    let user = &context.sender;
-    let tokens = state.token_balances.deduce_tokens_in_out(token_address);
+    record_price_internal(&mut state, context.block_production_time);
+    (state, vec![])
+}
+
+/// Records the current pools and total liquidity in the price history at `now`.
+fn record_price_internal(state: &mut LiquiditySwapContractState, now: i64) {
+    assert!(
+        state.contract_pools_have_liquidity(),
+        "Pools must have liquidity to record a price"
+    );
+    if let Some(latest) = state.price_history.latest() {
+        assert!(
+            latest.block_production_time < now,
+            "Price was already recorded at {}",
+            now
+        );
+    }
+
     let contract_token_balance = state
         .token_balances
         .get_balance_for(&state.liquidity_pool_address);
+    state.price_history.push(PriceSample {
+        block_production_time: now,
+        reserve_a: contract_token_balance.a_tokens,
+        reserve_b: contract_token_balance.b_tokens,
+        total_liquidity: contract_token_balance.liquidity_tokens,
+    });
+}
 
-    let (token_out_equivalent, minted_liquidity_tokens) = calculate_equivalent_and_minted_tokens(
-        amount,
-        contract_token_balance.get_amount_of(tokens.token_in),
-        contract_token_balance.get_amount_of(tokens.token_out),
-        contract_token_balance.liquidity_tokens,
-    );
-    assert!(
-        minted_liquidity_tokens > 0,
-        "The given input amount yielded 0 minted liquidity"
-    );
+/// Cancels multiple previously acquired locks in a single transaction, like [`cancel_lock`].
+///
+/// Fails, cancelling none of the locks, if any id in `lock_ids` is unknown, repeated, or
+/// associated with a lock not acquired by the sender.
+#[action(shortname = 0x1A)]
+pub fn cancel_locks(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    lock_ids: Vec<LiquidityLockId>,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    for lock_id in lock_ids {
+        state.virtual_state.remove_lock(lock_id, context.sender);
+    }
 
-    provide_liquidity_internal(
-        &mut state,
-        user,
-        tokens,
-        amount,
-        token_out_equivalent,
-        minted_liquidity_tokens,
-    );
     (state, vec![])
 }
 
-/// Reclaim a calling user's share of the contract's total liquidity based on `liquidity_token_amount`. <br>
-/// This is the inverse of [`provide_liquidity`].
-///
-/// Liquidity tokens are synonymous to weighted shares of the contract's total liquidity. <br>
-/// As such, we calculate how much to output of token A and B,
-/// based on the ratio between the input liquidity token amount and the total amount of liquidity minted by the contract.
-///
-/// ### Parameters:
-///
-/// * `context`: [`ContractContext`] - The context for the action call.
-///
-/// * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
+/// Burns stray liquidity tokens, which are counted in the total minted liquidity held by the
+/// contract, but not held by any user. <br>
+/// The liquidity tokens held by the contract track the total minted liquidity, and should equal
+/// the sum of liquidity tokens held by users and the
+/// [`LiquiditySwapContractState::locked_liquidity`], which is never burned. Any excess is not
+/// owned by anyone, so burning it distributes its share of the pools to the remaining liquidity
+/// providers.
 ///
-/// * `liquidity_token_amount`: [`TokenAmount`] - The amount of liquidity tokens to burn.
+/// The number of burned liquidity tokens is returned to any callbacks.
 ///
-/// ### Returns
+/// # Fails
 ///
-/// The updated state object of type [`LiquiditySwapContractState`].
-#[action(shortname = 0x05)]
-pub fn reclaim_liquidity(
+/// Fails if the sender (caller) does not have permission to administrate the contract.
+/// Fails if users hold more liquidity tokens than were minted.
+#[action(shortname = 0x20)]
+pub fn burn_stray_liquidity_tokens(
     context: ContractContext,
     mut state: LiquiditySwapContractState,
-    liquidity_token_amount: TokenAmount,
 ) -> (LiquiditySwapContractState, Vec<EventGroup>) {
-    assert!(
-        state.virtual_state.any_locked_liquidity(),
-        "Cannot reclaim liquidity while locks are present."
-    );
-
-    let user = &context.sender;
-
     state
-        .token_balances
-        .deduct_from_token_balance(*user, Token::LIQUIDITY, liquidity_token_amount);
+        .permission_admin
+        .assert_permission_for(&context.sender, "admin");
 
-    let contract_token_balance = state
-        .token_balances
-        .get_balance_for(&state.liquidity_pool_address);
+    let burned = burn_stray_liquidity_tokens_internal(&mut state);
 
-    let (a_output, b_output) = calculate_reclaim_output(
-        liquidity_token_amount,
-        contract_token_balance.a_tokens,
-        contract_token_balance.b_tokens,
-        contract_token_balance.liquidity_tokens,
-    );
+    let mut return_event = EventGroup::builder();
+    return_event.return_data(burned);
 
-    state
-        .token_balances
-        .move_tokens(state.liquidity_pool_address, *user, Token::A, a_output);
-    state
+    (state, vec![return_event.build()])
+}
+
+/// Burns the minted liquidity exceeding the liquidity tokens held by users and the locked
+/// liquidity, returning the amount burned.
+fn burn_stray_liquidity_tokens_internal(state: &mut LiquiditySwapContractState) -> TokenAmount {
+    let minted_liquidity = state
         .token_balances
-        .move_tokens(state.liquidity_pool_address, *user, Token::B, b_output);
-    state.token_balances.deduct_from_token_balance(
-        state.liquidity_pool_address,
-        Token::LIQUIDITY,
-        liquidity_token_amount,
-    );
+        .get_balance_for(&state.liquidity_pool_address)
+        .liquidity_tokens;
+    let unlocked_liquidity = minted_liquidity.saturating_sub(state.locked_liquidity);
+    // The running total includes the minted liquidity recorded by the contract itself.
+    let held_liquidity = state.token_balances.total_held(Token::LIQUIDITY) - minted_liquidity;
+    let stray_liquidity = unlocked_liquidity
+        .checked_sub(held_liquidity)
+        .unwrap_or_else(|| {
+            panic!(
+                "Users hold more liquidity tokens than were minted: {}/{}",
+                held_liquidity, unlocked_liquidity
+            )
+        });
 
-    (state, vec![])
+    if stray_liquidity != 0 {
+        state.token_balances.deduct_from_token_balance(
+            state.liquidity_pool_address,
+            Token::LIQUIDITY,
+            stray_liquidity,
+        );
+    }
+    stray_liquidity
 }
 
-/// Initialize token liquidity pools, and mint initial liquidity tokens.
-///
-/// Calling this action makes the calling user the first liquidity provider, receiving liquidity
-/// tokens amounting to 100% of the contract's total liquidity, until another user becomes an
-/// liquidity provider.
+/// Resets drained pools, such that they can be initialized again using [`provide_initial_liquidity`].
 ///
-/// ### Parameters:
+/// If both pools have been emptied while liquidity tokens are still outstanding, those tokens no
+/// longer represent a share of any liquidity, but would claim a share of liquidity provided later.
+/// This burns all outstanding liquidity tokens, including those held by the contract itself.
 ///
-///  * `context`: [`ContractContext`] - The contract context containing sender and chain information.
+/// # Fails
 ///
-///  * `state`: [`LiquiditySwapContractState`] - The current state of the contract.
+/// Fails if the sender (caller) does not have permission to administrate the contract.
+/// Fails if either pool still holds tokens, or if locks are present.
+#[action(shortname = 0x0A)]
+pub fn reset_drained_pools(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    state
+        .permission_admin
+        .assert_permission_for(&context.sender, "admin");
+
+    reset_drained_pools_internal(&mut state);
+
+    (state, vec![])
+}
+
+/// Sets the amount of liquidity tokens permanently locked by [`provide_initial_liquidity`]. <br>
+/// Does not affect pools which have already been initialized, whose locked amount is kept in
+/// [`LiquiditySwapContractState::locked_liquidity`].
 ///
-///  * `token_a_amount`: [`TokenAmount`] - The amount to initialize pool A with.
+/// # Fails
 ///
-///  * `token_b_amount`: [`TokenAmount`] - The amount to initialize pool B with.
+/// Fails if the sender (caller) does not have permission to administrate the contract.
+#[action(shortname = 0x21)]
+pub fn set_minimum_initial_liquidity(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    minimum_initial_liquidity: TokenAmount,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    state
+        .permission_admin
+        .assert_permission_for(&context.sender, "admin");
+
+    state.minimum_initial_liquidity = minimum_initial_liquidity;
+
+    (state, vec![])
+}
+
+/// Authorizes or deauthorizes `router` to swap the sender's allowance using
+/// [`swap_from_allowance`]. <br>
+/// The router must additionally have permission to swap on behalf of other users.
+#[action(shortname = 0x2E)]
+pub fn set_router_authorization(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    router: Address,
+    authorized: bool,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    let authorization = RouterAuthorization {
+        owner: context.sender,
+        router,
+    };
+    if authorized {
+        state.router_authorizations.insert(authorization, true);
+    } else {
+        state.router_authorizations.remove(&authorization);
+    }
+
+    (state, vec![])
+}
+
+/// Sets the maximum number of outstanding locks across all owners. <br>
+/// Lowering the maximum below the current number of locks does not affect existing locks, but
+/// prevents acquiring new locks until enough have been executed or cancelled.
 ///
-/// # Returns
+/// # Fails
 ///
-/// The updated state object of type [`LiquiditySwapContractState`].
-#[action(shortname = 0x06)]
-pub fn provide_initial_liquidity(
+/// Fails if the sender (caller) does not have permission to administrate the contract.
+#[action(shortname = 0x2D)]
+pub fn set_max_total_locks(
     context: ContractContext,
     mut state: LiquiditySwapContractState,
-    token_a_amount: TokenAmount,
-    token_b_amount: TokenAmount,
-) -> (LiquiditySwapContractState, Vec<EventGroup>) {
-    assert!(
-        !state.contract_pools_have_liquidity(),
-        "Can only initialize when both pools are empty"
-    );
+    max_total_locks: u32,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    state
+        .permission_admin
+        .assert_permission_for(&context.sender, "admin");
 
-    let minted_liquidity_tokens = initial_liquidity_tokens(token_a_amount, token_b_amount);
-    assert!(
-        minted_liquidity_tokens > 0,
-        "The given input amount yielded 0 minted liquidity"
-    );
+    state.max_total_locks = max_total_locks;
 
-    provide_liquidity_internal(
-        &mut state,
-        &context.sender,
-        TokensInOut::A_IN_B_OUT,
-        token_a_amount,
-        token_b_amount,
-        minted_liquidity_tokens,
-    );
     (state, vec![])
 }
 
-/// Saves a lock on the current state of the liquidity pools for Token A and B,
-/// implicitly updating the virtual pools.
-///
-/// A lock acts as a privilege for swapping `amount_in` of `token_in`, and receiving at least
-/// `amount_out_minimum` of the token being swapped to, at a later point in time,
-/// at the minimum exchange rate given by the actual and virtual liquidity pool states,
-/// at the acquisition time of the lock.
-/// The id, and output amount of the lock is returned to any callbacks.
-/// Other users can still interact with the swap contract while the lock exists.
+/// Enables or disables emergency mode. <br>
+/// A last-resort escape hatch for severe incidents: in emergency mode, liquidity providers can
+/// [`reclaim_liquidity`] against the actual pools even while locks are present. Lock owners may be
+/// shortchanged, as executing their locks can fail once the pools no longer cover them.
 ///
 /// # Fails
 ///
-/// Fails if `amount_out_minimum` is greater than what the current contract state will provide.
-/// Fails if the sender (caller) does not have permission to acquire locks.
-#[action(shortname = 0x07)]
-pub fn acquire_swap_lock(
+/// Fails if the sender (caller) does not have permission to administrate the contract.
+#[action(shortname = 0x29)]
+pub fn set_emergency_mode(
     context: ContractContext,
     mut state: LiquiditySwapContractState,
-    token_in: Address,
-    amount_in: TokenAmount,
-    amount_out_minimum: TokenAmount,
+    emergency_mode: bool,
 ) -> (LiquiditySwapContractState, Vec<EventGroup>) {
     state
-        .permission_lock_swap
-        .assert_permission_for(&context.sender, "lock swap");
-    assert!(
-        state.contract_pools_have_liquidity(),
-        "Pools must have existing liquidity to acquire a lock"
-    );
+        .permission_admin
+        .assert_permission_for(&context.sender, "admin");
 
-    // Acquire a lock internally.
-    let (lock_id, amount_out) = lock_internal(
-        &mut state,
-        amount_in,
-        token_in,
-        amount_out_minimum,
-        context.sender,
-    );
+    state.emergency_mode = emergency_mode;
 
-    // Pass the lock id to any callbacks.
     let mut event_group_builder = EventGroup::builder();
-    let lock_info = AcquiredLiquidityLockInformation {
-        lock_id,
-        amount_out,
-    };
-    event_group_builder.return_data(lock_info);
-
+    event_group_builder.return_data(emergency_mode);
     (state, vec![event_group_builder.build()])
 }
 
-/// Calculates the received amount of the outgoing swap token, if swapping `amount_in` of `token_in`,
-/// and updates the virtual state with a lock.
+/// Sets the minimum time between two [`instant_swap`]s by the same user, throttling
+/// high-frequency traders. `0` disables the limit, and forgets the recorded swap times.
 ///
-/// Fails if the calculated receiving amount is less than `amount_out_minimum`.
-/// The `owner` becomes the address associated with the lock, who has sole permission to execute it.
-fn lock_internal(
-    state: &mut LiquiditySwapContractState,
-    amount_in: TokenAmount,
-    token_in: Address,
-    amount_out_minimum: TokenAmount,
-    owner: Address,
-) -> (LiquidityLockId, TokenAmount) {
-    let tokens = state.token_balances.deduce_tokens_in_out(token_in);
-
-    let amount_out = calculate_minimum_swap_to_amount(state, amount_in, &tokens);
+/// The limit does not apply to swaps by permissioned routers, nor to the execution of locks,
+/// which are already restricted by their permissions.
+///
+/// # Fails
+///
+/// Fails if the sender (caller) does not have permission to administrate the contract.
+/// Fails if `min_swap_interval_millis` is negative.
+#[action(shortname = 0x2A)]
+pub fn set_min_swap_interval(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    min_swap_interval_millis: i64,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    state
+        .permission_admin
+        .assert_permission_for(&context.sender, "admin");
+    assert!(
+        min_swap_interval_millis >= 0,
+        "Minimum swap interval must not be negative"
+    );
 
-    if amount_out < amount_out_minimum {
-        panic!(
-            "Swap would produce {} output tokens, but minimum was set to {}.",
-            amount_out, amount_out_minimum
-        );
+    state.min_swap_interval_millis = min_swap_interval_millis;
+    if min_swap_interval_millis == 0 {
+        state.last_swap_millis = AvlTreeMap::new();
     }
 
-    let tokens_in_out = state.token_balances.deduce_tokens_in_out(token_in);
-
-    let lock = LiquidityLock {
-        amount_in,
-        amount_out,
-        tokens_in_out,
-        owner,
-    };
-    (state.virtual_state.add_lock(lock), amount_out)
+    (state, vec![])
 }
 
-/// Executes a previously acquired lock, performing the intended swap and
-/// updating the actual balances of the contract.
-///
-/// Returns the amount received from the swap to any registered callbacks.
+/// Sets the liquidity provider, e.g. the initial liquidity provider, who exclusively can provide
+/// liquidity until the end of a grace period, letting the pools stabilize. [`None`] allows anyone
+/// to provide liquidity.
 ///
 /// # Fails
 ///
-/// If an unknown `lock_id` is provided this fails.
-/// Also fails if a user who didn't acquire the lock associated with `lock_id` tries to execute it.
-#[action(shortname = 0x08)]
-pub fn execute_lock_swap(
+/// Fails if the sender (caller) does not have permission to administrate the contract.
+#[action(shortname = 0x25)]
+pub fn set_exclusive_lp(
     context: ContractContext,
     mut state: LiquiditySwapContractState,
-    lock_id: LiquidityLockId,
+    exclusive_lp: Option<ExclusiveLiquidityProvider>,
 ) -> (LiquiditySwapContractState, Vec<EventGroup>) {
-    let output_amount = execute_lock_swap_internal(&mut state, lock_id, context.sender);
+    state
+        .permission_admin
+        .assert_permission_for(&context.sender, "admin");
 
-    let mut return_event = EventGroup::builder();
-    return_event.return_data(output_amount);
+    state.exclusive_lp = exclusive_lp;
 
-    (state, vec![return_event.build()])
+    (state, vec![])
 }
 
-/// Removes the lock associated with `lock_id` from the internal state and executes the corresponding swap,
-/// exchanging tokens on the actual liquidity pools.
+/// Sets the maximum amount of each token the pool may hold. [`None`] removes the cap. <br>
+/// Caps limit deposits and provided liquidity, but not swaps. Deposits count towards the cap once
+/// credited, as they can be provided as liquidity at any time. Lowering a cap below the current
+/// pool does not affect existing liquidity.
 ///
-/// Returns the output amount of the lock.
+/// # Fails
 ///
-/// Does nothing if the lock was not acquired by `sender`.
-fn execute_lock_swap_internal(
-    state: &mut LiquiditySwapContractState,
-    lock_id: LiquidityLockId,
-    sender: Address,
-) -> TokenAmount {
-    let lock = state.virtual_state.remove_lock(lock_id, sender);
+/// Fails if the sender (caller) does not have permission to administrate the contract.
+#[action(shortname = 0x0E)]
+pub fn set_deposit_caps(
+    context: ContractContext,
+    mut state: LiquiditySwapContractState,
+    cap_a: Option<TokenAmount>,
+    cap_b: Option<TokenAmount>,
+) -> (LiquiditySwapContractState, Vec<EventGroup>) {
+    state
+        .permission_admin
+        .assert_permission_for(&context.sender, "admin");
 
-    state.token_balances.move_tokens(
-        lock.owner,
-        state.liquidity_pool_address,
-        lock.tokens_in_out.token_in,
-        lock.amount_in,
-    );
-    state.token_balances.move_tokens(
-        state.liquidity_pool_address,
-        lock.owner,
-        lock.tokens_in_out.token_out,
-        lock.amount_out,
-    );
+    state.cap_a = cap_a;
+    state.cap_b = cap_b;
 
-    lock.amount_out
+    (state, vec![])
 }
 
-/// Cancels a previously acquired lock, updating the virtual balances of the contract,
-/// as if the swap didn't happen.
+/// Resets the circuit breaker, starting a new window of the reference price at the current pools,
+/// and allowing swaps again. <br>
+/// The reference price is the time-weighted average price of the most recent samples recorded by
+/// [`record_price`] since the reset, see [`CircuitBreaker::reference_price`]. Swaps which
+/// move the spot price more than `max_deviation_per_mille` from the reference price trip the
+/// breaker, halting further swaps until the next reset. Existing locks can still be executed or
+/// cancelled while swaps are halted. [`None`] disables the breaker.
 ///
-/// If an unknown `lockID` is provided this fails.
-/// Also fails if a user who didn't acquire the lock associated with `lockID` tries to cancel the lock.
-#[action(shortname = 0x09)]
-pub fn cancel_lock(
+/// # Fails
+///
+/// Fails if the sender (caller) does not have permission to administrate the contract.
+/// Fails if the breaker is enabled while the pools have no liquidity.
+#[action(shortname = 0x19)]
+pub fn reset_circuit_breaker(
     context: ContractContext,
     mut state: LiquiditySwapContractState,
-    lock_id: LiquidityLockId,
+    max_deviation_per_mille: Option<u16>,
 ) -> (LiquiditySwapContractState, Vec<EventGroup>) {
-    state.virtual_state.remove_lock(lock_id, context.sender);
+    state
+        .permission_admin
+        .assert_permission_for(&context.sender, "admin");
+
+    reset_circuit_breaker_internal(
+        &mut state,
+        max_deviation_per_mille,
+        context.block_production_time,
+    );
 
     (state, vec![])
 }
 
+/// Replaces the circuit breaker with an untripped breaker, whose reference price starts at the
+/// current pools at `now`.
+fn reset_circuit_breaker_internal(
+    state: &mut LiquiditySwapContractState,
+    max_deviation_per_mille: Option<u16>,
+    now: i64,
+) {
+    state.circuit_breaker = max_deviation_per_mille.map(|max_deviation_per_mille| {
+        assert!(
+            state.contract_pools_have_liquidity(),
+            "Pools must have liquidity to record a reference price"
+        );
+        let contract_token_balance = state
+            .token_balances
+            .get_balance_for(&state.liquidity_pool_address);
+        CircuitBreaker {
+            max_deviation_per_mille,
+            reset_sample: PriceSample {
+                block_production_time: now,
+                reserve_a: contract_token_balance.a_tokens,
+                reserve_b: contract_token_balance.b_tokens,
+                total_liquidity: contract_token_balance.liquidity_tokens,
+            },
+            tripped: false,
+        }
+    });
+}
+
+/// Burns all outstanding liquidity tokens, given that both pools are empty and no locks exist.
+/// The attributed fees of the burned liquidity tokens are discarded along with them.
+fn reset_drained_pools_internal(state: &mut LiquiditySwapContractState) {
+    let contract_token_balance = state
+        .token_balances
+        .get_balance_for(&state.liquidity_pool_address);
+    assert!(
+        contract_token_balance.a_tokens == 0 && contract_token_balance.b_tokens == 0,
+        "Can only reset when both pools are empty"
+    );
+    assert!(
+        state.virtual_state.any_locked_liquidity(),
+        "Cannot reset pools while locks are present."
+    );
+
+    state.token_balances.clear_liquidity_tokens();
+    state.locked_liquidity = 0;
+    state.fee_growth_per_liquidity = EMPTY_BALANCE;
+    state.fee_snapshots = AvlTreeMap::new();
+}
+
 /// Determines the initial amount of liquidity tokens, or shares, representing some sensible '100%' of the contract's liquidity. <br>
 /// This implementation is derived from section 3.4 of: [Uniswap v2 whitepaper](https://uniswap.org/whitepaper.pdf). <br>
 /// It guarantees that the value of a liquidity token becomes independent of the ratio at which liquidity was initially token_in.
@@ -766,10 +3749,259 @@ fn initial_liquidity_tokens(
 /// as calculated by [`calculate_swap_to_amount`].
 /// When no locks are present, this is equivalent to [`calculate_swap_to_amount`].
 fn calculate_minimum_swap_to_amount(
-    state: &mut LiquiditySwapContractState,
+    state: &LiquiditySwapContractState,
     amount_in: TokenAmount,
     tokens_in_out: &TokensInOut,
+    swap_fee_per_mille: u16,
 ) -> TokenAmount {
+    calculate_swap_rates(state, amount_in, tokens_in_out, swap_fee_per_mille).minimum_amount_out()
+}
+
+/// Fixed-point scale of [`LiquiditySwapContractState::fee_growth_per_liquidity`].
+pub const FEE_GROWTH_SCALE: TokenAmount = 1_000_000_000_000;
+
+/// Snapshot of the fees attributed to a liquidity provider, used by [`LiquiditySwapContractState::claimable_fees`].
+#[derive(ReadWriteState, CreateTypeSpec, Debug)]
+pub struct LiquidityFeeSnapshot {
+    /// The fee growth per liquidity token when the snapshot was taken.
+    pub fee_growth_per_liquidity: TokenBalance,
+    /// The fees attributed to the liquidity provider before the snapshot was taken.
+    pub settled_fees: TokenBalance,
+}
+
+/// Maximum number of samples held by [`PriceHistory`].
+pub const PRICE_HISTORY_CAPACITY: usize = 64;
+
+/// The pools at a point in time, implying a spot price of `reserve_b / reserve_a` B tokens per A token.
+#[derive(ReadWriteState, CreateTypeSpec, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriceSample {
+    /// The block production time at which the sample was recorded.
+    pub block_production_time: i64,
+    /// The amount of token A in the pool.
+    pub reserve_a: TokenAmount,
+    /// The amount of token B in the pool.
+    pub reserve_b: TokenAmount,
+    /// The total amount of minted liquidity tokens.
+    pub total_liquidity: TokenAmount,
+}
+
+impl PriceSample {
+    /// The spot price of the sample, see [`scaled_price`].
+    fn scaled_price(&self) -> U256 {
+        scaled_price(self.reserve_a, self.reserve_b)
+            .expect("Price samples are only recorded while the pools have liquidity")
+    }
+
+    /// Values the pools by the geometric mean `sqrt(reserve_a * reserve_b)`, which is unaffected
+    /// by moving the price along the constant product curve. Swap fees increase the value.
+    fn reserves_value(&self) -> TokenAmount {
+        match self.reserve_a.checked_mul(self.reserve_b) {
+            Some(product) => u128_sqrt(product) as TokenAmount,
+            None => {
+                u128_sqrt(self.reserve_a) as TokenAmount * u128_sqrt(self.reserve_b) as TokenAmount
+            }
+        }
+    }
+}
+
+/// Fixed-point scale of the prices compared by [`CircuitBreaker`].
+pub const PRICE_SCALE: TokenAmount = 1_000_000_000_000;
+
+/// The spot price `reserve_b / reserve_a` in B tokens per A token, scaled by [`PRICE_SCALE`] and
+/// rounded down. [`None`] if `reserve_a` is `0`. <br>
+/// Always below `2^168`, leaving room for weighting and comparing prices in [`U256`].
+fn scaled_price(reserve_a: TokenAmount, reserve_b: TokenAmount) -> Option<U256> {
+    let (price, _) = u128_widening_mul(reserve_b, PRICE_SCALE).checked_div_rem_u128(reserve_a)?;
+    Some(price)
+}
+
+/// Ring buffer of the most recent [`PRICE_HISTORY_CAPACITY`] price samples, for charting.
+#[derive(ReadWriteState, CreateTypeSpec, Debug, Default)]
+pub struct PriceHistory {
+    /// The recorded samples. Once full, `next_index` points to the oldest sample.
+    samples: Vec<PriceSample>,
+    /// The index at which the next sample is stored.
+    next_index: u32,
+}
+
+impl PriceHistory {
+    /// Records `sample`, overwriting the oldest sample if the history is full.
+    fn push(&mut self, sample: PriceSample) {
+        if self.samples.len() < PRICE_HISTORY_CAPACITY {
+            self.samples.push(sample);
+        } else {
+            self.samples[self.next_index as usize] = sample;
+        }
+        self.next_index = ((self.next_index as usize + 1) % PRICE_HISTORY_CAPACITY) as u32;
+    }
+
+    /// The most recently recorded sample, if any.
+    fn latest(&self) -> Option<&PriceSample> {
+        let latest_index =
+            (self.next_index as usize + PRICE_HISTORY_CAPACITY - 1) % PRICE_HISTORY_CAPACITY;
+        self.samples.get(latest_index)
+    }
+
+    /// Retrieves the recorded samples, ordered from oldest to newest.
+    pub fn samples(&self) -> Vec<PriceSample> {
+        self.iter().copied().collect()
+    }
+
+    /// Iterates the recorded samples from oldest to newest, without copying them.
+    fn iter(&self) -> impl DoubleEndedIterator<Item = &PriceSample> {
+        // Until the history is full, `next_index` is the number of samples, leaving `newest` empty.
+        let (newest, oldest) = self.samples.split_at(self.next_index as usize);
+        oldest.iter().chain(newest.iter())
+    }
+
+    /// Computes the change in value per liquidity token between the oldest and newest samples. <br>
+    /// The pools are valued as in [`PriceSample`] by their geometric mean, such that the change
+    /// reflects accrued fees, adjusted for impermanent loss. A rising share price benefits
+    /// liquidity providers.
+    ///
+    /// ### Returns:
+    /// The newest share price relative to the oldest, as the numerator and denominator
+    /// `(newest_value * oldest_liquidity, oldest_value * newest_liquidity)`. [`None`] if fewer
+    /// than two samples have been recorded, or if the products overflow. [`Option<(u128, u128)>`]
+    pub fn share_price_change(&self) -> Option<(u128, u128)> {
+        let samples = self.samples();
+        if samples.len() < 2 {
+            return None;
+        }
+        let (oldest, newest) = (samples.first()?, samples.last()?);
+        Some((
+            newest
+                .reserves_value()
+                .checked_mul(oldest.total_liquidity)?,
+            oldest
+                .reserves_value()
+                .checked_mul(newest.total_liquidity)?,
+        ))
+    }
+}
+
+/// A liquidity provider with exclusive permission to provide liquidity during a grace period.
+#[derive(ReadWriteState, ReadWriteRPC, CreateTypeSpec, Debug, PartialEq, Eq)]
+pub struct ExclusiveLiquidityProvider {
+    /// The liquidity provider.
+    pub provider: Address,
+    /// The end of the grace period in UTC millis, from which anyone can provide liquidity.
+    pub end_utc_millis: i64,
+}
+
+/// Maximum number of the most recent samples of [`PriceHistory`] averaged by
+/// [`CircuitBreaker::reference_price`].
+pub const CIRCUIT_BREAKER_WINDOW_SAMPLES: usize = 16;
+
+/// Halts swaps when the spot price deviates too far from a reference price. <br>
+/// The reference price is the time-weighted average price of the most recent samples recorded
+/// since the breaker was last reset.
+#[derive(ReadWriteState, CreateTypeSpec, Debug, PartialEq, Eq)]
+pub struct CircuitBreaker {
+    /// The maximum deviation of the spot price from the reference price, in per mille.
+    pub max_deviation_per_mille: u16,
+    /// The pools when the breaker was last reset, used as the reference price until a sample is
+    /// recorded.
+    pub reset_sample: PriceSample,
+    /// Whether the breaker has tripped, halting swaps until reset by an admin.
+    pub tripped: bool,
+}
+
+impl CircuitBreaker {
+    /// Computes the time-weighted average price, see [`scaled_price`], of the at most
+    /// [`CIRCUIT_BREAKER_WINDOW_SAMPLES`] most recent samples of `price_history` recorded since the
+    /// last reset. <br>
+    /// Each sample is weighted by the time until the next sample, such that the price of the
+    /// newest sample only counts once a later sample is recorded, and the window follows the
+    /// price as new samples are recorded. Until a sample is recorded after the reset, the
+    /// reference price is the price at the reset, and with a single sample, it is its price. The
+    /// reference price therefore only follows the pools while a keeper calls [`record_price`].
+    pub fn reference_price(&self, price_history: &PriceHistory) -> U256 {
+        let reset_time = self.reset_sample.block_production_time;
+        let mut window = price_history
+            .iter()
+            .rev()
+            .take_while(|sample| sample.block_production_time > reset_time)
+            .take(CIRCUIT_BREAKER_WINDOW_SAMPLES);
+
+        let Some(newest) = window.next() else {
+            return self.reset_sample.scaled_price();
+        };
+        // Cannot overflow, as prices are below 2^168, and the window is below 2^63.
+        let (weighted_sum, oldest) = window.fold((U256::ZERO, newest), |(sum, later), sample| {
+            let duration = (later.block_production_time - sample.block_production_time) as u128;
+            let weighted_price = sample.scaled_price().checked_mul_u128(duration).unwrap();
+            (sum.checked_add(weighted_price).unwrap(), sample)
+        });
+        let duration = newest.block_production_time - oldest.block_production_time;
+        if duration == 0 {
+            return newest.scaled_price();
+        }
+        weighted_sum
+            .checked_div_rem_u128(duration as u128)
+            .unwrap()
+            .0
+    }
+
+    /// True if the price implied by `pool_a` and `pool_b` deviates from the reference price by
+    /// more than [`CircuitBreaker::max_deviation_per_mille`], otherwise false.
+    fn is_exceeded_by(
+        &self,
+        price_history: &PriceHistory,
+        pool_a: TokenAmount,
+        pool_b: TokenAmount,
+    ) -> bool {
+        let Some(price) = scaled_price(pool_a, pool_b) else {
+            return true;
+        };
+        let reference_price = self.reference_price(price_history);
+        let deviation = if price > reference_price {
+            price.checked_sub(reference_price)
+        } else {
+            reference_price.checked_sub(price)
+        }
+        .unwrap();
+        // Cannot overflow, as prices are below 2^168.
+        deviation.checked_mul_u128(1000).unwrap()
+            > reference_price
+                .checked_mul_u128(self.max_deviation_per_mille as u128)
+                .unwrap()
+    }
+}
+
+/// Output amounts of a swap on the actual and virtual pools.
+#[derive(ReadWriteRPC, CreateTypeSpec, Debug, PartialEq, Eq)]
+pub struct SwapRates {
+    /// The output amount when swapping on the actual pools.
+    pub actual_amount_out: TokenAmount,
+    /// The output amount when swapping on the virtual pools, which includes the locks.
+    pub virtual_amount_out: TokenAmount,
+}
+
+impl SwapRates {
+    /// The output amount of the swap, being the minimum of the actual and virtual output amounts.
+    pub fn minimum_amount_out(&self) -> TokenAmount {
+        self.actual_amount_out.min(self.virtual_amount_out)
+    }
+
+    /// How many fewer output tokens the swap yields due to the presence of locks.
+    ///
+    /// This is `0` if the locks do not lower the output amount.
+    pub fn lock_penalty(&self) -> TokenAmount {
+        self.actual_amount_out - self.minimum_amount_out()
+    }
+}
+
+/// Calculates the output amounts of swapping `amount_in` input tokens on both the actual and
+/// virtual pools with a fee of `swap_fee_per_mille`, as calculated by [`calculate_swap_to_amount`].
+///
+/// When no locks are present, both amounts are equal.
+pub fn calculate_swap_rates(
+    state: &LiquiditySwapContractState,
+    amount_in: TokenAmount,
+    tokens_in_out: &TokensInOut,
+    swap_fee_per_mille: u16,
+) -> SwapRates {
     let actual_balance = state
         .token_balances
         .get_balance_for(&state.liquidity_pool_address);
@@ -780,20 +4012,20 @@ fn calculate_minimum_swap_to_amount(
         .virtual_state
         .virtual_liquidity_pools(actual_a, actual_b);
 
-    let non_locked_rate = calculate_swap_to_amount(
-        actual_balance.get_amount_of(tokens_in_out.token_in),
-        actual_balance.get_amount_of(tokens_in_out.token_out),
-        amount_in,
-        state.swap_fee_per_mille,
-    );
-    let locked_rate = calculate_swap_to_amount(
-        virtual_balance.get_amount_of(tokens_in_out.token_in),
-        virtual_balance.get_amount_of(tokens_in_out.token_out),
-        amount_in,
-        state.swap_fee_per_mille,
-    );
-
-    non_locked_rate.min(locked_rate)
+    SwapRates {
+        actual_amount_out: calculate_swap_to_amount(
+            actual_balance.get_amount_of(tokens_in_out.token_in),
+            actual_balance.get_amount_of(tokens_in_out.token_out),
+            amount_in,
+            swap_fee_per_mille,
+        ),
+        virtual_amount_out: calculate_swap_to_amount(
+            virtual_balance.get_amount_of(tokens_in_out.token_in),
+            virtual_balance.get_amount_of(tokens_in_out.token_out),
+            amount_in,
+            swap_fee_per_mille,
+        ),
+    }
 }
 
 /// Finds the equivalent value of the output token during [`provide_liquidity`] based on the input amount and the weighted shares that they correspond to. <br>
@@ -817,16 +4049,56 @@ fn calculate_equivalent_and_minted_tokens(
     token_out_pool: TokenAmount,
     total_minted_liquidity: TokenAmount,
 ) -> (TokenAmount, TokenAmount) {
-    // Handle zero-case
-    let token_out_equivalent = if token_in_amount > 0 {
-        (token_in_amount * token_out_pool / token_in_pool) + 1
-    } else {
-        0
-    };
+    let token_out_equivalent =
+        required_token_out_for(token_in_amount, token_in_pool, token_out_pool);
     let minted_liquidity_tokens = token_in_amount * total_minted_liquidity / token_in_pool;
     (token_out_equivalent, minted_liquidity_tokens)
 }
 
+/// Calculates the amount of liquidity tokens minted when providing both `amount_a` and `amount_b`. <br>
+/// Each amount implies a share of the pools, and the smallest of the two shares is minted, as in
+/// section 3.4 of: [Uniswap v2 whitepaper](https://uniswap.org/whitepaper.pdf). <br>
+/// Unlike [`calculate_equivalent_and_minted_tokens`], the caller chooses both amounts, so no
+/// additional token is required to protect the pools against rounding. Any amount exceeding the
+/// current ratio of the pools is provided without minting additional liquidity tokens.
+///
+/// ### Parameters:
+///
+/// * `amount_a`: [`TokenAmount`] - The amount of token A being provided.
+///
+/// * `amount_b`: [`TokenAmount`] - The amount of token B being provided.
+///
+/// * `reserve_a`: [`TokenAmount`] - Pool a of this contract. Must be non-zero.
+///
+/// * `reserve_b`: [`TokenAmount`] - Pool b of this contract. Must be non-zero.
+///
+/// * `total_minted_liquidity` [`TokenAmount`] - The total current minted liquidity.
+/// # Returns
+/// The minted liquidity tokens, rounded down. [`TokenAmount`]
+///
+/// # Fails
+///
+/// Fails if either reserve is `0`.
+pub fn calculate_minted_for_pair(
+    amount_a: TokenAmount,
+    amount_b: TokenAmount,
+    reserve_a: TokenAmount,
+    reserve_b: TokenAmount,
+    total_minted_liquidity: TokenAmount,
+) -> TokenAmount {
+    assert!(
+        reserve_a != 0 && reserve_b != 0,
+        "Pools must have liquidity to provide both tokens"
+    );
+    // The minted share of a pool never exceeds the total liquidity by more than the provided
+    // multiple of the pool, so only unrealistic amounts overflow.
+    let minted_from_a = u128_mul_div(amount_a, total_minted_liquidity, reserve_a)
+        .expect("Minted liquidity exceeds the range of TokenAmount");
+    let minted_from_b = u128_mul_div(amount_b, total_minted_liquidity, reserve_b)
+        .expect("Minted liquidity exceeds the range of TokenAmount");
+    minted_from_a.min(minted_from_b)
+}
+
 /// Calculates the amount of token {A, B} that the input amount of liquidity tokens correspond to during [`reclaim_liquidity`]. <br>
 /// Due to integer rounding, a user may be withdrawing less of each pool token than expected. <br>
 /// Calculations are derived from section 2.2.2 of [UniSwap v1 whitepaper](
@@ -877,6 +4149,7 @@ fn provide_liquidity_internal(
     token_out_amount: TokenAmount,
     minted_liquidity_tokens: TokenAmount,
 ) {
+    state.settle_fees(user);
     state.token_balances.move_tokens(
         *user,
         state.liquidity_pool_address,
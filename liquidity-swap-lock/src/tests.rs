@@ -1,7 +1,2948 @@
+use pbc_contract_common::{
+    address::AddressType,
+    context::{CallbackContext, ExecutionResult},
+    Hash,
+};
+use pbc_traits::ReadWriteState;
 use proptest::prelude::*;
 
 use super::*;
 
+const CONTRACT: Address = Address {
+    address_type: AddressType::PublicContract,
+    identifier: [1; 20],
+};
+
+const TOKEN_A: Address = Address {
+    address_type: AddressType::PublicContract,
+    identifier: [2; 20],
+};
+
+const TOKEN_B: Address = Address {
+    address_type: AddressType::PublicContract,
+    identifier: [3; 20],
+};
+
+const USER: Address = Address {
+    address_type: AddressType::Account,
+    identifier: [4; 20],
+};
+
+const ROUTER: Address = Address {
+    address_type: AddressType::PublicContract,
+    identifier: [6; 20],
+};
+
+const OTHER_USER: Address = Address {
+    address_type: AddressType::Account,
+    identifier: [5; 20],
+};
+
+/// Creates a context for an invocation sent by `sender`.
+fn context(sender: Address) -> ContractContext {
+    context_at(sender, 0)
+}
+
+/// Creates a context for an invocation sent by `sender` in a block produced at `block_production_time`.
+fn context_at(sender: Address, block_production_time: i64) -> ContractContext {
+    ContractContext {
+        contract_address: CONTRACT,
+        sender,
+        block_time: 0,
+        block_production_time,
+        current_transaction: Hash { bytes: [0; 32] },
+        original_transaction: Hash { bytes: [0; 32] },
+    }
+}
+
+/// Creates a state without any balances, with a fee of 3 per mille.
+fn empty_state() -> LiquiditySwapContractState {
+    LiquiditySwapContractState {
+        permission_lock_swap: Permission::Anybody {},
+        permission_admin: Permission::Specific {
+            addresses: vec![USER],
+        },
+        permission_swap_on_behalf: Permission::Specific {
+            addresses: vec![ROUTER],
+        },
+        permission_fee_exempt: Permission::Specific { addresses: vec![] },
+        liquidity_pool_address: CONTRACT,
+        swap_fee_per_mille: 3,
+        token_balances: TokenBalances::new(CONTRACT, TOKEN_A, TOKEN_B).unwrap(),
+        virtual_state: VirtualState::new(),
+        cap_a: None,
+        cap_b: None,
+        fee_recipient: None,
+        protocol_fee_share_per_mille: 0,
+        accrued_protocol_fees: EMPTY_BALANCE,
+        fee_burn_share_per_mille: 0,
+        token_a_burnable: false,
+        token_b_burnable: false,
+        accrued_burn_fees: EMPTY_BALANCE,
+        lock_lifetime_millis: None,
+        keeper_bounty_per_lock: 0,
+        keeper_bounty_fund: EMPTY_BALANCE,
+        circuit_breaker: None,
+        has_been_initialized: false,
+        price_history: PriceHistory::default(),
+        fee_growth_per_liquidity: EMPTY_BALANCE,
+        fee_snapshots: AvlTreeMap::new(),
+        minimum_initial_liquidity: DEFAULT_MINIMUM_INITIAL_LIQUIDITY,
+        locked_liquidity: 0,
+        exclusive_lp: None,
+        emergency_mode: false,
+        min_swap_interval_millis: 0,
+        last_swap_millis: AvlTreeMap::new(),
+        max_total_locks: DEFAULT_MAX_TOTAL_LOCKS,
+        router_authorizations: AvlTreeMap::new(),
+        migration_targets: AvlTreeMap::new(),
+    }
+}
+
+/// Creates a state where [`USER`] has provided the given amounts as initial liquidity.
+fn state_with_pools(pool_a: TokenAmount, pool_b: TokenAmount) -> LiquiditySwapContractState {
+    let mut state = empty_state();
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, pool_a);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::B, pool_b);
+    provide_liquidity_internal(
+        &mut state,
+        &USER,
+        TokensInOut::A_IN_B_OUT,
+        pool_a,
+        pool_b,
+        initial_liquidity_tokens(pool_a, pool_b),
+    );
+    state
+}
+
+/// Creates a state with the given pools provided by [`USER`], like [`state_with_pools`], but
+/// minting a fixed amount of liquidity tokens, for pools whose product exceeds [`u128`].
+fn state_with_large_pools(pool_a: TokenAmount, pool_b: TokenAmount) -> LiquiditySwapContractState {
+    let mut state = empty_state();
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, pool_a);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::B, pool_b);
+    provide_liquidity_internal(
+        &mut state,
+        &USER,
+        TokensInOut::A_IN_B_OUT,
+        pool_a,
+        pool_b,
+        10u128.pow(18),
+    );
+    state
+}
+
+/// Asserts that the virtual pools equal the actual pools plus the liquidity of every lock,
+/// recomputing the lock liquidity from scratch rather than trusting the tracked sum.
+fn assert_virtual_invariant(state: &LiquiditySwapContractState) {
+    let actual = state
+        .token_balances
+        .get_balance_for(&state.liquidity_pool_address);
+    let mut expected_lock_liquidity = LockLiquidity {
+        a_tokens: 0,
+        b_tokens: 0,
+    };
+    for (_, lock) in state.virtual_state.locks.iter() {
+        *expected_lock_liquidity.get_mut_amount_of(lock.tokens_in_out.token_in) +=
+            TokenDelta::try_from(lock.amount_in).unwrap();
+        *expected_lock_liquidity.get_mut_amount_of(lock.tokens_in_out.token_out) -=
+            TokenDelta::try_from(lock.amount_out).unwrap();
+    }
+
+    let virtual_pools = state
+        .virtual_state
+        .virtual_liquidity_pools(actual.a_tokens, actual.b_tokens);
+    for (token, actual_amount, virtual_amount) in [
+        (Token::A, actual.a_tokens, virtual_pools.a_tokens),
+        (Token::B, actual.b_tokens, virtual_pools.b_tokens),
+    ] {
+        assert_eq!(
+            delta_to_amount(actual_amount, expected_lock_liquidity.get_amount_of(token)),
+            Ok(virtual_amount),
+            "Virtual {:?} pool diverged from actual pool plus lock liquidity",
+            token
+        );
+    }
+}
+
+#[test]
+fn reset_drained_pools_burns_stray_liquidity_tokens() {
+    let mut state = state_with_pools(100, 100);
+
+    // Drain both pools without burning the liquidity tokens, leaving them without backing.
+    state
+        .token_balances
+        .move_tokens(CONTRACT, USER, Token::A, 100);
+    state
+        .token_balances
+        .move_tokens(CONTRACT, USER, Token::B, 100);
+    assert!(!state.contract_pools_have_liquidity());
+    assert_eq!(
+        state.token_balances.get_balance_for(&USER).liquidity_tokens,
+        100
+    );
+
+    reset_drained_pools_internal(&mut state);
+
+    assert_eq!(
+        state.token_balances.get_balance_for(&CONTRACT),
+        EMPTY_BALANCE
+    );
+    assert_eq!(
+        state.token_balances.get_balance_for(&USER),
+        TokenBalance {
+            a_tokens: 100,
+            b_tokens: 100,
+            liquidity_tokens: 0,
+        }
+    );
+}
+
+#[test]
+fn reset_drained_pools_discards_attributed_fees() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, 11_000);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::B, 1_000);
+    instant_swap_internal(&mut state, TOKEN_A, 10_000, 0, USER);
+    // Providing more settles the fee of the swap in the snapshot of USER.
+    provide_liquidity_up_to(&mut state, &USER, TOKEN_A, 1_000, TokenAmount::MAX);
+    assert_eq!(state.claimable_fees(&USER).a_tokens, 30);
+
+    let pools = state.token_balances.get_balance_for(&CONTRACT);
+    state
+        .token_balances
+        .move_tokens(CONTRACT, USER, Token::A, pools.a_tokens);
+    state
+        .token_balances
+        .move_tokens(CONTRACT, USER, Token::B, pools.b_tokens);
+    reset_drained_pools_internal(&mut state);
+    assert_eq!(state.fee_snapshots.len(), 0);
+    assert_eq!(state.fee_growth_per_liquidity, EMPTY_BALANCE);
+
+    // Providing again attributes only the fees of later swaps.
+    provide_initial_liquidity_internal(&mut state, &USER, 10_000, 10_000);
+    assert_eq!(state.claimable_fees(&USER), EMPTY_BALANCE);
+    instant_swap_internal(&mut state, TOKEN_A, 10_000, 0, USER);
+    assert_eq!(
+        state.claimable_fees(&USER),
+        TokenBalance {
+            a_tokens: 27,
+            b_tokens: 0,
+            liquidity_tokens: 0,
+        }
+    );
+}
+
+#[test]
+#[should_panic(expected = "Can only reset when both pools are empty")]
+fn reset_drained_pools_requires_empty_pools() {
+    let mut state = state_with_pools(100, 100);
+    reset_drained_pools_internal(&mut state);
+}
+
+#[test]
+fn summary_matches_state_and_round_trips() {
+    let state = state_with_pools(400, 100);
+    let summary = state.summary();
+
+    assert_eq!(
+        summary,
+        PoolSummary {
+            version: POOL_SUMMARY_VERSION,
+            token_a: TOKEN_A,
+            token_b: TOKEN_B,
+            reserve_a: 400,
+            reserve_b: 100,
+            total_liquidity: 200,
+            fee_per_mille: 3,
+        }
+    );
+
+    let mut buffer = Vec::new();
+    summary.state_write_to(&mut buffer).unwrap();
+    let read_summary = PoolSummary::state_read_from(&mut buffer.as_slice());
+    assert_eq!(read_summary, summary);
+}
+
+/// Gives [`USER`] `amount_in` of token A, and acquires a lock swapping them, scheduled for `time`.
+fn acquire_scheduled_lock(
+    state: &mut LiquiditySwapContractState,
+    amount_in: TokenAmount,
+    time: i64,
+) -> (LiquidityLockId, TokenAmount) {
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, amount_in);
+    lock_internal(state, amount_in, TOKEN_A, 0, USER, Some(time), None)
+}
+
+#[test]
+fn scheduled_lock_executes_once_time_is_reached() {
+    let mut state = state_with_pools(1000, 1000);
+    let (lock_id, amount_out) = acquire_scheduled_lock(&mut state, 100, 500);
+
+    assert_eq!(
+        execute_scheduled_lock_internal(&mut state, lock_id, 499),
+        ScheduledExecution::Pending
+    );
+    assert!(state.virtual_state.get_lock(&lock_id).is_some());
+
+    assert_eq!(
+        execute_scheduled_lock_internal(&mut state, lock_id, 500),
+        ScheduledExecution::Executed(amount_out)
+    );
+    assert!(state.virtual_state.get_lock(&lock_id).is_none());
+    assert_eq!(
+        state.token_balances.get_balance_for(&USER),
+        TokenBalance {
+            a_tokens: 0,
+            b_tokens: amount_out,
+            liquidity_tokens: 1000,
+        }
+    );
+
+    // Executing again does nothing.
+    assert_eq!(
+        execute_scheduled_lock_internal(&mut state, lock_id, 501),
+        ScheduledExecution::Gone
+    );
+}
+
+#[test]
+fn cancel_preempts_scheduled_lock() {
+    let mut state = state_with_pools(1000, 1000);
+    let (lock_id, _) = acquire_scheduled_lock(&mut state, 100, 500);
+
+    state.virtual_state.remove_lock(lock_id, USER);
+    assert_virtual_invariant(&state);
+
+    assert_eq!(
+        execute_scheduled_lock_internal(&mut state, lock_id, 500),
+        ScheduledExecution::Gone
+    );
+    assert_eq!(
+        state.token_balances.get_balance_for(&USER),
+        TokenBalance {
+            a_tokens: 100,
+            b_tokens: 0,
+            liquidity_tokens: 1000,
+        }
+    );
+}
+
+#[test]
+#[should_panic(expected = "is not scheduled for execution.")]
+fn unscheduled_lock_cannot_be_executed_by_schedule() {
+    let mut state = state_with_pools(1000, 1000);
+    let (lock_id, _) = lock_internal(&mut state, 100, TOKEN_A, 0, USER, None, None);
+    execute_scheduled_lock_internal(&mut state, lock_id, 500);
+}
+
+/// Acquires a lock through [`acquire_and_schedule_lock`], swapping 100 of [`USER`]'s token A at
+/// time 1000, scheduled `delay_millis` later.
+fn acquire_scheduled_lock_by_action(
+    delay_millis: i64,
+) -> (LiquiditySwapContractState, LiquidityLockId) {
+    let mut state = state_with_pools(1000, 1000);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, 100);
+    let lock_id = state.virtual_state.next_lock_id();
+    let (state, events) =
+        acquire_and_schedule_lock(context_at(USER, 1000), state, TOKEN_A, 100, 0, delay_millis);
+
+    // Execution is left to keepers rather than scheduled through calls to this contract.
+    assert_eq!(events.len(), 1);
+    assert!(events[0].events.is_empty());
+    assert!(state.virtual_state.get_lock(&lock_id).is_some());
+    (state, lock_id)
+}
+
+#[test]
+fn keeper_executes_scheduled_lock_once_due() {
+    let (state, lock_id) = acquire_scheduled_lock_by_action(500);
+    let amount_out = state.virtual_state.get_lock(&lock_id).unwrap().amount_out;
+
+    let (state, events) = execute_scheduled_lock(context_at(OTHER_USER, 1500), state, lock_id);
+
+    assert!(events.is_empty());
+    assert!(state.virtual_state.get_lock(&lock_id).is_none());
+    assert_eq!(
+        state.token_balances.get_balance_for(&USER).b_tokens,
+        amount_out
+    );
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER),
+        EMPTY_BALANCE
+    );
+
+    // Executing again does nothing.
+    let (state, _) = execute_scheduled_lock(context_at(OTHER_USER, 1600), state, lock_id);
+    assert_eq!(
+        state.token_balances.get_balance_for(&USER).b_tokens,
+        amount_out
+    );
+}
+
+#[test]
+#[should_panic(expected = "is not due for execution yet.")]
+fn keeper_cannot_execute_scheduled_lock_early() {
+    let (state, lock_id) = acquire_scheduled_lock_by_action(500);
+    execute_scheduled_lock(context_at(OTHER_USER, 1499), state, lock_id);
+}
+
+#[test]
+#[should_panic(expected = "Scheduled execution time is out of range")]
+fn scheduled_lock_delay_must_not_overflow() {
+    acquire_scheduled_lock_by_action(i64::MAX);
+}
+
+#[test]
+fn user_position_aggregates_balances_share_and_locks() {
+    let mut state = state_with_pools(1000, 1000);
+
+    // Another user provides a quarter of the liquidity, keeps a deposit, and acquires a lock.
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 500);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::B, 500);
+    provide_liquidity_internal(
+        &mut state,
+        &OTHER_USER,
+        TokensInOut::A_IN_B_OUT,
+        333,
+        334,
+        333,
+    );
+    let (lock_id, amount_out) = lock_internal(&mut state, 100, TOKEN_A, 0, OTHER_USER, None, None);
+    lock_internal(&mut state, 100, TOKEN_B, 0, USER, None, None);
+
+    let position = state.user_position(&OTHER_USER);
+    assert_eq!(
+        position.balance,
+        TokenBalance {
+            a_tokens: 167,
+            b_tokens: 166,
+            liquidity_tokens: 333,
+        }
+    );
+    assert_eq!(position.lp_share_per_mille, 249);
+    assert_eq!(position.locks.len(), 1);
+    assert_eq!(position.locks[0].lock_id, lock_id);
+    assert_eq!(position.locks[0].lock.amount_out, amount_out);
+
+    let position = state.user_position(&CONTRACT);
+    assert_eq!(position.lp_share_per_mille, 1000);
+    assert!(position.locks.is_empty());
+}
+
+#[test]
+fn user_position_share_of_large_liquidity() {
+    let mut state = empty_state();
+    state
+        .token_balances
+        .add_to_token_balance(CONTRACT, Token::LIQUIDITY, u128::MAX);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::LIQUIDITY, u128::MAX / 4);
+
+    assert_eq!(state.user_position(&OTHER_USER).lp_share_per_mille, 249);
+    assert_eq!(state.user_position(&CONTRACT).lp_share_per_mille, 1000);
+}
+
+#[test]
+fn swap_from_allowance_credits_owner() {
+    let mut state = state_with_pools(1000, 1000);
+    let expected_out = calculate_swap_to_amount(1000, 1000, 100, 3);
+
+    // The router's transfer of 100 A tokens from the user has succeeded.
+    let amount_out = swap_from_allowance_internal(&mut state, OTHER_USER, TOKEN_A, 100, 90);
+
+    assert_eq!(amount_out, Some(expected_out));
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER),
+        TokenBalance {
+            a_tokens: 0,
+            b_tokens: expected_out,
+            liquidity_tokens: 0,
+        }
+    );
+    assert_eq!(state.summary().reserve_a, 1100);
+    assert_eq!(state.summary().reserve_b, 1000 - expected_out);
+}
+
+#[test]
+fn authorized_router_swaps_from_allowance() {
+    let state = state_with_pools(1000, 1000);
+    let (state, _) = set_router_authorization(context(OTHER_USER), state, ROUTER, true);
+
+    let (_, events) = swap_from_allowance(context(ROUTER), state, OTHER_USER, TOKEN_A, 100, 90);
+
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].events.len(), 1);
+    assert_eq!(events[0].events[0].dest, TOKEN_A);
+}
+
+#[test]
+#[should_panic(expected = "has not authorized")]
+fn router_cannot_swap_allowance_of_other_users() {
+    let state = state_with_pools(1000, 1000);
+    // Authorized by another user, but not by the owner of the allowance.
+    let (state, _) = set_router_authorization(context(USER), state, ROUTER, true);
+
+    swap_from_allowance(context(ROUTER), state, OTHER_USER, TOKEN_A, 100, 90);
+}
+
+#[test]
+#[should_panic(expected = "has not authorized")]
+fn deauthorized_router_cannot_swap_from_allowance() {
+    let state = state_with_pools(1000, 1000);
+    let (state, _) = set_router_authorization(context(OTHER_USER), state, ROUTER, true);
+    let (state, _) = set_router_authorization(context(OTHER_USER), state, ROUTER, false);
+
+    swap_from_allowance(context(ROUTER), state, OTHER_USER, TOKEN_A, 100, 90);
+}
+
+#[test]
+#[should_panic(expected = "Minimum output amount must be positive")]
+fn swap_from_allowance_requires_minimum_output() {
+    let state = state_with_pools(1000, 1000);
+    let (state, _) = set_router_authorization(context(OTHER_USER), state, ROUTER, true);
+
+    swap_from_allowance(context(ROUTER), state, OTHER_USER, TOKEN_A, 100, 0);
+}
+
+#[test]
+fn swap_from_allowance_keeps_deposit_on_slippage() {
+    let mut state = state_with_pools(1000, 1000);
+
+    let amount_out = swap_from_allowance_internal(&mut state, OTHER_USER, TOKEN_A, 100, 100);
+
+    assert_eq!(amount_out, None);
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER),
+        TokenBalance {
+            a_tokens: 100,
+            b_tokens: 0,
+            liquidity_tokens: 0,
+        }
+    );
+    assert_eq!(state.summary().reserve_a, 1000);
+}
+
+#[test]
+fn deposit_up_to_cap_is_allowed() {
+    let mut state = state_with_pools(1000, 1000);
+    state.assert_within_cap(Token::A, u128::MAX);
+
+    state.cap_a = Some(1500);
+    state.assert_within_cap(Token::A, 500);
+    state.assert_within_cap(Token::B, 5000);
+}
+
+#[test]
+#[should_panic(expected = "TokenA pool would exceed its cap: 1501/1500")]
+fn deposit_over_cap_is_rejected() {
+    let mut state = state_with_pools(1000, 1000);
+    state.cap_a = Some(1500);
+    state.assert_within_cap(Token::A, 501);
+}
+
+#[test]
+fn deposits_are_capped_cumulatively_when_credited() {
+    let mut state = state_with_pools(1000, 1000);
+    state.cap_a = Some(1500);
+
+    // Both deposits fit on their own, and are transferred before either is credited.
+    let (state, events) = deposit(context(USER), state, TOKEN_A, 300);
+    assert_eq!(events[0].events.len(), 1);
+    let (state, events) = deposit(context(OTHER_USER), state, TOKEN_A, 300);
+    assert_eq!(events[0].events.len(), 1);
+
+    let (state, events) = deposit_callback(
+        context(USER),
+        callback_context(&[true]),
+        state,
+        Token::A,
+        300,
+    );
+    assert!(events.is_empty());
+    assert_eq!(state.token_balances.get_balance_for(&USER).a_tokens, 300);
+
+    // The second deposit would bring the held tokens to 1600, so it is transferred back.
+    let (state, events) = deposit_callback(
+        context(OTHER_USER),
+        callback_context(&[true]),
+        state,
+        Token::A,
+        300,
+    );
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].events.len(), 1);
+    assert_eq!(events[0].events[0].dest, TOKEN_A);
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER),
+        EMPTY_BALANCE
+    );
+}
+
+#[test]
+fn bulk_deposit_transfers_back_deposits_over_cap() {
+    let mut state = state_with_pools(1000, 1000);
+    state.cap_b = Some(1100);
+    let deposits = vec![
+        Deposit {
+            token_address: TOKEN_A,
+            amount: 100,
+        },
+        Deposit {
+            token_address: TOKEN_B,
+            amount: 200,
+        },
+    ];
+
+    let (state, events) = bulk_deposit_callback(
+        context(USER),
+        callback_context(&[true, true]),
+        state,
+        deposits,
+    );
+
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].events.len(), 1);
+    assert_eq!(events[0].events[0].dest, TOKEN_B);
+    assert_eq!(
+        state.token_balances.get_balance_for(&USER),
+        TokenBalance {
+            a_tokens: 100,
+            b_tokens: 0,
+            liquidity_tokens: 1000,
+        }
+    );
+}
+
+#[test]
+fn protocol_fees_accrue_over_swaps_and_are_swept() {
+    let mut state = state_with_pools(100_000, 100_000);
+    state.fee_recipient = Some(OTHER_USER);
+    state.protocol_fee_share_per_mille = 500;
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::B, 10_000);
+
+    // Swap fees are 30 A, 30 B and 15 A, of which half goes to the protocol.
+    let out_b = instant_swap_internal(&mut state, TOKEN_A, 10_000, 0, USER);
+    let out_a = instant_swap_internal(&mut state, TOKEN_B, 10_000, 0, USER);
+    let out_b_2 = instant_swap_internal(&mut state, TOKEN_A, 5_000, 0, USER);
+
+    assert_eq!(
+        state.accrued_protocol_fees,
+        TokenBalance {
+            a_tokens: 22,
+            b_tokens: 15,
+            liquidity_tokens: 0,
+        }
+    );
+    let summary = state.summary();
+    assert_eq!(summary.reserve_a, 100_000 + 10_000 - out_a + 5_000 - 22);
+    assert_eq!(summary.reserve_b, 100_000 - out_b + 10_000 - out_b_2 - 15);
+
+    let fees = take_protocol_fees(&mut state);
+    assert_eq!(fees.a_tokens, 22);
+    assert_eq!(fees.b_tokens, 15);
+    assert_eq!(state.accrued_protocol_fees, EMPTY_BALANCE);
+}
+
+#[test]
+fn withdrawing_protocol_fees_sends_only_accrued_tokens() {
+    let mut state = state_with_pools(100_000, 100_000);
+    state.fee_recipient = Some(OTHER_USER);
+
+    let (mut state, events) = withdraw_protocol_fees(context(OTHER_USER), state);
+    assert!(events.is_empty());
+
+    state.accrued_protocol_fees.b_tokens = 15;
+    let (state, events) = withdraw_protocol_fees(context(OTHER_USER), state);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].events.len(), 1);
+    assert_eq!(events[0].events[0].dest, TOKEN_B);
+    assert_eq!(state.accrued_protocol_fees, EMPTY_BALANCE);
+}
+
+/// Creates a state where half of the swap fees of token A are burned, while token B does not
+/// support burning.
+fn state_burning_token_a_fees() -> LiquiditySwapContractState {
+    let state = state_with_pools(100_000, 100_000);
+    let (mut state, _) = set_fee_burn(context(USER), state, 500, true, false);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::B, 10_000);
+    state
+}
+
+#[test]
+fn swap_fees_are_split_between_burn_and_pools() {
+    let mut state = state_burning_token_a_fees();
+
+    // Swap fees are 30 A and 30 B, of which half of the A fees are burned.
+    let out_b = instant_swap_internal(&mut state, TOKEN_A, 10_000, 0, USER);
+    let out_a = instant_swap_internal(&mut state, TOKEN_B, 10_000, 0, USER);
+
+    assert_eq!(
+        state.accrued_burn_fees,
+        TokenBalance {
+            a_tokens: 15,
+            b_tokens: 0,
+            liquidity_tokens: 0,
+        }
+    );
+    let summary = state.summary();
+    assert_eq!(summary.reserve_a, 100_000 + 10_000 - out_a - 15);
+    assert_eq!(summary.reserve_b, 100_000 - out_b + 10_000);
+}
+
+#[test]
+fn burn_interactions_are_emitted_only_for_burnable_tokens() {
+    let mut state = state_burning_token_a_fees();
+    instant_swap_internal(&mut state, TOKEN_A, 10_000, 0, USER);
+    // Accrued while token B was burnable.
+    state.accrued_burn_fees.b_tokens = 7;
+
+    let (state, event_groups) = burn_accrued_fees(context(OTHER_USER), state);
+
+    assert_eq!(event_groups.len(), 1);
+    assert_eq!(event_groups[0].events.len(), 1);
+    assert_eq!(event_groups[0].events[0].dest, TOKEN_A);
+    assert_eq!(
+        state.accrued_burn_fees,
+        TokenBalance {
+            a_tokens: 0,
+            b_tokens: 7,
+            liquidity_tokens: 0,
+        }
+    );
+}
+
+#[test]
+fn burn_accrued_fees_without_fees_emits_nothing() {
+    let (state, event_groups) =
+        burn_accrued_fees(context(OTHER_USER), state_burning_token_a_fees());
+    assert!(event_groups.is_empty());
+    assert_eq!(state.accrued_burn_fees, EMPTY_BALANCE);
+}
+
+#[test]
+fn failed_burn_restores_accrued_fees() {
+    let mut state = state_burning_token_a_fees();
+    state.accrued_burn_fees.a_tokens = 15;
+
+    let (state, event_groups) = burn_accrued_fees(context(OTHER_USER), state);
+    assert_eq!(event_groups.len(), 1);
+    assert_eq!(state.accrued_burn_fees.a_tokens, 0);
+
+    let (state, _) = burn_accrued_fees_callback(
+        context(TOKEN_A),
+        callback_context(&[true]),
+        state,
+        Token::A,
+        15,
+    );
+    assert_eq!(state.accrued_burn_fees.a_tokens, 0);
+
+    let (state, _) = burn_accrued_fees_callback(
+        context(TOKEN_A),
+        callback_context(&[false]),
+        state,
+        Token::A,
+        15,
+    );
+    assert_eq!(state.accrued_burn_fees.a_tokens, 15);
+}
+
+#[test]
+fn disabling_burn_returns_accrued_fees_to_pools() {
+    let mut state = state_burning_token_a_fees();
+    state.accrued_burn_fees.a_tokens = 15;
+    let reserve_a = state.summary().reserve_a;
+
+    let (state, _) = set_fee_burn(context(USER), state, 500, false, false);
+
+    assert_eq!(state.accrued_burn_fees, EMPTY_BALANCE);
+    assert_eq!(state.summary().reserve_a, reserve_a + 15);
+}
+
+#[test]
+fn failed_burn_of_no_longer_burnable_token_returns_fees_to_pools() {
+    let mut state = state_burning_token_a_fees();
+    state.accrued_burn_fees.a_tokens = 15;
+    let (state, _) = burn_accrued_fees(context(OTHER_USER), state);
+    let (state, _) = set_fee_burn(context(USER), state, 500, false, false);
+    let reserve_a = state.summary().reserve_a;
+
+    let (state, _) = burn_accrued_fees_callback(
+        context(TOKEN_A),
+        callback_context(&[false]),
+        state,
+        Token::A,
+        15,
+    );
+
+    assert_eq!(state.accrued_burn_fees, EMPTY_BALANCE);
+    assert_eq!(state.summary().reserve_a, reserve_a + 15);
+}
+
+#[test]
+#[should_panic(
+    expected = "Protocol fee and burn shares must not exceed 1000 per mille combined, but was 600 + 500"
+)]
+fn fee_shares_must_fit_within_swap_fee() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state.protocol_fee_share_per_mille = 600;
+    set_fee_burn(context(USER), state, 500, true, true);
+}
+
+#[test]
+fn swap_rates_report_lock_penalty() {
+    let mut state = state_with_pools(10_000, 10_000);
+
+    let rates = calculate_swap_rates(&state, 1000, &TokensInOut::A_IN_B_OUT, 3);
+    assert_eq!(rates.actual_amount_out, rates.virtual_amount_out);
+    assert_eq!(rates.lock_penalty(), 0);
+
+    lock_internal(&mut state, 1000, TOKEN_A, 0, USER, None, None);
+
+    let rates = calculate_swap_rates(&state, 1000, &TokensInOut::A_IN_B_OUT, 3);
+    assert_eq!(
+        rates.actual_amount_out,
+        calculate_swap_to_amount(10_000, 10_000, 1000, 3)
+    );
+    assert!(rates.virtual_amount_out < rates.actual_amount_out);
+    assert_eq!(rates.minimum_amount_out(), rates.virtual_amount_out);
+    assert_eq!(
+        rates.lock_penalty(),
+        rates.actual_amount_out - rates.virtual_amount_out
+    );
+
+    // Swapping in the opposite direction benefits from the lock on the virtual pools.
+    let rates = calculate_swap_rates(&state, 1000, &TokensInOut::B_IN_A_OUT, 3);
+    assert!(rates.virtual_amount_out > rates.actual_amount_out);
+    assert_eq!(rates.lock_penalty(), 0);
+}
+
+#[test]
+fn withdraw_strict_keeps_deduction_on_success() {
+    let mut state = state_with_pools(1000, 1000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 100);
+
+    // The balance is deducted by the action, before the transfer is made.
+    state
+        .token_balances
+        .deduct_from_token_balance(OTHER_USER, Token::A, 100);
+    finalize_withdraw_strict(&mut state, true, OTHER_USER, Token::A, 100);
+
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER),
+        EMPTY_BALANCE
+    );
+}
+
+#[test]
+fn withdraw_strict_restores_balance_on_failure() {
+    let mut state = state_with_pools(1000, 1000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 100);
+
+    state
+        .token_balances
+        .deduct_from_token_balance(OTHER_USER, Token::A, 100);
+    finalize_withdraw_strict(&mut state, false, OTHER_USER, Token::A, 100);
+
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER),
+        TokenBalance {
+            a_tokens: 100,
+            b_tokens: 0,
+            liquidity_tokens: 0,
+        }
+    );
+}
+
+#[test]
+fn tvl_in_values_both_pools_at_spot_price() {
+    let state = state_with_pools(1000, 1000);
+    assert_eq!(state.tvl_in(Token::A), 2000);
+    assert_eq!(state.tvl_in(Token::B), 2000);
+
+    let state = state_with_pools(400, 100);
+    assert_eq!(state.tvl_in(Token::A), 800);
+    assert_eq!(state.tvl_in(Token::B), 200);
+
+    assert_eq!(empty_state().tvl_in(Token::A), 0);
+}
+
+#[test]
+fn tvl_in_handles_large_reserves() {
+    // 18 decimal pools of 50 and 2000 tokens, whose product exceeds u128.
+    let reserve_a = 50 * 10u128.pow(18);
+    let reserve_b = 2000 * 10u128.pow(18);
+    let state = state_with_large_pools(reserve_a, reserve_b);
+    assert_eq!(state.tvl_in(Token::A), 2 * reserve_a);
+    assert_eq!(state.tvl_in(Token::B), 2 * reserve_b);
+}
+
+#[test]
+fn deposit_and_call_invokes_next_callback_after_crediting() {
+    let state = state_with_pools(1000, 1000);
+
+    let (state, events) = deposit_and_call(
+        context(ROUTER),
+        state,
+        TOKEN_B,
+        100,
+        Some(Shortname::from_u32(0x42)),
+    );
+    assert_eq!(events[0].events.len(), 1);
+    assert_eq!(events[0].events[0].dest, TOKEN_B);
+
+    let (state, events) = deposit_and_call_callback(
+        context(ROUTER),
+        callback_context(&[true]),
+        state,
+        Token::B,
+        100,
+        Some(Shortname::from_u32(0x42)),
+    );
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].events[0].dest, ROUTER);
+    assert_eq!(state.token_balances.get_balance_for(&ROUTER).b_tokens, 100);
+}
+
+#[test]
+fn deposit_credits_before_chained_callback() {
+    let mut state = state_with_pools(1000, 1000);
+
+    let event_groups = credit_deposit(&mut state, ROUTER, Token::B, 100, None);
+    assert!(event_groups.is_empty());
+
+    let event_groups = credit_deposit(
+        &mut state,
+        ROUTER,
+        Token::B,
+        100,
+        Some(Shortname::from_u32(0x42)),
+    );
+    assert_eq!(event_groups.len(), 1);
+    assert_eq!(
+        state.token_balances.get_balance_for(&ROUTER),
+        TokenBalance {
+            a_tokens: 0,
+            b_tokens: 200,
+            liquidity_tokens: 0,
+        }
+    );
+}
+
+#[test]
+fn imbalanced_initial_liquidity_is_flagged() {
+    assert!(!is_initial_liquidity_imbalanced(1000, 1000));
+    assert!(!is_initial_liquidity_imbalanced(1000, 100));
+    assert!(!is_initial_liquidity_imbalanced(1000, 999_999));
+    assert!(is_initial_liquidity_imbalanced(1, 1000));
+    assert!(is_initial_liquidity_imbalanced(1_000_000, 10));
+    assert!(is_initial_liquidity_imbalanced(u128::MAX, 1 << 100));
+    assert!(!is_initial_liquidity_imbalanced(u128::MAX, u128::MAX / 2));
+}
+
+/// Acquires a lock swapping 100 A tokens for [`USER`], expiring at `expires_utc_millis`.
+fn acquire_expiring_lock(
+    state: &mut LiquiditySwapContractState,
+    expires_utc_millis: i64,
+) -> LiquidityLockId {
+    lock_internal(state, 100, TOKEN_A, 0, USER, None, Some(expires_utc_millis)).0
+}
+
+/// Sweeps the expired locks among the first `max_locks` locks at `now`, returning the number of
+/// swept locks.
+fn sweep_at(
+    state: &mut LiquiditySwapContractState,
+    keeper: Address,
+    now: i64,
+    max_locks: usize,
+) -> u32 {
+    sweep_expired_locks_internal(state, keeper, now, None, max_locks).swept
+}
+
+/// Funds the keeper bounty with `amount` A tokens deposited by [`USER`].
+fn fund_bounty(
+    state: LiquiditySwapContractState,
+    amount: TokenAmount,
+) -> LiquiditySwapContractState {
+    let mut state = state;
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, amount);
+    fund_keeper_bounty(context(USER), state, TOKEN_A, amount).0
+}
+
+#[test]
+fn keeper_is_paid_per_swept_lock() {
+    let mut state = fund_bounty(state_with_pools(10_000, 10_000), 1000);
+    state.keeper_bounty_per_lock = 5;
+    let first_lock = acquire_expiring_lock(&mut state, 100);
+    let second_lock = acquire_expiring_lock(&mut state, 200);
+    let third_lock = acquire_expiring_lock(&mut state, 300);
+    lock_internal(&mut state, 100, TOKEN_A, 0, USER, None, None);
+
+    assert_eq!(sweep_at(&mut state, OTHER_USER, 99, 10), 0);
+    assert_eq!(sweep_at(&mut state, OTHER_USER, 200, 10), 2);
+    assert!(state.virtual_state.get_lock(&first_lock).is_none());
+    assert!(state.virtual_state.get_lock(&second_lock).is_none());
+    assert!(state.virtual_state.get_lock(&third_lock).is_some());
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER),
+        TokenBalance {
+            a_tokens: 10,
+            b_tokens: 0,
+            liquidity_tokens: 0,
+        }
+    );
+    assert_eq!(state.keeper_bounty_fund.a_tokens, 990);
+    assert_eq!(state.summary().reserve_a, 10_000);
+
+    // Sweeping is bounded, and locks without expiry are never swept.
+    assert_eq!(sweep_at(&mut state, OTHER_USER, 1000, 0), 0);
+    assert_eq!(sweep_at(&mut state, OTHER_USER, 1000, 10), 1);
+    assert_eq!(state.virtual_state.locks_owned_by(&USER).len(), 1);
+}
+
+#[test]
+fn sweep_bounty_is_capped_at_fund() {
+    let mut state = fund_bounty(state_with_pools(10_000, 10_000), 7);
+    state.keeper_bounty_per_lock = 5;
+    acquire_expiring_lock(&mut state, 100);
+    acquire_expiring_lock(&mut state, 100);
+
+    assert_eq!(sweep_at(&mut state, OTHER_USER, 100, 10), 2);
+    // The first lock pays the full bounty, and the second lock what remains of the fund.
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER).a_tokens,
+        7
+    );
+    assert_eq!(state.keeper_bounty_fund, EMPTY_BALANCE);
+    assert_eq!(state.summary().reserve_a, 10_000);
+}
+
+#[test]
+fn owner_sweeping_own_unfunded_locks_gains_nothing() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state.keeper_bounty_per_lock = 100;
+    for _ in 0..3 {
+        acquire_expiring_lock(&mut state, 100);
+    }
+
+    assert_eq!(sweep_at(&mut state, USER, 100, 10), 3);
+    assert_eq!(state.token_balances.get_balance_for(&USER).a_tokens, 0);
+    assert_eq!(state.summary().reserve_a, 10_000);
+}
+
+#[test]
+fn zero_bounty_sweep_pays_nothing() {
+    let mut state = fund_bounty(state_with_pools(10_000, 10_000), 1000);
+    let lock_id = acquire_expiring_lock(&mut state, 100);
+
+    assert_eq!(sweep_at(&mut state, OTHER_USER, 100, 10), 1);
+    assert!(state.virtual_state.get_lock(&lock_id).is_none());
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER),
+        EMPTY_BALANCE
+    );
+    assert_eq!(state.keeper_bounty_fund.a_tokens, 1000);
+    assert_eq!(state.summary().reserve_a, 10_000);
+}
+
+#[test]
+fn sweep_reaches_expired_locks_behind_unexpired_ones() {
+    let mut state = state_with_pools(10_000, 10_000);
+    for _ in 0..50 {
+        lock_internal(&mut state, 10, TOKEN_A, 0, USER, None, None);
+    }
+    acquire_expiring_lock(&mut state, 100);
+    acquire_expiring_lock(&mut state, 100);
+
+    // Each invocation scans a single page, so the expired locks are only reached once the
+    // unexpired locks before them have been scanned.
+    let mut swept_per_invocation = vec![];
+    let mut start = None;
+    loop {
+        let sweep = sweep_expired_locks_internal(&mut state, OTHER_USER, 100, start, 10);
+        swept_per_invocation.push(sweep.swept);
+        start = sweep.next_start;
+        if start.is_none() {
+            break;
+        }
+    }
+
+    assert_eq!(swept_per_invocation, vec![0, 0, 0, 0, 0, 2]);
+    assert_eq!(state.virtual_state.locks_owned_by(&USER).len(), 50);
+}
+
+#[test]
+fn provide_liquidity_both_mints_smaller_share() {
+    let mut state = state_with_pools(10_000, 20_000);
+    let total_liquidity = state.summary().total_liquidity;
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 2000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::B, 3000);
+
+    // Providing 10% of each pool mints 10% of the liquidity.
+    let (state, _) = provide_liquidity_both(context(OTHER_USER), state, 1000, 2000, 1);
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER),
+        TokenBalance {
+            a_tokens: 1000,
+            b_tokens: 1000,
+            liquidity_tokens: total_liquidity / 10,
+        }
+    );
+    let summary = state.summary();
+    assert_eq!((summary.reserve_a, summary.reserve_b), (11_000, 22_000));
+
+    // Excess token A is provided without minting more than the share of token B.
+    let (state, _) = provide_liquidity_both(context(OTHER_USER), state, 1000, 1000, 1);
+    let minted = state
+        .token_balances
+        .get_balance_for(&OTHER_USER)
+        .liquidity_tokens
+        - total_liquidity / 10;
+    assert_eq!(
+        minted,
+        calculate_minted_for_pair(1000, 1000, 11_000, 22_000, summary.total_liquidity)
+    );
+    assert_eq!(minted, 1000 * summary.total_liquidity / 22_000);
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER).a_tokens,
+        0
+    );
+}
+
+#[test]
+#[should_panic(
+    expected = "Providing would mint 1414 liquidity tokens, but minimum was set to 1415."
+)]
+fn provide_liquidity_both_below_minimum_is_rejected() {
+    let mut state = state_with_pools(10_000, 20_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 1000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::B, 2000);
+
+    provide_liquidity_both(context(OTHER_USER), state, 1000, 2000, 1415);
+}
+
+#[test]
+#[should_panic(expected = "Pools must have liquidity to provide both tokens")]
+fn provide_liquidity_both_without_liquidity_is_rejected() {
+    let mut state = empty_state();
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 1000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::B, 2000);
+
+    provide_liquidity_both(context(OTHER_USER), state, 1000, 2000, 0);
+}
+
+#[test]
+fn provide_liquidity_within_maximum() {
+    let mut state = state_with_pools(1000, 2000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 100);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::B, 201);
+
+    // 100 A requires 200 B, plus one to protect the pools against rounding, which is refunded as
+    // the equivalent is exact, so the maximum only has to cover the 200 B.
+    provide_liquidity_up_to(&mut state, &OTHER_USER, TOKEN_A, 100, 200);
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER),
+        TokenBalance {
+            a_tokens: 0,
+            b_tokens: 1,
+            liquidity_tokens: 141,
+        }
+    );
+    let pools = state.token_balances.get_balance_for(&CONTRACT);
+    assert_eq!((pools.a_tokens, pools.b_tokens), (1100, 2200));
+}
+
+#[test]
+fn provide_liquidity_keeps_rounding_token_for_inexact_equivalent() {
+    let mut state = state_with_pools(3000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 100);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::B, 334);
+
+    // 100 A is worth 333.33 B, so the additional token rounds the equivalent up.
+    provide_liquidity_up_to(&mut state, &OTHER_USER, TOKEN_A, 100, TokenAmount::MAX);
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER).b_tokens,
+        0
+    );
+    let pools = state.token_balances.get_balance_for(&CONTRACT);
+    assert_eq!((pools.a_tokens, pools.b_tokens), (3100, 10_334));
+}
+
+#[test]
+#[should_panic(expected = "Output token equivalent exceeds the maximum: 200/199")]
+fn provide_liquidity_over_maximum_is_rejected() {
+    let mut state = state_with_pools(1000, 2000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 100);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::B, 1000);
+
+    provide_liquidity_up_to(&mut state, &OTHER_USER, TOKEN_A, 100, 199);
+}
+
+#[test]
+fn large_price_move_trips_circuit_breaker() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, 10_000);
+    reset_circuit_breaker_internal(&mut state, Some(100), 0);
+
+    // Moves the price by about 2%, within the allowed 10%.
+    instant_swap_internal(&mut state, TOKEN_A, 100, 0, USER);
+    assert!(!state.circuit_breaker.as_ref().unwrap().tripped);
+
+    // Moves the price by about 32%, tripping the breaker, but the swap itself succeeds.
+    instant_swap_internal(&mut state, TOKEN_A, 2000, 0, USER);
+    assert!(state.circuit_breaker.as_ref().unwrap().tripped);
+}
+
+#[test]
+fn circuit_breaker_references_time_weighted_price() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, 10_000);
+    reset_circuit_breaker_internal(&mut state, Some(100), 0);
+
+    // Moves the price from 1 to about 0.925, which is then recorded for 300 of 900 millis, and
+    // then to about 0.890, which is recorded for the remaining 600 millis.
+    instant_swap_internal(&mut state, TOKEN_A, 400, 0, USER);
+    let first_summary = state.summary();
+    record_price_internal(&mut state, 100);
+    instant_swap_internal(&mut state, TOKEN_A, 200, 0, USER);
+    let second_summary = state.summary();
+    record_price_internal(&mut state, 400);
+    record_price_internal(&mut state, 1000);
+    let first_price = PRICE_SCALE * first_summary.reserve_b / first_summary.reserve_a;
+    let second_price = PRICE_SCALE * second_summary.reserve_b / second_summary.reserve_a;
+    assert_eq!(
+        state
+            .circuit_breaker
+            .as_ref()
+            .unwrap()
+            .reference_price(&state.price_history),
+        U256::from_u128((first_price * 300 + second_price * 600) / 900)
+    );
+
+    // Moves the price to about 0.858, 5% from the time-weighted price of about 0.902, but 14%
+    // from the price at the reset.
+    instant_swap_internal(&mut state, TOKEN_A, 200, 0, USER);
+    assert!(!state.circuit_breaker.as_ref().unwrap().tripped);
+}
+
+#[test]
+fn circuit_breaker_reference_follows_slow_drift() {
+    let mut state = state_with_pools(1_000_000, 1_000_000);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, 1_000_000);
+    reset_circuit_breaker_internal(&mut state, Some(50), 0);
+
+    // Each swap moves the price by about 0.4%, drifting by about 35% in total, well beyond the
+    // allowed 5%, over more samples than the price history retains.
+    let total_samples = 2 * PRICE_HISTORY_CAPACITY as i64;
+    for time in 1..=total_samples {
+        instant_swap_internal(&mut state, TOKEN_A, 2000, 0, USER);
+        assert!(!state.circuit_breaker.as_ref().unwrap().tripped);
+        record_price_internal(&mut state, time);
+    }
+
+    // The reference price only averages the most recent samples, rather than the reset price.
+    let summary = state.summary();
+    let spot_price = U256::from_u128(PRICE_SCALE * summary.reserve_b / summary.reserve_a);
+    let reference_price = state
+        .circuit_breaker
+        .as_ref()
+        .unwrap()
+        .reference_price(&state.price_history);
+    assert!(reference_price > spot_price);
+    assert!(
+        reference_price
+            .checked_sub(spot_price)
+            .unwrap()
+            .checked_mul_u128(1000)
+            .unwrap()
+            < spot_price.checked_mul_u128(50).unwrap()
+    );
+}
+
+#[test]
+fn twap_staleness_grows_until_updated() {
+    let mut state = state_with_pools(10_000, 10_000);
+    assert_eq!(state.twap_staleness_millis(100), i64::MAX);
+
+    record_price_internal(&mut state, 100);
+    assert_eq!(state.twap_staleness_millis(150), 50);
+    assert_eq!(state.twap_staleness_millis(400), 300);
+
+    record_price_internal(&mut state, 400);
+    assert_eq!(state.twap_staleness_millis(400), 0);
+
+    reset_circuit_breaker_internal(&mut state, Some(100), 500);
+    assert_eq!(state.twap_staleness_millis(600), 100);
+}
+
+#[test]
+fn circuit_breaker_handles_large_reserves() {
+    // Pools of a single 18 decimal token, whose product times 1000 exceeds u128.
+    let pool = 10u128.pow(18);
+    let mut state = state_with_pools(pool, pool);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, pool);
+    reset_circuit_breaker_internal(&mut state, Some(100), 0);
+
+    instant_swap_internal(&mut state, TOKEN_A, pool / 100, 0, USER);
+    assert!(!state.circuit_breaker.as_ref().unwrap().tripped);
+
+    instant_swap_internal(&mut state, TOKEN_A, pool / 5, 0, USER);
+    assert!(state.circuit_breaker.as_ref().unwrap().tripped);
+}
+
+#[test]
+#[should_panic(expected = "Swaps are halted by the circuit breaker")]
+fn tripped_circuit_breaker_halts_swaps() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, 10_000);
+    reset_circuit_breaker_internal(&mut state, Some(100), 0);
+
+    instant_swap_internal(&mut state, TOKEN_A, 2000, 0, USER);
+    lock_internal(&mut state, 100, TOKEN_A, 0, USER, None, None);
+}
+
+#[test]
+fn reset_clears_tripped_circuit_breaker() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, 10_000);
+    reset_circuit_breaker_internal(&mut state, Some(100), 0);
+    instant_swap_internal(&mut state, TOKEN_A, 2000, 0, USER);
+
+    reset_circuit_breaker_internal(&mut state, Some(100), 0);
+    let summary = state.summary();
+    assert_eq!(
+        state.circuit_breaker,
+        Some(CircuitBreaker {
+            max_deviation_per_mille: 100,
+            reset_sample: PriceSample {
+                block_production_time: 0,
+                reserve_a: summary.reserve_a,
+                reserve_b: summary.reserve_b,
+                total_liquidity: summary.total_liquidity,
+            },
+            tripped: false,
+        })
+    );
+    // Swaps are allowed again, relative to the new reference price.
+    instant_swap_internal(&mut state, TOKEN_A, 100, 0, USER);
+    assert!(!state.circuit_breaker.as_ref().unwrap().tripped);
+
+    reset_circuit_breaker_internal(&mut state, None, 0);
+    instant_swap_internal(&mut state, TOKEN_A, 5000, 0, USER);
+    assert_eq!(state.circuit_breaker, None);
+}
+
+#[test]
+fn locks_stay_valid_through_pool_changes() {
+    let mut state = state_with_pools(10_000, 20_000);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::B, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 1000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::B, 10_000);
+
+    let (first_lock, _) = lock_internal(&mut state, 1000, TOKEN_A, 0, USER, None, None);
+    assert!(state.lock_still_valid(&first_lock));
+    assert_virtual_invariant(&state);
+
+    // Opposing and same direction swaps.
+    instant_swap_internal(&mut state, TOKEN_B, 3000, 0, USER);
+    assert!(state.lock_still_valid(&first_lock));
+    instant_swap_internal(&mut state, TOKEN_A, 2000, 0, USER);
+    assert!(state.lock_still_valid(&first_lock));
+
+    // Additional locks, and provided liquidity.
+    let (second_lock, _) = lock_internal(&mut state, 2000, TOKEN_B, 0, USER, None, None);
+    let (third_lock, _) = lock_internal(&mut state, 500, TOKEN_A, 0, USER, None, None);
+    provide_liquidity_up_to(&mut state, &OTHER_USER, TOKEN_A, 1000, TokenAmount::MAX);
+    for lock_id in [first_lock, second_lock, third_lock] {
+        assert!(state.lock_still_valid(&lock_id));
+    }
+    assert_virtual_invariant(&state);
+
+    // Executing or cancelling locks keeps the remaining valid.
+    execute_lock_swap_internal(&mut state, second_lock, USER);
+    assert!(state.lock_still_valid(&first_lock));
+    assert!(state.lock_still_valid(&third_lock));
+    assert_virtual_invariant(&state);
+    state.virtual_state.remove_lock(first_lock, USER);
+    assert!(state.lock_still_valid(&third_lock));
+    assert_virtual_invariant(&state);
+
+    assert!(!state.lock_still_valid(&first_lock));
+    assert!(!state.lock_still_valid(&second_lock));
+}
+
+#[test]
+fn locks_len_tracks_locks() {
+    let mut state = state_with_pools(10_000, 10_000);
+    assert_eq!(state.virtual_state.locks_len(), 0);
+
+    let (first_lock, _) = lock_internal(&mut state, 100, TOKEN_A, 0, USER, None, None);
+    let (second_lock, _) = lock_internal(&mut state, 100, TOKEN_B, 0, USER, None, None);
+    assert_eq!(state.virtual_state.locks_len(), 2);
+    assert_virtual_invariant(&state);
+
+    state.virtual_state.remove_lock(first_lock, USER);
+    assert_eq!(state.virtual_state.locks_len(), 1);
+    assert_virtual_invariant(&state);
+    state.virtual_state.remove_lock(second_lock, USER);
+    assert_eq!(state.virtual_state.locks_len(), 0);
+    assert_virtual_invariant(&state);
+}
+
+#[test]
+fn initialized_flag_survives_full_reclaim() {
+    let mut state = empty_state();
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, 1000);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::B, 1000);
+    assert!(!state.has_been_initialized);
+    assert!(!state.is_drained());
+
+    // Without locked liquidity, the entire liquidity can be reclaimed.
+    state.minimum_initial_liquidity = 0;
+    let (state, _) = provide_initial_liquidity(context(USER), state, 1000, 1000);
+    assert!(state.has_been_initialized);
+    assert!(!state.is_drained());
+
+    let (state, _) = reclaim_liquidity(context(USER), state, 1000);
+    assert!(!state.contract_pools_have_liquidity());
+    assert!(state.has_been_initialized);
+    assert!(state.is_drained());
+}
+
+#[test]
+fn cancel_locks_restores_virtual_liquidity() {
+    let mut state = state_with_pools(10_000, 10_000);
+    let (first_lock, _) = lock_internal(&mut state, 100, TOKEN_A, 0, USER, None, None);
+    let (second_lock, _) = lock_internal(&mut state, 200, TOKEN_B, 0, USER, None, None);
+    let (other_lock, _) = lock_internal(&mut state, 300, TOKEN_A, 0, OTHER_USER, None, None);
+
+    let (state, _) = cancel_locks(context(USER), state, vec![second_lock, first_lock]);
+
+    assert_eq!(state.virtual_state.locks_len(), 1);
+    assert!(state.lock_still_valid(&other_lock));
+    let (state, _) = cancel_locks(context(OTHER_USER), state, vec![other_lock]);
+    assert_eq!(state.virtual_state.lock_liquidity.a_tokens, 0);
+    assert_eq!(state.virtual_state.lock_liquidity.b_tokens, 0);
+}
+
+#[test]
+#[should_panic(expected = "Permission denied to handle lockID")]
+fn cancel_locks_reverts_on_foreign_lock() {
+    let mut state = state_with_pools(10_000, 10_000);
+    let (own_lock, _) = lock_internal(&mut state, 100, TOKEN_A, 0, USER, None, None);
+    let (other_lock, _) = lock_internal(&mut state, 300, TOKEN_A, 0, OTHER_USER, None, None);
+
+    cancel_locks(context(USER), state, vec![own_lock, other_lock]);
+}
+
+#[test]
+#[should_panic(expected = "is not a valid lock id.")]
+fn cancel_locks_reverts_on_repeated_lock() {
+    let mut state = state_with_pools(10_000, 10_000);
+    let (lock_id, _) = lock_internal(&mut state, 100, TOKEN_A, 0, USER, None, None);
+
+    cancel_locks(context(USER), state, vec![lock_id, lock_id]);
+}
+
+const NEW_CONTRACT: Address = Address {
+    address_type: AddressType::PublicContract,
+    identifier: [7; 20],
+};
+
+/// Creates a state with the given pools, from which users may migrate to [`NEW_CONTRACT`].
+fn state_allowing_migration(
+    pool_a: TokenAmount,
+    pool_b: TokenAmount,
+) -> LiquiditySwapContractState {
+    let (state, _) = set_migration_target(
+        context(USER),
+        state_with_pools(pool_a, pool_b),
+        NEW_CONTRACT,
+        true,
+    );
+    state
+}
+
+#[test]
+fn migrate_to_moves_entire_balance() {
+    let mut state = state_allowing_migration(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 100);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::B, 200);
+
+    let (state, event_groups) = migrate_to(context(OTHER_USER), state, NEW_CONTRACT);
+
+    // One event group per token, such that each can fail independently.
+    assert_eq!(event_groups.len(), 2);
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER),
+        EMPTY_BALANCE
+    );
+    // The pools are unaffected.
+    assert_eq!(state.summary().reserve_a, 10_000);
+    assert_eq!(state.summary().reserve_b, 10_000);
+}
+
+#[test]
+fn migrate_to_restores_failed_token_only() {
+    let mut state = state_allowing_migration(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 100);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::B, 200);
+
+    let (mut state, event_groups) = migrate_to(context(OTHER_USER), state, NEW_CONTRACT);
+    assert_eq!(event_groups.len(), 2);
+
+    // Token A is migrated, while the deposit of token B fails.
+    let events;
+    (state, events) = migrate_callback(
+        context(CONTRACT),
+        callback_context(&[true, true]),
+        state,
+        OTHER_USER,
+        Token::A,
+        100,
+        NEW_CONTRACT,
+    );
+    assert!(events.is_empty());
+    let events;
+    (state, events) = migrate_callback(
+        context(CONTRACT),
+        callback_context(&[true, false]),
+        state,
+        OTHER_USER,
+        Token::B,
+        200,
+        NEW_CONTRACT,
+    );
+    // The approval of token B is revoked.
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].events.len(), 1);
+    assert_eq!(events[0].events[0].dest, TOKEN_B);
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER),
+        TokenBalance {
+            a_tokens: 0,
+            b_tokens: 200,
+            liquidity_tokens: 0,
+        }
+    );
+}
+
+#[test]
+fn migrate_to_skips_empty_tokens() {
+    let mut state = state_allowing_migration(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::B, 200);
+
+    let (_, event_groups) = migrate_to(context(OTHER_USER), state, NEW_CONTRACT);
+    assert_eq!(event_groups.len(), 1);
+}
+
+#[test]
+#[should_panic(expected = "Nothing to migrate")]
+fn migrate_to_without_balance_fails() {
+    migrate_to(
+        context(OTHER_USER),
+        state_allowing_migration(10_000, 10_000),
+        NEW_CONTRACT,
+    );
+}
+
+#[test]
+#[should_panic(expected = "is not an allowed migration target")]
+fn migrate_to_disallowed_target_fails() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 100);
+
+    migrate_to(context(OTHER_USER), state, NEW_CONTRACT);
+}
+
+#[test]
+#[should_panic(expected = "is not an allowed migration target")]
+fn migrate_to_revoked_target_fails() {
+    let mut state = state_allowing_migration(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 100);
+    let (state, _) = set_migration_target(context(USER), state, NEW_CONTRACT, false);
+
+    migrate_to(context(OTHER_USER), state, NEW_CONTRACT);
+}
+
+#[test]
+#[should_panic(expected = "did not have permission \"admin\"")]
+fn set_migration_target_requires_admin() {
+    set_migration_target(
+        context(OTHER_USER),
+        state_with_pools(10_000, 10_000),
+        NEW_CONTRACT,
+        true,
+    );
+}
+
+#[test]
+fn price_history_wraps_after_capacity() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, 1_000_000);
+    assert!(state.price_history.samples().is_empty());
+
+    let total_samples = PRICE_HISTORY_CAPACITY as i64 + 3;
+    for time in 0..total_samples {
+        record_price_internal(&mut state, time);
+        instant_swap_internal(&mut state, TOKEN_A, 100, 0, USER);
+    }
+
+    let samples = state.price_history.samples();
+    assert_eq!(samples.len(), PRICE_HISTORY_CAPACITY);
+    assert_eq!(samples[0].block_production_time, 3);
+    assert_eq!(
+        samples.last().unwrap().block_production_time,
+        total_samples - 1
+    );
+    for (older, newer) in samples.iter().zip(samples.iter().skip(1)) {
+        assert_eq!(older.block_production_time + 1, newer.block_production_time);
+        assert_eq!(older.reserve_a + 100, newer.reserve_a);
+        assert!(older.reserve_b > newer.reserve_b);
+    }
+}
+
+#[test]
+fn share_price_rises_as_fees_accrue() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 1_000);
+    record_price_internal(&mut state, 1);
+    assert_eq!(state.price_history.share_price_change(), None);
+
+    // Swapping back and forth leaves the price roughly unchanged, but accrues fees.
+    let amount_b = instant_swap_internal(&mut state, TOKEN_A, 1_000, 0, OTHER_USER);
+    instant_swap_internal(&mut state, TOKEN_B, amount_b, 0, OTHER_USER);
+    record_price_internal(&mut state, 2);
+
+    let (newest, oldest) = state.price_history.share_price_change().unwrap();
+    assert!(newest > oldest);
+}
+
+#[test]
+fn share_price_unchanged_by_providing_liquidity() {
+    let mut state = state_with_pools(10_000, 10_000);
+    record_price_internal(&mut state, 1);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::B, 10_001);
+    provide_liquidity_up_to(&mut state, &USER, TOKEN_A, 10_000, TokenAmount::MAX);
+    record_price_internal(&mut state, 2);
+
+    let (newest, oldest) = state.price_history.share_price_change().unwrap();
+    assert_eq!(newest, oldest);
+}
+
+#[test]
+#[should_panic(expected = "Price was already recorded at 5")]
+fn price_is_recorded_once_per_time() {
+    let mut state = state_with_pools(10_000, 10_000);
+    record_price_internal(&mut state, 5);
+    record_price_internal(&mut state, 5);
+}
+
+#[test]
+fn stray_liquidity_tokens_are_burned() {
+    let mut state = state_with_pools(10_000, 10_000);
+    assert_eq!(burn_stray_liquidity_tokens_internal(&mut state), 0);
+
+    // Minted liquidity without any holder, e.g. due to an accounting mistake.
+    state
+        .token_balances
+        .add_to_token_balance(CONTRACT, Token::LIQUIDITY, 500);
+    assert_eq!(burn_stray_liquidity_tokens_internal(&mut state), 500);
+
+    assert_eq!(state.summary().total_liquidity, 10_000);
+    assert_eq!(
+        state.token_balances.get_balance_for(&USER).liquidity_tokens,
+        10_000
+    );
+    assert_eq!(burn_stray_liquidity_tokens_internal(&mut state), 0);
+}
+
+#[test]
+fn locked_initial_liquidity_is_not_stray() {
+    let mut state = empty_state();
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::B, 10_000);
+    let (mut state, _) = provide_initial_liquidity(context(USER), state, 10_000, 10_000);
+    assert_eq!(state.locked_liquidity, DEFAULT_MINIMUM_INITIAL_LIQUIDITY);
+
+    assert_eq!(burn_stray_liquidity_tokens_internal(&mut state), 0);
+    assert_eq!(state.summary().total_liquidity, 10_000);
+
+    // Strays beyond the locked liquidity are still burned.
+    state
+        .token_balances
+        .add_to_token_balance(CONTRACT, Token::LIQUIDITY, 500);
+    assert_eq!(burn_stray_liquidity_tokens_internal(&mut state), 500);
+    assert_eq!(state.summary().total_liquidity, 10_000);
+}
+
+#[test]
+#[should_panic(expected = "Users hold more liquidity tokens than were minted: 10500/10000")]
+fn unbacked_user_liquidity_tokens_are_rejected() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::LIQUIDITY, 500);
+    burn_stray_liquidity_tokens_internal(&mut state);
+}
+
+#[test]
+fn fees_are_attributed_by_liquidity_share() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::B, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 20_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::B, 5009);
+
+    // Only USER provides liquidity during the first swap, with a fee of 30 A.
+    instant_swap_internal(&mut state, TOKEN_A, 10_000, 0, USER);
+    assert_eq!(state.claimable_fees(&USER).a_tokens, 30);
+
+    // OTHER_USER mints as many liquidity tokens as USER holds, before a swap with a fee of 30 B.
+    provide_liquidity_up_to(&mut state, &OTHER_USER, TOKEN_A, 20_000, TokenAmount::MAX);
+    assert_eq!(
+        state
+            .token_balances
+            .get_balance_for(&OTHER_USER)
+            .liquidity_tokens,
+        10_000
+    );
+    assert_eq!(state.claimable_fees(&OTHER_USER), EMPTY_BALANCE);
+    instant_swap_internal(&mut state, TOKEN_B, 10_000, 0, USER);
+
+    assert_eq!(
+        state.claimable_fees(&USER),
+        TokenBalance {
+            a_tokens: 30,
+            b_tokens: 15,
+            liquidity_tokens: 0,
+        }
+    );
+    assert_eq!(
+        state.claimable_fees(&OTHER_USER),
+        TokenBalance {
+            a_tokens: 0,
+            b_tokens: 15,
+            liquidity_tokens: 0,
+        }
+    );
+}
+
+#[test]
+fn fee_growth_handles_large_liquidity() {
+    // Pools whose total liquidity exceeds 1e27, such that the scaled fee growth and the
+    // attributed fees exceed u128 before dividing.
+    let pool = 2 * 10u128.pow(27);
+    let mut state = empty_state();
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, pool);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::B, pool);
+    provide_liquidity_internal(&mut state, &USER, TokensInOut::A_IN_B_OUT, pool, pool, pool);
+
+    // A fee of 6e26 A, shared by 2e27 liquidity tokens.
+    accrue_fees(&mut state, Token::A, 2 * 10u128.pow(29), 3);
+    assert_eq!(
+        state.fee_growth_per_liquidity.a_tokens,
+        3 * FEE_GROWTH_SCALE / 10
+    );
+    assert_eq!(state.claimable_fees(&USER).a_tokens, 6 * 10u128.pow(26));
+
+    state.settle_fees(&USER);
+    assert_eq!(state.claimable_fees(&USER).a_tokens, 6 * 10u128.pow(26));
+}
+
+#[test]
+fn settled_fees_survive_reclaiming() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 10_000);
+    instant_swap_internal(&mut state, TOKEN_A, 10_000, 0, OTHER_USER);
+
+    let (state, _) = reclaim_liquidity(context(USER), state, 10_000);
+    assert_eq!(state.claimable_fees(&USER).a_tokens, 30);
+}
+
+/// Initializes the pools with a single liquidity token held by [`OTHER_USER`], whose value is
+/// then inflated by swapping back and forth, accumulating swap fees in the pools.
+fn inflated_single_share_state() -> LiquiditySwapContractState {
+    let mut state = empty_state();
+    state.minimum_initial_liquidity = 0;
+    state.swap_fee_per_mille = 1000;
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 1_000_001);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::B, 1);
+
+    let (mut state, _) = provide_initial_liquidity(context(OTHER_USER), state, 1, 1);
+    // With a fee of 1000 per mille, the entire input is donated to the pools.
+    instant_swap_internal(&mut state, TOKEN_A, 1_000_000, 0, OTHER_USER);
+    state
+}
+
+#[test]
+fn inflated_share_mints_zero_liquidity_without_minimum() {
+    let state = inflated_single_share_state();
+    let summary = state.summary();
+    assert_eq!(summary.total_liquidity, 1);
+    assert_eq!(summary.reserve_a, 1_000_001);
+
+    // A victim providing less than the inflated pool mints no liquidity tokens.
+    let (_, minted) = calculate_equivalent_and_minted_tokens(
+        500_000,
+        summary.reserve_a,
+        summary.reserve_b,
+        summary.total_liquidity,
+    );
+    assert_eq!(minted, 0);
+}
+
+#[test]
+#[should_panic(
+    expected = "Initial liquidity must mint more than 1000 liquidity tokens, but minted 1000"
+)]
+fn minimum_initial_liquidity_prevents_single_share() {
+    let mut state = empty_state();
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 1000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::B, 1000);
+
+    provide_initial_liquidity(context(OTHER_USER), state, 1000, 1000);
+}
+
+#[test]
+fn locked_initial_liquidity_defeats_share_inflation() {
+    let mut state = empty_state();
+    state.swap_fee_per_mille = 1000;
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 1_001_001);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::B, 1001);
+
+    // The attacker keeps a single liquidity token, while the minimum stays locked.
+    let (mut state, _) = provide_initial_liquidity(context(OTHER_USER), state, 1001, 1001);
+    assert_eq!(state.summary().total_liquidity, 1001);
+    assert_eq!(
+        state
+            .token_balances
+            .get_balance_for(&OTHER_USER)
+            .liquidity_tokens,
+        1
+    );
+
+    // Donating to the pools mostly benefits the locked liquidity.
+    instant_swap_internal(&mut state, TOKEN_A, 1_000_000, 0, OTHER_USER);
+    let summary = state.summary();
+    assert_eq!(summary.reserve_a, 1_001_001);
+
+    // A victim providing less than the inflated pool still mints liquidity tokens.
+    let (_, minted) = calculate_equivalent_and_minted_tokens(
+        500_000,
+        summary.reserve_a,
+        summary.reserve_b,
+        summary.total_liquidity,
+    );
+    assert_eq!(minted, 499);
+
+    // The attacker recovers only a thousandth of the donation.
+    let (state, _) = reclaim_liquidity(context(OTHER_USER), state, 1);
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER).a_tokens,
+        1000
+    );
+}
+
+#[test]
+fn locked_initial_liquidity_is_recorded_until_reset() {
+    let mut state = empty_state();
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::B, 10_000);
+    let (state, _) = provide_initial_liquidity(context(USER), state, 10_000, 10_000);
+    assert_eq!(state.locked_liquidity, DEFAULT_MINIMUM_INITIAL_LIQUIDITY);
+
+    // Changing the minimum does not affect the liquidity already locked.
+    let (mut state, _) = set_minimum_initial_liquidity(context(USER), state, 5);
+    assert_eq!(state.locked_liquidity, DEFAULT_MINIMUM_INITIAL_LIQUIDITY);
+
+    state
+        .token_balances
+        .move_tokens(CONTRACT, USER, Token::A, 10_000);
+    state
+        .token_balances
+        .move_tokens(CONTRACT, USER, Token::B, 10_000);
+    reset_drained_pools_internal(&mut state);
+    assert_eq!(state.locked_liquidity, 0);
+}
+
+#[test]
+#[should_panic(expected = "Both initial amounts must be positive")]
+fn initial_liquidity_rejects_zero_a() {
+    provide_initial_liquidity(context(USER), empty_state(), 0, 10_000);
+}
+
+#[test]
+#[should_panic(expected = "Both initial amounts must be positive")]
+fn initial_liquidity_rejects_zero_b() {
+    provide_initial_liquidity(context(USER), empty_state(), 10_000, 0);
+}
+
+#[test]
+fn next_lock_id_reflects_acquisitions() {
+    let mut state = state_with_pools(10_000, 10_000);
+    let initial_lock_id = state.virtual_state.next_lock_id();
+    assert_eq!(initial_lock_id, LiquidityLockId::initial_id());
+    assert_eq!(state.virtual_state.next_lock_id(), initial_lock_id);
+
+    let (lock_id, _) = lock_internal(&mut state, 100, TOKEN_A, 0, USER, None, None);
+    assert_eq!(lock_id, initial_lock_id);
+    assert_eq!(state.virtual_state.next_lock_id(), initial_lock_id.next());
+
+    // Cancelling does not reuse lock ids.
+    state.virtual_state.remove_lock(lock_id, USER);
+    assert_eq!(state.virtual_state.next_lock_id(), initial_lock_id.next());
+}
+
+#[test]
+fn migrated_contract_continues_lock_ids() {
+    let mut old_state = state_with_pools(10_000, 10_000);
+    lock_internal(&mut old_state, 100, TOKEN_A, 0, USER, None, None);
+    lock_internal(&mut old_state, 100, TOKEN_A, 0, USER, None, None);
+
+    let mut new_state = state_with_pools(10_000, 10_000);
+    new_state.virtual_state =
+        VirtualState::with_initial_lock_id(old_state.virtual_state.next_lock_id());
+    let (lock_id, _) = lock_internal(&mut new_state, 100, TOKEN_A, 0, USER, None, None);
+    assert_eq!(lock_id, old_state.virtual_state.next_lock_id());
+}
+
+#[test]
+fn lock_owner_summary_counts_locks_per_owner() {
+    let mut state = state_with_pools(10_000, 10_000);
+    assert_eq!(state.virtual_state.lock_owner_summary(), vec![]);
+
+    lock_internal(&mut state, 100, TOKEN_A, 0, OTHER_USER, None, None);
+    let (cancelled_lock_id, _) = lock_internal(&mut state, 100, TOKEN_B, 0, USER, None, None);
+    lock_internal(&mut state, 100, TOKEN_A, 0, USER, None, None);
+    lock_internal(&mut state, 100, TOKEN_B, 0, OTHER_USER, None, None);
+    lock_internal(&mut state, 100, TOKEN_A, 0, OTHER_USER, None, None);
+    assert_eq!(
+        state.virtual_state.lock_owner_summary(),
+        vec![(USER, 2), (OTHER_USER, 3)]
+    );
+
+    state.virtual_state.remove_lock(cancelled_lock_id, USER);
+    assert_eq!(
+        state.virtual_state.lock_owner_summary(),
+        vec![(USER, 1), (OTHER_USER, 3)]
+    );
+}
+
+#[test]
+fn instant_swap_to_credits_recipient() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, 1_000);
+    let expected_out = calculate_swap_to_amount(10_000, 10_000, 1_000, state.swap_fee_per_mille);
+
+    let (state, _) = instant_swap_to(context(USER), state, TOKEN_A, 1_000, 0, OTHER_USER);
+
+    assert_eq!(state.token_balances.get_balance_for(&USER), EMPTY_BALANCE);
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER),
+        TokenBalance {
+            a_tokens: 0,
+            b_tokens: expected_out,
+            liquidity_tokens: 0,
+        }
+    );
+}
+
+/// A callback context with a result per event, succeeding as given by `succeeded`.
+fn callback_context(succeeded: &[bool]) -> CallbackContext {
+    CallbackContext {
+        success: succeeded.iter().all(|succeeded| *succeeded),
+        results: succeeded
+            .iter()
+            .map(|succeeded| ExecutionResult {
+                succeeded: *succeeded,
+                return_data: vec![],
+            })
+            .collect(),
+    }
+}
+
+#[test]
+fn bulk_deposit_credits_both_tokens() {
+    let deposits = vec![
+        Deposit {
+            token_address: TOKEN_B,
+            amount: 200,
+        },
+        Deposit {
+            token_address: TOKEN_A,
+            amount: 100,
+        },
+    ];
+    let (state, event_groups) = bulk_deposit(
+        context(USER),
+        state_with_pools(10_000, 10_000),
+        deposits.clone(),
+    );
+    assert_eq!(event_groups.len(), 1);
+    assert_eq!(event_groups[0].events.len(), 2);
+
+    let (state, _) = bulk_deposit_callback(
+        context(USER),
+        callback_context(&[true, true]),
+        state,
+        deposits,
+    );
+    assert_eq!(
+        state.token_balances.get_balance_for(&USER),
+        TokenBalance {
+            a_tokens: 100,
+            b_tokens: 200,
+            liquidity_tokens: 0,
+        }
+    );
+}
+
+#[test]
+fn bulk_deposit_credits_only_successful_transfers() {
+    let deposits = vec![
+        Deposit {
+            token_address: TOKEN_A,
+            amount: 100,
+        },
+        Deposit {
+            token_address: TOKEN_B,
+            amount: 200,
+        },
+    ];
+    let (state, _) = bulk_deposit_callback(
+        context(USER),
+        callback_context(&[false, true]),
+        state_with_pools(10_000, 10_000),
+        deposits,
+    );
+    assert_eq!(
+        state.token_balances.get_balance_for(&USER),
+        TokenBalance {
+            a_tokens: 0,
+            b_tokens: 200,
+            liquidity_tokens: 0,
+        }
+    );
+}
+
+#[test]
+#[should_panic(expected = "Each token can be deposited at most once")]
+fn bulk_deposit_rejects_repeated_token() {
+    let deposit = Deposit {
+        token_address: TOKEN_A,
+        amount: 100,
+    };
+    bulk_deposit(
+        context(USER),
+        state_with_pools(10_000, 10_000),
+        vec![deposit, deposit],
+    );
+}
+
+#[test]
+fn fee_exempt_user_swaps_without_fee() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state.permission_fee_exempt = Permission::Specific {
+        addresses: vec![OTHER_USER],
+    };
+    state.protocol_fee_share_per_mille = 500;
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 1_000);
+
+    let amount_out = instant_swap_internal(&mut state, TOKEN_A, 1_000, 0, OTHER_USER);
+    assert_eq!(
+        amount_out,
+        calculate_swap_to_amount(10_000, 10_000, 1_000, 0)
+    );
+    assert_eq!(state.accrued_protocol_fees, EMPTY_BALANCE);
+    assert_eq!(state.fee_growth_per_liquidity, EMPTY_BALANCE);
+}
+
+#[test]
+fn fee_exempt_swap_outputs_more_than_normal_swap() {
+    let mut exempt_state = state_with_pools(10_000, 10_000);
+    exempt_state.permission_fee_exempt = Permission::Specific {
+        addresses: vec![OTHER_USER],
+    };
+    let mut normal_state = state_with_pools(10_000, 10_000);
+    for state in [&mut exempt_state, &mut normal_state] {
+        state
+            .token_balances
+            .add_to_token_balance(OTHER_USER, Token::A, 1_000);
+    }
+
+    let exempt_out = instant_swap_internal(&mut exempt_state, TOKEN_A, 1_000, 0, OTHER_USER);
+    let normal_out = instant_swap_internal(&mut normal_state, TOKEN_A, 1_000, 0, OTHER_USER);
+    assert_eq!(exempt_out, 909);
+    assert_eq!(normal_out, 906);
+}
+
+#[test]
+fn delta_to_amount_applies_delta() {
+    assert_eq!(delta_to_amount(1000, 500), Ok(1500));
+    assert_eq!(delta_to_amount(1000, -1000), Ok(0));
+    assert_eq!(
+        delta_to_amount(1000, -1001),
+        Err("Virtual reserve underflow")
+    );
+    assert_eq!(
+        delta_to_amount(TokenAmount::MAX, 1),
+        Err("Virtual reserve overflow")
+    );
+}
+
+#[test]
+fn lock_can_be_executed_in_portions() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 1_000);
+    let (lock_id, amount_out) =
+        lock_internal(&mut state, 1_000, TOKEN_A, 0, OTHER_USER, None, None);
+    assert_eq!(amount_out, 906);
+
+    let (state, _) = execute_lock_swap_partial(context(OTHER_USER), state, lock_id, 500);
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER),
+        TokenBalance {
+            a_tokens: 500,
+            b_tokens: 453,
+            liquidity_tokens: 0,
+        }
+    );
+    let remainder = state.virtual_state.get_lock(&lock_id).unwrap();
+    assert_eq!((remainder.amount_in, remainder.amount_out), (500, 453));
+    assert_eq!(state.virtual_state.lock_liquidity.a_tokens, 500);
+    assert_eq!(state.virtual_state.lock_liquidity.b_tokens, -453);
+
+    let (state, _) = execute_lock_swap_partial(context(OTHER_USER), state, lock_id, 500);
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER),
+        TokenBalance {
+            a_tokens: 0,
+            b_tokens: 906,
+            liquidity_tokens: 0,
+        }
+    );
+    assert!(state.virtual_state.get_lock(&lock_id).is_none());
+    assert_eq!(state.virtual_state.lock_liquidity.a_tokens, 0);
+    assert_eq!(state.virtual_state.lock_liquidity.b_tokens, 0);
+}
+
+#[test]
+fn large_lock_can_be_split() {
+    let mut virtual_state = VirtualState::new();
+    let lock_id = LiquidityLockId::initial_id();
+    // Amounts whose product exceeds u128.
+    let amount = 10u128.pow(30);
+    virtual_state.insert_lock(
+        lock_id,
+        LiquidityLock {
+            amount_in: amount,
+            amount_out: 2 * amount,
+            tokens_in_out: TokensInOut::A_IN_B_OUT,
+            owner: OTHER_USER,
+            swap_fee_per_mille: 3,
+            scheduled_execution_utc_millis: None,
+            expires_utc_millis: None,
+            cancel_on_failed_execution: false,
+        },
+    );
+
+    let portion = virtual_state.split_lock(lock_id, OTHER_USER, amount / 4);
+    assert_eq!(
+        (portion.amount_in, portion.amount_out),
+        (amount / 4, amount / 2)
+    );
+    let remainder = virtual_state.get_lock(&lock_id).unwrap();
+    assert_eq!(
+        (remainder.amount_in, remainder.amount_out),
+        (amount / 4 * 3, amount / 2 * 3)
+    );
+}
+
+#[test]
+#[should_panic(expected = "Lock amount exceeds the range of the lock liquidity")]
+fn lock_above_token_delta_range_is_rejected() {
+    let mut virtual_state = VirtualState::new();
+    // Would wrap to a negative delta, if cast.
+    virtual_state.insert_lock(
+        LiquidityLockId::initial_id(),
+        LiquidityLock {
+            amount_in: i128::MAX as TokenAmount + 1,
+            amount_out: 1,
+            tokens_in_out: TokensInOut::A_IN_B_OUT,
+            owner: OTHER_USER,
+            swap_fee_per_mille: 3,
+            scheduled_execution_utc_millis: None,
+            expires_utc_millis: None,
+            cancel_on_failed_execution: false,
+        },
+    );
+}
+
+#[test]
+fn lock_at_token_delta_limit_updates_lock_liquidity() {
+    let mut virtual_state = VirtualState::new();
+    let lock_id = LiquidityLockId::initial_id();
+    virtual_state.insert_lock(
+        lock_id,
+        LiquidityLock {
+            amount_in: i128::MAX as TokenAmount,
+            amount_out: i128::MAX as TokenAmount,
+            tokens_in_out: TokensInOut::A_IN_B_OUT,
+            owner: OTHER_USER,
+            swap_fee_per_mille: 3,
+            scheduled_execution_utc_millis: None,
+            expires_utc_millis: None,
+            cancel_on_failed_execution: false,
+        },
+    );
+    assert_eq!(virtual_state.lock_liquidity.a_tokens, i128::MAX);
+    assert_eq!(virtual_state.lock_liquidity.b_tokens, -i128::MAX);
+
+    virtual_state.remove_lock(lock_id, OTHER_USER);
+    assert_eq!(virtual_state.lock_liquidity.a_tokens, 0);
+    assert_eq!(virtual_state.lock_liquidity.b_tokens, 0);
+}
+
+#[test]
+#[should_panic(expected = "Portion must be in range [1,1000], but was 1001")]
+fn lock_portion_cannot_exceed_lock() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 1_000);
+    let (lock_id, _) = lock_internal(&mut state, 1_000, TOKEN_A, 0, OTHER_USER, None, None);
+
+    execute_lock_swap_partial(context(OTHER_USER), state, lock_id, 1_001);
+}
+
+#[test]
+fn user_exposure_includes_lock_inputs() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::B, 300);
+    lock_internal(&mut state, 1_000, TOKEN_A, 0, OTHER_USER, None, None);
+    lock_internal(&mut state, 200, TOKEN_B, 0, OTHER_USER, None, None);
+    lock_internal(&mut state, 500, TOKEN_A, 0, USER, None, None);
+
+    assert_eq!(
+        state.user_exposure(&OTHER_USER),
+        TokenBalance {
+            a_tokens: 1_000,
+            b_tokens: 500,
+            liquidity_tokens: 0,
+        }
+    );
+}
+
+/// Creates a state where [`USER`] is the exclusive liquidity provider until time 1000, and
+/// [`OTHER_USER`] holds tokens to provide liquidity with.
+fn state_with_exclusive_lp() -> LiquiditySwapContractState {
+    let mut state = state_with_pools(10_000, 10_000);
+    state.exclusive_lp = Some(ExclusiveLiquidityProvider {
+        provider: USER,
+        end_utc_millis: 1000,
+    });
+    for user in [USER, OTHER_USER] {
+        state
+            .token_balances
+            .add_to_token_balance(user, Token::A, 1_000);
+        state
+            .token_balances
+            .add_to_token_balance(user, Token::B, 1_000);
+    }
+    state
+}
+
+#[test]
+fn exclusive_lp_provides_during_grace_period() {
+    let (state, _) = provide_liquidity(
+        context_at(USER, 999),
+        state_with_exclusive_lp(),
+        TOKEN_A,
+        100,
+    );
+    assert_eq!(
+        state.token_balances.get_balance_for(&USER).liquidity_tokens,
+        10_100
+    );
+}
+
+#[test]
+#[should_panic(expected = "Only the exclusive liquidity provider can provide liquidity until 1000")]
+fn other_lp_blocked_during_grace_period() {
+    provide_liquidity(
+        context_at(OTHER_USER, 999),
+        state_with_exclusive_lp(),
+        TOKEN_A,
+        100,
+    );
+}
+
+#[test]
+fn other_lp_allowed_after_grace_period() {
+    let (state, _) = provide_liquidity(
+        context_at(OTHER_USER, 1000),
+        state_with_exclusive_lp(),
+        TOKEN_A,
+        100,
+    );
+    assert_eq!(
+        state
+            .token_balances
+            .get_balance_for(&OTHER_USER)
+            .liquidity_tokens,
+        100
+    );
+}
+
+#[test]
+fn preview_provide_matches_provided_liquidity() {
+    let mut state = state_with_pools(10_000, 20_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 1_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::B, 1_000);
+
+    // Token B limits the amounts provided.
+    let preview = state.preview_provide(1_000, 1_000);
+    assert_eq!(preview, (500, 1_000, 707));
+    provide_liquidity_up_to(&mut state, &OTHER_USER, TOKEN_B, 1_000, TokenAmount::MAX);
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER),
+        TokenBalance {
+            a_tokens: 1_000 - preview.0,
+            b_tokens: 1_000 - preview.1,
+            liquidity_tokens: preview.2,
+        }
+    );
+
+    // Token A limits the amounts provided.
+    let preview = state.preview_provide(100, 1_000);
+    assert_eq!(preview.0, 100);
+    assert!(preview.1 < 1_000);
+    assert_eq!(preview.2, 100 * 14_849 / 10_500);
+}
+
+#[test]
+fn preview_provide_without_liquidity() {
+    assert_eq!(empty_state().preview_provide(1_000, 1_000), (0, 0, 0));
+}
+
+/// Creates a state without liquidity, where [`USER`] holds tokens to initialize the pools with.
+fn state_ready_for_initial_liquidity() -> LiquiditySwapContractState {
+    let mut state = empty_state();
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, 11_000);
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::B, 10_000);
+    state
+}
+
+#[test]
+fn provide_initial_liquidity_and_lock_seeds_and_locks() {
+    let (state, event_groups) = provide_initial_liquidity_and_lock(
+        context(USER),
+        state_ready_for_initial_liquidity(),
+        10_000,
+        10_000,
+        TOKEN_A,
+        1_000,
+        0,
+    );
+    assert_eq!(event_groups.len(), 1);
+
+    assert_eq!(
+        state.token_balances.get_balance_for(&CONTRACT),
+        TokenBalance {
+            a_tokens: 10_000,
+            b_tokens: 10_000,
+            liquidity_tokens: 10_000,
+        }
+    );
+    assert!(state.has_been_initialized);
+    let lock = state
+        .virtual_state
+        .get_lock(&LiquidityLockId::initial_id())
+        .unwrap();
+    assert_eq!((lock.amount_in, lock.amount_out), (1_000, 906));
+    assert_eq!(lock.owner, USER);
+}
+
+#[test]
+#[should_panic(expected = "did not have permission \"lock swap\"")]
+fn provide_initial_liquidity_and_lock_requires_lock_permission() {
+    let mut state = state_ready_for_initial_liquidity();
+    state.permission_lock_swap = Permission::Specific {
+        addresses: vec![OTHER_USER],
+    };
+
+    provide_initial_liquidity_and_lock(context(USER), state, 10_000, 10_000, TOKEN_A, 1_000, 0);
+}
+
+#[test]
+fn estimate_swap_gas_grows_with_locks() {
+    let mut state = state_with_pools(1_000_000, 1_000_000);
+    let estimate_without_locks = estimate_swap_gas(&state);
+    assert_eq!(
+        estimate_without_locks,
+        GAS_COST_SWAP_BASE + 2 * GAS_COST_SWAP_PER_LOCK_LEVEL
+    );
+
+    let mut previous_estimate = estimate_without_locks;
+    for _ in 0..1000 {
+        lock_internal(&mut state, 1, TOKEN_A, 0, USER, None, None);
+        let estimate = estimate_swap_gas(&state);
+        assert!(estimate >= previous_estimate);
+        previous_estimate = estimate;
+    }
+    // 1001 locks fit in a tree of height 10.
+    assert_eq!(
+        previous_estimate,
+        GAS_COST_SWAP_BASE + 2 * 10 * GAS_COST_SWAP_PER_LOCK_LEVEL
+    );
+}
+
+#[test]
+fn auto_cancel_lock_is_cancelled_on_unfunded_execution() {
+    let state = state_with_pools(10_000, 10_000);
+    let lock_id = state.virtual_state.next_lock_id();
+    let (state, _) = acquire_swap_lock_auto_cancel(context(OTHER_USER), state, TOKEN_A, 1_000, 0);
+    assert!(
+        state
+            .virtual_state
+            .get_lock(&lock_id)
+            .unwrap()
+            .cancel_on_failed_execution
+    );
+
+    let (state, _) = execute_lock_swap(context(OTHER_USER), state, lock_id);
+    assert!(state.virtual_state.get_lock(&lock_id).is_none());
+    assert_eq!(state.virtual_state.lock_liquidity.a_tokens, 0);
+    assert_eq!(state.virtual_state.lock_liquidity.b_tokens, 0);
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER),
+        EMPTY_BALANCE
+    );
+    let pools = state.token_balances.get_balance_for(&CONTRACT);
+    assert_eq!((pools.a_tokens, pools.b_tokens), (10_000, 10_000));
+}
+
+#[test]
+fn auto_cancel_lock_swaps_when_funded() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 1_000);
+    let lock_id = state.virtual_state.next_lock_id();
+    let (state, _) = acquire_swap_lock_auto_cancel(context(OTHER_USER), state, TOKEN_A, 1_000, 0);
+
+    let (state, _) = execute_lock_swap(context(OTHER_USER), state, lock_id);
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER).b_tokens,
+        906
+    );
+}
+
+#[test]
+#[should_panic(expected = "Insufficient TokenA {} deposit: 0/1000")]
+fn lock_without_auto_cancel_fails_on_unfunded_execution() {
+    let state = state_with_pools(10_000, 10_000);
+    let lock_id = state.virtual_state.next_lock_id();
+    let (state, _) = acquire_swap_lock(context(OTHER_USER), state, TOKEN_A, 1_000, 0);
+
+    execute_lock_swap(context(OTHER_USER), state, lock_id);
+}
+
+#[test]
+fn list_locks_clamps_page_size() {
+    let mut state = state_with_pools(10_000, 10_000);
+    for _ in 0..=MAX_LOCKS_PER_PAGE {
+        lock_internal(&mut state, 1, TOKEN_A, 0, USER, None, None);
+    }
+
+    let (locks, next) = state.virtual_state.list_locks(None, u32::MAX);
+    assert_eq!(locks.len(), MAX_LOCKS_PER_PAGE as usize);
+    let (locks, next) = state.virtual_state.list_locks(next, u32::MAX);
+    assert_eq!(locks.len(), 1);
+    assert_eq!(next, None);
+}
+
+#[test]
+fn list_locks_paginates_in_fixed_size_pages() {
+    let mut state = state_with_pools(10_000, 10_000);
+    let lock_ids: Vec<LiquidityLockId> = (1..=7)
+        .map(|amount_in| lock_internal(&mut state, amount_in, TOKEN_A, 0, USER, None, None).0)
+        .collect();
+
+    let mut cursor = None;
+    let mut pages = vec![];
+    loop {
+        let (locks, next) = state.virtual_state.list_locks(cursor, 3);
+        pages.push(locks);
+        cursor = next;
+        if cursor.is_none() {
+            break;
+        }
+    }
+
+    assert_eq!(
+        pages.iter().map(Vec::len).collect::<Vec<_>>(),
+        vec![3, 3, 1]
+    );
+    let listed = pages.concat();
+    assert_eq!(
+        listed
+            .iter()
+            .map(|(lock_id, _)| *lock_id)
+            .collect::<Vec<_>>(),
+        lock_ids
+    );
+    assert_eq!(
+        listed
+            .iter()
+            .map(|(_, lock)| lock.amount_in)
+            .collect::<Vec<_>>(),
+        (1..=7).collect::<Vec<TokenAmount>>()
+    );
+}
+
+#[test]
+fn token_addresses_match_initialization() {
+    let (state, _) = initialize(
+        context(USER),
+        TOKEN_B,
+        TOKEN_A,
+        3,
+        InitialPermissions {
+            permission_lock_swap: Permission::Anybody {},
+            permission_admin: Permission::Anybody {},
+            permission_swap_on_behalf: Permission::Anybody {},
+            permission_fee_exempt: Permission::Anybody {},
+        },
+        None,
+    );
+    assert_eq!(state.token_addresses(), (TOKEN_B, TOKEN_A));
+}
+
+#[test]
+fn balanced_dust_is_provided_without_swap() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 100);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::B, 102);
+
+    let (state, _) = provide_dust_as_liquidity(context(OTHER_USER), state, 0);
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER),
+        TokenBalance {
+            a_tokens: 0,
+            b_tokens: 2,
+            liquidity_tokens: 100,
+        }
+    );
+    let pools = state.token_balances.get_balance_for(&CONTRACT);
+    assert_eq!((pools.a_tokens, pools.b_tokens), (10_100, 10_100));
+}
+
+#[test]
+fn large_balanced_dust_does_not_overflow() {
+    let mut state = state_with_pools(1, 1);
+    let amount = u128::MAX / 2;
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, amount);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::B, amount);
+
+    let (state, _) = provide_dust_as_liquidity(context(OTHER_USER), state, 0);
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER),
+        TokenBalance {
+            a_tokens: 0,
+            b_tokens: 0,
+            liquidity_tokens: amount,
+        }
+    );
+}
+
+#[test]
+fn one_sided_dust_is_zapped_into_liquidity() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 200);
+
+    // Half of the A dust is swapped to 98 B, after which both sides are provided.
+    let (state, _) = provide_dust_as_liquidity(context(OTHER_USER), state, 0);
+    assert_eq!(
+        state.token_balances.get_balance_for(&OTHER_USER),
+        TokenBalance {
+            a_tokens: 0,
+            b_tokens: 0,
+            liquidity_tokens: 98,
+        }
+    );
+    let pools = state.token_balances.get_balance_for(&CONTRACT);
+    assert_eq!((pools.a_tokens, pools.b_tokens), (10_200, 10_000));
+}
+
+#[test]
+#[should_panic(expected = "Dust would mint 98 liquidity tokens, but minimum was set to 99.")]
+fn dust_below_minimum_liquidity_is_rejected() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 200);
+
+    provide_dust_as_liquidity(context(OTHER_USER), state, 99);
+}
+
+#[test]
+#[should_panic(expected = "The dust balances yielded 0 minted liquidity")]
+fn dust_without_balance_is_rejected() {
+    let state = state_with_pools(10_000, 10_000);
+    provide_dust_as_liquidity(context(OTHER_USER), state, 0);
+}
+
+#[test]
+fn one_sided_pools_are_detected() {
+    let mut state = empty_state();
+    assert!(!state.is_one_sided());
+
+    state
+        .token_balances
+        .add_to_token_balance(CONTRACT, Token::A, 1000);
+    assert!(state.is_one_sided());
+    assert!(!state.contract_pools_have_liquidity());
+
+    state
+        .token_balances
+        .add_to_token_balance(CONTRACT, Token::B, 1000);
+    assert!(!state.is_one_sided());
+
+    state
+        .token_balances
+        .deduct_from_token_balance(CONTRACT, Token::A, 1000);
+    assert!(state.is_one_sided());
+}
+
+#[test]
+#[should_panic(expected = "Cannot reclaim liquidity while locks are present.")]
+fn reclaim_blocked_by_locks_outside_emergency_mode() {
+    let mut state = state_with_pools(10_000, 10_000);
+    lock_internal(&mut state, 1000, TOKEN_A, 0, OTHER_USER, None, None);
+    reclaim_liquidity(context(USER), state, 5000);
+}
+
+#[test]
+fn reclaim_ignores_locks_in_emergency_mode() {
+    let mut state = state_with_pools(10_000, 10_000);
+    lock_internal(&mut state, 1000, TOKEN_A, 0, OTHER_USER, None, None);
+
+    let (state, _) = set_emergency_mode(context(USER), state, true);
+    let (state, events) = reclaim_liquidity(context(USER), state, 5000);
+    assert_eq!(events.len(), 1);
+    assert_eq!(
+        state.token_balances.get_balance_for(&USER),
+        TokenBalance {
+            a_tokens: 5000,
+            b_tokens: 5000,
+            liquidity_tokens: 5000,
+        }
+    );
+    assert_eq!(state.virtual_state.locks_len(), 1);
+}
+
+#[test]
+#[should_panic(expected = "did not have permission \"admin\"")]
+fn emergency_mode_requires_admin() {
+    set_emergency_mode(context(OTHER_USER), state_with_pools(10_000, 10_000), true);
+}
+
+/// Creates a state with pools, where [`OTHER_USER`] has 1000 of each token and swaps are limited
+/// to one per 100 milliseconds.
+fn rate_limited_state() -> LiquiditySwapContractState {
+    let mut state = state_with_pools(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 1_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::B, 1_000);
+    let (state, _) = set_min_swap_interval(context(USER), state, 100);
+    state
+}
+
+#[test]
+#[should_panic(expected = "Swaps are limited to one per 100 milliseconds")]
+fn rapid_second_swap_is_rejected() {
+    let state = rate_limited_state();
+    let (state, _) = instant_swap(context_at(OTHER_USER, 1000), state, TOKEN_A, 100, 0);
+    instant_swap(context_at(OTHER_USER, 1099), state, TOKEN_B, 100, 0);
+}
+
+#[test]
+fn spaced_out_swaps_succeed() {
+    let mut state = rate_limited_state();
+    state
+        .token_balances
+        .add_to_token_balance(USER, Token::A, 1_000);
+    let (state, _) = instant_swap(context_at(OTHER_USER, 1000), state, TOKEN_A, 100, 0);
+    let (state, _) = instant_swap(context_at(OTHER_USER, 1100), state, TOKEN_B, 100, 0);
+    // Other users are limited separately.
+    let (state, _) = instant_swap(context_at(USER, 1100), state, TOKEN_A, 100, 0);
+
+    assert_eq!(state.last_swap_millis.get(&OTHER_USER), Some(1100));
+    assert_eq!(state.last_swap_millis.get(&USER), Some(1100));
+}
+
+#[test]
+fn swaps_are_not_recorded_without_limit() {
+    let mut state = state_with_pools(10_000, 10_000);
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 1_000);
+    let (state, _) = instant_swap(context_at(OTHER_USER, 1000), state, TOKEN_A, 100, 0);
+    let (state, _) = instant_swap(context_at(OTHER_USER, 1000), state, TOKEN_A, 100, 0);
+    assert_eq!(state.last_swap_millis.get(&OTHER_USER), None);
+}
+
+#[test]
+fn disabling_swap_interval_clears_recorded_swaps() {
+    let state = rate_limited_state();
+    let (state, _) = instant_swap(context_at(OTHER_USER, 1000), state, TOKEN_A, 100, 0);
+    assert_eq!(state.last_swap_millis.len(), 1);
+
+    let (state, _) = set_min_swap_interval(context(USER), state, 0);
+    assert_eq!(state.last_swap_millis.len(), 0);
+    assert_eq!(state.last_swap_millis.get(&OTHER_USER), None);
+}
+
+#[test]
+#[should_panic(expected = "Swaps are limited to one per 100 milliseconds")]
+fn dust_swap_right_after_swap_is_rejected() {
+    let state = rate_limited_state();
+    let (state, _) = instant_swap(context_at(OTHER_USER, 1000), state, TOKEN_A, 100, 0);
+    // The swap leaves the balances imbalanced, so providing them as dust swaps again.
+    provide_dust_as_liquidity(context_at(OTHER_USER, 1050), state, 0);
+}
+
+#[test]
+fn dust_swap_is_rate_limited_like_swaps() {
+    let mut state = rate_limited_state();
+    state
+        .token_balances
+        .add_to_token_balance(OTHER_USER, Token::A, 1_000);
+    let (state, _) = provide_dust_as_liquidity(context_at(OTHER_USER, 1000), state, 0);
+    assert_eq!(state.last_swap_millis.get(&OTHER_USER), Some(1000));
+}
+
+#[test]
+fn balanced_dust_is_not_rate_limited() {
+    let state = rate_limited_state();
+    let (state, _) = provide_dust_as_liquidity(context_at(OTHER_USER, 1000), state, 0);
+    assert_eq!(state.last_swap_millis.get(&OTHER_USER), None);
+}
+
+#[test]
+#[should_panic(expected = "did not have permission \"admin\"")]
+fn min_swap_interval_requires_admin() {
+    set_min_swap_interval(context(OTHER_USER), state_with_pools(10_000, 10_000), 100);
+}
+
+/// Creates a state with pools, where at most 3 locks can be held in total.
+fn state_with_lock_cap() -> LiquiditySwapContractState {
+    let (mut state, _) = set_max_total_locks(context(USER), state_with_pools(10_000, 10_000), 3);
+    lock_internal(&mut state, 100, TOKEN_A, 0, USER, None, None);
+    lock_internal(&mut state, 100, TOKEN_B, 0, OTHER_USER, None, None);
+    lock_internal(&mut state, 100, TOKEN_A, 0, OTHER_USER, None, None);
+    state
+}
+
+#[test]
+#[should_panic(expected = "Cannot hold more than 3 locks in total")]
+fn lock_beyond_total_cap_is_rejected() {
+    let mut state = state_with_lock_cap();
+    lock_internal(&mut state, 100, TOKEN_A, 0, USER, None, None);
+}
+
+#[test]
+fn cancelled_lock_frees_room_below_total_cap() {
+    let mut state = state_with_lock_cap();
+    state
+        .virtual_state
+        .remove_lock(LiquidityLockId::initial_id(), USER);
+
+    lock_internal(&mut state, 100, TOKEN_A, 0, USER, None, None);
+    assert_eq!(state.virtual_state.locks_len(), 3);
+}
+
+#[test]
+#[should_panic(expected = "did not have permission \"admin\"")]
+fn max_total_locks_requires_admin() {
+    set_max_total_locks(context(OTHER_USER), state_with_pools(10_000, 10_000), 3);
+}
+
 #[test]
 fn test_token_clone() {
     assert_eq!(Token::A, Token::A.clone());
@@ -107,6 +3048,77 @@ proptest! {
         );
     }
 
+    #[test]
+    fn required_token_out_for_matches_provided_equivalent(
+        provided_amount in any::<u64>(),
+        provided_pool in 1..u64::MAX,
+        opposite_pool in any::<u64>(),
+        total_minted_liquidity in any::<u64>()
+    ) {
+        let (token_out_equivalent, _) = calculate_equivalent_and_minted_tokens(
+            provided_amount.into(),
+            provided_pool.into(),
+            opposite_pool.into(),
+            total_minted_liquidity.into(),
+        );
+        assert_eq!(
+            required_token_out_for(provided_amount.into(), provided_pool.into(), opposite_pool.into()),
+            token_out_equivalent
+        );
+    }
+
+    #[test]
+    fn virtual_invariant_holds_across_lock_operations(
+        pool_a in 10_000..u32::MAX,
+        pool_b in 10_000..u32::MAX,
+        operations in prop::collection::vec((0u8..3, any::<bool>(), 1..u16::MAX), 1..30),
+    ) {
+        let mut state = state_with_pools(pool_a.into(), pool_b.into());
+        let mut lock_ids = vec![];
+
+        for (operation, a_in, amount_in) in operations {
+            match operation {
+                0 => {
+                    let token_address = if a_in { TOKEN_A } else { TOKEN_B };
+                    let (lock_id, _) = lock_internal(&mut state, amount_in.into(), token_address, 0, USER, None, None);
+                    lock_ids.push(lock_id);
+                }
+                1 if !lock_ids.is_empty() => {
+                    let lock_id = lock_ids.remove(0);
+                    let lock = state.virtual_state.get_lock(&lock_id).unwrap();
+                    state.token_balances.add_to_token_balance(USER, lock.tokens_in_out.token_in, lock.amount_in);
+                    execute_lock_swap_internal(&mut state, lock_id, USER);
+                }
+                2 if !lock_ids.is_empty() => {
+                    state.virtual_state.remove_lock(lock_ids.remove(0), USER);
+                }
+                _ => {}
+            }
+            assert_virtual_invariant(&state);
+        }
+    }
+
+    #[test]
+    fn invariant_never_decreases_across_swaps(
+        pool_a in 1000..u32::MAX,
+        pool_b in 1000..u32::MAX,
+        protocol_fee_share_per_mille in 0u16..=1000,
+        swaps in prop::collection::vec((any::<bool>(), 1..u32::MAX), 1..20),
+    ) {
+        let mut state = state_with_pools(pool_a.into(), pool_b.into());
+        state.fee_recipient = Some(OTHER_USER);
+        state.protocol_fee_share_per_mille = protocol_fee_share_per_mille;
+
+        for (a_in, amount_in) in swaps {
+            let (token, token_address) = if a_in { (Token::A, TOKEN_A) } else { (Token::B, TOKEN_B) };
+            state.token_balances.add_to_token_balance(USER, token, amount_in.into());
+
+            let invariant_before = state.invariant_with_fees();
+            instant_swap_internal(&mut state, token_address, amount_in.into(), 0, USER);
+            assert!(state.invariant_with_fees() >= invariant_before);
+        }
+    }
+
     #[test]
     fn calculate_reclaim_output_must_not_crash(
         liquidity_token_amount in any::<u64>(),
@@ -215,6 +3227,48 @@ mod test {
         assert_eq!(output_liquidity_tokens, 9); // Explicit case of minting 1 less token, despite being very close to expected value of 10
     }
 
+    #[test]
+    pub fn test_calculate_minted_for_pair() {
+        // Equal token values, providing 10% of each token.
+        let (output_b, single_sided_minted) =
+            calculate_equivalent_and_minted_tokens(10, 100, 100, 100);
+        assert_eq!(output_b, 11);
+        assert_eq!(single_sided_minted, 10);
+
+        // The pair mints the same without the additional token.
+        assert_eq!(calculate_minted_for_pair(10, 10, 100, 100, 100), 10);
+        assert_eq!(
+            calculate_minted_for_pair(10, output_b, 100, 100, 100),
+            single_sided_minted
+        );
+        // The smaller share is minted.
+        assert_eq!(calculate_minted_for_pair(10, 5, 100, 100, 100), 5);
+        assert_eq!(calculate_minted_for_pair(20, 10, 100, 100, 100), 10);
+
+        // Equal token values, providing (approximately) 10% of each token.
+        let (output_b, single_sided_minted) =
+            calculate_equivalent_and_minted_tokens(9999, 99999, 99999, 100);
+        assert_eq!(output_b, 10000);
+        assert_eq!(
+            calculate_minted_for_pair(9999, 9999, 99999, 99999, 100),
+            single_sided_minted
+        );
+
+        // Token A worth 2 token B, providing 25% of each token.
+        let (output_b, single_sided_minted) =
+            calculate_equivalent_and_minted_tokens(25, 100, 200, 100);
+        assert_eq!(output_b, 51);
+        assert_eq!(single_sided_minted, 25);
+        assert_eq!(calculate_minted_for_pair(25, 50, 100, 200, 100), 25);
+
+        // Products exceeding u128 are computed exactly.
+        let pool = 10u128.pow(30);
+        assert_eq!(
+            calculate_minted_for_pair(pool / 10, pool / 10, pool, pool, pool),
+            pool / 10
+        );
+    }
+
     #[test]
     pub fn test_calculate_updated_liquidity_reclaim() {
         // Equal token values, reclaiming 10% of total shares
@@ -1,20 +1,73 @@
 #![doc = include_str!("../README.md")]
 #![allow(unused_variables)]
 
+#[cfg(test)]
+mod tests;
+
 use core::*;
 
+use create_type_spec_derive::CreateTypeSpec;
 use pbc_contract_codegen::*;
 use pbc_contract_common::{
     address::Address,
+    avl_tree_map::AvlTreeMap,
     context::{CallbackContext, ContractContext},
     events::{EventGroup, GasCost},
 };
+use read_write_state_derive::ReadWriteState;
 
 /// This is the state of the contract which is persisted on the chain.
 ///
 /// The #\[state\] attribute generates serialization logic for the struct.
 #[state]
-pub struct PingContractState {}
+pub struct PingContractState {
+    /// The gas of failed pings from [`ping_with_refund`], which has been reclaimed by the contract,
+    /// and can be refunded to their sender.
+    failed_pings: AvlTreeMap<PingRoute, GasCost>,
+    /// The gas of failed pings being refunded, until [`refund_failed_ping_callback`] confirms
+    /// the refund.
+    pending_refunds: AvlTreeMap<PingRoute, GasCost>,
+}
+
+/// Sender and destination of a ping.
+///
+/// ### Fields:
+///
+/// * `sender`: [`Address`], sender of the ping.
+///
+/// * `destination`: [`Address`], destination of the ping.
+#[derive(ReadWriteState, CreateTypeSpec, Eq, Ord, PartialEq, PartialOrd)]
+pub struct PingRoute {
+    sender: Address,
+    destination: Address,
+}
+
+impl PingContractState {
+    /// Gets the gas of failed pings from `sender` to `destination`, which has not been refunded.
+    ///
+    /// ### Parameters:
+    ///
+    ///  * `sender`: [`Address`] - The sender of the pings.
+    ///  * `destination`: [`Address`] - The destination of the pings.
+    pub fn failed_ping_cost(&self, sender: Address, destination: Address) -> GasCost {
+        self.failed_pings
+            .get(&PingRoute {
+                sender,
+                destination,
+            })
+            .unwrap_or(0)
+    }
+
+    /// Adds `cost` to the gas recorded in `map` for `route`.
+    fn add_cost(map: &mut AvlTreeMap<PingRoute, GasCost>, route: PingRoute, cost: GasCost) {
+        let total_cost = map
+            .get(&route)
+            .unwrap_or(0)
+            .checked_add(cost)
+            .expect("Recorded gas cost would overflow");
+        map.insert(route, total_cost);
+    }
+}
 
 /// Initialize the contract.
 ///
@@ -23,7 +76,13 @@ pub struct PingContractState {}
 ///  * `context`: [`ContractContext`] - The contract context containing sender and chain information.
 #[init]
 pub fn initialize(context: ContractContext) -> (PingContractState, Vec<EventGroup>) {
-    (PingContractState {}, vec![])
+    (
+        PingContractState {
+            failed_pings: AvlTreeMap::new(),
+            pending_refunds: AvlTreeMap::new(),
+        },
+        vec![],
+    )
 }
 
 /// Pings contract at `destination` to check for its existence or transfer gas.
@@ -84,6 +143,41 @@ pub fn ping_no_callback(
     (state, vec![event_group_builder.build()])
 }
 
+/// Pings contract at `destination` to transfer gas, recording the gas if the ping fails.
+///
+/// `cost` must be at least the network fee.
+///
+/// `cost` amount of gas will be transferred to `destination`.
+///
+/// Creates a callback which checks for the existence of the `destination` contract, like [`ping`].
+/// Instead of failing, the callback reclaims `cost` from the remaining gas of the transaction if
+/// the ping fails, such that it can then be refunded using [`refund_failed_ping`]. The transaction
+/// must thus carry `cost` twice.
+///
+/// ### Parameters:
+///
+///  * `context`: [`ContractContext`] - The contract context containing sender and chain information.
+///  * `state`: [`PingContractState`] - The current state of the contract.
+///  * `destination`: [`Address`] - The destination address of the contract to ping.
+///  * `cost`: [`GasCost`] - How much gas to use for the interaction.
+#[action(shortname = 0x04)]
+pub fn ping_with_refund(
+    context: ContractContext,
+    state: PingContractState,
+    destination: Address,
+    cost: GasCost,
+) -> (PingContractState, Vec<EventGroup>) {
+    let mut event_group_builder = EventGroup::builder();
+    event_group_builder.ping(destination, Some(cost));
+    event_group_builder
+        .with_callback(SHORTNAME_PING_WITH_REFUND_CALLBACK)
+        .argument(context.sender)
+        .argument(destination)
+        .argument(cost)
+        .done();
+    (state, vec![event_group_builder.build()])
+}
+
 /// Checks for contract existence by handling `ping` callback.
 ///
 /// If the callback context of the `ping` call was unsuccessful, the `destination` doesn't exist.
@@ -109,3 +203,169 @@ pub fn ping_callback(
     );
     (state, vec![])
 }
+
+/// Handles callback from [`ping_with_refund`]. <br>
+/// If the callback context of the ping was unsuccessful, the `destination` doesn't exist, and the
+/// contract pings itself with `cost` to reclaim the gas of the failed ping. The gas is recorded
+/// for `sender` by [`reclaim_failed_ping_callback`] once the contract has received it.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`] - The contract context for the callback.
+/// * `callback_context`: [`CallbackContext`] - The context of the callback.
+/// * `state`: [`PingContractState`] - The current state of the contract.
+/// * `sender`: [`Address`] - The sender of the ping.
+/// * `destination`: [`Address`] - The destination of the ping.
+/// * `cost`: [`GasCost`] - The gas used for the ping.
+///
+/// ### Returns
+///
+/// The updated state object of type [`PingContractState`]
+#[callback(shortname = 0x12)]
+pub fn ping_with_refund_callback(
+    context: ContractContext,
+    callback_context: CallbackContext,
+    state: PingContractState,
+    sender: Address,
+    destination: Address,
+    cost: GasCost,
+) -> (PingContractState, Vec<EventGroup>) {
+    if callback_context.success {
+        return (state, vec![]);
+    }
+
+    let mut event_group_builder = EventGroup::builder();
+    event_group_builder.ping(context.contract_address, Some(cost));
+    event_group_builder
+        .with_callback(SHORTNAME_RECLAIM_FAILED_PING_CALLBACK)
+        .argument(sender)
+        .argument(destination)
+        .argument(cost)
+        .done();
+    (state, vec![event_group_builder.build()])
+}
+
+/// Handles callback from the reclaiming ping of [`ping_with_refund_callback`]. <br>
+/// If the contract received the reclaimed `cost`, it is recorded for `sender`, to be refunded
+/// using [`refund_failed_ping`]. Refunds are thus capped at the gas the contract got back.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`] - The contract context for the callback.
+/// * `callback_context`: [`CallbackContext`] - The context of the callback.
+/// * `state`: [`PingContractState`] - The current state of the contract.
+/// * `sender`: [`Address`] - The sender of the failed ping.
+/// * `destination`: [`Address`] - The destination of the failed ping.
+/// * `cost`: [`GasCost`] - The reclaimed gas.
+///
+/// ### Returns
+///
+/// The updated state object of type [`PingContractState`]
+#[callback(shortname = 0x13)]
+pub fn reclaim_failed_ping_callback(
+    context: ContractContext,
+    callback_context: CallbackContext,
+    mut state: PingContractState,
+    sender: Address,
+    destination: Address,
+    cost: GasCost,
+) -> (PingContractState, Vec<EventGroup>) {
+    if callback_context.success {
+        PingContractState::add_cost(
+            &mut state.failed_pings,
+            PingRoute {
+                sender,
+                destination,
+            },
+            cost,
+        );
+    }
+    (state, vec![])
+}
+
+/// Refunds the gas of failed pings from the sender to `destination`, by pinging the sender with
+/// the recorded cost. <br>
+/// The cost is kept as a pending refund until [`refund_failed_ping_callback`] confirms that the
+/// refund succeeded, and is otherwise recorded again.
+///
+/// Fails if no failed pings from the sender to `destination` have been recorded.
+///
+/// ### Parameters:
+///
+///  * `context`: [`ContractContext`] - The contract context containing sender and chain information.
+///  * `state`: [`PingContractState`] - The current state of the contract.
+///  * `destination`: [`Address`] - The destination of the failed pings.
+#[action(shortname = 0x03)]
+pub fn refund_failed_ping(
+    context: ContractContext,
+    mut state: PingContractState,
+    destination: Address,
+) -> (PingContractState, Vec<EventGroup>) {
+    let cost = state.failed_ping_cost(context.sender, destination);
+    assert_ne!(cost, 0, "No failed pings to refund");
+    let route = PingRoute {
+        sender: context.sender,
+        destination,
+    };
+    state.failed_pings.remove(&route);
+    PingContractState::add_cost(&mut state.pending_refunds, route, cost);
+
+    let mut event_group_builder = EventGroup::builder();
+    event_group_builder.ping(context.sender, Some(cost));
+    event_group_builder
+        .with_callback(SHORTNAME_REFUND_FAILED_PING_CALLBACK)
+        .argument(context.sender)
+        .argument(destination)
+        .argument(cost)
+        .done();
+    (state, vec![event_group_builder.build()])
+}
+
+/// Handles callback from [`refund_failed_ping`]. <br>
+/// Removes the `cost` from the pending refunds of `sender` to `destination`. If the refund
+/// failed, the `cost` is recorded as failed again, such that it can be refunded later.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`] - The contract context for the callback.
+/// * `callback_context`: [`CallbackContext`] - The context of the callback.
+/// * `state`: [`PingContractState`] - The current state of the contract.
+/// * `sender`: [`Address`] - The sender of the failed pings.
+/// * `destination`: [`Address`] - The destination of the failed pings.
+/// * `cost`: [`GasCost`] - The refunded gas.
+///
+/// ### Returns
+///
+/// The updated state object of type [`PingContractState`]
+#[callback(shortname = 0x11)]
+pub fn refund_failed_ping_callback(
+    context: ContractContext,
+    callback_context: CallbackContext,
+    mut state: PingContractState,
+    sender: Address,
+    destination: Address,
+    cost: GasCost,
+) -> (PingContractState, Vec<EventGroup>) {
+    let route = PingRoute {
+        sender,
+        destination,
+    };
+    let pending_cost = state.pending_refunds.get(&route).unwrap_or(0) - cost;
+    if pending_cost == 0 {
+        state.pending_refunds.remove(&route);
+    } else {
+        state.pending_refunds.insert(route, pending_cost);
+    }
+
+    if !callback_context.success {
+        PingContractState::add_cost(
+            &mut state.failed_pings,
+            PingRoute {
+                sender,
+                destination,
+            },
+            cost,
+        );
+    }
+    (state, vec![])
+}
@@ -0,0 +1,185 @@
+use pbc_contract_common::{
+    address::AddressType,
+    context::{CallbackContext, ExecutionResult},
+    Hash,
+};
+
+use super::*;
+
+const SENDER: Address = Address {
+    address_type: AddressType::Account,
+    identifier: [1; 20],
+};
+
+const DESTINATION: Address = Address {
+    address_type: AddressType::PublicContract,
+    identifier: [2; 20],
+};
+
+/// Creates a context for an invocation sent by `sender`.
+fn context(sender: Address) -> ContractContext {
+    ContractContext {
+        contract_address: Address {
+            address_type: AddressType::PublicContract,
+            identifier: [0; 20],
+        },
+        sender,
+        block_time: 0,
+        block_production_time: 0,
+        current_transaction: Hash { bytes: [0; 32] },
+        original_transaction: Hash { bytes: [0; 32] },
+    }
+}
+
+/// Creates a callback context for a single interaction, which `succeeded` or not.
+fn callback_context(succeeded: bool) -> CallbackContext {
+    CallbackContext {
+        success: succeeded,
+        results: vec![ExecutionResult {
+            succeeded,
+            return_data: vec![],
+        }],
+    }
+}
+
+/// Records a failed ping from [`SENDER`] to [`DESTINATION`] with `cost`, which the contract has
+/// reclaimed.
+fn with_failed_ping(state: PingContractState, cost: GasCost) -> PingContractState {
+    let (state, _) = ping_with_refund_callback(
+        context(SENDER),
+        callback_context(false),
+        state,
+        SENDER,
+        DESTINATION,
+        cost,
+    );
+    let (state, _) = reclaim_failed_ping_callback(
+        context(SENDER),
+        callback_context(true),
+        state,
+        SENDER,
+        DESTINATION,
+        cost,
+    );
+    state
+}
+
+/// Creates a state where a ping from [`SENDER`] to [`DESTINATION`] with `cost` has failed.
+fn state_with_failed_ping(cost: GasCost) -> PingContractState {
+    let (state, _) = initialize(context(SENDER));
+    with_failed_ping(state, cost)
+}
+
+#[test]
+fn failed_ping_to_nonexistent_contract_is_recorded() {
+    let state = state_with_failed_ping(1000);
+    assert_eq!(state.failed_ping_cost(SENDER, DESTINATION), 1000);
+
+    let state = with_failed_ping(state, 500);
+    assert_eq!(state.failed_ping_cost(SENDER, DESTINATION), 1500);
+}
+
+#[test]
+fn failed_ping_is_reclaimed_before_being_recorded() {
+    let (state, _) = initialize(context(SENDER));
+    let (state, event_groups) = ping_with_refund_callback(
+        context(SENDER),
+        callback_context(false),
+        state,
+        SENDER,
+        DESTINATION,
+        1000,
+    );
+    assert_eq!(event_groups.len(), 1);
+    assert_eq!(event_groups[0].events.len(), 1);
+    assert_eq!(
+        event_groups[0].events[0].dest,
+        context(SENDER).contract_address
+    );
+    assert_eq!(state.failed_ping_cost(SENDER, DESTINATION), 0);
+}
+
+#[test]
+fn unreclaimed_failed_ping_is_not_recorded() {
+    let (state, _) = initialize(context(SENDER));
+    let (state, _) = reclaim_failed_ping_callback(
+        context(SENDER),
+        callback_context(false),
+        state,
+        SENDER,
+        DESTINATION,
+        1000,
+    );
+    assert_eq!(state.failed_ping_cost(SENDER, DESTINATION), 0);
+}
+
+#[test]
+#[should_panic(expected = "Recorded gas cost would overflow")]
+fn recorded_gas_cost_cannot_overflow() {
+    let state = state_with_failed_ping(GasCost::MAX);
+    with_failed_ping(state, 1);
+}
+
+#[test]
+fn successful_ping_is_not_recorded() {
+    let (state, _) = initialize(context(SENDER));
+    let (state, event_groups) = ping_with_refund_callback(
+        context(SENDER),
+        callback_context(true),
+        state,
+        SENDER,
+        DESTINATION,
+        1000,
+    );
+    assert!(event_groups.is_empty());
+    assert_eq!(state.failed_ping_cost(SENDER, DESTINATION), 0);
+}
+
+#[test]
+#[should_panic(expected = "No contract found at called address")]
+fn failed_ping_fails() {
+    let (state, _) = initialize(context(SENDER));
+    ping_callback(context(SENDER), callback_context(false), state);
+}
+
+#[test]
+fn refund_is_pending_until_confirmed() {
+    let (state, event_groups) =
+        refund_failed_ping(context(SENDER), state_with_failed_ping(1000), DESTINATION);
+    assert_eq!(event_groups.len(), 1);
+    assert_eq!(state.failed_ping_cost(SENDER, DESTINATION), 0);
+
+    let (state, _) = refund_failed_ping_callback(
+        context(SENDER),
+        callback_context(true),
+        state,
+        SENDER,
+        DESTINATION,
+        1000,
+    );
+    assert_eq!(state.failed_ping_cost(SENDER, DESTINATION), 0);
+    assert_eq!(state.pending_refunds.len(), 0);
+}
+
+#[test]
+fn failed_refund_is_recorded_again() {
+    let (state, _) = refund_failed_ping(context(SENDER), state_with_failed_ping(1000), DESTINATION);
+
+    let (state, _) = refund_failed_ping_callback(
+        context(SENDER),
+        callback_context(false),
+        state,
+        SENDER,
+        DESTINATION,
+        1000,
+    );
+    assert_eq!(state.failed_ping_cost(SENDER, DESTINATION), 1000);
+    assert_eq!(state.pending_refunds.len(), 0);
+}
+
+#[test]
+#[should_panic(expected = "No failed pings to refund")]
+fn pending_refund_cannot_be_refunded_twice() {
+    let (state, _) = refund_failed_ping(context(SENDER), state_with_failed_ping(1000), DESTINATION);
+    refund_failed_ping(context(SENDER), state, DESTINATION);
+}
@@ -1,14 +1,43 @@
 #![doc = include_str!("../README.md")]
 
+#[cfg(test)]
+mod tests;
+
+use std::collections::BTreeSet;
 use std::ops::{Add, Sub};
 
 use create_type_spec_derive::CreateTypeSpec;
+use defi_common::pagination::paginate;
 use pbc_contract_codegen::*;
-use pbc_contract_common::{address::Address, avl_tree_map::AvlTreeMap, context::ContractContext};
+use pbc_contract_common::{
+    address::{Address, AddressType},
+    avl_tree_map::AvlTreeMap,
+    context::ContractContext,
+    events::EventGroup,
+    shortname::Shortname,
+    sorted_vec_map::SortedVecSet,
+};
 use pbc_traits::ReadWriteState;
 use read_write_rpc_derive::ReadWriteRPC;
 use read_write_state_derive::ReadWriteState;
 
+/// The address that owns the token once ownership is renounced. No one can send invocations from
+/// it, so privileged actions can no longer be taken.
+pub const BURN_ADDRESS: Address = Address {
+    address_type: AddressType::Account,
+    identifier: [0; 20],
+};
+
+/// Shortname of the `tokens_received(from: Address, amount: u128, data: Vec<u8>)` action invoked
+/// on the recipient by [`transfer_and_call`].
+pub const SHORTNAME_TOKENS_RECEIVED: Shortname = Shortname::from_u32(0x01);
+
+/// The maximum allowed [`TokenState::decimals`].
+///
+/// `10^38` is the largest power of ten representable by [`u128`], so any more decimals would make
+/// even a single whole token unrepresentable.
+pub const MAX_DECIMALS: u8 = 38;
+
 /// Custom struct for the state of the contract.
 ///
 /// The "state" attribute is attached.
@@ -28,7 +57,19 @@ use read_write_state_derive::ReadWriteState;
 ///
 /// * `balances`: [`AvlTreeMap<Address, u128>`], ledger for the accounts associated with the contract.
 ///
-/// * `allowed`: [`AvlTreeMap<AllowedAddress, u128>`], allowance from an owner to a spender.
+/// * `allowed`: [`AvlTreeMap<AllowedAddress, Allowance>`], allowance from an owner to a spender.
+///
+/// * `approved`: [`AvlTreeMap<AllowedAddress, u128>`], allowance from an owner to a spender, as
+/// of the latest approval.
+///
+/// * `paused`: [`bool`], whether token movement is paused by the owner.
+///
+/// * `frozen`: [`AvlTreeMap<Address, bool>`], addresses frozen by the owner, which can neither
+/// send, receive, spend, burn nor be minted tokens.
+///
+/// * `max_supply`: [`Option<u128>`], the hard cap on the total supply, if any.
+///
+/// * `transfer_tax`: [`Option<TransferTax>`], the tax levied on every transfer, if any.
 #[state]
 pub struct TokenState {
     name: String,
@@ -37,7 +78,38 @@ pub struct TokenState {
     owner: Address,
     total_supply: u128,
     balances: AvlTreeMap<Address, u128>,
-    allowed: AvlTreeMap<AllowedAddress, u128>,
+    allowed: AvlTreeMap<AllowedAddress, Allowance>,
+    approved: AvlTreeMap<AllowedAddress, u128>,
+    paused: bool,
+    frozen: AvlTreeMap<Address, bool>,
+    max_supply: Option<u128>,
+    transfer_tax: Option<TransferTax>,
+}
+
+/// A tax levied on transfers, redirecting a share of each transferred amount to a treasury.
+///
+/// ### Fields:
+///
+/// * `treasury`: [`Address`], the recipient of the tax.
+///
+/// * `tax_per_mille`: [`u16`], the share of each transfer taken as tax, at most `1000`.
+#[derive(ReadWriteRPC, ReadWriteState, CreateTypeSpec, Clone, Copy)]
+pub struct TransferTax {
+    /// The recipient of the tax.
+    pub treasury: Address,
+    /// The share of each transfer taken as tax, in per mille.
+    pub tax_per_mille: u16,
+}
+
+impl TransferTax {
+    /// Asserts that the tax takes at most the entire transferred amount.
+    fn assert_valid(&self) {
+        assert!(
+            self.tax_per_mille <= 1000,
+            "Transfer tax must be at most 1000 per mille, but was {}",
+            self.tax_per_mille
+        );
+    }
 }
 
 /// Address pair representing some allowance. Owner allows spender to spend an amount of tokens.
@@ -47,12 +119,36 @@ pub struct TokenState {
 /// * `owner`: [`Address`], owner of tokens.
 ///
 /// * `spender`: [`Address`], spender of tokens.
-#[derive(ReadWriteState, CreateTypeSpec, Eq, Ord, PartialEq, PartialOrd)]
+#[derive(ReadWriteState, ReadWriteRPC, CreateTypeSpec, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub struct AllowedAddress {
     owner: Address,
     spender: Address,
 }
 
+/// The expiry of allowances which never expire.
+pub const NEVER_EXPIRES: i64 = i64::MAX;
+
+/// An allowance from an owner to a spender, which expires at some point in time.
+///
+/// ### Fields:
+///
+/// * `amount`: [`u128`], the amount the spender is still allowed to spend.
+///
+/// * `expires_at_utc_millis`: [`i64`], the last time in UTC millis at which the allowance can
+/// be spent, or [`NEVER_EXPIRES`].
+#[derive(ReadWriteState, CreateTypeSpec, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Allowance {
+    amount: u128,
+    expires_at_utc_millis: i64,
+}
+
+impl Allowance {
+    /// Determines whether the allowance has expired at `now`.
+    fn is_expired(&self, now: i64) -> bool {
+        now > self.expires_at_utc_millis
+    }
+}
+
 /// Extension trait for inserting into a map holding balances.
 /// In a balance map only non-zero values are stored.
 /// If a key has no value in the map the implied value is zero.
@@ -100,7 +196,87 @@ impl TokenState {
         self.balances.get(owner).unwrap_or(0)
     }
 
-    /// Function to check the amount of tokens that an owner allowed to a spender.
+    /// Gets the `n` largest balances, for use in leaderboards.
+    ///
+    /// Balances are keyed by address, so every balance is read and partially sorted. The cost
+    /// thus grows with the number of holders rather than with `n`, and calling this on-chain for
+    /// tokens with many holders can be expensive. Prefer calling it off-chain on a state snapshot.
+    ///
+    /// ### Parameters:
+    ///
+    /// * `n`: [`usize`], the maximum number of holders to return.
+    ///
+    /// ### Returns:
+    ///
+    /// At most `n` pairs of [`Address`] and balance, ordered by decreasing balance. Holders with
+    /// equal balances are ordered by address.
+    pub fn top_holders(&self, n: usize) -> Vec<(Address, u128)> {
+        let by_decreasing_balance =
+            |a: &(Address, u128), b: &(Address, u128)| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0));
+
+        let mut holders: Vec<(Address, u128)> = self.balances.iter().collect();
+        if n < holders.len() {
+            holders.select_nth_unstable_by(n, by_decreasing_balance);
+            holders.truncate(n);
+        }
+        holders.sort_unstable_by(by_decreasing_balance);
+        holders
+    }
+
+    /// Gets the circulating supply, i.e. the total supply excluding the balances of e.g. treasury,
+    /// team or locked addresses.
+    ///
+    /// ### Parameters:
+    ///
+    /// * `excluded`: The [`Address`]es whose balances are not circulating. Duplicates are only
+    ///   excluded once.
+    ///
+    /// ### Returns:
+    ///
+    /// An [`u128`] representing the total supply minus the balances of the excluded addresses.
+    pub fn circulating_supply(&self, excluded: &[Address]) -> u128 {
+        let excluded: BTreeSet<&Address> = excluded.iter().collect();
+        excluded
+            .into_iter()
+            .fold(self.total_supply, |supply, address| {
+                supply
+                    .checked_sub(self.balance_of(address))
+                    .expect("Excluded balances exceed the total supply")
+            })
+    }
+
+    /// Checks up front whether `sender` can afford all of `transfers`, allowing a client to reject
+    /// a large bulk before any transfer is made. <br>
+    /// Transfers to `sender` itself are counted like any other transfer.
+    /// [`bulk_transfer`] itself still checks each transfer individually.
+    ///
+    /// ### Parameters:
+    ///
+    /// * `sender`: The [`Address`] sending the transfers.
+    ///
+    /// * `transfers`: The [`Transfer`]s to validate.
+    ///
+    /// ### Returns:
+    ///
+    /// [`Ok`] if the sum of the amounts is within the balance of `sender`, otherwise an error
+    /// message.
+    pub fn validate_bulk_transfer(
+        &self,
+        sender: &Address,
+        transfers: &[Transfer],
+    ) -> Result<(), &'static str> {
+        let total = transfers
+            .iter()
+            .try_fold(0u128, |total, t| total.checked_add(t.amount))
+            .ok_or("Sum of transfer amounts overflows")?;
+        if total > self.balance_of(sender) {
+            return Err("Sum of transfer amounts exceeds the balance of the sender");
+        }
+        Ok(())
+    }
+
+    /// Function to check the amount of tokens that an owner allowed to a spender, regardless of
+    /// whether the allowance has expired.
     ///
     /// ### Parameters:
     ///
@@ -112,35 +288,183 @@ impl TokenState {
     ///
     /// A [`u64`] specifying the amount whicher `spender` is still allowed to withdraw from `owner`.
     pub fn allowance(&self, owner: &Address, spender: &Address) -> u128 {
-        self.allowed
+        self.get_allowance(owner, spender)
+            .map_or(0, |allowance| allowance.amount)
+    }
+
+    /// Function to check the amount of tokens that an owner allowed to a spender, at `now`.
+    ///
+    /// ### Parameters:
+    ///
+    /// * `owner`: [`Address`] The address which owns the funds.
+    ///
+    /// * `spender`: [`Address`] The address which will spend the funds.
+    ///
+    /// * `now`: [`i64`] The current time in UTC millis.
+    ///
+    /// ### Returns:
+    ///
+    /// A [`u128`] specifying the amount `spender` is allowed to withdraw from `owner`, being zero
+    /// once the allowance has expired.
+    pub fn allowance_at(&self, owner: &Address, spender: &Address, now: i64) -> u128 {
+        self.get_allowance(owner, spender)
+            .filter(|allowance| !allowance.is_expired(now))
+            .map_or(0, |allowance| allowance.amount)
+    }
+
+    /// Gets the allowance from `owner` to `spender`, if any.
+    fn get_allowance(&self, owner: &Address, spender: &Address) -> Option<Allowance> {
+        self.allowed.get(&AllowedAddress {
+            owner: *owner,
+            spender: *spender,
+        })
+    }
+
+    /// Function to check how much of an allowance has been used, for security dashboards.
+    /// Like [`TokenState::allowance`], the remaining allowance is the stored value, regardless of
+    /// whether the allowance has expired, see [`TokenState::allowance_at`].
+    ///
+    /// ### Parameters:
+    ///
+    /// * `owner`: [`Address`] The address which owns the funds.
+    ///
+    /// * `spender`: [`Address`] The address which will spend the funds.
+    ///
+    /// ### Returns:
+    ///
+    /// A pair of [`u128`]s, being the remaining stored allowance from `owner` to `spender`, and
+    /// the allowance as of the latest approval by `owner`.
+    pub fn allowance_utilization(&self, owner: &Address, spender: &Address) -> (u128, u128) {
+        let originally_approved = self
+            .approved
             .get(&AllowedAddress {
                 owner: *owner,
                 spender: *spender,
             })
-            .unwrap_or(0)
+            .unwrap_or(0);
+        (self.allowance(owner, spender), originally_approved)
+    }
+
+    /// Asserts that `sender` is the owner of the token, allowing it to `action`.
+    fn assert_owner(&self, sender: &Address, action: &str) {
+        assert_eq!(*sender, self.owner, "Only the owner can {}", action);
+    }
+
+    /// Asserts that token movement is not paused.
+    fn assert_not_paused(&self) {
+        assert!(!self.paused, "Token transfers are paused");
+    }
+
+    /// Determines whether `address` has been frozen by the owner.
+    pub fn is_frozen(&self, address: &Address) -> bool {
+        self.frozen.get(address).unwrap_or(false)
     }
 
-    /// Updates the internal allowance map, overwriting `owner`'s allowance for `spender` to `amount`.
+    /// Asserts that `address` has not been frozen by the owner.
+    fn assert_not_frozen(&self, address: &Address) {
+        assert!(!self.is_frozen(address), "Address {:?} is frozen", address);
+    }
+
+    /// Approves `spender` to spend `amount` on behalf of `owner` until `expires_at_utc_millis`,
+    /// overwriting both the remaining and the originally approved allowance.
+    fn approve_allowance(
+        &mut self,
+        owner: Address,
+        spender: Address,
+        amount: u128,
+        expires_at_utc_millis: i64,
+    ) {
+        self.set_allowance(owner, spender, amount, expires_at_utc_millis);
+        self.approved
+            .insert_balance(AllowedAddress { owner, spender }, amount);
+    }
+
+    /// Updates the internal allowance map, overwriting `owner`'s allowance for `spender` to `amount`,
+    /// keeping its expiry.
     ///
-    /// If `owner` does not currently have any allowance, a new entry is added to `self`.
+    /// If `owner` does not currently have any allowance, a new entry is added to `self`, which
+    /// never expires.
     fn update_allowance(&mut self, owner: Address, spender: Address, amount: u128) {
-        self.allowed
-            .insert_balance(AllowedAddress { owner, spender }, amount);
+        let expires_at_utc_millis = self
+            .get_allowance(&owner, &spender)
+            .map_or(NEVER_EXPIRES, |allowance| allowance.expires_at_utc_millis);
+        self.set_allowance(owner, spender, amount, expires_at_utc_millis);
+    }
+
+    /// Overwrites `owner`'s allowance for `spender`. Zero allowances are removed from the map.
+    fn set_allowance(
+        &mut self,
+        owner: Address,
+        spender: Address,
+        amount: u128,
+        expires_at_utc_millis: i64,
+    ) {
+        let key = AllowedAddress { owner, spender };
+        if amount == 0 {
+            self.allowed.remove(&key);
+        } else {
+            self.allowed.insert(
+                key,
+                Allowance {
+                    amount,
+                    expires_at_utc_millis,
+                },
+            );
+        }
+    }
+
+    /// Removes the allowances which have expired at `now` among at most `max` allowances, in
+    /// order of owner and spender, starting from the first allowance at or after `start`.
+    ///
+    /// Returns the number of removed allowances, and the allowance to continue from, or [`None`]
+    /// if there are no more allowances.
+    fn remove_expired_allowances(
+        &mut self,
+        now: i64,
+        start: Option<AllowedAddress>,
+        max: u32,
+    ) -> AllowanceCleanup {
+        let page = paginate(&self.allowed, start.as_ref(), max as usize);
+        let mut removed = 0;
+        for (key, allowance) in page.entries {
+            if allowance.is_expired(now) {
+                self.allowed.remove(&key);
+                self.approved.remove(&key);
+                removed += 1;
+            }
+        }
+        AllowanceCleanup {
+            removed,
+            next_start: page.next_start,
+        }
     }
 
     /// Updates the internal allowance map, adding `delta` allowance for `spender` to additionally
     /// spend of on behalf of `owner`.
     ///
-    /// If `owner` does not currently have any allowance, a new entry is added to `self`, with `delta`
-    /// as the initial amount.
+    /// If `owner` does not currently have any allowance, or it has expired at `now`, a new entry
+    /// is added to `self`, with `delta` as the initial amount, which never expires.
     /// If `delta` is negative, the allowance is lowered.
+    /// The resulting allowance becomes the originally approved allowance, keeping the expiry of
+    /// any unexpired allowance.
     /// Panics if adding `delta` would overflow, or the allowed balance would become negative.
-    fn update_allowance_relative(&mut self, owner: Address, spender: Address, delta: i128) {
-        let existing_allowance = self.allowance(&owner, &spender);
+    fn update_allowance_relative(
+        &mut self,
+        owner: Address,
+        spender: Address,
+        delta: i128,
+        now: i64,
+    ) {
+        let (existing_allowance, expires_at_utc_millis) = self
+            .get_allowance(&owner, &spender)
+            .filter(|allowance| !allowance.is_expired(now))
+            .map_or((0, NEVER_EXPIRES), |allowance| {
+                (allowance.amount, allowance.expires_at_utc_millis)
+            });
         let new_allowance = existing_allowance
             .checked_add_signed(delta)
             .expect("Allowance would become negative.");
-        self.update_allowance(owner, spender, new_allowance);
+        self.approve_allowance(owner, spender, new_allowance, expires_at_utc_millis);
     }
 }
 
@@ -155,10 +479,14 @@ impl TokenState {
 /// * `symbol`: [`String`], the symbol of the token. E.g. "HIX".\
 ///
 /// * `decimals`: [`u8`], the number of decimals the token uses - e.g. 8,
-/// means to divide the token amount by `100000000` to get its user representation.\
+/// means to divide the token amount by `100000000` to get its user representation.
+/// Must be at most [`MAX_DECIMALS`].\
 ///
 /// * `total_supply`: [`u128`], current amount of tokens for the TokenContract.
 ///
+/// * `max_supply`: [`Option<u128>`], the hard cap on the total supply, which [`mint`] can never
+/// exceed. Unlimited if [`None`]. Must be at least `total_supply`.
+///
 /// ### Returns:
 ///
 /// The new state object of type [`TokenState`] with an initialized ledger.
@@ -169,7 +497,23 @@ pub fn initialize(
     symbol: String,
     decimals: u8,
     total_supply: u128,
+    max_supply: Option<u128>,
 ) -> TokenState {
+    assert!(
+        decimals <= MAX_DECIMALS,
+        "Decimals must be at most {}, but was {}",
+        MAX_DECIMALS,
+        decimals
+    );
+    if let Some(max_supply) = max_supply {
+        assert!(
+            total_supply <= max_supply,
+            "Total supply must be at most the max supply {}, but was {}",
+            max_supply,
+            total_supply
+        );
+    }
+
     let mut balances = AvlTreeMap::new();
     balances.insert_balance(ctx.sender, total_supply);
 
@@ -181,6 +525,11 @@ pub fn initialize(
         total_supply,
         balances,
         allowed: AvlTreeMap::new(),
+        approved: AvlTreeMap::new(),
+        paused: false,
+        frozen: AvlTreeMap::new(),
+        max_supply,
+        transfer_tax: None,
     }
 }
 
@@ -193,6 +542,33 @@ pub struct Transfer {
     pub amount: u128,
 }
 
+/// The balance of a single holder, as exported by [`export_holders`].
+#[derive(ReadWriteRPC, CreateTypeSpec, Debug, PartialEq, Eq)]
+pub struct Holder {
+    /// The address of the holder.
+    pub address: Address,
+    /// The balance of the holder.
+    pub balance: u128,
+}
+
+/// The result of a single [`cleanup_expired_allowances`] invocation.
+#[derive(ReadWriteRPC, CreateTypeSpec, Debug, PartialEq, Eq)]
+pub struct AllowanceCleanup {
+    /// The number of removed allowances.
+    pub removed: u32,
+    /// The allowance to continue the cleanup from, or [`None`] if there are no more allowances.
+    pub next_start: Option<AllowedAddress>,
+}
+
+/// Represents the type of an approval.
+#[derive(ReadWriteRPC, CreateTypeSpec)]
+pub struct Approval {
+    /// The address of the spender.
+    pub spender: Address,
+    /// The approved amount.
+    pub amount: u128,
+}
+
 /// Transfers `amount` of tokens to address `to` from the caller.
 /// The function throws if the message caller's account
 /// balance does not have enough tokens to spend.
@@ -249,6 +625,112 @@ pub fn bulk_transfer(
     state
 }
 
+/// Transfers a bulk of `amount` of tokens to address `to` from the caller, like [`bulk_transfer`],
+/// but rejects the entire bulk if any address `to` appears more than once.
+/// Duplicate recipients often indicate an error in the list of transfers.
+/// The function throws if the message caller's account
+/// balance does not have enough tokens to spend.
+/// If the sender's account goes to 0, the sender's address is removed from state.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`], the context for the action call.
+///
+/// * `state`: [`TokenState`], the current state of the contract.
+///
+/// * `transfers`: [`Vec[Transfer]`], vector of [the address to transfer to, amount to transfer].
+///
+/// ### Returns
+///
+/// The new state object of type [`TokenState`] with an updated ledger.
+#[action(shortname = 0x15)]
+pub fn bulk_transfer_unique(
+    context: ContractContext,
+    state: TokenState,
+    transfers: Vec<Transfer>,
+) -> TokenState {
+    assert_unique_recipients(&transfers);
+    bulk_transfer(context, state, transfers)
+}
+
+/// Exports every holder and their balance, ordered by address, as the return data of the
+/// invocation. Allows indexers to read all holders without scanning transfers. Does not change
+/// the state.
+///
+/// Every balance is read and serialized, so the gas cost grows linearly with the number of
+/// holders, and tokens with many holders may not be able to export them within the gas limit of
+/// a single invocation.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`], the context for the action call.
+///
+/// * `state`: [`TokenState`], the current state of the contract.
+///
+/// ### Returns
+///
+/// The unchanged state object of type [`TokenState`], and the event group returning the holders
+/// as a [`Vec<Holder>`].
+#[action(shortname = 0x14)]
+pub fn export_holders(
+    _context: ContractContext,
+    state: TokenState,
+) -> (TokenState, Vec<EventGroup>) {
+    let holders: Vec<Holder> = state
+        .balances
+        .iter()
+        .map(|(address, balance)| Holder { address, balance })
+        .collect();
+
+    let mut event_group_builder = EventGroup::builder();
+    event_group_builder.return_data(holders);
+    (state, vec![event_group_builder.build()])
+}
+
+/// Transfers `amount` of tokens to the contract `to` from the caller, and notifies `to` in the
+/// same transaction by invoking its `tokens_received` action, see [`SHORTNAME_TOKENS_RECEIVED`].
+/// `to` is notified of the amount it received, i.e. after any transfer tax.
+/// The function throws under the same conditions as [`transfer`]. The transfer is committed
+/// before `to` is notified, and is not reverted if the notification fails, so `to` must be able
+/// to handle `tokens_received`, or be able to return the tokens by other means.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`], the context for the action call.
+///
+/// * `state`: [`TokenState`], the current state of the contract.
+///
+/// * `to`: [`Address`], the contract to transfer to.
+///
+/// * `amount`: [`u128`], amount to transfer.
+///
+/// * `data`: [`Vec<u8>`], arbitrary data passed on to `to`.
+///
+/// ### Returns
+///
+/// The new state object of type [`TokenState`] with an updated ledger, and the event group
+/// notifying `to`.
+#[action(shortname = 0x10)]
+pub fn transfer_and_call(
+    context: ContractContext,
+    state: TokenState,
+    to: Address,
+    amount: u128,
+    data: Vec<u8>,
+) -> (TokenState, Vec<EventGroup>) {
+    let (state, received) = core_transfer_net(context.sender, state, to, amount);
+
+    let mut event_group_builder = EventGroup::builder();
+    event_group_builder
+        .call(to, SHORTNAME_TOKENS_RECEIVED)
+        .argument(context.sender)
+        .argument(received)
+        .argument(data)
+        .done();
+
+    (state, vec![event_group_builder.build()])
+}
+
 /// Transfers `amount` of tokens from address `from` to address `to`.\
 /// This requires that the sender is allowed to do the transfer by the `from`
 /// account through the `approve` action.
@@ -278,7 +760,14 @@ pub fn transfer_from(
     to: Address,
     amount: u128,
 ) -> TokenState {
-    core_transfer_from(context.sender, state, from, to, amount)
+    core_transfer_from(
+        context.sender,
+        state,
+        from,
+        to,
+        amount,
+        context.block_production_time,
+    )
 }
 
 /// Transfers a bulk of `amount` of tokens to address `to` from address `from` .\
@@ -308,13 +797,21 @@ pub fn bulk_transfer_from(
     transfers: Vec<Transfer>,
 ) -> TokenState {
     for t in transfers {
-        state = core_transfer_from(context.sender, state, from, t.to, t.amount);
+        state = core_transfer_from(
+            context.sender,
+            state,
+            from,
+            t.to,
+            t.amount,
+            context.block_production_time,
+        );
     }
     state
 }
 
 /// Allows `spender` to withdraw from the owners account multiple times, up to the `amount`.
 /// If this function is called again it overwrites the current allowance with `amount`.
+/// The allowance never expires.
 ///
 /// ### Parameters:
 ///
@@ -336,14 +833,122 @@ pub fn approve(
     spender: Address,
     amount: u128,
 ) -> TokenState {
-    state.update_allowance(context.sender, spender, amount);
+    state.approve_allowance(context.sender, spender, amount, NEVER_EXPIRES);
+    state
+}
+
+/// Allows `spender` to withdraw from the owners account multiple times, up to the `amount`, until
+/// `expires_at_utc_millis`. Afterwards, the allowance is treated as zero.
+/// If this function is called again it overwrites the current allowance with `amount`.
+/// The function throws if `expires_at_utc_millis` is already in the past.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`], the context for the action call.
+///
+/// * `state`: [`TokenState`], the current state of the contract.
+///
+/// * `spender`: [`Address`], the address of the spender.
+///
+/// * `amount`: [`u128`], approved amount.
+///
+/// * `expires_at_utc_millis`: [`i64`], the last time in UTC millis at which the allowance can be
+/// spent.
+///
+/// ### Returns
+///
+/// The new state object of type [`TokenState`] with an updated ledger.
+#[action(shortname = 0x13)]
+pub fn approve_until(
+    context: ContractContext,
+    mut state: TokenState,
+    spender: Address,
+    amount: u128,
+    expires_at_utc_millis: i64,
+) -> TokenState {
+    assert!(
+        expires_at_utc_millis >= context.block_production_time,
+        "Allowance expiry {} is in the past",
+        expires_at_utc_millis
+    );
+    state.approve_allowance(context.sender, spender, amount, expires_at_utc_millis);
+    state
+}
+
+/// Removes the expired allowances among at most `max` allowances from the state, see
+/// [`approve_until`]. Expired allowances are already treated as zero, so anyone may remove them,
+/// keeping the state small.
+///
+/// The allowances are scanned in order of owner and spender, starting from `start`, so the gas
+/// cost is bounded by `max`. The entire map is cleaned up over several invocations, by passing
+/// the returned cursor as the `start` of the next invocation.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`], the context for the action call.
+///
+/// * `state`: [`TokenState`], the current state of the contract.
+///
+/// * `start`: [`Option<AllowedAddress>`], the allowance to start from, usually the cursor returned
+///   by the previous invocation. Starts from the first allowance if [`None`].
+///
+/// * `max`: [`u32`], the maximum number of allowances to scan.
+///
+/// ### Returns
+///
+/// The new state object of type [`TokenState`] without the removed allowances, and the event
+/// group returning the number of removed allowances and the cursor as an [`AllowanceCleanup`].
+#[action(shortname = 0x16)]
+pub fn cleanup_expired_allowances(
+    context: ContractContext,
+    mut state: TokenState,
+    start: Option<AllowedAddress>,
+    max: u32,
+) -> (TokenState, Vec<EventGroup>) {
+    let cleanup = state.remove_expired_allowances(context.block_production_time, start, max);
+
+    let mut event_group_builder = EventGroup::builder();
+    event_group_builder.return_data(cleanup);
+    (state, vec![event_group_builder.build()])
+}
+
+/// Allows each spender of `approvals` to withdraw from the owners account multiple times, up to
+/// the given amount, as if calling [`approve`] for each approval in order.
+/// If a spender appears more than once, the last approval takes effect.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`], the context for the action call.
+///
+/// * `state`: [`TokenState`], the current state of the contract.
+///
+/// * `approvals`: [`Vec<Approval>`], vector of [the spender, approved amount].
+///
+/// ### Returns
+///
+/// The new state object of type [`TokenState`] with an updated ledger.
+#[action(shortname = 0x11)]
+pub fn bulk_approve(
+    context: ContractContext,
+    mut state: TokenState,
+    approvals: Vec<Approval>,
+) -> TokenState {
+    for approval in approvals {
+        state.approve_allowance(
+            context.sender,
+            approval.spender,
+            approval.amount,
+            NEVER_EXPIRES,
+        );
+    }
     state
 }
 
 /// Allows `spender` to withdraw `delta` additional tokens from the owners account, relative to any
 /// pre-existing allowance.
 ///
-/// If there is no pre-existing allowance, this is equivalent to [`approve`], with `delta` = `amount`.
+/// If there is no pre-existing allowance, or it has expired, this is equivalent to [`approve`],
+/// with `delta` = `amount`.
 /// If `delta` is negative, the allowance is lowered.
 /// If the resulting allowance is negative, the call fails, and the allowance is unchanged.
 #[action(shortname = 0x07)]
@@ -353,14 +958,278 @@ pub fn approve_relative(
     spender: Address,
     delta: i128,
 ) -> TokenState {
-    state.update_allowance_relative(context.sender, spender, delta);
+    state.update_allowance_relative(
+        context.sender,
+        spender,
+        delta,
+        context.block_production_time,
+    );
+    state
+}
+
+/// Creates `amount` new tokens, and adds them to the balance of `to`, increasing the total supply.
+/// The function throws if the caller is not the owner of the token, if `to` is frozen, or if the
+/// total supply would overflow or exceed the max supply.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`], the context for the action call.
+///
+/// * `state`: [`TokenState`], the current state of the contract.
+///
+/// * `to`: [`Address`], the address to mint to.
+///
+/// * `amount`: [`u128`], amount to mint.
+///
+/// ### Returns
+///
+/// The new state object of type [`TokenState`] with an updated ledger and total supply.
+#[action(shortname = 0x06)]
+pub fn mint(
+    context: ContractContext,
+    mut state: TokenState,
+    to: Address,
+    amount: u128,
+) -> TokenState {
+    state.assert_owner(&context.sender, "mint tokens");
+    state.assert_not_frozen(&to);
+    state.total_supply = state
+        .total_supply
+        .checked_add(amount)
+        .expect("Total supply would overflow");
+    if let Some(max_supply) = state.max_supply {
+        assert!(
+            state.total_supply <= max_supply,
+            "Mint would exceed max supply"
+        );
+    }
+    // No balance exceeds the total supply, so the balance cannot overflow either.
+    let to_amount = state.balance_of(&to);
+    state.balances.insert_balance(to, to_amount.add(amount));
+    state
+}
+
+/// Destroys `amount` of tokens from the caller's balance, decreasing the total supply.
+/// The function throws if the message caller's account
+/// balance does not have enough tokens to burn.
+/// If the sender's account goes to 0, the sender's address is removed from state.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`], the context for the action call.
+///
+/// * `state`: [`TokenState`], the current state of the contract.
+///
+/// * `amount`: [`u128`], amount to burn.
+///
+/// ### Returns
+///
+/// The new state object of type [`TokenState`] with an updated ledger and total supply.
+#[action(shortname = 0x08)]
+pub fn burn(context: ContractContext, state: TokenState, amount: u128) -> TokenState {
+    core_burn(context.sender, state, amount)
+}
+
+/// Destroys `amount` of tokens from address `from`, decreasing the total supply.\
+/// This requires that the sender is allowed to spend the tokens by the `from`
+/// account through the `approve` action, and consumes the allowance like [`transfer_from`].
+/// The function throws if the `from` account balance does not have enough tokens to burn,
+/// if the tokens were not approved, or the allowance has expired, or if the sender or `from` is
+/// frozen.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`], the context for the action call.
+///
+/// * `state`: [`TokenState`], the current state of the contract.
+///
+/// * `from`: [`Address`], the address to burn from.
+///
+/// * `amount`: [`u128`], amount to burn.
+///
+/// ### Returns
+///
+/// The new state object of type [`TokenState`] with an updated ledger and total supply.
+#[action(shortname = 0x0A)]
+pub fn burn_from(
+    context: ContractContext,
+    mut state: TokenState,
+    from: Address,
+    amount: u128,
+) -> TokenState {
+    state.assert_not_frozen(&context.sender);
+    let from_allowed = state.allowance_at(&from, &context.sender, context.block_production_time);
+    let o_new_allowed_amount = from_allowed.checked_sub(amount);
+    match o_new_allowed_amount {
+        Some(new_allowed_amount) => {
+            state.update_allowance(from, context.sender, new_allowed_amount);
+        }
+        None => {
+            panic!(
+                "Insufficient allowance for burn_from: {}/{}",
+                from_allowed, amount
+            );
+        }
+    }
+    core_burn(from, state, amount)
+}
+
+/// Pauses or resumes all token movement, for use during incident response.
+/// While paused, transfers and burns fail, but approvals are still allowed.
+/// The function throws if the caller is not the owner of the token.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`], the context for the action call.
+///
+/// * `state`: [`TokenState`], the current state of the contract.
+///
+/// * `paused`: [`bool`], whether token movement should be paused.
+///
+/// ### Returns
+///
+/// The new state object of type [`TokenState`].
+#[action(shortname = 0x0B)]
+pub fn set_paused(context: ContractContext, mut state: TokenState, paused: bool) -> TokenState {
+    state.assert_owner(&context.sender, "pause transfers");
+    state.paused = paused;
+    state
+}
+
+/// Freezes `address`, preventing it from sending, receiving or spending tokens.
+/// The function throws if the caller is not the owner of the token.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`], the context for the action call.
+///
+/// * `state`: [`TokenState`], the current state of the contract.
+///
+/// * `address`: [`Address`], the address to freeze.
+///
+/// ### Returns
+///
+/// The new state object of type [`TokenState`].
+#[action(shortname = 0x0E)]
+pub fn freeze(context: ContractContext, mut state: TokenState, address: Address) -> TokenState {
+    state.assert_owner(&context.sender, "freeze addresses");
+    state.frozen.insert(address, true);
+    state
+}
+
+/// Unfreezes `address`, allowing it to move tokens again.
+/// The function throws if the caller is not the owner of the token.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`], the context for the action call.
+///
+/// * `state`: [`TokenState`], the current state of the contract.
+///
+/// * `address`: [`Address`], the address to unfreeze.
+///
+/// ### Returns
+///
+/// The new state object of type [`TokenState`].
+#[action(shortname = 0x0F)]
+pub fn unfreeze(context: ContractContext, mut state: TokenState, address: Address) -> TokenState {
+    state.assert_owner(&context.sender, "unfreeze addresses");
+    state.frozen.remove(&address);
+    state
+}
+
+/// Sets the tax levied on every transfer, see [`taxed_core_transfer`]. [`None`] disables the tax.
+/// The function throws if the caller is not the owner of the token, or if the tax exceeds
+/// 1000 per mille.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`], the context for the action call.
+///
+/// * `state`: [`TokenState`], the current state of the contract.
+///
+/// * `transfer_tax`: [`Option<TransferTax>`], the new transfer tax.
+///
+/// ### Returns
+///
+/// The new state object of type [`TokenState`].
+#[action(shortname = 0x12)]
+pub fn set_transfer_tax(
+    context: ContractContext,
+    mut state: TokenState,
+    transfer_tax: Option<TransferTax>,
+) -> TokenState {
+    state.assert_owner(&context.sender, "set the transfer tax");
+    if let Some(transfer_tax) = transfer_tax {
+        transfer_tax.assert_valid();
+    }
+    state.transfer_tax = transfer_tax;
+    state
+}
+
+/// Transfers ownership of the token to `new_owner`, who takes over all privileged actions.
+/// The function throws if the caller is not the owner of the token.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`], the context for the action call.
+///
+/// * `state`: [`TokenState`], the current state of the contract.
+///
+/// * `new_owner`: [`Address`], the new owner of the token.
+///
+/// ### Returns
+///
+/// The new state object of type [`TokenState`].
+#[action(shortname = 0x0C)]
+pub fn transfer_ownership(
+    context: ContractContext,
+    mut state: TokenState,
+    new_owner: Address,
+) -> TokenState {
+    state.assert_owner(&context.sender, "transfer ownership");
+    state.owner = new_owner;
     state
 }
 
+/// Renounces ownership of the token, by transferring it to [`BURN_ADDRESS`]. Afterwards, no
+/// privileged actions can be taken.
+/// The function throws if the caller is not the owner of the token.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`], the context for the action call.
+///
+/// * `state`: [`TokenState`], the current state of the contract.
+///
+/// ### Returns
+///
+/// The new state object of type [`TokenState`].
+#[action(shortname = 0x0D)]
+pub fn renounce_ownership(context: ContractContext, state: TokenState) -> TokenState {
+    transfer_ownership(context, state, BURN_ADDRESS)
+}
+
+/// Asserts that no address appears as the recipient of more than one of `transfers`.
+///
+/// ### Parameters:
+///
+/// * `transfers`: [`&[Transfer]`], the transfers to check.
+pub fn assert_unique_recipients(transfers: &[Transfer]) {
+    let unique_recipients: SortedVecSet<Address> = transfers.iter().map(|t| t.to).collect();
+    assert_eq!(
+        transfers.len(),
+        unique_recipients.len(),
+        "All recipients must be unique"
+    );
+}
+
 /// Transfers `amount` of tokens to address `to` from the caller.
 /// The function throws if the message caller's account
-/// balance does not have enough tokens to spend.
+/// balance does not have enough tokens to spend, if transfers are paused, or if either the sender
+/// or `to` is frozen.
 /// If the sender's account goes to 0, the sender's address is removed from state.
+/// If a transfer tax is set, it is levied as by [`taxed_core_transfer`].
 ///
 /// ### Parameters:
 ///
@@ -375,16 +1244,97 @@ pub fn approve_relative(
 /// ### Returns
 ///
 /// The new state object of type [`TokenState`] with an updated ledger.
-pub fn core_transfer(
+pub fn core_transfer(sender: Address, state: TokenState, to: Address, amount: u128) -> TokenState {
+    core_transfer_net(sender, state, to, amount).0
+}
+
+/// Transfers `amount` of tokens to address `to` from the caller, like [`core_transfer`], and
+/// returns the amount received by `to` after any transfer tax.
+fn core_transfer_net(
+    sender: Address,
+    state: TokenState,
+    to: Address,
+    amount: u128,
+) -> (TokenState, u128) {
+    match state.transfer_tax {
+        Some(transfer_tax) => taxed_core_transfer(sender, state, to, amount, transfer_tax),
+        None => (untaxed_core_transfer(sender, state, to, amount), amount),
+    }
+}
+
+/// Calculates `per_mille` of `amount`, rounded down.
+///
+/// ### Parameters:
+///
+/// * `amount`: [`u128`], the amount to take a share of.
+///
+/// * `per_mille`: [`u16`], the share in per mille.
+///
+/// ### Returns
+///
+/// The share of `amount`, of type [`u128`].
+pub fn apply_per_mille(amount: u128, per_mille: u16) -> u128 {
+    let per_mille = per_mille as u128;
+    // Splits the multiplication, such that large amounts cannot overflow.
+    amount / 1000 * per_mille + amount % 1000 * per_mille / 1000
+}
+
+/// Transfers `amount` of tokens to address `to` from the caller, of which `to` pays the tax given
+/// by `transfer_tax` to the treasury. The recipient and treasury thus receive `amount` combined.
+/// The function throws under the same conditions as [`core_transfer`], or if the tax exceeds
+/// 1000 per mille. The treasury is credited even if it is frozen.
+///
+/// ### Parameters:
+///
+/// * `sender`: [`Address`], the sender of the transaction.
+///
+/// * `state`: [`TokenState`], the current state of the contract.
+///
+/// * `to`: [`Address`], the address to transfer to.
+///
+/// * `amount`: [`u128`], amount to transfer, including the tax.
+///
+/// * `transfer_tax`: [`TransferTax`], the tax to levy.
+///
+/// ### Returns
+///
+/// The new state object of type [`TokenState`] with an updated ledger, and the amount received by
+/// `to` after the tax.
+pub fn taxed_core_transfer(
+    sender: Address,
+    state: TokenState,
+    to: Address,
+    amount: u128,
+    transfer_tax: TransferTax,
+) -> (TokenState, u128) {
+    transfer_tax.assert_valid();
+    let tax = apply_per_mille(amount, transfer_tax.tax_per_mille);
+    let mut state = untaxed_core_transfer(sender, state, to, amount);
+    // The treasury is credited directly, such that freezing it does not block every transfer.
+    let to_amount = state.balance_of(&to);
+    state.balances.insert_balance(to, to_amount.sub(tax));
+    let treasury_amount = state.balance_of(&transfer_tax.treasury);
+    state
+        .balances
+        .insert_balance(transfer_tax.treasury, treasury_amount.add(tax));
+    (state, amount - tax)
+}
+
+/// Transfers `amount` of tokens to address `to` from the caller, ignoring any transfer tax.
+fn untaxed_core_transfer(
     sender: Address,
     mut state: TokenState,
     to: Address,
     amount: u128,
 ) -> TokenState {
+    state.assert_not_paused();
+    state.assert_not_frozen(&sender);
+    state.assert_not_frozen(&to);
     let from_amount = state.balance_of(&sender);
     let o_new_from_amount = from_amount.checked_sub(amount);
     match o_new_from_amount {
         Some(new_from_amount) => {
+            // Transferring the full balance closes the account, removing it from the holders.
             state.balances.insert_balance(sender, new_from_amount);
         }
         None => {
@@ -399,11 +1349,47 @@ pub fn core_transfer(
     state
 }
 
+/// Destroys `amount` of tokens from the balance of `owner`, decreasing the total supply.
+/// The function throws if `owner`'s account balance does not have enough tokens to burn, if
+/// transfers are paused, or if `owner` is frozen.
+/// If `owner`'s account goes to 0, the address is removed from state.
+///
+/// ### Parameters:
+///
+/// * `owner`: [`Address`], the owner of the burned tokens.
+///
+/// * `state`: [`TokenState`], the current state of the contract.
+///
+/// * `amount`: [`u128`], amount to burn.
+///
+/// ### Returns
+///
+/// The new state object of type [`TokenState`] with an updated ledger and total supply.
+pub fn core_burn(owner: Address, mut state: TokenState, amount: u128) -> TokenState {
+    state.assert_not_paused();
+    state.assert_not_frozen(&owner);
+    let owner_amount = state.balance_of(&owner);
+    let o_new_owner_amount = owner_amount.checked_sub(amount);
+    match o_new_owner_amount {
+        Some(new_owner_amount) => {
+            // Burning the full balance closes the account, removing it from the holders.
+            state.balances.insert_balance(owner, new_owner_amount);
+        }
+        None => {
+            panic!("Insufficient funds for burn: {}/{}", owner_amount, amount);
+        }
+    }
+    // The burned balance is part of the total supply, so this cannot underflow.
+    state.total_supply = state.total_supply.sub(amount);
+    state
+}
+
 /// Transfers `amount` of tokens from address `from` to address `to`.\
 /// This requires that the sender is allowed to do the transfer by the `from`
 /// account through the `approve` action.
 /// The function throws if the message caller's account
-/// balance does not have enough tokens to spend, or if the tokens were not approved.
+/// balance does not have enough tokens to spend, if the tokens were not approved or the allowance
+/// has expired, if transfers are paused, or if the sender, `from` or `to` is frozen.
 ///
 /// ### Parameters:
 ///
@@ -417,6 +1403,8 @@ pub fn core_transfer(
 ///
 /// * `amount`: [`u128`], amount to transfer.
 ///
+/// * `now`: [`i64`], the current time in UTC millis, against which the allowance expiry is checked.
+///
 /// ### Returns
 ///
 /// The new state object of type [`TokenState`] with an updated ledger.
@@ -426,8 +1414,11 @@ pub fn core_transfer_from(
     from: Address,
     to: Address,
     amount: u128,
+    now: i64,
 ) -> TokenState {
-    let from_allowed = state.allowance(&from, &sender);
+    state.assert_not_paused();
+    state.assert_not_frozen(&sender);
+    let from_allowed = state.allowance_at(&from, &sender, now);
     let o_new_allowed_amount = from_allowed.checked_sub(amount);
     match o_new_allowed_amount {
         Some(new_allowed_amount) => {
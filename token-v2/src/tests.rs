@@ -0,0 +1,892 @@
+use pbc_contract_common::{address::AddressType, Hash};
+use pbc_traits::ReadWriteRPC;
+
+use super::*;
+
+const OWNER: Address = Address {
+    address_type: AddressType::Account,
+    identifier: [1; 20],
+};
+
+const ALICE: Address = Address {
+    address_type: AddressType::Account,
+    identifier: [2; 20],
+};
+
+const BOB: Address = Address {
+    address_type: AddressType::Account,
+    identifier: [3; 20],
+};
+
+const CAROL: Address = Address {
+    address_type: AddressType::Account,
+    identifier: [4; 20],
+};
+
+/// Creates a context for an invocation sent by `sender`.
+fn context(sender: Address) -> ContractContext {
+    context_at(sender, 0)
+}
+
+/// Creates a context for an invocation sent by `sender` in a block produced at `block_production_time`.
+fn context_at(sender: Address, block_production_time: i64) -> ContractContext {
+    ContractContext {
+        contract_address: Address {
+            address_type: AddressType::PublicContract,
+            identifier: [0; 20],
+        },
+        sender,
+        block_time: 0,
+        block_production_time,
+        current_transaction: Hash { bytes: [0; 32] },
+        original_transaction: Hash { bytes: [0; 32] },
+    }
+}
+
+/// Initializes a token with 18 decimals, owned by [`OWNER`].
+fn initial_state(total_supply: u128) -> TokenState {
+    initialize(
+        context(OWNER),
+        "Token".to_string(),
+        "TOK".to_string(),
+        18,
+        total_supply,
+        None,
+    )
+}
+
+#[test]
+fn initialize_typical_decimals() {
+    let state = initial_state(1000);
+    assert_eq!(state.decimals, 18);
+    assert_eq!(state.balance_of(&OWNER), 1000);
+
+    let state = initialize(
+        context(OWNER),
+        "Token".to_string(),
+        "TOK".to_string(),
+        MAX_DECIMALS,
+        1000,
+        None,
+    );
+    assert_eq!(state.decimals, MAX_DECIMALS);
+}
+
+#[test]
+#[should_panic(expected = "Decimals must be at most 38, but was 100")]
+fn initialize_absurd_decimals() {
+    initialize(
+        context(OWNER),
+        "Token".to_string(),
+        "TOK".to_string(),
+        100,
+        1000,
+        None,
+    );
+}
+
+#[test]
+fn bulk_transfer_allows_duplicate_recipients() {
+    let transfers = vec![
+        Transfer {
+            to: ALICE,
+            amount: 10,
+        },
+        Transfer {
+            to: ALICE,
+            amount: 20,
+        },
+    ];
+    let state = bulk_transfer(context(OWNER), initial_state(1000), transfers);
+    assert_eq!(state.balance_of(&OWNER), 970);
+    assert_eq!(state.balance_of(&ALICE), 30);
+}
+
+#[test]
+fn bulk_transfer_unique_distinct_recipients() {
+    let transfers = vec![
+        Transfer {
+            to: ALICE,
+            amount: 10,
+        },
+        Transfer {
+            to: BOB,
+            amount: 20,
+        },
+    ];
+    let state = bulk_transfer_unique(context(OWNER), initial_state(1000), transfers);
+    assert_eq!(state.balance_of(&OWNER), 970);
+    assert_eq!(state.balance_of(&ALICE), 10);
+    assert_eq!(state.balance_of(&BOB), 20);
+}
+
+#[test]
+#[should_panic(expected = "All recipients must be unique")]
+fn bulk_transfer_unique_rejects_duplicate_recipients() {
+    // The duplicate is detected before the first transfer is performed.
+    let transfers = vec![
+        Transfer {
+            to: ALICE,
+            amount: 10,
+        },
+        Transfer {
+            to: BOB,
+            amount: 20,
+        },
+        Transfer {
+            to: ALICE,
+            amount: 30,
+        },
+    ];
+    bulk_transfer_unique(context(OWNER), initial_state(1000), transfers);
+}
+
+#[test]
+fn top_holders_ordered_by_balance() {
+    let state = initial_state(1000);
+    let state = transfer(context(OWNER), state, ALICE, 300);
+    let state = transfer(context(OWNER), state, BOB, 100);
+    let state = transfer(context(OWNER), state, CAROL, 300);
+
+    assert_eq!(
+        state.top_holders(3),
+        vec![(OWNER, 300), (ALICE, 300), (CAROL, 300)]
+    );
+    assert_eq!(state.top_holders(1), vec![(OWNER, 300)]);
+    assert_eq!(state.top_holders(10).len(), 4);
+    assert_eq!(state.top_holders(10)[3], (BOB, 100));
+    assert!(state.top_holders(0).is_empty());
+}
+
+#[test]
+fn circulating_supply_excludes_addresses() {
+    let state = initial_state(1000);
+    let state = transfer(context(OWNER), state, ALICE, 300);
+    let state = transfer(context(OWNER), state, BOB, 100);
+
+    assert_eq!(state.circulating_supply(&[]), 1000);
+    assert_eq!(state.circulating_supply(&[OWNER]), 400);
+    assert_eq!(state.circulating_supply(&[OWNER, ALICE]), 100);
+    // Duplicates and addresses without a balance are harmless.
+    assert_eq!(state.circulating_supply(&[OWNER, ALICE, OWNER, CAROL]), 100);
+}
+
+#[test]
+fn validate_bulk_transfer_within_balance() {
+    let state = initial_state(1000);
+    let transfers = [
+        Transfer {
+            to: ALICE,
+            amount: 600,
+        },
+        Transfer {
+            to: BOB,
+            amount: 400,
+        },
+    ];
+    assert_eq!(state.validate_bulk_transfer(&OWNER, &transfers), Ok(()));
+    assert_eq!(state.validate_bulk_transfer(&OWNER, &[]), Ok(()));
+}
+
+#[test]
+fn validate_bulk_transfer_rejects_sum_over_balance() {
+    let state = initial_state(1000);
+    let transfers = [
+        Transfer {
+            to: ALICE,
+            amount: 600,
+        },
+        Transfer {
+            to: BOB,
+            amount: 401,
+        },
+    ];
+    assert_eq!(
+        state.validate_bulk_transfer(&OWNER, &transfers),
+        Err("Sum of transfer amounts exceeds the balance of the sender")
+    );
+}
+
+#[test]
+fn validate_bulk_transfer_rejects_overflowing_sum() {
+    let state = initial_state(u128::MAX);
+    let transfers = [
+        Transfer {
+            to: ALICE,
+            amount: u128::MAX,
+        },
+        Transfer { to: BOB, amount: 1 },
+    ];
+    assert_eq!(
+        state.validate_bulk_transfer(&OWNER, &transfers),
+        Err("Sum of transfer amounts overflows")
+    );
+}
+
+#[test]
+fn mint_to_fresh_address() {
+    let state = initial_state(1000);
+    let state = mint(context(OWNER), state, ALICE, 500);
+
+    assert_eq!(state.balance_of(&ALICE), 500);
+    assert_eq!(state.balance_of(&OWNER), 1000);
+    assert_eq!(state.total_supply, 1500);
+}
+
+#[test]
+fn mint_to_existing_holder() {
+    let state = initial_state(1000);
+    let state = transfer(context(OWNER), state, ALICE, 300);
+    let state = mint(context(OWNER), state, ALICE, 200);
+    let state = mint(context(OWNER), state, OWNER, 50);
+
+    assert_eq!(state.balance_of(&ALICE), 500);
+    assert_eq!(state.balance_of(&OWNER), 750);
+    assert_eq!(state.total_supply, 1250);
+}
+
+#[test]
+#[should_panic(expected = "Only the owner can mint tokens")]
+fn mint_by_non_owner_is_rejected() {
+    let state = initial_state(1000);
+    mint(context(ALICE), state, ALICE, 500);
+}
+
+#[test]
+#[should_panic(expected = "Total supply would overflow")]
+fn mint_overflowing_total_supply_is_rejected() {
+    let state = initial_state(u128::MAX);
+    mint(context(OWNER), state, ALICE, 1);
+}
+
+#[test]
+fn burn_reduces_total_supply() {
+    let state = initial_state(1000);
+    let state = burn(context(OWNER), state, 300);
+
+    assert_eq!(state.balance_of(&OWNER), 700);
+    assert_eq!(state.total_supply, 700);
+}
+
+#[test]
+fn burn_full_balance_removes_holder() {
+    let state = initial_state(1000);
+    let state = transfer(context(OWNER), state, ALICE, 400);
+    let state = burn(context(ALICE), state, 400);
+
+    assert_eq!(state.balance_of(&ALICE), 0);
+    assert_eq!(state.balances.get(&ALICE), None);
+    assert_eq!(state.total_supply, 600);
+}
+
+#[test]
+#[should_panic(expected = "Insufficient funds for burn: 400/401")]
+fn burn_more_than_held_is_rejected() {
+    let state = initial_state(1000);
+    let state = transfer(context(OWNER), state, ALICE, 400);
+    burn(context(ALICE), state, 401);
+}
+
+#[test]
+fn burn_from_consumes_allowance() {
+    let state = approve(context(OWNER), initial_state(1000), ALICE, 500);
+    let state = burn_from(context(ALICE), state, OWNER, 300);
+
+    assert_eq!(state.balance_of(&OWNER), 700);
+    assert_eq!(state.allowance(&OWNER, &ALICE), 200);
+    assert_eq!(state.total_supply, 700);
+}
+
+#[test]
+#[should_panic(expected = "Insufficient allowance for burn_from: 500/501")]
+fn burn_from_over_allowance_is_rejected() {
+    let state = approve(context(OWNER), initial_state(1000), ALICE, 500);
+    burn_from(context(ALICE), state, OWNER, 501);
+}
+
+#[test]
+#[should_panic(expected = "Insufficient funds for burn: 1000/1001")]
+fn burn_from_over_balance_is_rejected() {
+    let state = approve(context(OWNER), initial_state(1000), ALICE, 2000);
+    burn_from(context(ALICE), state, OWNER, 1001);
+}
+
+#[test]
+#[should_panic(expected = "Token transfers are paused")]
+fn transfer_while_paused_is_rejected() {
+    let state = set_paused(context(OWNER), initial_state(1000), true);
+    transfer(context(OWNER), state, ALICE, 100);
+}
+
+#[test]
+#[should_panic(expected = "Token transfers are paused")]
+fn transfer_from_while_paused_is_rejected() {
+    let state = approve(context(OWNER), initial_state(1000), ALICE, 500);
+    let state = set_paused(context(OWNER), state, true);
+    transfer_from(context(ALICE), state, OWNER, BOB, 100);
+}
+
+#[test]
+fn approvals_allowed_and_transfers_resume_after_unpause() {
+    let state = set_paused(context(OWNER), initial_state(1000), true);
+    let state = approve(context(OWNER), state, ALICE, 500);
+    let state = set_paused(context(OWNER), state, false);
+
+    let state = transfer_from(context(ALICE), state, OWNER, BOB, 100);
+    let state = transfer(context(OWNER), state, ALICE, 100);
+    assert_eq!(state.balance_of(&BOB), 100);
+    assert_eq!(state.balance_of(&ALICE), 100);
+}
+
+#[test]
+#[should_panic(expected = "Only the owner can pause transfers")]
+fn pause_by_non_owner_is_rejected() {
+    set_paused(context(ALICE), initial_state(1000), true);
+}
+
+#[test]
+fn new_owner_takes_over_privileges() {
+    let state = transfer_ownership(context(OWNER), initial_state(1000), ALICE);
+    let state = mint(context(ALICE), state, BOB, 100);
+    let state = set_paused(context(ALICE), state, true);
+
+    assert_eq!(state.owner, ALICE);
+    assert_eq!(state.balance_of(&BOB), 100);
+    assert!(state.paused);
+}
+
+#[test]
+#[should_panic(expected = "Only the owner can mint tokens")]
+fn old_owner_loses_privileges() {
+    let state = transfer_ownership(context(OWNER), initial_state(1000), ALICE);
+    mint(context(OWNER), state, OWNER, 100);
+}
+
+#[test]
+#[should_panic(expected = "Only the owner can transfer ownership")]
+fn ownership_transfer_by_non_owner_is_rejected() {
+    transfer_ownership(context(ALICE), initial_state(1000), ALICE);
+}
+
+#[test]
+#[should_panic(expected = "Only the owner can pause transfers")]
+fn renounced_owner_loses_privileges() {
+    let state = renounce_ownership(context(OWNER), initial_state(1000));
+    assert_eq!(state.owner, BURN_ADDRESS);
+    set_paused(context(OWNER), state, true);
+}
+
+/// Initializes a token with 18 decimals and the given max supply, owned by [`OWNER`].
+fn capped_state(total_supply: u128, max_supply: u128) -> TokenState {
+    initialize(
+        context(OWNER),
+        "Token".to_string(),
+        "TOK".to_string(),
+        18,
+        total_supply,
+        Some(max_supply),
+    )
+}
+
+#[test]
+fn mint_up_to_max_supply() {
+    let state = capped_state(1000, 1500);
+    let state = mint(context(OWNER), state, ALICE, 400);
+    assert_eq!(state.total_supply, 1400);
+
+    // Exactly at the cap.
+    let state = mint(context(OWNER), state, ALICE, 100);
+    assert_eq!(state.total_supply, 1500);
+    assert_eq!(state.balance_of(&ALICE), 500);
+}
+
+#[test]
+#[should_panic(expected = "Mint would exceed max supply")]
+fn mint_over_max_supply_cap_is_rejected() {
+    let state = capped_state(1000, 1500);
+    mint(context(OWNER), state, ALICE, 501);
+}
+
+#[test]
+#[should_panic(expected = "Total supply must be at most the max supply 999, but was 1000")]
+fn initialize_over_max_supply() {
+    capped_state(1000, 999);
+}
+
+#[test]
+#[should_panic(expected = "is frozen")]
+fn transfer_from_frozen_sender_is_rejected() {
+    let state = transfer(context(OWNER), initial_state(1000), ALICE, 100);
+    let state = freeze(context(OWNER), state, ALICE);
+    transfer(context(ALICE), state, BOB, 50);
+}
+
+#[test]
+#[should_panic(expected = "is frozen")]
+fn transfer_to_frozen_recipient_is_rejected() {
+    let state = freeze(context(OWNER), initial_state(1000), ALICE);
+    transfer(context(OWNER), state, ALICE, 100);
+}
+
+#[test]
+#[should_panic(expected = "is frozen")]
+fn frozen_spender_cannot_transfer_from() {
+    let state = approve(context(OWNER), initial_state(1000), ALICE, 500);
+    let state = freeze(context(OWNER), state, ALICE);
+    transfer_from(context(ALICE), state, OWNER, BOB, 100);
+}
+
+#[test]
+#[should_panic(expected = "is frozen")]
+fn frozen_owner_cannot_burn() {
+    let state = transfer(context(OWNER), initial_state(1000), ALICE, 100);
+    let state = freeze(context(OWNER), state, ALICE);
+    burn(context(ALICE), state, 50);
+}
+
+#[test]
+#[should_panic(expected = "is frozen")]
+fn frozen_owner_cannot_be_burned_from() {
+    let state = transfer(context(OWNER), initial_state(1000), ALICE, 100);
+    let state = approve(context(ALICE), state, BOB, 100);
+    let state = freeze(context(OWNER), state, ALICE);
+    burn_from(context(BOB), state, ALICE, 50);
+}
+
+#[test]
+#[should_panic(expected = "is frozen")]
+fn frozen_spender_cannot_burn_from() {
+    let state = approve(context(OWNER), initial_state(1000), ALICE, 500);
+    let state = freeze(context(OWNER), state, ALICE);
+    burn_from(context(ALICE), state, OWNER, 100);
+}
+
+#[test]
+#[should_panic(expected = "is frozen")]
+fn mint_to_frozen_address_is_rejected() {
+    let state = freeze(context(OWNER), initial_state(1000), ALICE);
+    mint(context(OWNER), state, ALICE, 100);
+}
+
+#[test]
+#[should_panic(expected = "Only the owner can freeze addresses")]
+fn only_owner_can_freeze() {
+    freeze(context(ALICE), initial_state(1000), BOB);
+}
+
+#[test]
+fn unfrozen_address_can_transfer_again() {
+    let state = transfer(context(OWNER), initial_state(1000), ALICE, 100);
+    let state = freeze(context(OWNER), state, ALICE);
+    assert!(state.is_frozen(&ALICE));
+
+    let state = unfreeze(context(OWNER), state, ALICE);
+    assert!(!state.is_frozen(&ALICE));
+
+    let state = transfer(context(ALICE), state, BOB, 50);
+    assert_eq!(state.balance_of(&ALICE), 50);
+    assert_eq!(state.balance_of(&BOB), 50);
+}
+
+#[test]
+fn transfer_and_call_notifies_recipient() {
+    let receiver = Address {
+        address_type: AddressType::PublicContract,
+        identifier: [5; 20],
+    };
+    let (state, event_groups) = transfer_and_call(
+        context(OWNER),
+        initial_state(1000),
+        receiver,
+        100,
+        vec![7, 8],
+    );
+
+    assert_eq!(state.balance_of(&OWNER), 900);
+    assert_eq!(state.balance_of(&receiver), 100);
+
+    assert_eq!(event_groups.len(), 1);
+    let events = &event_groups[0].events;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].dest, receiver);
+
+    let mut expected_payload = SHORTNAME_TOKENS_RECEIVED.bytes();
+    OWNER.rpc_write_to(&mut expected_payload).unwrap();
+    100u128.rpc_write_to(&mut expected_payload).unwrap();
+    vec![7u8, 8].rpc_write_to(&mut expected_payload).unwrap();
+    assert_eq!(events[0].payload, expected_payload);
+}
+
+#[test]
+fn transfer_and_call_is_not_reverted_by_notification() {
+    let receiver = Address {
+        address_type: AddressType::PublicContract,
+        identifier: [5; 20],
+    };
+    let (state, event_groups) =
+        transfer_and_call(context(OWNER), initial_state(1000), receiver, 100, vec![]);
+
+    // No callback is registered, so the outcome of `tokens_received` cannot undo the transfer.
+    assert_eq!(event_groups.len(), 1);
+    assert!(event_groups[0].callback_rpc.is_none());
+    assert_eq!(state.balance_of(&OWNER), 900);
+    assert_eq!(state.balance_of(&receiver), 100);
+}
+
+#[test]
+#[should_panic(expected = "Insufficient funds for transfer: 1000/1001")]
+fn transfer_and_call_requires_balance() {
+    transfer_and_call(context(OWNER), initial_state(1000), ALICE, 1001, vec![]);
+}
+
+#[test]
+fn bulk_approve_sets_all_allowances() {
+    let approvals = vec![
+        Approval {
+            spender: ALICE,
+            amount: 100,
+        },
+        Approval {
+            spender: BOB,
+            amount: 200,
+        },
+    ];
+    let state = bulk_approve(context(OWNER), initial_state(1000), approvals);
+    assert_eq!(state.allowance(&OWNER, &ALICE), 100);
+    assert_eq!(state.allowance(&OWNER, &BOB), 200);
+    assert_eq!(state.allowance(&OWNER, &CAROL), 0);
+}
+
+#[test]
+fn bulk_approve_duplicate_spender_takes_last_value() {
+    let approvals = vec![
+        Approval {
+            spender: ALICE,
+            amount: 100,
+        },
+        Approval {
+            spender: ALICE,
+            amount: 50,
+        },
+    ];
+    let state = bulk_approve(context(OWNER), initial_state(1000), approvals);
+    assert_eq!(state.allowance_utilization(&OWNER, &ALICE), (50, 50));
+}
+
+const TAX_TO_CAROL: TransferTax = TransferTax {
+    treasury: CAROL,
+    tax_per_mille: 25,
+};
+
+#[test]
+fn taxed_transfer_credits_treasury() {
+    let (state, received) =
+        taxed_core_transfer(OWNER, initial_state(1000), ALICE, 400, TAX_TO_CAROL);
+    assert_eq!(received, 390);
+    assert_eq!(state.balance_of(&OWNER), 600);
+    assert_eq!(state.balance_of(&ALICE), 390);
+    assert_eq!(state.balance_of(&CAROL), 10);
+}
+
+#[test]
+fn taxed_transfer_sums_to_original_amount() {
+    for amount in [0, 1, 39, 40, 999, 1000] {
+        let (state, _) =
+            taxed_core_transfer(OWNER, initial_state(1000), ALICE, amount, TAX_TO_CAROL);
+        assert_eq!(state.balance_of(&OWNER), 1000 - amount);
+        assert_eq!(state.balance_of(&ALICE) + state.balance_of(&CAROL), amount);
+        assert_eq!(state.balance_of(&CAROL), amount * 25 / 1000);
+    }
+}
+
+#[test]
+fn transfers_are_taxed_once_tax_is_set() {
+    let state = transfer(context(OWNER), initial_state(1000), ALICE, 400);
+    assert_eq!(state.balance_of(&ALICE), 400);
+
+    let state = set_transfer_tax(context(OWNER), state, Some(TAX_TO_CAROL));
+    let state = transfer(context(ALICE), state, BOB, 200);
+    assert_eq!(state.balance_of(&BOB), 195);
+    assert_eq!(state.balance_of(&CAROL), 5);
+}
+
+#[test]
+fn transfer_and_call_notifies_taxed_amount() {
+    let state = set_transfer_tax(context(OWNER), initial_state(1000), Some(TAX_TO_CAROL));
+    let (state, event_groups) = transfer_and_call(context(OWNER), state, ALICE, 400, vec![]);
+    assert_eq!(state.balance_of(&ALICE), 390);
+
+    let mut expected_payload = SHORTNAME_TOKENS_RECEIVED.bytes();
+    OWNER.rpc_write_to(&mut expected_payload).unwrap();
+    390u128.rpc_write_to(&mut expected_payload).unwrap();
+    Vec::<u8>::new()
+        .rpc_write_to(&mut expected_payload)
+        .unwrap();
+    assert_eq!(event_groups[0].events[0].payload, expected_payload);
+}
+
+#[test]
+fn transfers_are_taxed_while_treasury_is_frozen() {
+    let state = set_transfer_tax(context(OWNER), initial_state(1000), Some(TAX_TO_CAROL));
+    let state = freeze(context(OWNER), state, CAROL);
+    let state = transfer(context(OWNER), state, ALICE, 400);
+    assert_eq!(state.balance_of(&ALICE), 390);
+    assert_eq!(state.balance_of(&CAROL), 10);
+}
+
+#[test]
+fn apply_per_mille_does_not_overflow() {
+    assert_eq!(apply_per_mille(1000, 25), 25);
+    assert_eq!(apply_per_mille(u128::MAX, 1000), u128::MAX);
+    assert_eq!(apply_per_mille(u128::MAX, 0), 0);
+}
+
+#[test]
+#[should_panic(expected = "Transfer tax must be at most 1000 per mille, but was 1001")]
+fn transfer_tax_above_1000_per_mille_is_rejected() {
+    set_transfer_tax(
+        context(OWNER),
+        initial_state(1000),
+        Some(TransferTax {
+            treasury: CAROL,
+            tax_per_mille: 1001,
+        }),
+    );
+}
+
+#[test]
+fn allowance_can_be_spent_until_expiry() {
+    let state = approve_until(context(OWNER), initial_state(1000), ALICE, 500, 1000);
+    let state = transfer_from(context_at(ALICE, 500), state, OWNER, BOB, 100);
+    let state = transfer_from(context_at(ALICE, 1000), state, OWNER, BOB, 100);
+    assert_eq!(state.balance_of(&BOB), 200);
+    assert_eq!(state.allowance_at(&OWNER, &ALICE, 1000), 300);
+    // Spending keeps the expiry.
+    assert_eq!(state.allowance_at(&OWNER, &ALICE, 1001), 0);
+    assert_eq!(state.allowance(&OWNER, &ALICE), 300);
+}
+
+#[test]
+#[should_panic(expected = "Insufficient allowance for transfer_from: 0/100")]
+fn transfer_from_fails_once_allowance_expired() {
+    let state = approve_until(context(OWNER), initial_state(1000), ALICE, 500, 1000);
+    transfer_from(context_at(ALICE, 1001), state, OWNER, BOB, 100);
+}
+
+#[test]
+#[should_panic(expected = "Insufficient allowance for burn_from: 0/100")]
+fn burn_from_fails_once_allowance_expired() {
+    let state = approve_until(context(OWNER), initial_state(1000), ALICE, 500, 1000);
+    burn_from(context_at(ALICE, 1001), state, OWNER, 100);
+}
+
+#[test]
+#[should_panic(expected = "Allowance expiry 999 is in the past")]
+fn approve_until_rejects_past_expiry() {
+    approve_until(
+        context_at(OWNER, 1000),
+        initial_state(1000),
+        ALICE,
+        500,
+        999,
+    );
+}
+
+#[test]
+fn approve_relative_restarts_expired_allowance() {
+    let state = approve_until(context(OWNER), initial_state(1000), ALICE, 500, 1000);
+    let state = approve_relative(context_at(OWNER, 1001), state, ALICE, 100);
+
+    assert_eq!(state.allowance(&OWNER, &ALICE), 100);
+    assert_eq!(state.allowance_at(&OWNER, &ALICE, NEVER_EXPIRES), 100);
+    assert_eq!(state.allowance_utilization(&OWNER, &ALICE), (100, 100));
+}
+
+#[test]
+fn approve_relative_keeps_unexpired_expiry() {
+    let state = approve_until(context(OWNER), initial_state(1000), ALICE, 500, 1000);
+    let state = approve_relative(context_at(OWNER, 1000), state, ALICE, 100);
+
+    assert_eq!(state.allowance_at(&OWNER, &ALICE, 1000), 600);
+    assert_eq!(state.allowance_at(&OWNER, &ALICE, 1001), 0);
+}
+
+#[test]
+fn approve_never_expires() {
+    let state = approve(context(OWNER), initial_state(1000), ALICE, 500);
+    let state = transfer_from(context_at(ALICE, NEVER_EXPIRES), state, OWNER, BOB, 100);
+    assert_eq!(state.balance_of(&BOB), 100);
+}
+
+/// Invokes [`cleanup_expired_allowances`] at `now`, and reads the returned [`AllowanceCleanup`].
+fn cleanup_at(
+    now: i64,
+    state: TokenState,
+    start: Option<AllowedAddress>,
+    max: u32,
+) -> (TokenState, AllowanceCleanup) {
+    let (state, event_groups) =
+        cleanup_expired_allowances(context_at(CAROL, now), state, start, max);
+    assert_eq!(event_groups.len(), 1);
+    let mut return_data = event_groups[0].return_data.as_deref().unwrap();
+    (state, AllowanceCleanup::rpc_read_from(&mut return_data))
+}
+
+#[test]
+fn cleanup_removes_expired_allowances_only() {
+    let state = approve_until(context(OWNER), initial_state(1000), ALICE, 500, 1000);
+    let state = approve_until(context(OWNER), state, BOB, 500, 2000);
+    let state = approve(context(OWNER), state, CAROL, 500);
+
+    let (state, cleanup) = cleanup_at(1001, state, None, 10);
+    assert_eq!(
+        cleanup,
+        AllowanceCleanup {
+            removed: 1,
+            next_start: None,
+        }
+    );
+    assert_eq!(state.allowance(&OWNER, &ALICE), 0);
+    assert_eq!(state.allowance_utilization(&OWNER, &ALICE), (0, 0));
+    assert_eq!(state.allowance(&OWNER, &BOB), 500);
+    assert_eq!(state.allowance(&OWNER, &CAROL), 500);
+}
+
+#[test]
+fn cleanup_scans_at_most_max_allowances() {
+    let state = approve_until(context(OWNER), initial_state(1000), ALICE, 500, 1000);
+    let state = approve_until(context(OWNER), state, BOB, 500, 1000);
+    let state = approve_until(context(OWNER), state, CAROL, 500, 1000);
+
+    let (state, cleanup) = cleanup_at(1001, state, None, 2);
+    // Scanned in order of spender.
+    assert_eq!(cleanup.removed, 2);
+    assert_eq!(state.allowance(&OWNER, &ALICE), 0);
+    assert_eq!(state.allowance(&OWNER, &BOB), 0);
+    assert_eq!(state.allowance(&OWNER, &CAROL), 500);
+
+    let (state, cleanup) = cleanup_at(1001, state, cleanup.next_start, 2);
+    assert_eq!(
+        cleanup,
+        AllowanceCleanup {
+            removed: 1,
+            next_start: None,
+        }
+    );
+    assert_eq!(state.allowance(&OWNER, &CAROL), 0);
+}
+
+#[test]
+fn cleanup_reaches_expired_allowances_behind_unexpired_ones() {
+    let spender = |id: u8| Address {
+        address_type: AddressType::Account,
+        identifier: [id; 20],
+    };
+    let mut state = initial_state(1000);
+    for id in 10..60 {
+        state = approve(context(OWNER), state, spender(id), 500);
+    }
+    state = approve_until(context(OWNER), state, spender(200), 500, 1000);
+    state = approve_until(context(OWNER), state, spender(201), 500, 1000);
+
+    // Each invocation scans a single page, so the expired allowances are only reached once the
+    // unexpired allowances before them have been scanned.
+    let mut removed_per_invocation = vec![];
+    let mut start = None;
+    loop {
+        let (new_state, cleanup) = cleanup_at(1001, state, start, 10);
+        state = new_state;
+        removed_per_invocation.push(cleanup.removed);
+        start = cleanup.next_start;
+        if start.is_none() {
+            break;
+        }
+    }
+
+    assert_eq!(removed_per_invocation, vec![0, 0, 0, 0, 0, 2]);
+    assert_eq!(state.allowance(&OWNER, &spender(200)), 0);
+    assert_eq!(state.allowance(&OWNER, &spender(201)), 0);
+    assert_eq!(state.allowance(&OWNER, &spender(59)), 500);
+}
+
+#[test]
+fn cleanup_keeps_allowances_until_expiry() {
+    let state = approve_until(context(OWNER), initial_state(1000), ALICE, 500, 1000);
+
+    let (state, cleanup) = cleanup_at(1000, state, None, 10);
+    assert_eq!(cleanup.removed, 0);
+    assert_eq!(state.allowance_at(&OWNER, &ALICE, 1000), 500);
+}
+
+#[test]
+fn export_holders_returns_all_balances() {
+    let state = transfer(context(OWNER), initial_state(1000), ALICE, 100);
+    let state = transfer(context(OWNER), state, BOB, 200);
+
+    let (state, event_groups) = export_holders(context(CAROL), state);
+    assert_eq!(state.balance_of(&OWNER), 700);
+    assert_eq!(event_groups.len(), 1);
+
+    let expected_holders = vec![
+        Holder {
+            address: OWNER,
+            balance: 700,
+        },
+        Holder {
+            address: ALICE,
+            balance: 100,
+        },
+        Holder {
+            address: BOB,
+            balance: 200,
+        },
+    ];
+    let mut return_data = event_groups[0].return_data.as_deref().unwrap();
+    let holders = Vec::<Holder>::rpc_read_from(&mut return_data);
+    assert_eq!(holders.len(), 3);
+    assert_eq!(holders, expected_holders);
+}
+
+#[test]
+fn full_balance_transfer_removes_sender() {
+    let state = transfer(context(OWNER), initial_state(1000), ALICE, 1000);
+    assert_eq!(state.balances.get(&OWNER), None);
+    assert_eq!(state.balance_of(&ALICE), 1000);
+    assert_eq!(state.top_holders(10), vec![(ALICE, 1000)]);
+
+    let state = transfer(context(ALICE), state, BOB, 1000);
+    assert_eq!(state.balances.get(&ALICE), None);
+    assert_eq!(state.top_holders(10), vec![(BOB, 1000)]);
+}
+
+#[test]
+fn full_balance_transfer_to_self_keeps_sender() {
+    let state = transfer(context(OWNER), initial_state(1000), OWNER, 1000);
+    assert_eq!(state.balances.get(&OWNER), Some(1000));
+}
+
+#[test]
+fn allowance_utilization_tracks_spending() {
+    let state = approve(context(OWNER), initial_state(1000), ALICE, 600);
+    assert_eq!(state.allowance_utilization(&OWNER, &ALICE), (600, 600));
+
+    let state = transfer_from(context(ALICE), state, OWNER, BOB, 250);
+    assert_eq!(state.allowance_utilization(&OWNER, &ALICE), (350, 600));
+
+    // A new approval resets the originally approved amount.
+    let state = approve_relative(context(OWNER), state, ALICE, 50);
+    assert_eq!(state.allowance_utilization(&OWNER, &ALICE), (400, 400));
+
+    let state = transfer_from(context(ALICE), state, OWNER, BOB, 400);
+    assert_eq!(state.allowance_utilization(&OWNER, &ALICE), (0, 400));
+    assert_eq!(state.allowance_utilization(&OWNER, &BOB), (0, 0));
+}
+
+#[test]
+fn full_balance_transfer_from_removes_owner() {
+    let state = approve(context(OWNER), initial_state(1000), ALICE, 1000);
+    let state = transfer_from(context(ALICE), state, OWNER, BOB, 1000);
+    assert_eq!(state.balances.get(&OWNER), None);
+    assert_eq!(state.allowance(&OWNER, &ALICE), 0);
+    assert_eq!(state.balance_of(&BOB), 1000);
+}
@@ -1,6 +1,7 @@
 #![doc = include_str!("../README.md")]
 #![allow(unused_variables)]
 
+use defi_common::time_guard::{assert_after, assert_before};
 use pbc_contract_codegen::*;
 use pbc_contract_common::{
     address::Address,
@@ -78,10 +79,8 @@ pub fn initialize(
 /// The updated vote state reflecting the newly cast vote.
 #[action(shortname = 0x01)]
 pub fn vote(ctx: ContractContext, mut state: VoteState, vote: bool) -> VoteState {
-    assert!(
-        state.result.is_none() && ctx.block_production_time < state.deadline_utc_millis,
-        "The deadline has passed"
-    );
+    assert!(state.result.is_none(), "The deadline has passed");
+    assert_before(&ctx, state.deadline_utc_millis, "The deadline has passed");
     assert!(state.voters.contains(&ctx.sender), "Not an eligible voter");
     state.votes.insert(ctx.sender, vote);
     state
@@ -101,9 +100,10 @@ pub fn vote(ctx: ContractContext, mut state: VoteState, vote: bool) -> VoteState
 #[action(shortname = 0x02)]
 pub fn count(ctx: ContractContext, mut state: VoteState) -> VoteState {
     assert_eq!(state.result, None, "The votes have already been counted");
-    assert!(
-        ctx.block_production_time >= state.deadline_utc_millis,
-        "The deadline has not yet passed"
+    assert_after(
+        &ctx,
+        state.deadline_utc_millis,
+        "The deadline has not yet passed",
     );
     let voters_approving = state.votes.values().filter(|vote| **vote).count();
     let vote_passed = voters_approving > state.voters.len() / 2;
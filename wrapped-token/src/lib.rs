@@ -0,0 +1,281 @@
+#![doc = include_str!("../README.md")]
+
+#[cfg(test)]
+mod tests;
+
+use defi_common::interact_mpc20::{MPC20Contract, TokenTransferAmount};
+use pbc_contract_codegen::*;
+use pbc_contract_common::{
+    address::Address,
+    context::{CallbackContext, ContractContext},
+    events::EventGroup,
+};
+
+/// The state of the wrapper, which is persisted on the chain.
+///
+/// The "state" attribute is attached.
+///
+/// ### Fields:
+///
+/// * `native_coin`: [`Address`], the MPC-20 contract of the wrapped native coin.
+///
+/// * `wrapped_token`: [`Address`], the token-v2 contract of the wrapped token, owned by the
+/// wrapper.
+///
+/// * `deposited`: [`TokenTransferAmount`], the native coins held by the wrapper.
+///
+/// * `wrapped_supply`: [`TokenTransferAmount`], the wrapped tokens minted by the wrapper, and not
+/// yet burned. Equal to `deposited`, except while a wrap awaits [`wrap_mint_callback`].
+#[state]
+pub struct WrappedTokenState {
+    native_coin: Address,
+    wrapped_token: Address,
+    deposited: TokenTransferAmount,
+    wrapped_supply: TokenTransferAmount,
+}
+
+impl WrappedTokenState {
+    /// Gets the native coins held by the wrapper.
+    pub fn deposited(&self) -> TokenTransferAmount {
+        self.deposited
+    }
+
+    /// Gets the wrapped tokens minted by the wrapper, and not yet burned.
+    pub fn wrapped_supply(&self) -> TokenTransferAmount {
+        self.wrapped_supply
+    }
+}
+
+/// Initializes the wrapper of `native_coin` into `wrapped_token`.
+///
+/// The ownership of `wrapped_token` must be transferred to the wrapper before the first wrap.
+///
+/// ### Parameters:
+///
+/// * `_context`: [`ContractContext`], initial context.
+///
+/// * `native_coin`: [`Address`], the MPC-20 contract of the native coin to wrap.
+///
+/// * `wrapped_token`: [`Address`], the token-v2 contract of the wrapped token, with a total
+/// supply of `0`.
+///
+/// ### Returns:
+///
+/// The new state object of type [`WrappedTokenState`], without any deposits.
+#[init]
+pub fn initialize(
+    _context: ContractContext,
+    native_coin: Address,
+    wrapped_token: Address,
+) -> (WrappedTokenState, Vec<EventGroup>) {
+    let state = WrappedTokenState {
+        native_coin,
+        wrapped_token,
+        deposited: 0,
+        wrapped_supply: 0,
+    };
+    (state, vec![])
+}
+
+/// Deposits `amount` of native coins from the sender, and mints the same amount of wrapped tokens
+/// to the sender once the deposit has succeeded.
+///
+/// Requires that the wrapper has been approved at the native coin contract by the sender.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`], the context for the action call.
+///
+/// * `state`: [`WrappedTokenState`], the current state of the contract.
+///
+/// * `amount`: [`TokenTransferAmount`], the amount of native coins to wrap.
+///
+/// ### Returns
+///
+/// The unchanged state object of type [`WrappedTokenState`].
+#[action(shortname = 0x01)]
+pub fn wrap(
+    context: ContractContext,
+    state: WrappedTokenState,
+    amount: TokenTransferAmount,
+) -> (WrappedTokenState, Vec<EventGroup>) {
+    assert!(amount > 0, "Cannot wrap 0 native coins");
+
+    let mut event_group_builder = EventGroup::builder();
+    MPC20Contract::at_address(state.native_coin).transfer_from(
+        &mut event_group_builder,
+        &context.sender,
+        &context.contract_address,
+        amount,
+    );
+    event_group_builder
+        .with_callback(SHORTNAME_WRAP_CALLBACK)
+        .argument(amount)
+        .done();
+
+    (state, vec![event_group_builder.build()])
+}
+
+/// Handles callback from [`wrap`]. <br>
+/// Registers the deposited native coins, and mints the same amount of wrapped tokens to the
+/// sender of [`wrap`].
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`], the context for the callback.
+///
+/// * `callback_context`: [`CallbackContext`], the result of the deposit.
+///
+/// * `state`: [`WrappedTokenState`], the current state of the contract.
+///
+/// * `amount`: [`TokenTransferAmount`], the amount of deposited native coins.
+///
+/// ### Returns
+///
+/// The updated state object of type [`WrappedTokenState`] with the deposit registered.
+#[callback(shortname = 0x10)]
+pub fn wrap_callback(
+    context: ContractContext,
+    callback_context: CallbackContext,
+    mut state: WrappedTokenState,
+    amount: TokenTransferAmount,
+) -> (WrappedTokenState, Vec<EventGroup>) {
+    assert!(callback_context.success, "Deposit did not succeed");
+    state.deposited = state
+        .deposited
+        .checked_add(amount)
+        .expect("Deposited native coins would overflow");
+
+    let mut event_group_builder = EventGroup::builder();
+    MPC20Contract::at_address(state.wrapped_token).mint(
+        &mut event_group_builder,
+        &context.sender,
+        amount,
+    );
+    event_group_builder
+        .with_callback(SHORTNAME_WRAP_MINT_CALLBACK)
+        .argument(amount)
+        .done();
+
+    (state, vec![event_group_builder.build()])
+}
+
+/// Handles callback from the mint of [`wrap_callback`]. <br>
+/// Registers the minted wrapped tokens. If the mint failed, the deposited native coins are
+/// instead transferred back to the sender of [`wrap`].
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`], the context for the callback.
+///
+/// * `callback_context`: [`CallbackContext`], the result of the mint.
+///
+/// * `state`: [`WrappedTokenState`], the current state of the contract.
+///
+/// * `amount`: [`TokenTransferAmount`], the amount of minted wrapped tokens.
+///
+/// ### Returns
+///
+/// The updated state object of type [`WrappedTokenState`] with the mint or refund registered.
+#[callback(shortname = 0x11)]
+pub fn wrap_mint_callback(
+    context: ContractContext,
+    callback_context: CallbackContext,
+    mut state: WrappedTokenState,
+    amount: TokenTransferAmount,
+) -> (WrappedTokenState, Vec<EventGroup>) {
+    if callback_context.success {
+        // Only deposited coins are minted, so the supply cannot exceed the deposits.
+        state.wrapped_supply += amount;
+        return (state, vec![]);
+    }
+
+    state.deposited -= amount;
+    let mut event_group_builder = EventGroup::builder();
+    MPC20Contract::at_address(state.native_coin).transfer(
+        &mut event_group_builder,
+        &context.sender,
+        amount,
+    );
+    (state, vec![event_group_builder.build()])
+}
+
+/// Burns `amount` of wrapped tokens from the sender, and transfers the same amount of native coins
+/// to the sender once the burn has succeeded.
+///
+/// Requires that the wrapper has been approved at the wrapped token contract by the sender.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`], the context for the action call.
+///
+/// * `state`: [`WrappedTokenState`], the current state of the contract.
+///
+/// * `amount`: [`TokenTransferAmount`], the amount of wrapped tokens to unwrap.
+///
+/// ### Returns
+///
+/// The unchanged state object of type [`WrappedTokenState`].
+#[action(shortname = 0x02)]
+pub fn unwrap(
+    context: ContractContext,
+    state: WrappedTokenState,
+    amount: TokenTransferAmount,
+) -> (WrappedTokenState, Vec<EventGroup>) {
+    assert!(amount > 0, "Cannot unwrap 0 wrapped tokens");
+
+    let mut event_group_builder = EventGroup::builder();
+    MPC20Contract::at_address(state.wrapped_token).burn_from(
+        &mut event_group_builder,
+        &context.sender,
+        amount,
+    );
+    event_group_builder
+        .with_callback(SHORTNAME_UNWRAP_CALLBACK)
+        .argument(amount)
+        .done();
+
+    (state, vec![event_group_builder.build()])
+}
+
+/// Handles callback from [`unwrap`]. <br>
+/// Registers the burned wrapped tokens, and transfers the same amount of native coins to the
+/// sender of [`unwrap`]. The wrapper holds every deposited coin, so the transfer cannot fail for
+/// lack of funds.
+///
+/// ### Parameters:
+///
+/// * `context`: [`ContractContext`], the context for the callback.
+///
+/// * `callback_context`: [`CallbackContext`], the result of the burn.
+///
+/// * `state`: [`WrappedTokenState`], the current state of the contract.
+///
+/// * `amount`: [`TokenTransferAmount`], the amount of burned wrapped tokens.
+///
+/// ### Returns
+///
+/// The updated state object of type [`WrappedTokenState`] with the withdrawal registered.
+#[callback(shortname = 0x12)]
+pub fn unwrap_callback(
+    context: ContractContext,
+    callback_context: CallbackContext,
+    mut state: WrappedTokenState,
+    amount: TokenTransferAmount,
+) -> (WrappedTokenState, Vec<EventGroup>) {
+    assert!(callback_context.success, "Burn did not succeed");
+    state.wrapped_supply = state
+        .wrapped_supply
+        .checked_sub(amount)
+        .expect("Cannot unwrap more than the wrapped supply");
+    // Every wrapped token is backed by a deposited coin.
+    state.deposited -= amount;
+
+    let mut event_group_builder = EventGroup::builder();
+    MPC20Contract::at_address(state.native_coin).transfer(
+        &mut event_group_builder,
+        &context.sender,
+        amount,
+    );
+    (state, vec![event_group_builder.build()])
+}
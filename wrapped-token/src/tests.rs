@@ -0,0 +1,169 @@
+use pbc_contract_common::{
+    address::AddressType,
+    context::{CallbackContext, ExecutionResult},
+    Hash,
+};
+
+use super::*;
+
+const WRAPPER: Address = Address {
+    address_type: AddressType::PublicContract,
+    identifier: [0; 20],
+};
+
+const NATIVE_COIN: Address = Address {
+    address_type: AddressType::PublicContract,
+    identifier: [1; 20],
+};
+
+const WRAPPED_TOKEN: Address = Address {
+    address_type: AddressType::PublicContract,
+    identifier: [2; 20],
+};
+
+const ALICE: Address = Address {
+    address_type: AddressType::Account,
+    identifier: [3; 20],
+};
+
+const BOB: Address = Address {
+    address_type: AddressType::Account,
+    identifier: [4; 20],
+};
+
+/// Creates a context for an invocation sent by `sender`.
+fn context(sender: Address) -> ContractContext {
+    ContractContext {
+        contract_address: WRAPPER,
+        sender,
+        block_time: 0,
+        block_production_time: 0,
+        current_transaction: Hash { bytes: [0; 32] },
+        original_transaction: Hash { bytes: [0; 32] },
+    }
+}
+
+/// A callback context with a single event, succeeding as given by `succeeded`.
+fn callback_context(succeeded: bool) -> CallbackContext {
+    CallbackContext {
+        success: succeeded,
+        results: vec![ExecutionResult {
+            succeeded,
+            return_data: vec![],
+        }],
+    }
+}
+
+/// Initializes a wrapper of [`NATIVE_COIN`] into [`WRAPPED_TOKEN`].
+fn initial_state() -> WrappedTokenState {
+    initialize(context(ALICE), NATIVE_COIN, WRAPPED_TOKEN).0
+}
+
+/// Wraps `amount` for `sender`, with both the deposit and the mint succeeding.
+fn wrapped(state: WrappedTokenState, sender: Address, amount: u128) -> WrappedTokenState {
+    let (state, _) = wrap(context(sender), state, amount);
+    let (state, _) = wrap_callback(context(sender), callback_context(true), state, amount);
+    wrap_mint_callback(context(sender), callback_context(true), state, amount).0
+}
+
+/// Unwraps `amount` for `sender`, with the burn succeeding.
+fn unwrapped(state: WrappedTokenState, sender: Address, amount: u128) -> WrappedTokenState {
+    let (state, _) = unwrap(context(sender), state, amount);
+    unwrap_callback(context(sender), callback_context(true), state, amount).0
+}
+
+#[test]
+fn wrap_deposits_native_coins_before_minting() {
+    let (state, events) = wrap(context(ALICE), initial_state(), 100);
+
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].events[0].dest, NATIVE_COIN);
+    assert_eq!(state.deposited(), 0);
+
+    let (state, events) = wrap_callback(context(ALICE), callback_context(true), state, 100);
+
+    assert_eq!(events[0].events[0].dest, WRAPPED_TOKEN);
+    assert_eq!(state.deposited(), 100);
+    assert_eq!(state.wrapped_supply(), 0);
+
+    let (state, events) = wrap_mint_callback(context(ALICE), callback_context(true), state, 100);
+
+    assert!(events.is_empty());
+    assert_eq!(state.deposited(), 100);
+    assert_eq!(state.wrapped_supply(), 100);
+}
+
+#[test]
+#[should_panic(expected = "Deposit did not succeed")]
+fn wrap_fails_if_deposit_fails() {
+    let (state, _) = wrap(context(ALICE), initial_state(), 100);
+    wrap_callback(context(ALICE), callback_context(false), state, 100);
+}
+
+#[test]
+fn wrap_refunds_deposit_if_mint_fails() {
+    let state = wrapped(initial_state(), BOB, 50);
+    let (state, _) = wrap(context(ALICE), state, 100);
+    let (state, _) = wrap_callback(context(ALICE), callback_context(true), state, 100);
+    let (state, events) = wrap_mint_callback(context(ALICE), callback_context(false), state, 100);
+
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].events[0].dest, NATIVE_COIN);
+    assert_eq!(state.deposited(), 50);
+    assert_eq!(state.wrapped_supply(), 50);
+}
+
+#[test]
+#[should_panic(expected = "Cannot wrap 0 native coins")]
+fn wrap_nothing_is_rejected() {
+    wrap(context(ALICE), initial_state(), 0);
+}
+
+#[test]
+fn unwrap_burns_before_withdrawing() {
+    let state = wrapped(initial_state(), ALICE, 100);
+    let (state, events) = unwrap(context(ALICE), state, 40);
+
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].events[0].dest, WRAPPED_TOKEN);
+    assert_eq!(state.deposited(), 100);
+
+    let (state, events) = unwrap_callback(context(ALICE), callback_context(true), state, 40);
+
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].events[0].dest, NATIVE_COIN);
+    assert_eq!(state.deposited(), 60);
+    assert_eq!(state.wrapped_supply(), 60);
+}
+
+#[test]
+#[should_panic(expected = "Burn did not succeed")]
+fn unwrap_fails_if_burn_fails() {
+    let state = wrapped(initial_state(), ALICE, 100);
+    let (state, _) = unwrap(context(ALICE), state, 40);
+    unwrap_callback(context(ALICE), callback_context(false), state, 40);
+}
+
+#[test]
+#[should_panic(expected = "Cannot unwrap 0 wrapped tokens")]
+fn unwrap_nothing_is_rejected() {
+    let state = wrapped(initial_state(), ALICE, 100);
+    unwrap(context(ALICE), state, 0);
+}
+
+#[test]
+fn wrapped_supply_matches_deposited_native_coins() {
+    let state = wrapped(initial_state(), ALICE, 1_000);
+    let state = wrapped(state, BOB, 250);
+    let state = unwrapped(state, ALICE, 400);
+    let state = wrapped(state, ALICE, 75);
+    let state = unwrapped(state, BOB, 250);
+
+    assert_eq!(state.deposited(), 1_000 + 250 - 400 + 75 - 250);
+    assert_eq!(state.wrapped_supply(), state.deposited());
+
+    let state = unwrapped(state, ALICE, 675);
+
+    assert_eq!(state.deposited(), 0);
+    assert_eq!(state.wrapped_supply(), 0);
+}